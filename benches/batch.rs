@@ -0,0 +1,54 @@
+//! times evaluating a formula over a million inputs with a call per input,
+//! against one map_unary call running the loop in jitted code - run with
+//! cargo bench --bench batch
+use std::time::{Duration, Instant};
+
+use inkwell::context::Context;
+use wizarding::{
+    codegen::Codegen,
+    compile,
+    engine::{EngineKind, OptLevel},
+    plan::CompileOptions,
+};
+
+const INPUTS: usize = 1_000_000;
+const RUNS: u32 = 5;
+
+const SOURCE: &str = "🜹sqrt🜄x🜂;🜙f🜄x🜂sqrt🜄x*x + 1🜂*0.5 - x/3;";
+
+/// the fastest of RUNS runs of f
+fn time<T>(name: &str, mut f: impl FnMut() -> T) {
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        std::hint::black_box(f());
+        best = best.min(start.elapsed());
+    }
+    println!("{:<24} {:>10.3?}", name, best);
+}
+
+fn main() {
+    let context = Context::create();
+    let program = compile::compile(
+        Codegen::new(&context),
+        &[("f.wiz", SOURCE)],
+        &mut CompileOptions::default(),
+    )
+    .unwrap()
+    .into_program();
+    let inputs: Vec<f64> = (0..INPUTS).map(|i| i as f64 * 1e-3).collect();
+    let mut outputs = vec![0.0; INPUTS];
+    println!("{} inputs", INPUTS);
+
+    let engine = program.engine(EngineKind::Jit, OptLevel::Default).unwrap();
+    time("call per input", || {
+        for (input, output) in inputs.iter().zip(outputs.iter_mut()) {
+            *output = engine.call("f", &[*input]).unwrap();
+        }
+    });
+    // the first map compiles the wrapper
+    program.map_unary("f", &inputs, &mut outputs).unwrap();
+    time("map_unary", || {
+        program.map_unary("f", &inputs, &mut outputs).unwrap()
+    });
+}
@@ -0,0 +1,61 @@
+//! times codegen of a generated program making 100k calls, against looking
+//! each callee up in the module and counting its params the way calls used
+//! to - run with cargo bench --bench codegen
+use std::time::{Duration, Instant};
+
+use inkwell::context::Context;
+use wizarding::{codegen::Codegen, parser::Parser};
+
+const FUNCTIONS: usize = 1_000;
+const CALLS: usize = 100;
+const RUNS: u32 = 5;
+
+/// a program of functions that each call the one before them CALLS times
+fn program() -> String {
+    let mut res = String::from("🜙f0🜄x🜌 y🜂x*y;\n");
+    for n in 1..FUNCTIONS {
+        let calls: Vec<String> = (0..CALLS)
+            .map(|i| format!("f{}🜄x🜌 {}🜂", n - 1, i))
+            .collect();
+        res += &format!("🜙f{}🜄x🜌 y🜂{};\n", n, calls.join(" + "));
+    }
+    res
+}
+
+/// the fastest of RUNS runs of f
+fn time<T>(name: &str, mut f: impl FnMut() -> T) {
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        std::hint::black_box(f());
+        best = best.min(start.elapsed());
+    }
+    println!("{:<24} {:>10.3?}", name, best);
+}
+
+fn main() {
+    let ast = Parser::default().parse_str(&program()).unwrap();
+    println!("{} functions, {} calls", FUNCTIONS, FUNCTIONS * CALLS);
+
+    time("codegen", || {
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        codegen.module.print_to_string().to_bytes().len()
+    });
+
+    let context = Context::create();
+    let mut codegen = Codegen::new(&context);
+    codegen.codegen(&ast).unwrap();
+    let names: Vec<String> = (0..FUNCTIONS).map(|n| format!("f{}", n)).collect();
+    time("get_function per call", || {
+        let mut params = 0;
+        for name in &names {
+            for _ in 0..CALLS {
+                let func = codegen.module.get_function(name).unwrap();
+                params += func.get_params().len();
+            }
+        }
+        params
+    });
+}
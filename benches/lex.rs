@@ -0,0 +1,92 @@
+//! lexing and parsing a generated 10k function program of about a megabyte,
+//! with the borrowed tokens against owning every token's text the way the
+//! lexer used to, and the scanner against the regex lexer it replaced - run
+//! with cargo bench --bench lex --features reference-lexer
+//!
+//! the allocations one run of each makes are printed before criterion's
+//! timings, through a global allocator that counts them
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{black_box, Criterion};
+use wizarding::{
+    lexer::{self, Token},
+    parser::Parser,
+};
+
+/// the system allocator, counting every allocation made through it
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const FUNCTIONS: usize = 10_000;
+
+/// a program with a function for every n, each calling the one before it
+fn program(functions: usize) -> String {
+    let mut res = String::from("🜙f0🜄x🜌 y🜂 x*y + 1; # the first\n");
+    for n in 1..functions {
+        res += &format!(
+            "🜙f{0}🜄alpha🜌 beta🜂 # calls f{1}\n    f{1}🜄alpha - 2.5🜌 beta🜂 / 0x1F + alpha*beta;\n",
+            n,
+            n - 1
+        );
+    }
+    res
+}
+
+/// lex the source into tokens that own their text
+fn lex_owned(source: &str) -> Vec<lexer::OwnedToken> {
+    lexer::lex(source)
+        .unwrap()
+        .into_iter()
+        .map(Token::into_owned)
+        .collect()
+}
+
+/// print how many allocations one run of f makes
+fn count<T>(name: &str, f: impl FnOnce() -> T) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    black_box(f());
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!("{:<16} {:>9} allocations", name, allocations);
+}
+
+fn main() {
+    let source = program(FUNCTIONS);
+    println!("{} functions, {} bytes", FUNCTIONS, source.len());
+    let parser = Parser::default();
+
+    count("lex", || lexer::lex(&source).unwrap());
+    count("regex lex", || lexer::reference::lex(&source).unwrap());
+    count("lex owned", || lex_owned(&source));
+    count("lex and parse", || parser.parse_str(&source).unwrap());
+
+    let mut c = Criterion::default().sample_size(20).configure_from_args();
+    c.bench_function("lex", |b| {
+        b.iter(|| lexer::lex(black_box(&source)).unwrap())
+    });
+    c.bench_function("regex lex", |b| {
+        b.iter(|| lexer::reference::lex(black_box(&source)).unwrap())
+    });
+    c.bench_function("lex owned", |b| b.iter(|| lex_owned(black_box(&source))));
+    c.bench_function("lex and parse", |b| {
+        b.iter(|| parser.parse_str(black_box(&source)).unwrap())
+    });
+    c.final_summary();
+}
@@ -0,0 +1,100 @@
+//! parsing and compiling a generated program with thousands of uses of the
+//! same few parameters, each copied into the ast as a string of its own and
+//! looked up by it in codegen, next to the same program with literals in
+//! place of the names - run with cargo bench --bench names
+//!
+//! the allocations and bytes one parse of each makes are printed before
+//! criterion's timings, through a global allocator that counts them
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{black_box, Criterion};
+use inkwell::context::Context;
+use wizarding::{ast::ASTNode, codegen::Codegen, parser::Parser};
+
+/// the system allocator, counting every allocation made through it and
+/// the bytes they asked for
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const FUNCTIONS: usize = 100;
+const USES: usize = 1_000;
+const PARAMS: [&str; 3] = ["alpha", "beta", "gamma"];
+
+/// FUNCTIONS functions of the three params, each adding up USES operands
+/// with the operand for each use
+fn program(operand: impl Fn(usize) -> String) -> String {
+    let mut res = String::new();
+    for n in 0..FUNCTIONS {
+        let uses: Vec<String> = (0..USES).map(&operand).collect();
+        res += &format!("🜙f{}🜄{}🜂{};\n", n, PARAMS.join("🜌 "), uses.join(" + "));
+    }
+    res
+}
+
+/// print how many allocations and bytes one run of f makes
+fn count<T>(name: &str, f: impl FnOnce() -> T) {
+    let (allocations, bytes) = (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        BYTES.load(Ordering::Relaxed),
+    );
+    black_box(f());
+    println!(
+        "{:<16} {:>9} allocations {:>11} bytes",
+        name,
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        BYTES.load(Ordering::Relaxed) - bytes
+    );
+}
+
+fn main() {
+    let names = program(|i| PARAMS[i % PARAMS.len()].to_string());
+    let literals = program(|i| format!("{}.5", i % PARAMS.len()));
+    println!("{} functions, {} uses each", FUNCTIONS, USES);
+    let parser = Parser::default();
+
+    count("parse names", || parser.parse_str(&names).unwrap());
+    count("parse literals", || parser.parse_str(&literals).unwrap());
+
+    let mut c = Criterion::default().sample_size(20).configure_from_args();
+    c.bench_function("parse names", |b| {
+        b.iter(|| parser.parse_str(black_box(&names)).unwrap())
+    });
+    c.bench_function("parse literals", |b| {
+        b.iter(|| parser.parse_str(black_box(&literals)).unwrap())
+    });
+    let codegen = |ast: &Vec<ASTNode>| {
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(ast).unwrap();
+        codegen.module.print_to_string().to_bytes().len()
+    };
+    let (names, literals) = (
+        parser.parse_str(&names).unwrap(),
+        parser.parse_str(&literals).unwrap(),
+    );
+    c.bench_function("codegen names", |b| b.iter(|| codegen(black_box(&names))));
+    c.bench_function("codegen literals", |b| {
+        b.iter(|| codegen(black_box(&literals)))
+    });
+    c.final_summary();
+}
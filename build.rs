@@ -0,0 +1,13 @@
+//! regenerates include/wizarding.h from src/capi.rs with cbindgen when the
+//! capi feature is on, so the header can't drift from the functions
+fn main() {
+    #[cfg(feature = "capi")]
+    {
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        println!("cargo:rerun-if-changed=src/capi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        cbindgen::generate(&dir)
+            .expect("cbindgen couldn't read src/capi.rs")
+            .write_to_file(std::path::Path::new(&dir).join("include/wizarding.h"));
+    }
+}
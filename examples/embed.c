@@ -0,0 +1,37 @@
+/* evaluates wizarding from c, calling back into the host - tests/capi.rs
+ * builds and runs it */
+#include <stdio.h>
+
+#include "wizarding.h"
+
+static double twice(double x) { return x * 2; }
+
+int main(void) {
+    WizSession *session = wiz_session_new();
+    if (!session) {
+        return 1;
+    }
+    char err[256];
+    double value = 0;
+
+    if (wiz_register_fn(session, "twice", 1, (const void *)twice) != WIZ_OK) {
+        return 1;
+    }
+    if (wiz_eval(session, "🜙hyp🜄a🜌b🜂🜄a*a🜂 + b*b;twice🜄hyp🜄3🜌4🜂🜂;", &value, err, sizeof err) != WIZ_OK) {
+        fprintf(stderr, "%s\n", err);
+        return 1;
+    }
+    printf("eval %g\n", value);
+
+    double (*hyp)(double, double) = (double (*)(double, double))wiz_get_fn(session, "hyp", 2);
+    if (!hyp) {
+        return 1;
+    }
+    printf("hyp %g\n", hyp(1, 2));
+
+    int status = wiz_eval(session, "missing🜄1🜂;", &value, err, sizeof err);
+    printf("status %d\nerror %s\njson %s\n", status, err, wiz_last_error_json(session));
+
+    wiz_session_free(session);
+    return 0;
+}
@@ -0,0 +1,124 @@
+/* generated by cbindgen from src/capi.rs with the capi feature - edit that instead */
+
+#ifndef WIZARDING_H
+#define WIZARDING_H
+
+#include <stddef.h>
+
+/*
+ * the call did what it was asked
+ */
+#define WIZ_OK 0
+
+/*
+ * the source didn't compile or run, or the function wasn't found - the
+ * details are in wiz_last_error_json
+ */
+#define WIZ_ERROR 1
+
+/*
+ * a pointer that has to be set was null or a string wasn't utf-8
+ */
+#define WIZ_INVALID_ARGUMENT 2
+
+/*
+ * the compiler panicked, which was caught - the session can still be freed
+ * but nothing else about it should be relied on
+ */
+#define WIZ_PANIC 3
+
+/*
+ * an embedded compiler - each source evaluated in it is compiled on its own
+ * and calls what earlier ones define, see session::Session
+ *
+ * a session isn't thread safe, it has to be used by one thread at a time
+ */
+typedef struct WizSession WizSession;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ * a new empty session, which has to be freed with wiz_session_free -
+ * returns null if creating it panicked
+ */
+WizSession *wiz_session_new(void);
+
+/*
+ * free a session from wiz_session_new along with everything compiled in
+ * it - every address wiz_get_fn returned and the last error string are
+ * invalid afterwards, and null is ignored
+ *
+ * # Safety
+ *
+ * session has to be null or come from wiz_session_new and not be freed yet
+ */
+void wiz_session_free(WizSession *session);
+
+/*
+ * compile source, a nul terminated utf-8 string, with everything evaluated
+ * in the session before it and run its top level expressions - the value of
+ * the last one is written to out_value if that isn't null, which is left
+ * alone when there is none
+ *
+ * on WIZ_ERROR the message is copied into err_buf, cut to err_len bytes
+ * with the nul, unless err_buf is null, and nothing of source is kept
+ *
+ * # Safety
+ *
+ * session has to come from wiz_session_new, source has to be a nul
+ * terminated string, out_value null or writable and err_buf null or
+ * writable for err_len bytes
+ */
+int wiz_eval(WizSession *session,
+             const char *source,
+             double *out_value,
+             char *err_buf,
+             size_t err_len);
+
+/*
+ * make name, a nul terminated utf-8 string, callable from sources evaluated
+ * after this as a function of arity arguments that runs fn_ptr - the
+ * session declares the extern itself, and a source that declares it too
+ * fails to evaluate unless its extern takes arity arguments
+ *
+ * # Safety
+ *
+ * session has to come from wiz_session_new and name has to be a nul
+ * terminated string. fn_ptr has to be a function taking arity doubles and
+ * returning a double with the C calling convention, that doesn't unwind and
+ * stays valid as long as the session
+ */
+int wiz_register_fn(WizSession *session, const char *name, size_t arity, const void *fn_ptr);
+
+/*
+ * the address of the function named name, a nul terminated utf-8 string,
+ * that the sources evaluated so far define with arity parameters, or null
+ * if there's none or it isn't a function of doubles - it takes and
+ * returns doubles with the C calling convention and stays valid until the
+ * session is freed
+ *
+ * # Safety
+ *
+ * session has to come from wiz_session_new and name has to be a nul
+ * terminated string
+ */
+const void *wiz_get_fn(WizSession *session, const char *name, size_t arity);
+
+/*
+ * the last error of the session as a json object with a severity, code and
+ * message, or null if the last call succeeded - the string belongs to the
+ * session and is only valid until the next call with it
+ *
+ * # Safety
+ *
+ * session has to be null or come from wiz_session_new
+ */
+const char *wiz_last_error_json(const WizSession *session);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* WIZARDING_H */
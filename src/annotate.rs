@@ -0,0 +1,198 @@
+use std::{fmt, ops::Range};
+
+use inkwell::context::Context;
+
+use crate::ast::ASTNode;
+use crate::classify;
+use crate::codegen::{Codegen, CodegenError};
+use crate::diagnostic::Diagnostic;
+use crate::engine::{Engine, EngineKind, OptLevel};
+use crate::lexer::{self, Token};
+use crate::numfmt;
+use crate::parser::Parser;
+use crate::plan::Plan;
+
+/// the comments an annotation starts with, so they can be found again
+const MARKERS: &[&str] = &["# =>", "# !!"];
+
+/// what a top level expression came out to
+#[derive(Debug, PartialEq, Clone)]
+pub enum Annotation {
+    Value(f64),
+    /// it couldn't be compiled or run
+    Error(Diagnostic),
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Annotation::Value(value) => write!(f, "# => {}", numfmt::shortest(*value)),
+            // the comment ends at the end of the line
+            Annotation::Error(diagnostic) => {
+                write!(f, "# !! {}", diagnostic.message.replace('\n', " "))
+            }
+        }
+    }
+}
+
+/// compile the program with each top level expression on its own, so one
+/// that doesn't compile only fails itself, and run them in source order
+pub fn evaluate(plan: &Plan) -> Result<Vec<Annotation>, CodegenError> {
+    let context = Context::create();
+    let mut codegen = Codegen::new(&context);
+    let mut symbols = Vec::new();
+    for node in &plan.ast {
+        match node {
+            ASTNode::Function(func) if func.prototype.name == "lambda" => {
+                symbols.push(match codegen.codegen_node(node) {
+                    Ok(()) => Ok(codegen.entries.last().unwrap().0.clone()),
+                    Err(e) => Err(Diagnostic::error("codegen", e)),
+                })
+            }
+            node => codegen.codegen_node(node)?,
+        }
+    }
+
+    let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None);
+    Ok(symbols
+        .into_iter()
+        .map(|symbol| {
+            let value = symbol.and_then(|symbol| match &engine {
+                Ok(engine) => engine
+                    .run_entry(&symbol)
+                    .map_err(|e| Diagnostic::error("run", e)),
+                Err(e) => Err(Diagnostic::error("engine", e)),
+            });
+            match value {
+                Ok(value) => Annotation::Value(value),
+                Err(diagnostic) => Annotation::Error(diagnostic),
+            }
+        })
+        .collect())
+}
+
+/// the byte offset each top level expression in source ends at, in order
+fn entry_ends(source: &str) -> Vec<usize> {
+    let parser = Parser {
+        allow_reserved: true,
+        allow_inline_ir: true,
+        ..Parser::default()
+    };
+    // a source that doesn't lex has no plan to annotate it with
+    let tokens = lexer::lex_spanned(source).unwrap_or_default();
+    classify::chunks(&tokens)
+        .into_iter()
+        .filter(|chunk| {
+            let mut stack: Vec<Token> = chunk.iter().rev().map(|(tok, _)| *tok).collect();
+            match parser.parse(&mut stack).as_deref() {
+                Ok([ASTNode::Function(func)]) => func.prototype.name == "lambda",
+                _ => false,
+            }
+        })
+        .map(|chunk| chunk.last().unwrap().1.end)
+        .collect()
+}
+
+/// the range from the end of the code on the line containing offset to the
+/// end of the line, without its line break - an annotation that's already
+/// there is part of it, comments before that are not
+fn annotation_range(source: &str, offset: usize, comments: &[Range<usize>]) -> Range<usize> {
+    let line_end = source[offset..]
+        .find('\n')
+        .map_or(source.len(), |i| offset + i);
+    let line_end = match source[..line_end].ends_with('\r') {
+        true => line_end - 1,
+        false => line_end,
+    };
+    let marker = comments
+        .iter()
+        .filter(|comment| comment.start >= offset && comment.start < line_end)
+        .find_map(|comment| {
+            MARKERS
+                .iter()
+                .filter_map(|marker| source[comment.clone()].find(marker))
+                .min()
+                .map(|i| comment.start + i)
+        });
+    let start = marker.unwrap_or(line_end);
+    let start = offset + source[offset..start].trim_end().len();
+    start..line_end
+}
+
+/// source with what each top level expression came out to as a comment at
+/// the end of the line it ends on, replacing the annotation that's already
+/// there - expressions sharing a line share a comment, which is the first
+/// error among them or else their values in order
+pub fn annotate(source: &str, annotations: &[Annotation]) -> String {
+    let comments = lexer::comments(source);
+    let mut lines: Vec<(Range<usize>, Vec<&Annotation>)> = Vec::new();
+    for (end, annotation) in entry_ends(source).into_iter().zip(annotations) {
+        let range = annotation_range(source, end, &comments);
+        match lines.last_mut() {
+            Some((last, line)) if last.end == range.end => {
+                *last = range;
+                line.push(annotation);
+            }
+            _ => lines.push((range, vec![annotation])),
+        }
+    }
+
+    let mut res = String::with_capacity(source.len());
+    let mut copied = 0;
+    for (range, line) in lines {
+        let comment = match line
+            .iter()
+            .find(|annotation| matches!(annotation, Annotation::Error(_)))
+        {
+            Some(error) => error.to_string(),
+            None => {
+                let values: Vec<String> = line
+                    .iter()
+                    .filter_map(|annotation| match annotation {
+                        Annotation::Value(value) => Some(numfmt::shortest(*value)),
+                        Annotation::Error(_) => None,
+                    })
+                    .collect();
+                format!("# => {}", values.join(", "))
+            }
+        };
+        res += &source[copied..range.start];
+        res += " ";
+        res += &comment;
+        copied = range.end;
+    }
+    res += &source[copied..];
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::CompileOptions;
+
+    fn worksheet(source: &str) -> String {
+        let plan = Plan::new("sheet.wiz", source, &mut CompileOptions::default()).unwrap();
+        annotate(source, &evaluate(&plan).unwrap())
+    }
+
+    #[test]
+    fn annotate_works() {
+        let source =
+            "# squares\n🜙sq🜄x🜂x*x;\nsq🜄3🜂;   \n1; 2; # two of them\nsq🜄\n  4🜂; # => 9\r\n0.1 + 0.2";
+        let annotated = worksheet(source);
+        assert_eq!(
+            annotated,
+            "# squares\n🜙sq🜄x🜂x*x;\nsq🜄3🜂; # => 9\n1; 2; # two of them # => 1, 2\nsq🜄\n  4🜂; # => 16\r\n0.1 + 0.2 # => 0.30000000000000004"
+        );
+        assert_eq!(worksheet(&annotated), annotated);
+    }
+
+    #[test]
+    fn errors_are_annotated() {
+        let source = "🜙sq🜄x🜂x*x;\ncube🜄2🜂; # => 8\nsq🜄2🜂;\n3; cube🜄1🜂;\n";
+        assert_eq!(
+            worksheet(source),
+            "🜙sq🜄x🜂x*x;\ncube🜄2🜂; # !! unknown function cube\nsq🜄2🜂; # => 4\n3; cube🜄1🜂; # !! unknown function cube\n"
+        );
+    }
+}
@@ -1,7 +1,49 @@
+use std::fmt::{self, Write};
+
+use crate::lexer;
+use crate::numfmt;
+use crate::parser::ParserError;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Prototype {
     pub name: String,
     pub args: Vec<String>,
+    /// the symbol an extern resolves to, when it differs from its name
+    pub link_name: Option<String>,
+    /// the value of each argument when a call leaves it out, if it has one
+    pub defaults: Vec<Option<Expression>>,
+    /// whether it returns nothing, only externs to host procedures can
+    pub void: bool,
+    /// how freely the body's floating point math may be rewritten
+    pub math: MathMode,
+}
+
+impl Prototype {
+    /// the host symbol it's declared under, its link name if it has one
+    pub fn symbol(&self) -> &str {
+        self.link_name.as_ref().unwrap_or(&self.name)
+    }
+}
+
+/// the fast or strict modifier of a definition
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum MathMode {
+    /// fast when the whole program is compiled with fast math
+    #[default]
+    Inherit,
+    Fast,
+    /// exact even when the whole program is compiled with fast math
+    Strict,
+}
+
+impl MathMode {
+    pub fn is_fast(self, fast_math: bool) -> bool {
+        match self {
+            MathMode::Inherit => fast_math,
+            MathMode::Fast => true,
+            MathMode::Strict => false,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -10,6 +52,290 @@ pub enum Expression {
     Variable(String),
     Binary(String, Box<Expression>, Box<Expression>),
     Call(String, Vec<Expression>),
+    /// a call with at least one argument given by parameter name, which is
+    /// reordered into a plain call once every prototype is known
+    NamedCall(String, Vec<(Option<String>, Expression)>),
+    /// where a syntax error was, standing in for the rest of the expression
+    /// so parsing can go on and find more - see Parser::parse_recovering
+    Error(Box<ParserError>),
+}
+
+fn precedence(op: &str) -> u32 {
+    match op {
+        "*" | "/" => 40,
+        "+" | "-" => 20,
+        _ => 0,
+    }
+}
+
+/// whether `a op 🜄b next c🜂` has the same value as `🜄a op b🜂 next c`,
+/// so a reader who groups from the left needs no parens to get it right
+fn associative(op: &str, next: &str) -> bool {
+    matches!(
+        (op, next),
+        ("+", "+") | ("+", "-") | ("*", "*") | ("*", "/")
+    )
+}
+
+impl Expression {
+    /// whether a syntax error was recovered from anywhere in it
+    pub fn has_error(&self) -> bool {
+        match self {
+            Expression::Literal(_) | Expression::Variable(_) => false,
+            Expression::Error(_) => true,
+            Expression::Binary(_, lhs, rhs) => lhs.has_error() || rhs.has_error(),
+            Expression::Call(_, args) => args.iter().any(Expression::has_error),
+            Expression::NamedCall(_, args) => args.iter().any(|(_, arg)| arg.has_error()),
+        }
+    }
+
+    /// an indented rendering with one node a line, showing at most
+    /// max_depth levels and max_children children of each node and ending
+    /// in a node count - unlike {:#?} it stays short for huge trees and
+    /// doesn't recurse, so any depth can be printed
+    pub fn display_tree(&self, max_depth: usize, max_children: usize) -> String {
+        display_tree(TreeNode::Expr(self), max_depth, max_children)
+    }
+
+    /// one line like `Binary(+) with 14,203 nodes, depth 87`
+    pub fn summary(&self) -> String {
+        summary(TreeNode::Expr(self))
+    }
+}
+
+/// either kind of node, so items and expressions share one tree printer
+#[derive(Clone, Copy)]
+enum TreeNode<'a> {
+    Item(&'a ASTNode),
+    Expr(&'a Expression),
+}
+
+impl<'a> TreeNode<'a> {
+    fn label(self) -> String {
+        match self {
+            TreeNode::Item(ASTNode::Extern(proto)) => format!("Extern({})", proto.name),
+            TreeNode::Item(ASTNode::Function(func)) => format!("Function({})", func.prototype.name),
+            TreeNode::Item(ASTNode::Table(table)) => format!("Table({})", table.name),
+            TreeNode::Item(ASTNode::When(cond, _)) => format!("When({})", cond),
+            TreeNode::Item(ASTNode::InlineIr(_)) => "InlineIr".to_string(),
+            TreeNode::Item(ASTNode::Test(test)) => format!("Test({})", test.name),
+            TreeNode::Expr(Expression::Literal(value)) => format!("Literal({})", value),
+            TreeNode::Expr(Expression::Variable(name)) => format!("Variable({})", name),
+            TreeNode::Expr(Expression::Binary(op, ..)) => format!("Binary({})", op),
+            TreeNode::Expr(Expression::Call(callee, _)) => format!("Call({})", callee),
+            TreeNode::Expr(Expression::NamedCall(callee, _)) => format!("NamedCall({})", callee),
+            TreeNode::Expr(Expression::Error(_)) => "Error".to_string(),
+        }
+    }
+
+    /// the children, each with the name of the parameter it's for when it
+    /// has one
+    fn children(self) -> Vec<(Option<&'a str>, TreeNode<'a>)> {
+        let defaults = |proto: &'a Prototype| {
+            proto
+                .args
+                .iter()
+                .zip(&proto.defaults)
+                .filter_map(|(arg, default)| {
+                    Some((Some(arg.as_str()), TreeNode::Expr(default.as_ref()?)))
+                })
+                .collect::<Vec<_>>()
+        };
+        match self {
+            TreeNode::Item(ASTNode::Extern(proto)) => defaults(proto),
+            TreeNode::Item(ASTNode::Function(func)) => {
+                let mut res = defaults(&func.prototype);
+                res.push((None, TreeNode::Expr(&func.body)));
+                res
+            }
+            TreeNode::Item(ASTNode::Table(_)) | TreeNode::Item(ASTNode::InlineIr(_)) => Vec::new(),
+            TreeNode::Item(ASTNode::When(_, item)) => vec![(None, TreeNode::Item(item))],
+            TreeNode::Item(ASTNode::Test(test)) => vec![
+                (None, TreeNode::Expr(&test.expr)),
+                (Some("expect"), TreeNode::Expr(&test.expected)),
+            ],
+            TreeNode::Expr(expr) => match expr {
+                Expression::Literal(_) | Expression::Variable(_) | Expression::Error(_) => {
+                    Vec::new()
+                }
+                Expression::Binary(_, lhs, rhs) => {
+                    vec![(None, TreeNode::Expr(lhs)), (None, TreeNode::Expr(rhs))]
+                }
+                Expression::Call(_, args) => {
+                    args.iter().map(|arg| (None, TreeNode::Expr(arg))).collect()
+                }
+                Expression::NamedCall(_, args) => args
+                    .iter()
+                    .map(|(name, arg)| (name.as_deref(), TreeNode::Expr(arg)))
+                    .collect(),
+            },
+        }
+    }
+}
+
+/// the number of nodes in the tree and its depth, counting the root as 1
+fn measure(root: TreeNode) -> (usize, usize) {
+    let mut stack = vec![(root, 1)];
+    let (mut nodes, mut depth) = (0, 0);
+    while let Some((node, level)) = stack.pop() {
+        nodes += 1;
+        depth = depth.max(level);
+        stack.extend(
+            node.children()
+                .into_iter()
+                .map(|(_, child)| (child, level + 1)),
+        );
+    }
+    (nodes, depth)
+}
+
+fn summary(root: TreeNode) -> String {
+    let (nodes, depth) = measure(root);
+    format!(
+        "{} with {} nodes, depth {}",
+        root.label(),
+        numfmt::grouped(nodes),
+        numfmt::grouped(depth)
+    )
+}
+
+fn display_tree(root: TreeNode, max_depth: usize, max_children: usize) -> String {
+    enum Line<'a> {
+        Node(Option<&'a str>, TreeNode<'a>, usize),
+        /// what was left out, and the indent it's shown at
+        Elided(String, usize),
+    }
+
+    let mut res = String::new();
+    let mut stack = vec![Line::Node(None, root, 0)];
+    while let Some(line) = stack.pop() {
+        let (name, node, level) = match line {
+            Line::Node(name, node, level) => (name, node, level),
+            Line::Elided(text, level) => {
+                writeln!(res, "{}… {}", "  ".repeat(level), text).unwrap();
+                continue;
+            }
+        };
+        match name {
+            Some(name) => writeln!(res, "{}{}: {}", "  ".repeat(level), name, node.label()),
+            None => writeln!(res, "{}{}", "  ".repeat(level), node.label()),
+        }
+        .unwrap();
+
+        let children = node.children();
+        if children.is_empty() {
+            continue;
+        }
+        if level + 1 >= max_depth {
+            let nodes: usize = children.iter().map(|(_, child)| measure(*child).0).sum();
+            stack.push(Line::Elided(
+                format!("{} nodes", numfmt::grouped(nodes)),
+                level + 1,
+            ));
+            continue;
+        }
+        if children.len() > max_children {
+            let more = children.len() - max_children;
+            stack.push(Line::Elided(
+                format!("{} more", numfmt::grouped(more)),
+                level + 1,
+            ));
+        }
+        for (name, child) in children.into_iter().take(max_children).rev() {
+            stack.push(Line::Node(name, child, level + 1));
+        }
+    }
+    let (nodes, depth) = measure(root);
+    write!(
+        res,
+        "{} nodes, depth {}",
+        numfmt::grouped(nodes),
+        numfmt::grouped(depth)
+    )
+    .unwrap();
+    res
+}
+
+/// an expression printed with the precedence each operator has in some
+/// parser's table, operators missing from it having 0
+pub struct WithPrecedence<'a> {
+    pub expr: &'a Expression,
+    pub precedence: &'a dyn Fn(&str) -> u32,
+}
+
+impl WithPrecedence<'_> {
+    fn child<'b>(&'b self, expr: &'b Expression) -> WithPrecedence<'b> {
+        WithPrecedence {
+            expr,
+            precedence: self.precedence,
+        }
+    }
+}
+
+/// prints source that parses back to the same expression - the parser
+/// takes everything after an operator as its right operand, so a binary
+/// left operand always needs parens, and a right one only gets them when
+/// they make the grouping clearer to a reader, binding looser than its
+/// parent or as tight on the side a non-associative operator groups from
+impl fmt::Display for WithPrecedence<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let precedence = self.precedence;
+        match self.expr {
+            Expression::Literal(value) => write!(f, "{}", value),
+            Expression::Variable(name) => write!(f, "{}", name),
+            Expression::Error(_) => write!(f, "<error>"),
+            Expression::Call(callee, args) => {
+                write!(f, "{}🜄", callee)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "🜌")?;
+                    }
+                    write!(f, "{}", self.child(arg))?;
+                }
+                write!(f, "🜂")
+            }
+            Expression::NamedCall(callee, args) => {
+                write!(f, "{}🜄", callee)?;
+                for (i, (name, arg)) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "🜌")?;
+                    }
+                    if let Some(name) = name {
+                        write!(f, "{}: ", name)?;
+                    }
+                    write!(f, "{}", self.child(arg))?;
+                }
+                write!(f, "🜂")
+            }
+            Expression::Binary(op, lhs, rhs) => {
+                match lhs.as_ref() {
+                    Expression::Binary(..) => write!(f, "🜄{}🜂", self.child(lhs))?,
+                    _ => write!(f, "{}", self.child(lhs))?,
+                }
+                write!(f, " {} ", op)?;
+                match rhs.as_ref() {
+                    Expression::Binary(next, ..)
+                        if precedence(next) < precedence(op)
+                            || (precedence(next) == precedence(op) && !associative(op, next)) =>
+                    {
+                        write!(f, "🜄{}🜂", self.child(rhs))
+                    }
+                    _ => write!(f, "{}", self.child(rhs)),
+                }
+            }
+        }
+    }
+}
+
+/// printed with the default operator table
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        WithPrecedence {
+            expr: self,
+            precedence: &precedence,
+        }
+        .fmt(f)
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -18,8 +344,256 @@ pub struct Function {
     pub body: Expression,
 }
 
+/// numbers read from a file at compile time, exposed as name(i) and name_len()
+#[derive(Debug, PartialEq, Clone)]
+pub struct Table {
+    pub name: String,
+    pub path: String,
+    /// the contents, filled in when the file is loaded
+    pub values: Option<Vec<f64>>,
+}
+
+/// a check that expr comes out close to expected, only run by the test
+/// runner
+#[derive(Debug, PartialEq, Clone)]
+pub struct Test {
+    pub name: String,
+    pub expr: Expression,
+    pub expected: Expression,
+}
+
+/// which configurations a conditional item is kept in
+#[derive(Debug, PartialEq, Clone)]
+pub enum Condition {
+    Flag(String),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    pub fn holds(&self, is_defined: &dyn Fn(&str) -> bool) -> bool {
+        match self {
+            Condition::Flag(name) => is_defined(name),
+            Condition::Not(cond) => !cond.holds(is_defined),
+            Condition::And(lhs, rhs) => lhs.holds(is_defined) && rhs.holds(is_defined),
+            Condition::Or(lhs, rhs) => lhs.holds(is_defined) || rhs.holds(is_defined),
+        }
+    }
+}
+
+/// not binds tightest, then and, then or, and both group to the left
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Flag(name) => write!(f, "{}", name),
+            Condition::Not(cond) => match cond.as_ref() {
+                Condition::And(..) | Condition::Or(..) => write!(f, "not 🜄{}🜂", cond),
+                _ => write!(f, "not {}", cond),
+            },
+            Condition::And(lhs, rhs) => {
+                match lhs.as_ref() {
+                    Condition::Or(..) => write!(f, "🜄{}🜂", lhs)?,
+                    _ => write!(f, "{}", lhs)?,
+                }
+                match rhs.as_ref() {
+                    Condition::And(..) | Condition::Or(..) => write!(f, " and 🜄{}🜂", rhs),
+                    _ => write!(f, " and {}", rhs),
+                }
+            }
+            Condition::Or(lhs, rhs) => match rhs.as_ref() {
+                Condition::Or(..) => write!(f, "{} or 🜄{}🜂", lhs, rhs),
+                _ => write!(f, "{} or {}", lhs, rhs),
+            },
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum ASTNode {
     Extern(Prototype),
     Function(Function),
+    Table(Table),
+    /// an item only compiled when its condition holds
+    When(Condition, Box<ASTNode>),
+    /// llvm ir linked into the module as it is, each function it defines
+    /// callable like an extern
+    InlineIr(String),
+    Test(Test),
+}
+
+impl ASTNode {
+    /// like Expression::display_tree, with the item as the root and its
+    /// defaults and body as children
+    pub fn display_tree(&self, max_depth: usize, max_children: usize) -> String {
+        display_tree(TreeNode::Item(self), max_depth, max_children)
+    }
+
+    /// like Expression::summary
+    pub fn summary(&self) -> String {
+        summary(TreeNode::Item(self))
+    }
+}
+
+impl fmt::Display for Prototype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.void {
+            write!(f, "void ")?;
+        }
+        match self.math {
+            MathMode::Inherit => (),
+            MathMode::Fast => write!(f, "fast ")?,
+            MathMode::Strict => write!(f, "strict ")?,
+        }
+        write!(f, "{}🜄", self.name)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, "🜌")?;
+            }
+            match self.defaults.get(i) {
+                Some(Some(default)) => write!(f, "{} = {}", arg, default)?,
+                _ => write!(f, "{}", arg)?,
+            }
+        }
+        write!(f, "🜂")
+    }
+}
+
+impl fmt::Display for ASTNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ASTNode::Extern(proto) => match &proto.link_name {
+                Some(link_name) => write!(f, "🜹{} = \"{}\"", proto, lexer::escape(link_name)),
+                None => write!(f, "🜹{}", proto),
+            },
+            ASTNode::Function(func) if func.prototype.name == "lambda" => {
+                write!(f, "{}", func.body)
+            }
+            ASTNode::Function(func) => write!(f, "🜙{} {}", func.prototype, func.body),
+            ASTNode::Table(table) => write!(
+                f,
+                "table {} from \"{}\"",
+                table.name,
+                lexer::escape(&table.path)
+            ),
+            ASTNode::When(cond, item) => write!(f, "when {} {}", cond, item),
+            ASTNode::InlineIr(ir) => write!(f, "llvm \"{}\"", lexer::escape(ir)),
+            ASTNode::Test(test) => write!(
+                f,
+                "test \"{}\" {} expect {}",
+                lexer::escape(&test.name),
+                test.expr,
+                test.expected
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn expression_display_round_trips() {
+        let sources = [
+            "x * 🜄2 + 1🜂",
+            "🜄x * 2🜂 + 1",
+            "a - 🜄b - c🜂",
+            "f🜄x🜌g🜄🜂🜂 / 2.5",
+            "🜄a + b🜂 * 🜄c + d🜂",
+        ];
+        for source in sources.iter() {
+            let expr = Parser::default().parse_expression_str(source).unwrap();
+            assert_eq!(expr.to_string(), *source);
+        }
+        let expr = Parser::default().parse_expression_str("a - b - c").unwrap();
+        assert_eq!(expr.to_string(), "a - 🜄b - c🜂");
+    }
+
+    #[test]
+    fn display_tree_works() {
+        let source = "🜙f🜄x🜌y = 2🜂g🜄x🜌1🜌2🜌3🜂 + x * 🜄y - h🜄x: 1🜂🜂;";
+        let ast = Parser::default().parse_str(source).unwrap();
+        assert_eq!(
+            ast[0].display_tree(8, 8),
+            "Function(f)
+  y: Literal(2)
+  Binary(+)
+    Call(g)
+      Variable(x)
+      Literal(1)
+      Literal(2)
+      Literal(3)
+    Binary(*)
+      Variable(x)
+      Binary(-)
+        Variable(y)
+        NamedCall(h)
+          x: Literal(1)
+14 nodes, depth 6"
+        );
+        assert_eq!(
+            ast[0].display_tree(4, 2),
+            "Function(f)
+  y: Literal(2)
+  Binary(+)
+    Call(g)
+      Variable(x)
+      Literal(1)
+      … 2 more
+    Binary(*)
+      Variable(x)
+      Binary(-)
+        … 3 nodes
+14 nodes, depth 6"
+        );
+        let body = match &ast[0] {
+            ASTNode::Function(func) => &func.body,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            body.display_tree(3, 3),
+            "Binary(+)
+  Call(g)
+    Variable(x)
+    Literal(1)
+    Literal(2)
+    … 1 more
+  Binary(*)
+    Variable(x)
+    Binary(-)
+      … 3 nodes
+12 nodes, depth 5"
+        );
+        assert_eq!(body.summary(), "Binary(+) with 12 nodes, depth 5");
+    }
+
+    #[test]
+    fn display_tree_handles_deep_trees() {
+        let mut expr = Expression::Variable("x".to_string());
+        for i in 0..500_000 {
+            let lhs = Expression::Literal(i as f64);
+            expr = Expression::Binary("+".to_string(), Box::new(lhs), Box::new(expr));
+        }
+        assert_eq!(
+            expr.summary(),
+            "Binary(+) with 1,000,001 nodes, depth 500,001"
+        );
+        assert_eq!(
+            expr.display_tree(3, 4),
+            "Binary(+)
+  Literal(499999)
+  Binary(+)
+    Literal(499998)
+    Binary(+)
+      … 999,996 nodes
+1,000,001 nodes, depth 500,001"
+        );
+        // dropping it recursively would overflow too
+        while let Expression::Binary(_, _, rhs) = expr {
+            expr = *rhs;
+        }
+    }
 }
@@ -0,0 +1,408 @@
+use std::{
+    fmt, fs, io,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use inkwell::context::Context;
+
+use crate::codegen::Codegen;
+use crate::console;
+use crate::diagnostic::{Diagnostic, Severity, Sink};
+use crate::engine::{Engine, EngineKind, OptLevel};
+use crate::lint::Lint;
+use crate::plan::{CompileOptions, Plan};
+use crate::span::SourceSpan;
+use crate::table::{FsLoader, LoadTables, TableFormat};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct BatchOptions {
+    /// the number of files compiled at once
+    pub jobs: usize,
+    /// also run the entry of each file
+    pub run: bool,
+    pub lints: Vec<Lint>,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            jobs: 1,
+            run: false,
+            lints: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Status {
+    Passed,
+    Warned,
+    Failed,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Passed => write!(f, "pass"),
+            Status::Warned => write!(f, "warn"),
+            Status::Failed => write!(f, "fail"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub status: Status,
+    /// each with where its item was written, when that's known
+    pub diagnostics: Vec<(Option<SourceSpan>, Diagnostic)>,
+}
+
+/// every .wiz file under dir, sorted so results come out in a stable order
+pub fn discover(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension() == Some("wiz".as_ref()) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// the warnings of a file and the failures of its items, or what stopped it
+/// compiling at all
+fn compile(
+    path: &Path,
+    options: &BatchOptions,
+) -> Result<Vec<(Option<SourceSpan>, Diagnostic)>, Diagnostic> {
+    let source = fs::read_to_string(path).map_err(|e| Diagnostic::error("io", e))?;
+    let mut compile_options = CompileOptions {
+        lints: options.lints.clone(),
+        ..CompileOptions::default()
+    };
+    compile_options
+        .add_pass(Box::new(LoadTables {
+            loader: Box::new(FsLoader::beside(path)),
+            format: TableFormat::F64,
+        }))
+        .map_err(|e| Diagnostic::error("plan", e))?;
+    let plan = Plan::new(&path.to_string_lossy(), &source, &mut compile_options)
+        .map_err(|e| e.diagnostic())?;
+
+    let context = Context::create();
+    let mut codegen = Codegen::new(&context);
+    let (summary, failures) = codegen.codegen_all(&plan.ast, &plan.item_spans());
+    let mut diagnostics: Vec<_> = plan
+        .warnings
+        .iter()
+        .map(|warning| (None, Diagnostic::from(warning)))
+        .collect();
+    diagnostics.extend(
+        failures
+            .iter()
+            .map(|failure| (failure.span.clone(), failure.diagnostic())),
+    );
+
+    // the entries that compiled still run
+    if options.run && !summary.entries.is_empty() {
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None)
+            .map_err(|e| Diagnostic::error("engine", e))?;
+        for (snippet, value) in engine.run_all(40) {
+            value.map_err(|e| Diagnostic::error("run", format!("({}) {}", snippet, e)))?;
+        }
+    }
+
+    Ok(diagnostics)
+}
+
+/// compile a single file, turning panics into failures so one file can't
+/// take down the batch
+pub fn check_file(path: &Path, options: &BatchOptions) -> FileReport {
+    let res = panic::catch_unwind(AssertUnwindSafe(|| compile(path, options)));
+    let (status, diagnostics) = match res {
+        Ok(Ok(diagnostics)) if diagnostics.is_empty() => (Status::Passed, diagnostics),
+        Ok(Ok(diagnostics)) => {
+            let failed = diagnostics
+                .iter()
+                .any(|(_, diagnostic)| diagnostic.severity == Severity::Error);
+            let status = if failed {
+                Status::Failed
+            } else {
+                Status::Warned
+            };
+            (status, diagnostics)
+        }
+        Ok(Err(err)) => (Status::Failed, vec![(None, err)]),
+        Err(_) => (
+            Status::Failed,
+            vec![(None, Diagnostic::error("panic", "compiler panicked"))],
+        ),
+    };
+    FileReport {
+        path: path.to_path_buf(),
+        status,
+        diagnostics,
+    }
+}
+
+/// check every .wiz file under dir across options.jobs threads
+pub fn build(dir: &Path, options: &BatchOptions) -> io::Result<Vec<FileReport>> {
+    let files = discover(dir)?;
+    let next = AtomicUsize::new(0);
+    let reports = Mutex::new(vec![None; files.len()]);
+
+    thread::scope(|scope| {
+        for _ in 0..options.jobs.max(1).min(files.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let path = match files.get(index) {
+                    Some(path) => path,
+                    None => break,
+                };
+                let report = check_file(path, options);
+                reports.lock().unwrap()[index] = Some(report);
+            });
+        }
+    });
+
+    Ok(reports
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+/// the summary table followed by the diagnostics, showing at most
+/// error_limit of them, with every line fitted to width
+pub fn summarize(reports: &[FileReport], error_limit: usize, width: usize) -> String {
+    let mut res = String::new();
+    for report in reports {
+        let line = format!("{} {}", report.status, report.path.display());
+        res += &console::fit(&line, width);
+        res.push('\n');
+    }
+    let count = |status| reports.iter().filter(|r| r.status == status).count();
+    res += &format!(
+        "{} files: {} passed, {} warned, {} failed",
+        reports.len(),
+        count(Status::Passed),
+        count(Status::Warned),
+        count(Status::Failed)
+    );
+    let mut sink = Sink::default();
+    sink.error_limit = error_limit;
+    sink.width = width;
+    for report in reports {
+        for (span, diagnostic) in &report.diagnostics {
+            let location = match span {
+                Some(span) => span.to_string(),
+                None => report.path.display().to_string(),
+            };
+            sink.report(&location, diagnostic.clone());
+        }
+    }
+    res += "\n";
+    res += &sink.render();
+    res
+}
+
+pub fn to_json(reports: &[FileReport]) -> String {
+    let files: Vec<String> = reports
+        .iter()
+        .map(|report| {
+            let diagnostics: Vec<String> = report
+                .diagnostics
+                .iter()
+                .map(|(span, diagnostic)| match span {
+                    Some(span) => {
+                        let json = diagnostic.to_json();
+                        format!("{{\"location\":{:?},{}", span.to_string(), &json[1..])
+                    }
+                    None => diagnostic.to_json(),
+                })
+                .collect();
+            format!(
+                "{{\"path\":{:?},\"status\":\"{}\",\"diagnostics\":[{}]}}",
+                report.path.to_string_lossy(),
+                report.status,
+                diagnostics.join(",")
+            )
+        })
+        .collect();
+    format!("[{}]", files.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_works() {
+        let dir = std::env::temp_dir().join(format!("wizarding-batch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("pass.wiz"), "🜙f🜄x🜂x*2;f🜄2🜂;").unwrap();
+        fs::write(dir.join("nested/warn.wiz"), "🜹sqrt🜄x🜂;sqrt🜄0-1🜂;").unwrap();
+        fs::write(dir.join("fail.wiz"), "🜙f🜄x🜂+;").unwrap();
+        fs::write(dir.join("unknown.wiz"), "g🜄1🜂;").unwrap();
+        fs::write(dir.join("partial.wiz"), "🜙f🜄x🜂nope🜄x🜂;🜙g🜄x🜂f🜄x🜂;1 + 2;").unwrap();
+        fs::write(dir.join("notes.txt"), "not wizarding").unwrap();
+
+        let options = BatchOptions {
+            jobs: 3,
+            run: true,
+            lints: vec![Lint::DomainCheck],
+        };
+        let reports = build(&dir, &options).unwrap();
+        let statuses: Vec<_> = reports
+            .iter()
+            .map(|r| (r.path.strip_prefix(&dir).unwrap().to_path_buf(), r.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (PathBuf::from("fail.wiz"), Status::Failed),
+                (PathBuf::from("nested/warn.wiz"), Status::Warned),
+                (PathBuf::from("partial.wiz"), Status::Failed),
+                (PathBuf::from("pass.wiz"), Status::Passed),
+                (PathBuf::from("unknown.wiz"), Status::Failed),
+            ]
+        );
+        let located: Vec<_> = reports[2]
+            .diagnostics
+            .iter()
+            .map(|(span, diagnostic)| (span.as_ref().unwrap().to_string(), diagnostic.clone()))
+            .collect();
+        let partial = dir.join("partial.wiz").display().to_string();
+        assert_eq!(
+            located,
+            [
+                (
+                    format!("{}:1:1", partial),
+                    Diagnostic::error("codegen", "f: unknown function nope")
+                ),
+                (
+                    format!("{}:1:14", partial),
+                    Diagnostic::note("codegen", "g: not compiled because f failed to compile")
+                ),
+            ]
+        );
+        assert_eq!(
+            reports[4].diagnostics[0].1,
+            Diagnostic::error("codegen", "top level expression 1: unknown function g")
+        );
+        let serial = BatchOptions {
+            jobs: 1,
+            ..options.clone()
+        };
+        assert_eq!(build(&dir, &serial).unwrap(), reports);
+        let summary = summarize(&reports, 50, usize::MAX);
+        assert!(summary.contains("5 files: 1 passed, 1 warned, 3 failed"));
+        assert!(summary.contains(&format!(
+            "{}:1:1: error[codegen]: f: unknown function nope",
+            partial
+        )));
+        assert!(summary.ends_with("\n3 errors, 1 warning, 1 note"));
+        assert!(to_json(&reports).starts_with("[{\"path\":"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn summarize_limits_diagnostics() {
+        let dir = std::env::temp_dir().join(format!("wizarding-flood-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // every lambda gets the same warning, every function its own
+        let mut source = "🜹sqrt🜄x🜂;".to_string();
+        for i in 0..300 {
+            source += &format!("sqrt🜄0-1🜂;🜙f{}🜄🜂1 / 0;", i);
+        }
+        fs::write(dir.join("flood.wiz"), source).unwrap();
+
+        let options = BatchOptions {
+            lints: vec![Lint::DomainCheck],
+            ..BatchOptions::default()
+        };
+        let reports = build(&dir, &options).unwrap();
+        assert_eq!(reports[0].diagnostics.len(), 600);
+        let summary = summarize(&reports, 50, usize::MAX);
+        let path = dir.join("flood.wiz").display().to_string();
+        let diagnostics: Vec<&str> = summary.lines().skip(2).collect();
+        assert_eq!(diagnostics.len(), 12);
+        assert_eq!(
+            diagnostics[0],
+            format!(
+                "{}: warning[domain_check]: sqrt argument is always negative in lambda",
+                path
+            )
+        );
+        assert_eq!(diagnostics[10], "… and 291 more similar warnings");
+        assert_eq!(diagnostics[11], "0 errors, 301 warnings, 0 notes");
+        assert!(summarize(&reports, 5, usize::MAX)
+            .contains("stopped after 5 diagnostics, 5 more not shown"));
+        assert_eq!(to_json(&reports).matches("domain_check").count(), 600);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn summarize_fits_the_width() {
+        let reports = [
+            FileReport {
+                path: PathBuf::from(
+                    "examples/a_rather_long_directory_name/with_a_longer_file_name.wiz",
+                ),
+                status: Status::Passed,
+                diagnostics: Vec::new(),
+            },
+            FileReport {
+                path: PathBuf::from("short.wiz"),
+                status: Status::Failed,
+                diagnostics: vec![(
+                    None,
+                    Diagnostic::error(
+                        "codegen",
+                        "top level expression 1: unknown function a_function_nobody_wrote",
+                    ),
+                )],
+            },
+        ];
+        let summaries: Vec<String> = [60, 80, 120]
+            .iter()
+            .map(|width| summarize(&reports, 50, *width))
+            .collect();
+        assert_eq!(
+            summaries,
+            [
+                "pass examples/a_rather_long_directory_name/with_a_longer_fi…\n\
+                 fail short.wiz\n\
+                 2 files: 1 passed, 0 warned, 1 failed\n\
+                 short.wiz: error[codegen]: top level expression 1: unknown …\n\
+                 1 error, 0 warnings, 0 notes",
+                "pass examples/a_rather_long_directory_name/with_a_longer_file_name.wiz\n\
+                 fail short.wiz\n\
+                 2 files: 1 passed, 0 warned, 1 failed\n\
+                 short.wiz: error[codegen]: top level expression 1: unknown function a_function_…\n\
+                 1 error, 0 warnings, 0 notes",
+                "pass examples/a_rather_long_directory_name/with_a_longer_file_name.wiz\n\
+                 fail short.wiz\n\
+                 2 files: 1 passed, 0 warned, 1 failed\n\
+                 short.wiz: error[codegen]: top level expression 1: unknown function a_function_nobody_wrote\n\
+                 1 error, 0 warnings, 0 notes",
+            ]
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use std::f64::consts;
+
+/// the unit trig functions take and return their angles in
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum AngleUnit {
+    #[default]
+    Radians,
+    Degrees,
+}
+
+/// zero argument functions that are lowered straight to a constant
+const CONSTANTS: &[(&str, f64)] = &[
+    ("pi", consts::PI),
+    ("e", consts::E),
+    ("tau", consts::TAU),
+    ("inf", f64::INFINITY),
+    ("nan", f64::NAN),
+];
+
+/// the tolerance approx_eq compares with unless the program sets another
+pub const DEFAULT_EPSILON: f64 = 1e-9;
+
+/// functions that are lowered to an inline comparison, with their arity
+const COMPARISONS: &[(&str, usize)] = &[("approx_eq", 2), ("approx_eq_eps", 3)];
+
+/// functions that are lowered to llvm intrinsics, with their parameters -
+/// min and max ignore a nan operand and return the other one, like
+/// llvm.minnum and llvm.maxnum, so only two nans make nan, clamp is min of
+/// max and abs is llvm.fabs, which keeps a nan
+const INTRINSICS: &[(&str, &[&str])] = &[
+    ("min", &["a", "b"]),
+    ("max", &["a", "b"]),
+    ("clamp", &["x", "lo", "hi"]),
+    ("abs", &["x"]),
+];
+
+/// host math functions with no side effects
+const PURE: &[&str] = &[
+    "sin", "cos", "tan", "asin", "acos", "atan", "exp", "log", "sqrt", "pow", "fabs", "floor",
+    "ceil",
+];
+
+/// the libm functions externs usually link against, with the number of
+/// doubles each takes
+const HOST_ARITIES: &[(&str, usize)] = &[
+    ("sin", 1),
+    ("cos", 1),
+    ("tan", 1),
+    ("asin", 1),
+    ("acos", 1),
+    ("atan", 1),
+    ("atan2", 2),
+    ("sinh", 1),
+    ("cosh", 1),
+    ("tanh", 1),
+    ("exp", 1),
+    ("exp2", 1),
+    ("log", 1),
+    ("log2", 1),
+    ("log10", 1),
+    ("sqrt", 1),
+    ("cbrt", 1),
+    ("pow", 2),
+    ("hypot", 2),
+    ("fabs", 1),
+    ("floor", 1),
+    ("ceil", 1),
+    ("round", 1),
+    ("trunc", 1),
+    ("fmod", 2),
+    ("fmin", 2),
+    ("fmax", 2),
+    ("copysign", 2),
+    ("fma", 3),
+];
+
+/// functions whose argument is an angle
+const ANGLE_ARGUMENT: &[&str] = &["sin", "cos", "tan"];
+
+/// functions whose result is an angle
+const ANGLE_RESULT: &[&str] = &["asin", "acos", "atan"];
+
+pub fn constant(name: &str) -> Option<f64> {
+    CONSTANTS
+        .iter()
+        .find(|(constant, _)| *constant == name)
+        .map(|(_, value)| *value)
+}
+
+pub fn comparison_arity(name: &str) -> Option<usize> {
+    COMPARISONS
+        .iter()
+        .find(|(comparison, _)| *comparison == name)
+        .map(|(_, arity)| *arity)
+}
+
+pub fn intrinsic_params(name: &str) -> Option<&'static [&'static str]> {
+    INTRINSICS
+        .iter()
+        .find(|(intrinsic, _)| *intrinsic == name)
+        .map(|(_, params)| *params)
+}
+
+/// how many arguments the host symbol takes, if it's one whose signature is
+/// known
+pub fn host_arity(symbol: &str) -> Option<usize> {
+    HOST_ARITIES
+        .iter()
+        .find(|(host, _)| *host == symbol)
+        .map(|(_, arity)| *arity)
+}
+
+/// whether name can be called without being defined or declared
+pub fn is_builtin(name: &str) -> bool {
+    constant(name).is_some() || comparison_arity(name).is_some() || intrinsic_params(name).is_some()
+}
+
+/// whether calling name can't have an effect beyond its result
+pub fn is_pure(name: &str) -> bool {
+    PURE.contains(&name) || is_builtin(name)
+}
+
+/// what approx_eq computes - equal values are always close, infinities
+/// included, and otherwise both have to be finite and differ by at most eps
+/// times the larger magnitude, or by eps itself when both are below 1, so
+/// the tolerance is absolute near zero and relative everywhere else
+pub fn approx_eq(a: f64, b: f64, eps: f64) -> bool {
+    if a == b {
+        return true;
+    }
+    if !a.is_finite() || !b.is_finite() {
+        return false;
+    }
+    (a - b).abs() <= eps * a.abs().max(b.abs()).max(1.0)
+}
+
+/// how many doubles lie between a and b, so neighbours are 1 apart and the
+/// two zeros 0 - nan is 0 from nan and as far as can be from anything else
+pub fn ulp_distance(a: f64, b: f64) -> u64 {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => return 0,
+        (true, false) | (false, true) => return u64::MAX,
+        _ => (),
+    }
+    // ordered so that counting up the integers counts up the doubles
+    let ordered = |x: f64| {
+        let bits = x.to_bits() as i64;
+        if bits < 0 {
+            i64::MIN.wrapping_sub(bits)
+        } else {
+            bits
+        }
+    };
+    ordered(a).abs_diff(ordered(b))
+}
+
+pub fn takes_angle(name: &str) -> bool {
+    ANGLE_ARGUMENT.contains(&name)
+}
+
+pub fn returns_angle(name: &str) -> bool {
+    ANGLE_RESULT.contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_lookup_works() {
+        assert_eq!(constant("pi"), Some(consts::PI));
+        assert_eq!(constant("tau"), Some(consts::TAU));
+        assert!(constant("nan").unwrap().is_nan());
+        assert_eq!(constant("sin"), None);
+        assert!(is_pure("sin") && is_pure("pi"));
+        assert!(!is_pure("printd"));
+        assert!(is_builtin("approx_eq") && is_pure("approx_eq_eps"));
+        assert_eq!(comparison_arity("approx_eq_eps"), Some(3));
+        assert_eq!(host_arity("pow"), Some(2));
+        assert_eq!(intrinsic_params("clamp"), Some(&["x", "lo", "hi"][..]));
+        assert!(is_builtin("abs") && is_pure("min"));
+        assert_eq!(host_arity("printd"), None);
+    }
+
+    #[test]
+    fn approx_eq_works() {
+        let eps = DEFAULT_EPSILON;
+        // a, b and whether they're close
+        let cases = [
+            (0.1 + 0.2, 0.3, true),
+            (0.0, -0.0, true),
+            (0.0, 1e-10, true),
+            (0.0, 1e-8, false),
+            (1e-300, -1e-300, true),
+            (1e20, 1e20 + 1e10, true),
+            (1e20, 1.000_001e20, false),
+            (f64::MAX, -f64::MAX, false),
+            (f64::INFINITY, f64::INFINITY, true),
+            (f64::INFINITY, f64::NEG_INFINITY, false),
+            (f64::INFINITY, f64::MAX, false),
+            (f64::NAN, f64::NAN, false),
+            (f64::NAN, 1.0, false),
+        ];
+        for (a, b, close) in cases.iter() {
+            assert_eq!(approx_eq(*a, *b, eps), *close, "{} {}", a, b);
+            assert_eq!(approx_eq(*b, *a, eps), *close, "{} {}", b, a);
+        }
+        assert!(approx_eq(1.0, 1.05, 0.1) && !approx_eq(1.0, 1.05, 0.01));
+        assert!(!approx_eq(1.0, 1.0 + 1e-12, 0.0));
+    }
+
+    #[test]
+    fn ulp_distance_works() {
+        let cases = [
+            (1.0, 1.0, 0),
+            (0.0, -0.0, 0),
+            (1.0, 1.0 + f64::EPSILON, 1),
+            (0.1 + 0.2, 0.3, 1),
+            (f64::from_bits(1), -f64::from_bits(1), 2),
+            (f64::MAX, f64::INFINITY, 1),
+            (f64::NAN, f64::NAN, 0),
+            (f64::NAN, 1.0, u64::MAX),
+        ];
+        for (a, b, ulps) in cases.iter() {
+            assert_eq!(ulp_distance(*a, *b), *ulps, "{} {}", a, b);
+            assert_eq!(ulp_distance(*b, *a), *ulps, "{} {}", b, a);
+        }
+        assert_eq!(ulp_distance(-1.0, 1.0), 2 * ulp_distance(0.0, 1.0));
+    }
+}
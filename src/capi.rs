@@ -0,0 +1,376 @@
+use std::{
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int, c_void},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+use crate::diagnostic::Diagnostic;
+use crate::session::{Session, SessionError};
+
+/// the call did what it was asked
+pub const WIZ_OK: c_int = 0;
+/// the source didn't compile or run, or the function wasn't found - the
+/// details are in wiz_last_error_json
+pub const WIZ_ERROR: c_int = 1;
+/// a pointer that has to be set was null or a string wasn't utf-8
+pub const WIZ_INVALID_ARGUMENT: c_int = 2;
+/// the compiler panicked, which was caught - the session can still be freed
+/// but nothing else about it should be relied on
+pub const WIZ_PANIC: c_int = 3;
+
+/// an embedded compiler - each source evaluated in it is compiled on its own
+/// and calls what earlier ones define, see session::Session
+///
+/// a session isn't thread safe, it has to be used by one thread at a time
+pub struct WizSession {
+    session: Session,
+    last_error: Option<CString>,
+}
+
+impl WizSession {
+    fn fail(&mut self, err: &SessionError) -> c_int {
+        self.fail_with(&Diagnostic::error(err.code(), err))
+    }
+
+    fn fail_with(&mut self, diagnostic: &Diagnostic) -> c_int {
+        let json = diagnostic.to_json().replace('\0', "");
+        self.last_error = CString::new(json).ok();
+        WIZ_ERROR
+    }
+}
+
+/// run f, turning a panic into WIZ_PANIC with the error recorded on session
+fn guard(session: *mut WizSession, f: impl FnOnce(&mut WizSession) -> c_int) -> c_int {
+    let session = match unsafe { session.as_mut() } {
+        Some(session) => session,
+        None => return WIZ_INVALID_ARGUMENT,
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| f(&mut *session))) {
+        Ok(status) => status,
+        Err(_) => {
+            session.fail_with(&Diagnostic::error("panic", "the compiler panicked"));
+            WIZ_PANIC
+        }
+    }
+}
+
+/// the utf-8 string behind a pointer, or none if it's null or not utf-8
+unsafe fn read_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+/// copy as much of message as fits into the buffer of len bytes, always
+/// ending it with a nul
+unsafe fn write_message(buf: *mut c_char, len: usize, message: &str) {
+    if buf.is_null() || len == 0 {
+        return;
+    }
+    let bytes = message.as_bytes();
+    let count = bytes.len().min(len - 1);
+    ptr::copy_nonoverlapping(bytes.as_ptr() as *const c_char, buf, count);
+    *buf.add(count) = 0;
+}
+
+/// a new empty session, which has to be freed with wiz_session_free -
+/// returns null if creating it panicked
+#[no_mangle]
+pub extern "C" fn wiz_session_new() -> *mut WizSession {
+    let session = panic::catch_unwind(|| WizSession {
+        session: Session::new(),
+        last_error: None,
+    });
+    match session {
+        Ok(session) => Box::into_raw(Box::new(session)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// free a session from wiz_session_new along with everything compiled in
+/// it - every address wiz_get_fn returned and the last error string are
+/// invalid afterwards, and null is ignored
+///
+/// # Safety
+///
+/// session has to be null or come from wiz_session_new and not be freed yet
+#[no_mangle]
+pub unsafe extern "C" fn wiz_session_free(session: *mut WizSession) {
+    if !session.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(session))));
+    }
+}
+
+/// compile source, a nul terminated utf-8 string, with everything evaluated
+/// in the session before it and run its top level expressions - the value of
+/// the last one is written to out_value if that isn't null, which is left
+/// alone when there is none
+///
+/// on WIZ_ERROR the message is copied into err_buf, cut to err_len bytes
+/// with the nul, unless err_buf is null, and nothing of source is kept
+///
+/// # Safety
+///
+/// session has to come from wiz_session_new, source has to be a nul
+/// terminated string, out_value null or writable and err_buf null or
+/// writable for err_len bytes
+#[no_mangle]
+pub unsafe extern "C" fn wiz_eval(
+    session: *mut WizSession,
+    source: *const c_char,
+    out_value: *mut f64,
+    err_buf: *mut c_char,
+    err_len: usize,
+) -> c_int {
+    let status = guard(session, |session| {
+        let source = match read_str(source) {
+            Some(source) => source,
+            None => return WIZ_INVALID_ARGUMENT,
+        };
+        match session.session.eval(source) {
+            Ok(value) => {
+                session.last_error = None;
+                if let (Some(value), false) = (value, out_value.is_null()) {
+                    *out_value = value;
+                }
+                WIZ_OK
+            }
+            Err(err) => {
+                write_message(err_buf, err_len, &err.to_string());
+                session.fail(&err)
+            }
+        }
+    });
+    if status == WIZ_PANIC {
+        write_message(err_buf, err_len, "the compiler panicked");
+    }
+    status
+}
+
+/// make name, a nul terminated utf-8 string, callable from sources evaluated
+/// after this as a function of arity arguments that runs fn_ptr - the
+/// session declares the extern itself, and a source that declares it too
+/// fails to evaluate unless its extern takes arity arguments
+///
+/// # Safety
+///
+/// session has to come from wiz_session_new and name has to be a nul
+/// terminated string. fn_ptr has to be a function taking arity doubles and
+/// returning a double with the C calling convention, that doesn't unwind and
+/// stays valid as long as the session
+#[no_mangle]
+pub unsafe extern "C" fn wiz_register_fn(
+    session: *mut WizSession,
+    name: *const c_char,
+    arity: usize,
+    fn_ptr: *const c_void,
+) -> c_int {
+    guard(session, |session| {
+        let name = match read_str(name) {
+            Some(name) if !fn_ptr.is_null() => name,
+            _ => return WIZ_INVALID_ARGUMENT,
+        };
+        session
+            .session
+            .register_function(name, arity, fn_ptr as usize);
+        session.last_error = None;
+        WIZ_OK
+    })
+}
+
+/// the address of the function named name, a nul terminated utf-8 string,
+/// that the sources evaluated so far define with arity parameters, or null
+/// if there's none or it isn't a function of doubles - it takes and
+/// returns doubles with the C calling convention and stays valid until the
+/// session is freed
+///
+/// # Safety
+///
+/// session has to come from wiz_session_new and name has to be a nul
+/// terminated string
+#[no_mangle]
+pub unsafe extern "C" fn wiz_get_fn(
+    session: *mut WizSession,
+    name: *const c_char,
+    arity: usize,
+) -> *const c_void {
+    let mut address = ptr::null();
+    guard(session, |session| {
+        let name = match read_str(name) {
+            Some(name) => name,
+            None => return WIZ_INVALID_ARGUMENT,
+        };
+        match session.session.function(name, arity) {
+            Ok(found) => {
+                address = found as *const c_void;
+                session.last_error = None;
+                WIZ_OK
+            }
+            Err(err) => session.fail(&err),
+        }
+    });
+    address
+}
+
+/// the last error of the session as a json object with a severity, code and
+/// message, or null if the last call succeeded - the string belongs to the
+/// session and is only valid until the next call with it
+///
+/// # Safety
+///
+/// session has to be null or come from wiz_session_new
+#[no_mangle]
+pub unsafe extern "C" fn wiz_last_error_json(session: *const WizSession) -> *const c_char {
+    match session
+        .as_ref()
+        .and_then(|session| session.last_error.as_ref())
+    {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn halve(x: f64) -> f64 {
+        x / 2.0
+    }
+
+    #[test]
+    fn session_works() {
+        let source = |s: &str| CString::new(s).unwrap();
+        let session = wiz_session_new();
+        let mut value = 0.0;
+        let mut err = [0 as c_char; 16];
+        unsafe {
+            let name = source("halve");
+            assert_eq!(
+                wiz_register_fn(session, name.as_ptr(), 1, halve as *const c_void),
+                WIZ_OK
+            );
+            let defs = source("🜙sq🜄x🜂x*x;");
+            assert_eq!(
+                wiz_eval(session, defs.as_ptr(), &mut value, ptr::null_mut(), 0),
+                WIZ_OK
+            );
+            assert_eq!(value, 0.0);
+            let call = source("sq🜄3🜂;halve🜄sq🜄5🜂🜂;");
+            let status = wiz_eval(session, call.as_ptr(), &mut value, err.as_mut_ptr(), 16);
+            assert_eq!((status, value), (WIZ_OK, 12.5));
+            assert!(wiz_last_error_json(session).is_null());
+
+            let sq = source("sq");
+            let address = wiz_get_fn(session, sq.as_ptr(), 1);
+            let sq_fn: extern "C" fn(f64) -> f64 = std::mem::transmute(address);
+            assert_eq!(sq_fn(4.0), 16.0);
+            assert!(wiz_get_fn(session, sq.as_ptr(), 2).is_null());
+            let error = CStr::from_ptr(wiz_last_error_json(session));
+            assert!(error
+                .to_str()
+                .unwrap()
+                .contains("sq takes 1 arguments, not 2"));
+
+            // nothing of a source that fails is kept
+            let bad = source("🜙cube🜄x🜂x*x*x;nope🜄1🜂;");
+            let status = wiz_eval(session, bad.as_ptr(), &mut value, err.as_mut_ptr(), 16);
+            assert_eq!(status, WIZ_ERROR);
+            assert_eq!(CStr::from_ptr(err.as_ptr()).to_str(), Ok("<eval-3>:1:15: "));
+            let cube = source("cube");
+            assert!(wiz_get_fn(session, cube.as_ptr(), 1).is_null());
+            assert_eq!(sq_fn(5.0), 25.0);
+
+            let misdeclared = source("🜹halve🜄x🜌y🜂;halve🜄1🜌2🜂;");
+            let status = wiz_eval(
+                session,
+                misdeclared.as_ptr(),
+                &mut value,
+                ptr::null_mut(),
+                0,
+            );
+            assert_eq!(status, WIZ_ERROR);
+            let error = CStr::from_ptr(wiz_last_error_json(session));
+            assert!(error
+                .to_str()
+                .unwrap()
+                .contains("extern halve is declared with 2 args but the host's halve takes 1"));
+            let declared = source("🜹halve🜄x🜂;halve🜄7🜂;");
+            let status = wiz_eval(session, declared.as_ptr(), &mut value, ptr::null_mut(), 0);
+            assert_eq!((status, value), (WIZ_OK, 3.5));
+
+            assert_eq!(
+                wiz_eval(session, ptr::null(), &mut value, ptr::null_mut(), 0),
+                WIZ_INVALID_ARGUMENT
+            );
+            wiz_session_free(session);
+        }
+    }
+
+    #[test]
+    fn get_fn_checks_signatures() {
+        let session = wiz_session_new();
+        unsafe {
+            (*session).session.allow_inline_ir = true;
+            let ir = CString::new(
+                "llvm \"define float @narrow(float %x) {
+  ret float %x
+}
+define double @wide(double %x) {
+  ret double %x
+}\";",
+            )
+            .unwrap();
+            let status = wiz_eval(session, ir.as_ptr(), ptr::null_mut(), ptr::null_mut(), 0);
+            assert_eq!(status, WIZ_OK);
+
+            let narrow = CString::new("narrow").unwrap();
+            assert!(wiz_get_fn(session, narrow.as_ptr(), 1).is_null());
+            let error = CStr::from_ptr(wiz_last_error_json(session));
+            assert!(error
+                .to_str()
+                .unwrap()
+                .contains("narrow is float (float), not a function of doubles returning a double"));
+            let wide = CString::new("wide").unwrap();
+            let address = wiz_get_fn(session, wide.as_ptr(), 1);
+            let wide_fn: extern "C" fn(f64) -> f64 = std::mem::transmute(address);
+            assert_eq!(wide_fn(1.5), 1.5);
+            assert!(wiz_get_fn(session, wide.as_ptr(), 2).is_null());
+            wiz_session_free(session);
+        }
+    }
+
+    #[test]
+    fn header_matches() {
+        let header = include_str!("../include/wizarding.h");
+        let source = include_str!("capi.rs");
+        for line in source.lines() {
+            if let Some(rest) = line.split("extern \"C\" fn wiz_").nth(1) {
+                let name = format!("wiz_{}", rest.split('(').next().unwrap());
+                assert!(
+                    header.contains(&format!(" {}(", name))
+                        || header.contains(&format!("*{}(", name)),
+                    "{}",
+                    name
+                );
+            }
+            if let Some(rest) = line.strip_prefix("pub const ") {
+                let (name, value) = (
+                    rest.split(':').next().unwrap(),
+                    rest.split("= ").nth(1).unwrap(),
+                );
+                assert!(
+                    header.contains(&format!(
+                        "#define {} {}\n",
+                        name,
+                        value.trim_end_matches(';')
+                    )),
+                    "{}",
+                    name
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::{collections::HashSet, ops::Range};
+
+use crate::ast::ASTNode;
+use crate::builtins;
+use crate::lexer::{self, Token};
+use crate::parser::Parser;
+
+/// what a span of source is, for semantic highlighting
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Classification {
+    FunctionName,
+    Parameter,
+    CallTarget,
+    Unresolved,
+    Keyword,
+    Operator,
+    Number,
+    Str,
+    Punctuation,
+    Comment,
+    /// part of an item that failed to parse
+    Unknown,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Classified {
+    pub span: Range<usize>,
+    pub classification: Classification,
+}
+
+/// the classification a token has regardless of what surrounds it
+fn lexical(tok: &Token) -> Classification {
+    match tok {
+        Token::Def
+        | Token::Extern
+        | Token::If
+        | Token::Then
+        | Token::Else
+        | Token::For
+        | Token::In
+        | Token::Bool(_) => Classification::Keyword,
+        Token::Delimiter | Token::OpenParen | Token::CloseParen | Token::Comma => {
+            Classification::Punctuation
+        }
+        Token::Operator(_) => Classification::Operator,
+        Token::Number(_) => Classification::Number,
+        Token::Str(_) => Classification::Str,
+        Token::Comment(_) => Classification::Comment,
+        Token::Ident(_) => Classification::Unknown,
+    }
+}
+
+/// split the tokens into chunks ending in a delimiter, the same points the
+/// parser recovers at
+pub(crate) fn chunks<'a, 'src>(
+    tokens: &'a [(Token<'src>, Range<usize>)],
+) -> Vec<&'a [(Token<'src>, Range<usize>)]> {
+    let mut res = Vec::new();
+    let mut start = 0;
+    for (i, (tok, _)) in tokens.iter().enumerate() {
+        if *tok == Token::Delimiter {
+            res.push(&tokens[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < tokens.len() {
+        res.push(&tokens[start..]);
+    }
+    res
+}
+
+/// classify the identifiers of a chunk that parsed
+fn classify_chunk(
+    chunk: &[(Token, Range<usize>)],
+    functions: &HashSet<String>,
+    res: &mut Vec<Classified>,
+) {
+    let mut params = HashSet::new();
+    let mut expect_name = false;
+    let mut in_proto = false;
+    let is_table = match chunk {
+        [(Token::Ident(table), _), (Token::Ident(_), _), (Token::Ident(from), _), ..] => {
+            *table == "table" && *from == "from"
+        }
+        _ => false,
+    };
+    let is_test = matches!(chunk, [(Token::Ident("test"), _), (Token::Str(_), _), ..]);
+    let mut expect = is_test;
+
+    for (i, (tok, span)) in chunk.iter().enumerate() {
+        let classification = match tok {
+            Token::Ident(_) if is_table && i < 3 => match i {
+                1 => Classification::FunctionName,
+                _ => Classification::Keyword,
+            },
+            Token::Ident("test") if is_test && i == 0 => Classification::Keyword,
+            Token::Ident("expect")
+                if expect && !matches!(chunk.get(i + 1), Some((Token::OpenParen, _))) =>
+            {
+                expect = false;
+                Classification::Keyword
+            }
+            Token::Def | Token::Extern => {
+                params.clear();
+                expect_name = true;
+                Classification::Keyword
+            }
+            Token::Ident("void") | Token::Ident("fast") | Token::Ident("strict")
+                if expect_name && matches!(chunk.get(i + 1), Some((Token::Ident(_), _))) =>
+            {
+                Classification::Keyword
+            }
+            Token::Ident(_) if expect_name => {
+                expect_name = false;
+                in_proto = true;
+                Classification::FunctionName
+            }
+            Token::Ident(ident) if in_proto => {
+                params.insert(*ident);
+                Classification::Parameter
+            }
+            Token::Ident(ident) => match chunk.get(i + 1) {
+                Some((Token::OpenParen, _)) if functions.contains(*ident) => {
+                    Classification::CallTarget
+                }
+                Some((Token::OpenParen, _)) if builtins::is_builtin(ident) => {
+                    Classification::CallTarget
+                }
+                Some((Token::OpenParen, _)) => Classification::Unresolved,
+                _ if params.contains(ident) => Classification::Parameter,
+                _ => Classification::Unresolved,
+            },
+            Token::CloseParen if in_proto => {
+                in_proto = false;
+                Classification::Punctuation
+            }
+            tok => lexical(tok),
+        };
+        res.push(Classified {
+            span: span.clone(),
+            classification,
+        });
+    }
+}
+
+/// classify every token and comment in the source, sorted by position -
+/// items that fail to parse have their identifiers marked unknown
+pub fn classify(source: &str) -> Vec<Classified> {
+    let parser = Parser::default();
+    // everything from where the source stops being tokens is left out
+    let tokens = lexer::lex_spanned(source).unwrap_or_else(|err| {
+        lexer::lex_spanned(&source[..err.span().start]).expect("it lexed up to here")
+    });
+
+    let mut parsed = Vec::new();
+    let mut functions = HashSet::new();
+    for chunk in chunks(&tokens) {
+        let mut stack: Vec<Token> = chunk.iter().rev().map(|(tok, _)| *tok).collect();
+        match parser.parse(&mut stack) {
+            Ok(ast) => {
+                let mut nodes = ast;
+                while let Some(node) = nodes.pop() {
+                    match node {
+                        ASTNode::Function(func) => functions.insert(func.prototype.name),
+                        ASTNode::Extern(proto) => functions.insert(proto.name),
+                        ASTNode::Table(table) => {
+                            functions.insert(format!("{}_len", table.name));
+                            functions.insert(table.name)
+                        }
+                        ASTNode::When(_, item) => {
+                            nodes.push(*item);
+                            true
+                        }
+                        // its functions are only known once llvm parses it
+                        ASTNode::InlineIr(_) | ASTNode::Test(_) => false,
+                    };
+                }
+                parsed.push((chunk, true));
+            }
+            Err(_) => parsed.push((chunk, false)),
+        }
+    }
+
+    let mut res: Vec<Classified> = lexer::comments(source)
+        .into_iter()
+        .map(|span| Classified {
+            span,
+            classification: Classification::Comment,
+        })
+        .collect();
+    for (chunk, ok) in parsed {
+        if ok {
+            classify_chunk(chunk, &functions, &mut res);
+        } else {
+            res.extend(chunk.iter().map(|(tok, span)| Classified {
+                span: span.clone(),
+                classification: lexical(tok),
+            }));
+        }
+    }
+    res.sort_by_key(|classified| classified.span.start);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Classification::*;
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn classes(source: &str) -> Vec<(&str, Classification)> {
+        classify(source)
+            .into_iter()
+            .map(|classified| (&source[classified.span], classified.classification))
+            .collect()
+    }
+
+    #[test]
+    fn classify_works() {
+        let source = "🜹sin🜄x🜂; # wrap it\n🜙f🜄sin🜂sin🜄sin🜂+pi🜄🜂*g🜄1🜂;";
+        let target = vec![
+            ("🜹", Keyword),
+            ("sin", FunctionName),
+            ("🜄", Punctuation),
+            ("x", Parameter),
+            ("🜂", Punctuation),
+            (";", Punctuation),
+            ("# wrap it", Comment),
+            ("🜙", Keyword),
+            ("f", FunctionName),
+            ("🜄", Punctuation),
+            ("sin", Parameter),
+            ("🜂", Punctuation),
+            ("sin", CallTarget),
+            ("🜄", Punctuation),
+            ("sin", Parameter),
+            ("🜂", Punctuation),
+            ("+", Operator),
+            ("pi", CallTarget),
+            ("🜄", Punctuation),
+            ("🜂", Punctuation),
+            ("*", Operator),
+            ("g", Unresolved),
+            ("🜄", Punctuation),
+            ("1", Number),
+            ("🜂", Punctuation),
+            (";", Punctuation),
+        ];
+        assert_eq!(classes(source), target);
+
+        let test = classes("test \"twice\" expect🜄1🜂 expect 2;");
+        assert_eq!(
+            test,
+            [
+                ("test", Keyword),
+                ("\"twice\"", Str),
+                ("expect", Unresolved),
+                ("🜄", Punctuation),
+                ("1", Number),
+                ("🜂", Punctuation),
+                ("expect", Keyword),
+                ("2", Number),
+                (";", Punctuation),
+            ]
+        );
+
+        let void = classes("🜹void beep🜄🜂;beep🜄🜂;");
+        assert_eq!(
+            &void[..3],
+            [("🜹", Keyword), ("void", Keyword), ("beep", FunctionName)]
+        );
+        assert_eq!(void[6], ("beep", CallTarget));
+    }
+
+    #[test]
+    fn classify_recovers_from_errors() {
+        let source = "🜙one🜄🜂1;🜙bad🜄x🜂x+;one🜄🜂";
+        let target = vec![
+            ("🜙", Keyword),
+            ("one", FunctionName),
+            ("🜄", Punctuation),
+            ("🜂", Punctuation),
+            ("1", Number),
+            (";", Punctuation),
+            ("🜙", Keyword),
+            ("bad", Unknown),
+            ("🜄", Punctuation),
+            ("x", Unknown),
+            ("🜂", Punctuation),
+            ("x", Unknown),
+            ("+", Operator),
+            (";", Punctuation),
+            ("one", CallTarget),
+            ("🜄", Punctuation),
+            ("🜂", Punctuation),
+        ];
+        assert_eq!(classes(source), target);
+    }
+}
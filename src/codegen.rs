@@ -1,14 +1,26 @@
-use std::{collections::HashMap, iter};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    iter,
+};
 
 use inkwell::{
+    attributes::{Attribute, AttributeLoc},
     builder::Builder,
     context::Context,
-    module::Module,
+    memory_buffer::MemoryBuffer,
+    module::{Linkage, Module},
     types::BasicTypeEnum,
-    values::{BasicValue, BasicValueEnum, FloatValue, FunctionValue},
+    values::{BasicValue, BasicValueEnum, CallableValue, FloatValue, FunctionValue, PointerValue},
+    AddressSpace, FloatPredicate,
 };
 
-use crate::ast::{ASTNode, Expression, Function, Prototype};
+use crate::ast::{ASTNode, Expression, Function, MathMode, Prototype, Table};
+use crate::builtins::{self, AngleUnit};
+use crate::diagnostic::Diagnostic;
+use crate::interval;
+use crate::runtime;
+use crate::span::SourceSpan;
 
 #[derive(Debug, thiserror::Error)]
 pub enum CodegenError {
@@ -22,13 +34,183 @@ pub enum CodegenError {
     InvalidCall(String, usize, usize),
     #[error("failed to verify function {0}")]
     InvalidFunction(String),
+    #[error("too many args in {0}, found {1} but at most {2} are allowed")]
+    TooManyArgs(String, usize, usize),
+    #[error("table {0} was never loaded")]
+    UnloadedTable(String),
+    #[error("conditional items have to be configured before codegen")]
+    UnconfiguredItem,
+    #[error("named arguments to {0} have to be resolved before codegen")]
+    UnresolvedCall(String),
+    #[error("{0} returns nothing, so its result can't be used")]
+    VoidValue(String),
+    #[error("can't compile an expression with a syntax error in it")]
+    SyntaxError,
+    #[error("invalid inline ir: {0}")]
+    InvalidIr(String),
+    #[error("{0} from inline ir has to take and return doubles")]
+    IrSignature(String),
+    #[error("{0} is already defined")]
+    Redefined(String),
+    #[error("not compiled because {0} failed to compile")]
+    FailedCallee(String),
+}
+
+/// a node that failed to compile, with the name of what it defines
+#[derive(Debug, thiserror::Error)]
+#[error("{item}: {error}")]
+pub struct ItemError {
+    pub item: String,
+    pub error: CodegenError,
+    /// where the node was written, when that's known
+    pub span: Option<SourceSpan>,
+}
+
+impl ItemError {
+    /// only a note when it failed because something it calls did, since
+    /// that already has its own error
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self.error {
+            CodegenError::FailedCallee(_) => Diagnostic::note("codegen", self),
+            _ => Diagnostic::error("codegen", self),
+        }
+    }
+}
+
+/// the nodes of a program that failed to compile so far
+#[derive(Debug, Default)]
+pub struct Failures {
+    /// what the failed nodes define, so a call to one can say it failed
+    failed: HashSet<String>,
+    /// the top level expressions so far, failed or not
+    lambdas: usize,
+    pub errors: Vec<ItemError>,
 }
 
+impl Failures {
+    /// how many top level expressions there have been, failed or not
+    pub fn entries(&self) -> usize {
+        self.lambdas
+    }
+}
+
+/// what one call to codegen added to the module
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CodegenSummary {
+    /// the symbols of the functions it defined, in order
+    pub functions: Vec<String>,
+    /// the symbols of the top level expressions it compiled, in order
+    pub entries: Vec<String>,
+}
+
+/// the default limit on parameters and call arguments, well below where llvm
+/// starts to struggle
+pub const DEFAULT_MAX_ARITY: usize = 256;
+
 pub struct Codegen<'a> {
     pub context: &'a Context,
     pub module: Module<'a>,
     pub builder: Builder<'a>,
     pub named_values: HashMap<String, BasicValueEnum<'a>>,
+    pub angle_unit: AngleUnit,
+    /// mark defined functions as flushing denormal inputs and results to zero
+    pub denormals_are_zero: bool,
+    /// the most parameters a prototype or arguments a call may have
+    pub max_arity: usize,
+    /// reduce binary ops with a literal operand, like x * 1, to something cheaper
+    pub peephole: bool,
+    /// let the peephole assume values are finite, so x * 0 folds to 0, and
+    /// mark functions as allowing unsafe fp math - a definition's fast or
+    /// strict modifier overrides it
+    pub fast_math: bool,
+    /// build the four arithmetic ops as constrained intrinsics in the
+    /// host's rounding mode, which llvm can't reorder or fold - this turns
+    /// off the peephole and fast math, fast definitions included
+    pub strict_fp: bool,
+    /// trap when a table is indexed out of bounds or with nan, instead of
+    /// returning nan - see the runtime module
+    pub check_bounds: bool,
+    /// trap when one of the four arithmetic ops makes an infinity from
+    /// finite operands, instead of carrying it on - this turns off fast
+    /// math, which would let llvm assume the infinity away
+    pub check_overflow: bool,
+    /// call functions through a global slot per callee, which its
+    /// `<name>.rebind` repoints, and give each definition a `<name>.hot`
+    /// wrapper calling through its slot - a session reloads with these
+    pub hot_reload: bool,
+    /// the tolerance of approx_eq, approx_eq_eps takes its own
+    pub epsilon: f64,
+    /// the symbol and body of every compiled top level expression, in order
+    pub entries: Vec<(String, Expression)>,
+    /// what each site generated code traps at stands for, by its id - the
+    /// table indexed or the op that overflowed
+    pub trap_sites: Vec<String>,
+    /// every declared function by the name the source calls it, with its
+    /// parameter count - this is the only place an extern's link name is
+    /// resolved, so calls never see the host symbol
+    functions: HashMap<String, (FunctionValue<'a>, usize)>,
+    /// the symbol of every function given a body, in order
+    defined: Vec<String>,
+    /// the functions inline ir defined, which nothing can define again
+    linked: HashSet<String>,
+    /// fast_math for the function being compiled, after its modifier
+    fast_body: bool,
+    /// whether the function being compiled is strict, which the peephole
+    /// leaves as written
+    strict_body: bool,
+    /// what the function being compiled is called in overflow sites
+    body_name: String,
+    /// where the item being compiled was written, when codegen_all knows
+    item_span: Option<SourceSpan>,
+}
+
+/// the function attributes set to "true" on fast functions and "false" on
+/// strict ones, which llvm merges so a strict function inlined into a fast
+/// one stays strict
+const FAST_MATH_ATTRIBUTES: &[&str] = &[
+    "unsafe-fp-math",
+    "no-infs-fp-math",
+    "no-nans-fp-math",
+    "no-signed-zeros-fp-math",
+];
+
+/// the helpers strict_fp calls for each arithmetic op - build_call can't
+/// pass the metadata operands of a constrained intrinsic, so they are
+/// written as ir and linked in the first time one is needed
+const STRICT_FP_IR: &str = r#"
+define internal double @wizarding.strict.fadd(double %a, double %b) #0 {
+  %r = call double @llvm.experimental.constrained.fadd.f64(double %a, double %b, metadata !"round.dynamic", metadata !"fpexcept.strict") #0
+  ret double %r
+}
+define internal double @wizarding.strict.fsub(double %a, double %b) #0 {
+  %r = call double @llvm.experimental.constrained.fsub.f64(double %a, double %b, metadata !"round.dynamic", metadata !"fpexcept.strict") #0
+  ret double %r
+}
+define internal double @wizarding.strict.fmul(double %a, double %b) #0 {
+  %r = call double @llvm.experimental.constrained.fmul.f64(double %a, double %b, metadata !"round.dynamic", metadata !"fpexcept.strict") #0
+  ret double %r
+}
+define internal double @wizarding.strict.fdiv(double %a, double %b) #0 {
+  %r = call double @llvm.experimental.constrained.fdiv.f64(double %a, double %b, metadata !"round.dynamic", metadata !"fpexcept.strict") #0
+  ret double %r
+}
+declare double @llvm.experimental.constrained.fadd.f64(double, double, metadata, metadata)
+declare double @llvm.experimental.constrained.fsub.f64(double, double, metadata, metadata)
+declare double @llvm.experimental.constrained.fmul.f64(double, double, metadata, metadata)
+declare double @llvm.experimental.constrained.fdiv.f64(double, double, metadata, metadata)
+attributes #0 = { strictfp }
+"#;
+
+/// what an item is called in the spans codegen logs
+fn node_name(node: &ASTNode) -> &str {
+    match node {
+        ASTNode::Function(func) => &func.prototype.name,
+        ASTNode::Extern(proto) => &proto.name,
+        ASTNode::Table(table) => &table.name,
+        ASTNode::Test(test) => &test.name,
+        ASTNode::When(_, item) => node_name(item),
+        ASTNode::InlineIr(_) => "inline ir",
+    }
 }
 
 impl<'a> Codegen<'a> {
@@ -41,6 +223,25 @@ impl<'a> Codegen<'a> {
             module,
             builder,
             named_values: HashMap::new(),
+            angle_unit: AngleUnit::default(),
+            denormals_are_zero: false,
+            max_arity: DEFAULT_MAX_ARITY,
+            peephole: true,
+            fast_math: false,
+            strict_fp: false,
+            hot_reload: false,
+            check_bounds: false,
+            check_overflow: false,
+            epsilon: builtins::DEFAULT_EPSILON,
+            entries: Vec::new(),
+            trap_sites: Vec::new(),
+            functions: HashMap::new(),
+            defined: Vec::new(),
+            linked: HashSet::new(),
+            fast_body: false,
+            strict_body: false,
+            body_name: String::new(),
+            item_span: None,
         }
     }
 
@@ -51,61 +252,501 @@ impl<'a> Codegen<'a> {
                 Some(var) => Ok(var.into_float_value()),
                 None => Err(CodegenError::UnknownVariable(name.clone())),
             },
+            Expression::Error(_) => Err(CodegenError::SyntaxError),
             Expression::Binary(op, left, right) => {
+                if self.peephole && !self.strict_body {
+                    if let Some(value) = self.codegen_identity(op, left, right)? {
+                        return Ok(value);
+                    }
+                }
                 let lhs = self.codegen_expr(left)?;
                 let rhs = self.codegen_expr(right)?;
 
                 match op.as_str() {
-                    "+" => Ok(self.builder.build_float_add(lhs, rhs, "tmpadd")),
-                    "-" => Ok(self.builder.build_float_sub(lhs, rhs, "tmpsub")),
-                    "*" => Ok(self.builder.build_float_mul(lhs, rhs, "tmpmul")),
-                    "/" => Ok(self.builder.build_float_div(lhs, rhs, "tmpdiv")),
+                    "+" | "-" | "*" | "/" => {
+                        let res = self.build_arithmetic(op, lhs, rhs);
+                        Ok(self.check_overflow(op, left, right, (lhs, rhs), res))
+                    }
                     _ => Err(CodegenError::UnknownOperator(op.clone())),
                 }
             }
-            Expression::Call(callee, args) => match self.module.get_function(callee) {
-                Some(func) => {
-                    if func.get_params().len() != args.len() {
-                        return Err(CodegenError::InvalidCall(
-                            callee.clone(),
-                            func.get_params().len(),
-                            args.len(),
-                        ));
-                    }
+            Expression::Call(callee, args) => match self.codegen_call(callee, args)? {
+                Some(value) => Ok(value),
+                None => Err(CodegenError::VoidValue(callee.clone())),
+            },
+            Expression::NamedCall(callee, _) => Err(CodegenError::UnresolvedCall(callee.clone())),
+        }
+    }
 
-                    let mut gened_args = Vec::with_capacity(args.len());
+    /// the cheaper form of a binary op with a literal operand, if it has one -
+    /// these hold for nan, the infinities and both zeros, except that x + 0
+    /// and x - -0 turn -0 into 0 so they are only folded in fast bodies, and
+    /// x * 0 is only folded when x can be left out without changing the
+    /// result, sign included
+    fn codegen_identity(
+        &mut self,
+        op: &str,
+        left: &Expression,
+        right: &Expression,
+    ) -> Result<Option<FloatValue<'a>>, CodegenError> {
+        let literal = |expr: &Expression| match expr {
+            Expression::Literal(value) => Some(*value),
+            _ => None,
+        };
+        let (operand, value) = match (literal(left), literal(right)) {
+            (_, Some(value)) => (left, value),
+            (Some(value), None) if op == "+" || op == "*" => (right, value),
+            _ => return Ok(None),
+        };
+
+        match op {
+            "*" | "/" if value == 1.0 => self.codegen_expr(operand).map(Some),
+            // x + -0 and x - 0 are x for every x
+            "+" | "-"
+                if value == 0.0 && (self.fast_body || value.is_sign_negative() == (op == "+")) =>
+            {
+                self.codegen_expr(operand).map(Some)
+            }
+            "*" if value == 2.0 => {
+                let operand = self.codegen_expr(operand)?;
+                let res = self.builder.build_float_add(operand, operand, "tmpadd");
+                Ok(Some(self.check_overflow(
+                    op,
+                    left,
+                    right,
+                    (operand, operand),
+                    res,
+                )))
+            }
+            // a zero lower bound could be a negative zero, which would flip
+            // the sign of the product
+            "*" if value == 0.0
+                && self.is_pure(operand)
+                && (self.fast_body
+                    || matches!(interval::finite_bounds(operand), Some(bounds) if bounds.lo > 0.0)) =>
+            {
+                Ok(Some(self.context.f64_type().const_float(value)))
+            }
+            _ => Ok(None),
+        }
+    }
 
-                    for arg in args {
-                        gened_args.push(self.codegen_expr(arg)?);
+    /// whether expr would compile without errors and only calls pure host
+    /// functions, so leaving it out can't change anything but the result
+    fn is_pure(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::Literal(_) => true,
+            Expression::Variable(name) => self.named_values.contains_key(name),
+            Expression::Error(_) => false,
+            Expression::Binary(op, lhs, rhs) => {
+                ["+", "-", "*", "/"].contains(&op.as_str())
+                    && self.is_pure(lhs)
+                    && self.is_pure(rhs)
+            }
+            Expression::Call(callee, args) => {
+                let host = match self.functions.get(callee) {
+                    Some((func, arity)) => {
+                        func.get_first_basic_block().is_none() && *arity == args.len()
+                    }
+                    None => {
+                        (builtins::constant(callee).is_some() && args.is_empty())
+                            || builtins::comparison_arity(callee) == Some(args.len())
+                            || builtins::intrinsic_params(callee).map(<[_]>::len)
+                                == Some(args.len())
                     }
+                };
+                host && builtins::is_pure(callee) && args.iter().all(|arg| self.is_pure(arg))
+            }
+            Expression::NamedCall(..) => false,
+        }
+    }
 
-                    let argsv: Vec<BasicValueEnum> =
-                        gened_args.iter().by_ref().map(|&val| val.into()).collect();
-
-                    match self
-                        .builder
-                        .build_call(func, argsv.as_slice(), "tmp")
-                        .try_as_basic_value()
-                        .left()
-                    {
-                        Some(value) => Ok(value.into_float_value()),
-                        None => panic!("recieved instruction from build call somehow"),
+    /// a call, which has no value when the callee is a void extern
+    fn codegen_call(
+        &mut self,
+        callee: &str,
+        args: &[Expression],
+    ) -> Result<Option<FloatValue<'a>>, CodegenError> {
+        if args.len() > self.max_arity {
+            return Err(CodegenError::TooManyArgs(
+                callee.to_string(),
+                args.len(),
+                self.max_arity,
+            ));
+        }
+        let (func, arity) = match self.functions.get(callee).copied() {
+            Some(func) => func,
+            None if builtins::comparison_arity(callee).is_some() => {
+                tracing::debug!(callee, "inlining the builtin comparison");
+                return self.codegen_approx_eq(callee, args).map(Some);
+            }
+            None if builtins::intrinsic_params(callee).is_some() => {
+                tracing::debug!(callee, "inlining the builtin intrinsic");
+                return self.codegen_intrinsic(callee, args).map(Some);
+            }
+            None => {
+                return match builtins::constant(callee) {
+                    Some(value) if args.is_empty() => {
+                        tracing::debug!(callee, value, "inlining the builtin constant");
+                        Ok(Some(self.context.f64_type().const_float(value)))
                     }
+                    Some(_) => Err(CodegenError::InvalidCall(callee.to_string(), 0, args.len())),
+                    None => Err(CodegenError::UnknownFunction(callee.to_string())),
                 }
-                None => Err(CodegenError::UnknownFunction(callee.clone())),
-            },
+            }
+        };
+        if arity != args.len() {
+            return Err(CodegenError::InvalidCall(
+                callee.to_string(),
+                arity,
+                args.len(),
+            ));
+        }
+
+        // only convert around the external math functions, so that user
+        // definitions wrapping them aren't converted a second time - an
+        // alias declared under another name is a host function like any other
+        let convert_angles = self.angle_unit == AngleUnit::Degrees
+            && func.get_first_basic_block().is_none()
+            && func.get_name().to_bytes() == callee.as_bytes();
+        let scale_args = convert_angles && builtins::takes_angle(callee);
+        let scale_result = convert_angles && builtins::returns_angle(callee);
+
+        let mut argsv: Vec<BasicValueEnum> = Vec::with_capacity(args.len());
+        for arg in args {
+            let mut arg = self.codegen_expr(arg)?;
+            if scale_args {
+                arg = self.build_scale(arg, std::f64::consts::PI / 180.0);
+            }
+            argsv.push(arg.into());
+        }
+
+        let call = if self.hot_reload {
+            let target = self.load_slot(callee, func);
+            self.builder.build_call(target, argsv.as_slice(), "tmp")
+        } else {
+            self.builder.build_call(func, argsv.as_slice(), "tmp")
+        };
+        let value = match call.try_as_basic_value().left() {
+            Some(value) => value.into_float_value(),
+            None => return Ok(None),
+        };
+
+        if scale_result {
+            Ok(Some(self.build_scale(value, 180.0 / std::f64::consts::PI)))
+        } else {
+            Ok(Some(value))
+        }
+    }
+
+    /// the function the slot of name points to now, making the slot pointing
+    /// to func and its rebind function the first time
+    fn load_slot(&mut self, name: &str, func: FunctionValue<'a>) -> CallableValue<'a> {
+        let slot_name = format!("{}.slot", name);
+        let slot = match self.module.get_global(&slot_name) {
+            Some(slot) => slot,
+            None => {
+                let fn_ptr_type = func.get_type().ptr_type(AddressSpace::Generic);
+                let slot = self.module.add_global(fn_ptr_type, None, &slot_name);
+                slot.set_initializer(&func.as_global_value().as_pointer_value());
+
+                let block = self.builder.get_insert_block();
+                let rebind_type = self
+                    .context
+                    .void_type()
+                    .fn_type(&[fn_ptr_type.into()], false);
+                let rebind =
+                    self.module
+                        .add_function(&format!("{}.rebind", name), rebind_type, None);
+                let entry = self.context.append_basic_block(rebind, "entry");
+                self.builder.position_at_end(entry);
+                let target = rebind.get_first_param().unwrap().into_pointer_value();
+                self.builder.build_store(slot.as_pointer_value(), target);
+                self.builder.build_return(None);
+                if let Some(block) = block {
+                    self.builder.position_at_end(block);
+                }
+                slot
+            }
+        };
+        let target: PointerValue = self
+            .builder
+            .build_load(slot.as_pointer_value(), "tmpslot")
+            .into_pointer_value();
+        CallableValue::try_from(target).expect("slots hold function pointers")
+    }
+
+    /// name.hot, which calls whatever the slot of name points to, so a
+    /// handle to it follows every reload
+    fn compile_hot_wrapper(&mut self, name: &str, func: FunctionValue<'a>) {
+        let wrapper = self
+            .module
+            .add_function(&format!("{}.hot", name), func.get_type(), None);
+        let entry = self.context.append_basic_block(wrapper, "entry");
+        self.builder.position_at_end(entry);
+        let target = self.load_slot(name, func);
+        let args = wrapper.get_params();
+        let value = self
+            .builder
+            .build_call(target, &args, "tmp")
+            .try_as_basic_value()
+            .left()
+            .unwrap();
+        self.builder.build_return(Some(&value));
+    }
+
+    /// builtins::approx_eq inline, as 1 or 0
+    fn codegen_approx_eq(
+        &mut self,
+        callee: &str,
+        args: &[Expression],
+    ) -> Result<FloatValue<'a>, CodegenError> {
+        let arity = builtins::comparison_arity(callee).unwrap_or(0);
+        if args.len() != arity {
+            return Err(CodegenError::InvalidCall(
+                callee.to_string(),
+                arity,
+                args.len(),
+            ));
         }
+        let f64_type = self.context.f64_type();
+        let a = self.codegen_expr(&args[0])?;
+        let b = self.codegen_expr(&args[1])?;
+        let eps = match args.get(2) {
+            Some(eps) => self.codegen_expr(eps)?,
+            None => f64_type.const_float(self.epsilon),
+        };
+
+        let builder = &self.builder;
+        let abs = |value: FloatValue<'a>, name| {
+            let negative = builder.build_float_compare(
+                FloatPredicate::OLT,
+                value,
+                f64_type.const_float(0.0),
+                name,
+            );
+            let negated = builder.build_float_neg(value, name);
+            builder
+                .build_select(negative, negated, value, name)
+                .into_float_value()
+        };
+        let max = |lhs: FloatValue<'a>, rhs: FloatValue<'a>| {
+            let greater = builder.build_float_compare(FloatPredicate::OGT, lhs, rhs, "tmpcmp");
+            builder
+                .build_select(greater, lhs, rhs, "tmpmax")
+                .into_float_value()
+        };
+        let (abs_a, abs_b) = (abs(a, "tmpabs"), abs(b, "tmpabs"));
+        let infinity = f64_type.const_float(f64::INFINITY);
+        // ordered compares are false for nan, so this is false for it too
+        let finite = builder.build_and(
+            builder.build_float_compare(FloatPredicate::OLT, abs_a, infinity, "tmpfinite"),
+            builder.build_float_compare(FloatPredicate::OLT, abs_b, infinity, "tmpfinite"),
+            "tmpfinite",
+        );
+        let diff = abs(self.build_arithmetic("-", a, b), "tmpdiff");
+        let scale = max(max(abs_a, abs_b), f64_type.const_float(1.0));
+        let tolerance = self.build_arithmetic("*", eps, scale);
+        let close = builder.build_and(
+            finite,
+            builder.build_float_compare(FloatPredicate::OLE, diff, tolerance, "tmpclose"),
+            "tmpclose",
+        );
+        let equal = builder.build_float_compare(FloatPredicate::OEQ, a, b, "tmpeq");
+        let res = builder.build_or(equal, close, "tmpapprox");
+        Ok(builder.build_unsigned_int_to_float(res, f64_type, "tmpbool"))
     }
 
-    fn compile_proto(&self, proto: &Prototype) -> Result<FunctionValue<'a>, CodegenError> {
+    /// min, max, clamp or abs as calls to the llvm intrinsics, which don't
+    /// branch
+    fn codegen_intrinsic(
+        &mut self,
+        callee: &str,
+        args: &[Expression],
+    ) -> Result<FloatValue<'a>, CodegenError> {
+        let arity = builtins::intrinsic_params(callee).map_or(0, <[_]>::len);
+        if args.len() != arity {
+            return Err(CodegenError::InvalidCall(
+                callee.to_string(),
+                arity,
+                args.len(),
+            ));
+        }
+        let mut argsv = Vec::with_capacity(args.len());
+        for arg in args {
+            argsv.push(self.codegen_expr(arg)?);
+        }
+        Ok(match callee {
+            "min" => self.build_intrinsic("llvm.minnum.f64", &argsv),
+            "max" => self.build_intrinsic("llvm.maxnum.f64", &argsv),
+            "clamp" => {
+                let lower = self.build_intrinsic("llvm.maxnum.f64", &argsv[..2]);
+                self.build_intrinsic("llvm.minnum.f64", &[lower, argsv[2]])
+            }
+            _ => self.build_intrinsic("llvm.fabs.f64", &argsv),
+        })
+    }
+
+    /// a call to the intrinsic taking and returning doubles, declaring it
+    /// the first time
+    fn build_intrinsic(&self, name: &str, args: &[FloatValue<'a>]) -> FloatValue<'a> {
+        let f64_type = self.context.f64_type();
+        let func = self.module.get_function(name).unwrap_or_else(|| {
+            let params: Vec<BasicTypeEnum> = args.iter().map(|_| f64_type.into()).collect();
+            self.module
+                .add_function(name, f64_type.fn_type(&params, false), None)
+        });
+        let args: Vec<BasicValueEnum> = args.iter().map(|arg| (*arg).into()).collect();
+        self.builder
+            .build_call(func, &args, "tmpintr")
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_float_value()
+    }
+
+    fn build_scale(&self, value: FloatValue<'a>, factor: f64) -> FloatValue<'a> {
+        let factor = self.context.f64_type().const_float(factor);
+        self.build_arithmetic("*", value, factor)
+    }
+
+    /// lhs op rhs for one of the four arithmetic ops, through its constrained
+    /// intrinsic when strict_fp is set
+    fn build_arithmetic(
+        &self,
+        op: &str,
+        lhs: FloatValue<'a>,
+        rhs: FloatValue<'a>,
+    ) -> FloatValue<'a> {
+        if self.strict_fp {
+            return self.build_constrained(op, lhs, rhs);
+        }
+        match op {
+            "+" => self.builder.build_float_add(lhs, rhs, "tmpadd"),
+            "-" => self.builder.build_float_sub(lhs, rhs, "tmpsub"),
+            "*" => self.builder.build_float_mul(lhs, rhs, "tmpmul"),
+            _ => self.builder.build_float_div(lhs, rhs, "tmpdiv"),
+        }
+    }
+
+    /// res of left op right, or with check_overflow the nan of a trap when
+    /// it's an infinity and neither operand was - an infinity carried on
+    /// from an operand isn't where the overflow happened
+    fn check_overflow(
+        &mut self,
+        op: &str,
+        left: &Expression,
+        right: &Expression,
+        (lhs, rhs): (FloatValue<'a>, FloatValue<'a>),
+        res: FloatValue<'a>,
+    ) -> FloatValue<'a> {
+        if !self.check_overflow {
+            return res;
+        }
+        let site = self.trap_sites.len();
+        let expr = Expression::Binary(
+            op.to_string(),
+            Box::new(left.clone()),
+            Box::new(right.clone()),
+        );
+        self.trap_sites.push(match &self.item_span {
+            Some(span) => format!("{} in {} ({})", expr, self.body_name, span),
+            None => format!("{} in {}", expr, self.body_name),
+        });
+
+        let infinity = self.context.f64_type().const_float(f64::INFINITY);
+        let abs = |value| self.build_intrinsic("llvm.fabs.f64", &[value]);
+        let builder = &self.builder;
+        // ordered compares are false for nan, which can't overflow either
+        let finite = builder.build_and(
+            builder.build_float_compare(FloatPredicate::OLT, abs(lhs), infinity, "tmpfinite"),
+            builder.build_float_compare(FloatPredicate::OLT, abs(rhs), infinity, "tmpfinite"),
+            "tmpfinite",
+        );
+        let overflowed = builder.build_and(
+            finite,
+            builder.build_float_compare(FloatPredicate::OEQ, abs(res), infinity, "tmpinf"),
+            "tmpoverflow",
+        );
+        let block = builder.get_insert_block().unwrap();
+        let func = block.get_parent().unwrap();
+        let trap = self.context.append_basic_block(func, "overflow");
+        let done = self.context.append_basic_block(func, "checked");
+        builder.build_conditional_branch(overflowed, trap, done);
+        builder.position_at_end(trap);
+        let trapped = self.build_trap(runtime::OVERFLOW, site, res);
+        builder.build_unconditional_branch(done);
+        builder.position_at_end(done);
+        let phi = builder.build_phi(self.context.f64_type(), "tmpchecked");
+        phi.add_incoming(&[(&res, block), (&trapped, trap)]);
+        phi.as_basic_value().into_float_value()
+    }
+
+    fn build_constrained(
+        &self,
+        op: &str,
+        lhs: FloatValue<'a>,
+        rhs: FloatValue<'a>,
+    ) -> FloatValue<'a> {
+        let name = match op {
+            "+" => "fadd",
+            "-" => "fsub",
+            "*" => "fmul",
+            _ => "fdiv",
+        };
+        let symbol = format!("wizarding.strict.{}", name);
+        let func = self.module.get_function(&symbol).unwrap_or_else(|| {
+            let buffer =
+                MemoryBuffer::create_from_memory_range_copy(STRICT_FP_IR.as_bytes(), "strict fp");
+            let helpers = self
+                .context
+                .create_module_from_ir(buffer)
+                .expect("the strict fp helpers are valid ir");
+            self.module
+                .link_in_module(helpers)
+                .expect("the strict fp helpers only define their own symbols");
+            self.module.get_function(&symbol).unwrap()
+        });
+        let call = self
+            .builder
+            .build_call(func, &[lhs.into(), rhs.into()], "tmpstrict");
+        call.add_attribute(AttributeLoc::Function, self.strictfp_attribute());
+        call.try_as_basic_value().left().unwrap().into_float_value()
+    }
+
+    /// what every call to a constrained intrinsic and the functions making
+    /// them have to be marked with
+    fn strictfp_attribute(&self) -> Attribute {
+        self.context
+            .create_enum_attribute(Attribute::get_named_enum_kind_id("strictfp"), 0)
+    }
+
+    fn compile_proto(&mut self, proto: &Prototype) -> Result<FunctionValue<'a>, CodegenError> {
+        if proto.args.len() > self.max_arity {
+            return Err(CodegenError::TooManyArgs(
+                proto.name.clone(),
+                proto.args.len(),
+                self.max_arity,
+            ));
+        }
+
         let args_types: Vec<BasicTypeEnum> = iter::repeat(self.context.f64_type())
             .take(proto.args.len())
             .map(|f| f.into())
             .collect();
         let args_types = args_types.as_slice();
 
-        let fn_type = self.context.f64_type().fn_type(args_types, false);
-        let fn_val = self.module.add_function(proto.name.as_str(), fn_type, None);
+        let fn_type = if proto.void {
+            self.context.void_type().fn_type(args_types, false)
+        } else {
+            self.context.f64_type().fn_type(args_types, false)
+        };
+        let fn_val = self.module.add_function(proto.symbol(), fn_type, None);
+        // llvm renames a second function with the same symbol, and calls by
+        // that name keep finding the first
+        self.functions
+            .entry(proto.name.clone())
+            .or_insert((fn_val, proto.args.len()));
 
         for (i, arg) in fn_val.get_param_iter().enumerate() {
             arg.into_float_value().set_name(proto.args[i].as_str());
@@ -117,55 +758,442 @@ impl<'a> Codegen<'a> {
     fn compile_fn(&mut self, function: &Function) -> Result<FunctionValue<'a>, CodegenError> {
         let Function {
             prototype: proto,
-            body,
+            body: body_expr,
         } = function;
+        // a definition made after inline ir defined the name would be
+        // renamed, and never called
+        if self.linked.contains(&proto.name) {
+            return Err(CodegenError::Redefined(proto.name.clone()));
+        }
+        let shadowed = self.functions.contains_key(&proto.name);
         let llvm_func = self.compile_proto(proto)?;
 
+        if self.denormals_are_zero {
+            let attribute = self
+                .context
+                .create_string_attribute("denormal-fp-math", "preserve-sign,preserve-sign");
+            llvm_func.add_attribute(AttributeLoc::Function, attribute);
+        }
+        if self.strict_fp {
+            llvm_func.add_attribute(AttributeLoc::Function, self.strictfp_attribute());
+        }
+        self.fast_body =
+            !self.strict_fp && !self.check_overflow && proto.math.is_fast(self.fast_math);
+        self.strict_body = self.strict_fp || proto.math == MathMode::Strict;
+        self.body_name = match proto.name.as_str() {
+            "lambda" => "a top level expression".to_string(),
+            name => name.to_string(),
+        };
+        // functions that just follow a strict program are left unmarked
+        if self.fast_body || proto.math == MathMode::Strict {
+            let value = if self.fast_body { "true" } else { "false" };
+            for name in FAST_MATH_ATTRIBUTES {
+                let attribute = self.context.create_string_attribute(name, value);
+                llvm_func.add_attribute(AttributeLoc::Function, attribute);
+            }
+        }
+
         let entry = self.context.append_basic_block(llvm_func, "entry");
 
         self.builder.position_at_end(entry);
 
+        self.named_values.clear();
         self.named_values.reserve(proto.args.len());
 
         for (i, arg) in llvm_func.get_param_iter().enumerate() {
             self.named_values.insert(proto.args[i].clone(), arg);
         }
 
-        let body = self.codegen_expr(body)?;
+        // a top level call to a void extern is run for its effect alone
+        let body = match body_expr {
+            Expression::Call(callee, args) if proto.name == "lambda" => self
+                .codegen_call(callee, args)
+                .map(|value| value.unwrap_or_else(|| self.context.f64_type().const_float(0.0))),
+            body_expr => self.codegen_expr(body_expr),
+        };
+        let body = match body {
+            Ok(body) => body,
+            Err(err) => {
+                self.discard(llvm_func);
+                return Err(err);
+            }
+        };
 
         self.builder.build_return(Some(&body));
 
         if llvm_func.verify(true) {
+            let symbol = llvm_func.get_name().to_string_lossy().into_owned();
+            if proto.name == "lambda" {
+                self.entries.push((symbol, body_expr.clone()));
+            } else if !shadowed {
+                self.defined.push(symbol);
+                if self.hot_reload {
+                    self.compile_hot_wrapper(&proto.name, llvm_func);
+                }
+            }
             Ok(llvm_func)
         } else {
-            unsafe {
-                llvm_func.delete();
+            self.discard(llvm_func);
+            Err(CodegenError::InvalidFunction(proto.name.clone()))
+        }
+    }
+
+    /// remove a function whose body failed, along with everything that still
+    /// points into it, so the module stays valid for the functions after it
+    fn discard(&mut self, llvm_func: FunctionValue<'a>) {
+        let mut names = Vec::new();
+        self.functions.retain(|name, (func, _)| {
+            let keep = *func != llvm_func;
+            if !keep {
+                names.push(name.clone());
             }
+            keep
+        });
+        self.named_values.clear();
+        self.builder.clear_insertion_position();
+        // a recursive call made a slot pointing back at it, which has to
+        // stop pointing there before either can go
+        let slots: Vec<_> = names
+            .iter()
+            .filter_map(|name| self.module.get_global(&format!("{}.slot", name)))
+            .collect();
+        for slot in &slots {
+            let null = llvm_func
+                .get_type()
+                .ptr_type(AddressSpace::Generic)
+                .const_null();
+            slot.set_initializer(&null);
+        }
+        unsafe {
+            llvm_func.delete();
+            for name in &names {
+                if let Some(rebind) = self.module.get_function(&format!("{}.rebind", name)) {
+                    rebind.delete();
+                }
+            }
+            for slot in slots {
+                slot.delete();
+            }
+        }
+    }
 
-            Err(CodegenError::InvalidFunction(proto.name.clone()))
+    /// embed the table as a private constant array behind two accessors -
+    /// name(i) loads element i, truncated, or returns nan when it's out of
+    /// bounds or traps with check_bounds, and name_len() returns the length
+    fn compile_table(&mut self, table: &Table) -> Result<FunctionValue<'a>, CodegenError> {
+        let values = match &table.values {
+            Some(values) => values,
+            None => return Err(CodegenError::UnloadedTable(table.name.clone())),
+        };
+        let len_name = format!("{}_len", table.name);
+        let shadowed = self.functions.contains_key(&table.name);
+        let f64_type = self.context.f64_type();
+        let len = f64_type.const_float(values.len() as f64);
+
+        let len_fn = self.compile_proto(&Prototype {
+            name: len_name.clone(),
+            args: vec![],
+            link_name: None,
+            defaults: vec![],
+            void: false,
+            math: MathMode::default(),
+        })?;
+        let entry = self.context.append_basic_block(len_fn, "entry");
+        self.builder.position_at_end(entry);
+        self.builder.build_return(Some(&len));
+
+        let get_fn = self.compile_proto(&Prototype {
+            name: table.name.clone(),
+            args: vec!["i".to_string()],
+            link_name: None,
+            defaults: vec![None],
+            void: false,
+            math: MathMode::default(),
+        })?;
+        if !shadowed {
+            self.defined.push(len_name);
+            self.defined.push(table.name.clone());
+        }
+        let entry = self.context.append_basic_block(get_fn, "entry");
+        self.builder.position_at_end(entry);
+        let nan = f64_type.const_float(f64::NAN);
+        let i = get_fn.get_nth_param(0).unwrap().into_float_value();
+        let site = self.trap_sites.len();
+        if self.check_bounds {
+            self.trap_sites.push(table.name.clone());
+        }
+        if values.is_empty() {
+            if self.check_bounds {
+                let trapped = self.build_trap(runtime::OUT_OF_BOUNDS, site, i);
+                self.builder.build_return(Some(&trapped));
+            } else {
+                self.builder.build_return(Some(&nan));
+            }
+            return Ok(get_fn);
+        }
+
+        let elements: Vec<FloatValue> = values.iter().map(|v| f64_type.const_float(*v)).collect();
+        let data = self.module.add_global(
+            f64_type.array_type(values.len() as u32),
+            None,
+            &format!("{}.data", table.name),
+        );
+        data.set_initializer(&f64_type.const_array(&elements));
+        data.set_constant(true);
+        data.set_linkage(Linkage::Private);
+
+        // nan fails both comparisons, so it counts as out of bounds too
+        let zero = f64_type.const_float(0.0);
+        let above = self
+            .builder
+            .build_float_compare(FloatPredicate::OGE, i, zero, "above");
+        let below = self
+            .builder
+            .build_float_compare(FloatPredicate::OLT, i, len, "below");
+        let in_bounds = self.builder.build_and(above, below, "inbounds");
+        if self.check_bounds {
+            let load = self.context.append_basic_block(get_fn, "load");
+            let trap = self.context.append_basic_block(get_fn, "outofbounds");
+            self.builder.build_conditional_branch(in_bounds, load, trap);
+            self.builder.position_at_end(trap);
+            let trapped = self.build_trap(runtime::OUT_OF_BOUNDS, site, i);
+            self.builder.build_return(Some(&trapped));
+            self.builder.position_at_end(load);
         }
+        let clamped = self
+            .builder
+            .build_select(in_bounds, i, zero, "clamped")
+            .into_float_value();
+        let index =
+            self.builder
+                .build_float_to_signed_int(clamped, self.context.i64_type(), "index");
+        let ptr = unsafe {
+            self.builder.build_in_bounds_gep(
+                data.as_pointer_value(),
+                &[self.context.i64_type().const_int(0, false), index],
+                "element",
+            )
+        };
+        let element = self.builder.build_load(ptr, "tmpload").into_float_value();
+        let value = self
+            .builder
+            .build_select(in_bounds, element, nan, "tmpvalue");
+        self.builder.build_return(Some(&value));
+
+        Ok(get_fn)
     }
 
-    pub fn codegen(&mut self, ast_nodes: &Vec<ASTNode>) -> Result<(), CodegenError> {
-        for node in ast_nodes {
-            match node {
-                ASTNode::Function(func) => self.compile_fn(func),
-                ASTNode::Extern(func) => self.compile_proto(func),
-            }?;
+    /// record a trap of kind at site with value in the trap slot, linking
+    /// the runtime in the first time, and give the nan to return instead
+    fn build_trap(&self, kind: f64, site: usize, value: FloatValue<'a>) -> FloatValue<'a> {
+        let trap = self.module.get_function(runtime::TRAP).unwrap_or_else(|| {
+            let buffer =
+                MemoryBuffer::create_from_memory_range_copy(runtime::IR.as_bytes(), "runtime");
+            let runtime = self
+                .context
+                .create_module_from_ir(buffer)
+                .expect("the runtime is valid ir");
+            self.module
+                .link_in_module(runtime)
+                .expect("the runtime only defines its own symbols");
+            self.module.get_function(runtime::TRAP).unwrap()
+        });
+        let f64_type = self.context.f64_type();
+        let args = [
+            f64_type.const_float(kind).into(),
+            f64_type.const_float(site as f64).into(),
+            value.into(),
+        ];
+        let call = self.builder.build_call(trap, &args, "tmptrap");
+        call.try_as_basic_value().left().unwrap().into_float_value()
+    }
+
+    /// parse ir and link it into the module, registering every function it
+    /// defines so calls find them like externs
+    fn link_ir(&mut self, ir: &str) -> Result<(), CodegenError> {
+        let buffer = MemoryBuffer::create_from_memory_range_copy(ir.as_bytes(), "inline ir");
+        let module = self
+            .context
+            .create_module_from_ir(buffer)
+            .map_err(|err| CodegenError::InvalidIr(err.to_string()))?;
+
+        let double = BasicTypeEnum::from(self.context.f64_type());
+        let mut defined = Vec::new();
+        let mut next = module.get_first_function();
+        while let Some(func) = next {
+            next = func.get_next_function();
+            if func.get_first_basic_block().is_none() {
+                continue;
+            }
+            let name = func.get_name().to_string_lossy().into_owned();
+            let doubles = func.get_type().get_return_type() == Some(double)
+                && func
+                    .get_param_iter()
+                    .all(|param| param.get_type() == double);
+            if !doubles {
+                return Err(CodegenError::IrSignature(name));
+            }
+            match self.functions.get(&name) {
+                Some((existing, _)) if existing.get_first_basic_block().is_some() => {
+                    return Err(CodegenError::Redefined(name))
+                }
+                _ => defined.push((name, func.count_params() as usize)),
+            }
         }
 
+        self.module
+            .link_in_module(module)
+            .map_err(|err| CodegenError::InvalidIr(err.to_string()))?;
+        for (name, arity) in defined {
+            if let Some(func) = self.module.get_function(&name) {
+                self.functions.insert(name.clone(), (func, arity));
+                self.linked.insert(name.clone());
+                self.defined.push(name);
+            }
+        }
         Ok(())
     }
+
+    /// compile the nodes into the module after whatever earlier calls
+    /// compiled, so they can call what those defined - a name defined again
+    /// keeps calling the first definition, like it does within one call
+    pub fn codegen(&mut self, ast_nodes: &Vec<ASTNode>) -> Result<CodegenSummary, CodegenError> {
+        let _span = tracing::info_span!("codegen", items = ast_nodes.len()).entered();
+        let (functions, entries) = (self.defined.len(), self.entries.len());
+        for node in ast_nodes {
+            self.codegen_node(node)?;
+        }
+
+        Ok(CodegenSummary {
+            functions: self.defined[functions..].to_vec(),
+            entries: self.entries[entries..]
+                .iter()
+                .map(|(symbol, _)| symbol.clone())
+                .collect(),
+        })
+    }
+
+    /// compile every node, carrying on past the ones that fail, which are
+    /// left out of the module - one that only failed because it calls a
+    /// function that failed says so instead of that the function is unknown,
+    /// and spans, if it has one for the node, says where it was written
+    pub fn codegen_all(
+        &mut self,
+        ast_nodes: &[ASTNode],
+        spans: &[Option<SourceSpan>],
+    ) -> (CodegenSummary, Vec<ItemError>) {
+        let span = tracing::info_span!(
+            "codegen",
+            items = ast_nodes.len(),
+            failed = tracing::field::Empty
+        )
+        .entered();
+        let (functions, entries) = (self.defined.len(), self.entries.len());
+        let mut failures = Failures::default();
+        for (i, node) in ast_nodes.iter().enumerate() {
+            self.codegen_item(node, spans.get(i).cloned().flatten(), &mut failures);
+        }
+        span.record("failed", &failures.errors.len());
+
+        let summary = CodegenSummary {
+            functions: self.defined[functions..].to_vec(),
+            entries: self.entries[entries..]
+                .iter()
+                .map(|(symbol, _)| symbol.clone())
+                .collect(),
+        };
+        (summary, failures.errors)
+    }
+
+    /// compile the next node of a program like codegen_all does, adding
+    /// its error to failures if it fails
+    pub fn codegen_item(
+        &mut self,
+        node: &ASTNode,
+        span: Option<SourceSpan>,
+        failures: &mut Failures,
+    ) {
+        let item = match node {
+            ASTNode::Function(func) if func.prototype.name == "lambda" => {
+                failures.lambdas += 1;
+                format!("top level expression {}", failures.lambdas)
+            }
+            ASTNode::Function(Function { prototype, .. }) | ASTNode::Extern(prototype) => {
+                prototype.name.clone()
+            }
+            ASTNode::Table(table) => table.name.clone(),
+            ASTNode::When(..) => "conditional item".to_string(),
+            ASTNode::InlineIr(_) => "inline ir".to_string(),
+            ASTNode::Test(test) => format!("test {:?}", test.name),
+        };
+        self.item_span = span.clone();
+        let res = self.codegen_node(node);
+        self.item_span = None;
+        let error = match res {
+            Ok(()) => return,
+            Err(CodegenError::UnknownFunction(callee)) if failures.failed.contains(&callee) => {
+                CodegenError::FailedCallee(callee)
+            }
+            Err(e) => e,
+        };
+        failures.failed.insert(item.clone());
+        failures.errors.push(ItemError { item, error, span });
+    }
+
+    /// compile one node, leaving no parameters in scope and the builder
+    /// positioned nowhere afterwards
+    pub fn codegen_node(&mut self, node: &ASTNode) -> Result<(), CodegenError> {
+        let _span = tracing::debug_span!("item", name = node_name(node)).entered();
+        let res = match node {
+            ASTNode::Function(func) => self.compile_fn(func).map(|_| ()),
+            ASTNode::Extern(func) => self.compile_proto(func).map(|_| ()),
+            ASTNode::Table(table) => self.compile_table(table).map(|_| ()),
+            ASTNode::When(..) => Err(CodegenError::UnconfiguredItem),
+            ASTNode::InlineIr(ir) => self.link_ir(ir),
+            // only the test runner compiles tests
+            ASTNode::Test(_) => Ok(()),
+        };
+        self.named_values.clear();
+        self.builder.clear_insertion_position();
+        res
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use inkwell::context::Context;
+    use std::os::raw::c_void;
+
+    use inkwell::{
+        attributes::AttributeLoc, context::Context, execution_engine::JitFunction,
+        OptimizationLevel,
+    };
     use parser::Parser;
 
-    use crate::parser;
+    use crate::{
+        builtins::{self, AngleUnit},
+        engine::{Engine, EngineError, EngineKind, OptLevel},
+        parser,
+        runtime::RuntimeError,
+        span::{FileId, Location, SourceSpan, Span},
+    };
+
+    use super::{Codegen, CodegenError};
 
-    use super::Codegen;
+    type EntryFunc = unsafe extern "C" fn() -> f64;
+
+    fn run(source: &str, angle_unit: AngleUnit) -> f64 {
+        let ast = Parser::default().parse_str(source).unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.angle_unit = angle_unit;
+        codegen.codegen(&ast).unwrap();
+        let ee = codegen
+            .module
+            .create_jit_execution_engine(OptimizationLevel::None)
+            .unwrap();
+        let entry: JitFunction<EntryFunc> = unsafe { ee.get_function("lambda") }.unwrap();
+        unsafe { entry.call() }
+    }
 
     #[test]
     fn codegen_works() {
@@ -178,4 +1206,568 @@ mod tests {
         codegen.codegen(&mut ast).unwrap();
         println!("{}", codegen.module.print_to_string().to_str().unwrap());
     }
+
+    #[test]
+    fn degrees_mode_works() {
+        let source = "🜹sin🜄x🜂;sin🜄90🜂;";
+        assert!((run(source, AngleUnit::Degrees) - 1.0).abs() < 1e-12);
+        assert!((run(source, AngleUnit::Radians) - 0.893_996_663_600_558).abs() < 1e-12);
+        let source = "🜹sin🜄x🜂;🜹asin🜄x🜂;🜙wrap🜄x🜂sin🜄x🜂;asin🜄wrap🜄30🜂🜂;";
+        assert!((run(source, AngleUnit::Degrees) - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn builtin_constants_work() {
+        assert_eq!(run("pi🜄🜂;", AngleUnit::Radians), std::f64::consts::PI);
+        assert_eq!(run("🜙pi🜄🜂3;pi🜄🜂;", AngleUnit::Radians), 3.0);
+    }
+
+    #[test]
+    fn decomposed_names_work() {
+        let source = "🜙f🜄café🜂 cafe\u{301} * 2; f🜄3🜂;";
+        assert_eq!(run(source, AngleUnit::Radians), 6.0);
+    }
+
+    #[test]
+    fn digit_separators_work() {
+        assert_eq!(run("1_000;", AngleUnit::Radians), 1000.0);
+    }
+
+    #[test]
+    fn approx_eq_works() {
+        let values = [0.3, 0.1 + 0.2, 0.0, -0.0, 1e-10, 1e20, 1e20 + 1e10];
+        let specials = ["inf🜄🜂", "-inf🜄🜂", "nan🜄🜂", "1"];
+        let mut sources: Vec<(String, f64, f64)> = Vec::new();
+        for a in values.iter() {
+            for b in values.iter() {
+                sources.push((format!("{}🜌{}", a, b), *a, *b));
+            }
+        }
+        for a in specials.iter() {
+            for b in specials.iter() {
+                let value = |special: &str| match special {
+                    "inf🜄🜂" => f64::INFINITY,
+                    "-inf🜄🜂" => f64::NEG_INFINITY,
+                    "nan🜄🜂" => f64::NAN,
+                    _ => 1.0,
+                };
+                sources.push((format!("{}🜌{}", a, b), value(a), value(b)));
+            }
+        }
+        for (args, a, b) in sources {
+            let expected = builtins::approx_eq(a, b, builtins::DEFAULT_EPSILON) as u8 as f64;
+            let source = format!("approx_eq🜄{}🜂;", args);
+            assert_eq!(run(&source, AngleUnit::Radians), expected, "{}", source);
+        }
+        let source = "approx_eq_eps🜄1🜌1.05🜌0.1🜂 * 10 + approx_eq_eps🜄1🜌1.05🜌0.01🜂;";
+        assert_eq!(run(source, AngleUnit::Radians), 10.0);
+        assert_eq!(
+            run("🜙approx_eq🜄a🜌b🜂7;approx_eq🜄1🜌1🜂;", AngleUnit::Radians),
+            7.0
+        );
+    }
+
+    #[test]
+    fn negative_arguments_work() {
+        let source = "🜙f🜄a🜌b🜂🜄a * 10🜂 + b;f🜄-1🜌 -🜄2 + 1🜂🜂;";
+        assert_eq!(run(source, AngleUnit::Radians), -13.0);
+        let source = "🜙sq🜄x🜂x * x;-sq🜄-3🜂 - -1;";
+        assert_eq!(run(source, AngleUnit::Radians), -8.0);
+    }
+
+    #[test]
+    fn extern_alias_works() {
+        let source = r#"🜹mysin🜄x🜂 = "sin";mysin🜄0.5🜂;"#;
+        assert_eq!(run(source, AngleUnit::Radians), 0.5f64.sin());
+        // degrees are only converted around the builtin's own name
+        let source = r#"🜹mysin🜄x🜂 = "sin";mysin🜄90🜂;"#;
+        assert_eq!(run(source, AngleUnit::Degrees), 90f64.sin());
+
+        // the host symbol isn't a name the source can call
+        let ast = Parser::default()
+            .parse_str(r#"🜹mysin🜄x🜂 = "sin";sin🜄0.5🜂;"#)
+            .unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        assert!(matches!(
+            codegen.codegen(&ast),
+            Err(CodegenError::UnknownFunction(name)) if name == "sin"
+        ));
+
+        // a host symbol no source can spell is still reachable through one
+        let symbol = "eng.scaled-area$f64";
+        assert!(Parser::default()
+            .parse_str(&format!("{}🜄2🜌3🜂;", symbol))
+            .is_err());
+        let source = format!(r#"🜹area🜄r🜌scale🜂 = "{}";area🜄2🜌3🜂;"#, symbol);
+        let ast = Parser::default().parse_str(&source).unwrap();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        unsafe { engine.register_function(symbol, 2, scaled_area as *const () as usize) }.unwrap();
+        assert_eq!(engine.call("lambda", &[]), Ok(12.0));
+    }
+
+    extern "C" fn scaled_area(r: f64, scale: f64) -> f64 {
+        r * r * scale
+    }
+
+    #[test]
+    fn denormals_are_zero_works() {
+        let ast = Parser::default().parse_str("🜹sin🜄x🜂;🜙half🜄x🜂x/2;").unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.denormals_are_zero = true;
+        codegen.codegen(&ast).unwrap();
+        let ir = codegen.module.print_to_string().to_string();
+        assert!(ir.contains("\"denormal-fp-math\"=\"preserve-sign,preserve-sign\""));
+        let half = codegen.module.get_function("half").unwrap();
+        assert!(half
+            .get_string_attribute(AttributeLoc::Function, "denormal-fp-math")
+            .is_some());
+        let sin = codegen.module.get_function("sin").unwrap();
+        assert!(sin
+            .get_string_attribute(AttributeLoc::Function, "denormal-fp-math")
+            .is_none());
+    }
+
+    #[test]
+    fn math_modes_work() {
+        let source = "🜙fast kernel🜄x🜂x * 0;🜙strict sum🜄x🜂x * 0;🜙plain🜄x🜂x * 0;";
+        let attributes = |fast_math: bool| {
+            let ast = Parser::default().parse_str(source).unwrap();
+            let context = Context::create();
+            let mut codegen = Codegen::new(&context);
+            codegen.fast_math = fast_math;
+            codegen.codegen(&ast).unwrap();
+            let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+            let mut res = Vec::new();
+            for name in ["kernel", "sum", "plain"].iter() {
+                let func = codegen.module.get_function(name).unwrap();
+                let unsafe_math = func
+                    .get_string_attribute(AttributeLoc::Function, "unsafe-fp-math")
+                    .map(|attr| attr.get_string_value().to_string_lossy().into_owned());
+                let value = engine.call(name, &[f64::INFINITY]).unwrap();
+                res.push((unsafe_math, value.to_bits()));
+            }
+            res
+        };
+        let (strict, fast) = (attributes(false), attributes(true));
+        let unsafe_math: Vec<Option<&str>> = strict
+            .iter()
+            .chain(&fast)
+            .map(|(attr, _)| attr.as_deref())
+            .collect();
+        assert_eq!(
+            unsafe_math,
+            [
+                Some("true"),
+                Some("false"),
+                None,
+                Some("true"),
+                Some("false"),
+                Some("true")
+            ]
+        );
+        assert_eq!((strict[0].1, fast[0].1, fast[2].1), (0, 0, 0));
+        assert!(f64::from_bits(strict[2].1).is_nan());
+        // the strict function's result is the same bits either way
+        assert_eq!(strict[1].1, strict[2].1);
+        assert_eq!(fast[1].1, strict[1].1);
+
+        // strict bodies get none of the peephole's rewrites
+        let source = "🜙strict f🜄x🜂🜄x * 1🜂 + 🜄x * 2🜂;🜙g🜄x🜂🜄x * 1🜂 + 🜄x * 2🜂;";
+        assert_eq!(arithmetic(source, true, true), 3 + 2);
+        let source = "🜙strict f🜄x🜂x + 0;🜙fast g🜄x🜂x + 0;";
+        let ast = Parser::default().parse_str(source).unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        let sign = |name| engine.call(name, &[-0.0]).unwrap().is_sign_negative();
+        assert_eq!((sign("f"), sign("g")), (false, true));
+    }
+
+    #[test]
+    fn strict_fp_works() {
+        let source = "🜙fast blend🜄a🜌b🜂a * 0.75 + b / 4 - 0;blend🜄1🜌2🜂;";
+        let compile = |strict_fp: bool| {
+            let ast = Parser::default().parse_str(source).unwrap();
+            let context = Context::create();
+            let mut codegen = Codegen::new(&context);
+            codegen.fast_math = true;
+            codegen.strict_fp = strict_fp;
+            codegen.codegen(&ast).unwrap();
+            let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+            let value = engine.call("blend", &[-0.0, -0.0]).unwrap();
+            (
+                codegen.module.print_to_string().to_string(),
+                value.to_bits(),
+            )
+        };
+        let (strict_ir, strict) = compile(true);
+        let (fast_ir, _) = compile(false);
+        for op in ["fadd", "fsub", "fmul", "fdiv"].iter() {
+            let intrinsic = format!("llvm.experimental.constrained.{}.f64", op);
+            assert!(strict_ir.contains(&intrinsic), "{}", op);
+            assert!(!fast_ir.contains(&intrinsic), "{}", op);
+        }
+        assert!(strict_ir.contains("strictfp"));
+        assert!(!strict_ir.contains("\"unsafe-fp-math\"=\"true\""));
+        // every step keeps the sign of a -0 input
+        assert_eq!(strict, (-0.0f64).to_bits());
+    }
+
+    #[test]
+    fn check_overflow_works() {
+        let source = "🜙growth🜄x🜂x * x;
+                      🜙middle🜄x🜂growth🜄x🜂 + 1;
+                      🜙outer🜄x🜂middle🜄x🜂 * 3;
+                      🜙double🜄x🜂x * 2;";
+        let ast = Parser::default().parse_str(source).unwrap();
+        let span = SourceSpan {
+            path: "growth.wiz".to_string(),
+            span: Span::new(FileId(0), 0..0),
+            start: Location { line: 1, column: 1 },
+        };
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.fast_math = true;
+        codegen.check_overflow = true;
+        let (_, failures) = codegen.codegen_all(&ast, &[Some(span)]);
+        assert!(failures.is_empty());
+        let ir = codegen.module.print_to_string().to_string();
+        assert!(!ir.contains("\"no-infs-fp-math\"=\"true\""));
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        let overflow = |op: &str, value| {
+            Err(EngineError::Trap(RuntimeError::Overflow {
+                op: op.to_string(),
+                value,
+            }))
+        };
+        assert_eq!(engine.call("outer", &[2.0]), Ok(15.0));
+        // the multiply that overflowed is reported, not the ones after it
+        assert_eq!(
+            engine.call("outer", &[-1e200]),
+            overflow("x * x in growth (growth.wiz:1:1)", f64::INFINITY)
+        );
+        // an infinity that was already there isn't an overflow
+        assert_eq!(engine.call("outer", &[f64::INFINITY]), Ok(f64::INFINITY));
+        // x * 2 is still checked once the peephole makes it x + x
+        assert_eq!(
+            engine.call("double", &[-1e308]),
+            overflow("x * 2 in double", f64::NEG_INFINITY)
+        );
+    }
+
+    extern "C" fn triple(x: f64) -> f64 {
+        x * 3.0
+    }
+
+    #[test]
+    fn hot_reload_works() {
+        let source = "🜙f🜄x🜂x + 1;🜙g🜄x🜂f🜄x🜂 * 2;🜙r🜄x🜂r🜄x🜂 + nope🜄x🜂;";
+        let ast = Parser::default().parse_str(source).unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.hot_reload = true;
+        let (_, failures) = codegen.codegen_all(&ast, &[]);
+        assert_eq!(failures.len(), 1);
+        // the failed recursive function took its slot with it
+        assert!(codegen.module.verify().is_ok());
+        let ir = codegen.module.print_to_string().to_string();
+        assert!(ir.contains("@f.slot = global double (double)* @f"));
+        assert!(!ir.contains("@r.slot"));
+
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        assert_eq!(engine.call("g", &[1.0]), Ok(4.0));
+        assert_eq!(engine.call("f.hot", &[1.0]), Ok(2.0));
+        let rebind = engine.address("f.rebind").unwrap();
+        unsafe {
+            let rebind: extern "C" fn(*const c_void) = std::mem::transmute(rebind);
+            rebind(triple as *const c_void);
+        }
+        assert_eq!(engine.call("g", &[1.0]), Ok(6.0));
+        assert_eq!(engine.call("f.hot", &[1.0]), Ok(3.0));
+        assert_eq!(engine.call("f", &[1.0]), Ok(2.0));
+    }
+
+    #[test]
+    fn inline_ir_works() {
+        let parser = Parser {
+            allow_inline_ir: true,
+            ..Parser::default()
+        };
+        // a name the host doesn't export, so only the ir can define it
+        let with_sign = "llvm \"declare double @llvm.copysign.f64(double, double)
+define double @with_sign(double %x, double %y) {
+  %r = call double @llvm.copysign.f64(double %x, double %y)
+  ret double %r
+}\";";
+        let ast = parser
+            .parse_str(&format!("{}with_sign🜄3🜌-0🜂;", with_sign))
+            .unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        assert_eq!(engine.call("lambda", &[]).unwrap(), -3.0);
+
+        let codegen_error = |source: &str| {
+            let ast = parser.parse_str(source).unwrap();
+            let context = Context::create();
+            let mut codegen = Codegen::new(&context);
+            codegen.codegen(&ast).unwrap_err().to_string()
+        };
+        assert!(codegen_error("llvm \"define double\";").starts_with("invalid inline ir: "));
+        assert_eq!(
+            codegen_error("llvm \"define i32 @count() {\n  ret i32 1\n}\";"),
+            "count from inline ir has to take and return doubles"
+        );
+        // either way round
+        assert_eq!(
+            codegen_error(&format!("🜙with_sign🜄x🜌y🜂x;{}", with_sign)),
+            "with_sign is already defined"
+        );
+        assert_eq!(
+            codegen_error(&format!("{}🜙with_sign🜄x🜌y🜂x;", with_sign)),
+            "with_sign is already defined"
+        );
+    }
+
+    #[test]
+    fn max_arity_works() {
+        let params: Vec<String> = (0..300).map(|i| format!("x{}", i)).collect();
+        let source = format!("🜙wide🜄{}🜂x0;", params.join("🜌"));
+        let ast = Parser::default().parse_str(&source).unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        assert!(matches!(
+            codegen.codegen(&ast),
+            Err(CodegenError::TooManyArgs(name, 300, 256)) if name == "wide"
+        ));
+        let args = vec!["1"; 300].join("🜌");
+        let ast = Parser::default()
+            .parse_str(&format!("f🜄{}🜂;", args))
+            .unwrap();
+        assert!(matches!(
+            codegen.codegen(&ast),
+            Err(CodegenError::TooManyArgs(name, 300, 256)) if name == "f"
+        ));
+        codegen.max_arity = 2;
+        let ast = Parser::default().parse_str("🜙add🜄x🜌y🜂x+y;").unwrap();
+        assert!(codegen.codegen(&ast).is_ok());
+        assert_eq!(run("🜙add🜄x🜌y🜂x+y;add🜄1🜌2🜂;", AngleUnit::Radians), 3.0);
+    }
+
+    #[test]
+    fn codegen_can_be_called_again() {
+        let parse = |source: &str| Parser::default().parse_str(source).unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        let summary = codegen.codegen(&parse("🜙sq🜄x🜂x*x;sq🜄2🜂;")).unwrap();
+        assert_eq!(summary.functions, ["sq"]);
+        assert_eq!(summary.entries, ["lambda"]);
+        assert!(codegen.module.verify().is_ok());
+        assert!(codegen.named_values.is_empty());
+        assert!(codegen.builder.get_insert_block().is_none());
+
+        // x was sq's parameter, it isn't in scope for the next call
+        assert!(matches!(
+            codegen.codegen(&parse("x;")),
+            Err(CodegenError::UnknownVariable(name)) if name == "x"
+        ));
+        let summary = codegen
+            .codegen(&parse("🜙sq🜄x🜂x;🜙cube🜄x🜂x*sq🜄x🜂;cube🜄3🜂;sq🜄3🜂;"))
+            .unwrap();
+        assert_eq!(summary.functions, ["cube"]);
+        // llvm numbers the entries, so they never collide with earlier ones
+        let symbols: Vec<&str> = codegen.entries.iter().map(|(s, _)| s.as_str()).collect();
+        assert_eq!(symbols.len(), 3);
+        assert!(!summary.entries.contains(&"lambda".to_string()));
+        assert_eq!(summary.entries, symbols[1..]);
+        assert!(codegen.module.verify().is_ok());
+
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        let results: Vec<_> = engine
+            .run_all(40)
+            .into_iter()
+            .map(|(_, value)| value.unwrap())
+            .collect();
+        assert_eq!(results, [4.0, 27.0, 9.0]);
+    }
+
+    #[test]
+    fn codegen_all_works() {
+        let source = "🜙f🜄x🜂nope🜄x🜂;🜙g🜄x🜂x + y;🜙h🜄x🜂f🜄x🜂 + 1;🜙k🜄x🜂x*2;k🜄2🜂;h🜄1🜂;";
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        let ast = Parser::default().parse_str(source).unwrap();
+        let (summary, errors) = codegen.codegen_all(&ast, &[]);
+        assert_eq!(summary.functions, ["k"]);
+        assert_eq!(summary.entries, ["lambda"]);
+        let errors: Vec<String> = errors.iter().map(|e| e.diagnostic().to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "error[codegen]: f: unknown function nope",
+                "error[codegen]: g: unknown variable referenced y",
+                "note[codegen]: h: not compiled because f failed to compile",
+                "note[codegen]: top level expression 2: not compiled because h failed to compile",
+            ]
+        );
+        assert!(codegen.module.verify().is_ok());
+    }
+
+    #[test]
+    fn failed_bodies_are_discarded() {
+        let source = "🜙one🜄🜂1;🜙bad🜄x🜂🜄x*x🜂 + y;🜙two🜄🜂one🜄🜂 + 1;";
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        let results: Vec<_> = Parser::default()
+            .parse_str(source)
+            .unwrap()
+            .iter()
+            .map(|node| codegen.codegen_node(node))
+            .collect();
+        assert!(results[0].is_ok());
+        assert!(matches!(&results[1], Err(CodegenError::UnknownVariable(name)) if name == "y"));
+        assert!(results[2].is_ok());
+        assert!(codegen.module.get_function("bad").is_none());
+        assert!(codegen.module.verify().is_ok());
+
+        let ast = Parser::default().parse_str("bad🜄2🜂;x;").unwrap();
+        assert!(matches!(
+            codegen.codegen_node(&ast[0]),
+            Err(CodegenError::UnknownFunction(_))
+        ));
+        assert!(matches!(
+            codegen.codegen_node(&ast[1]),
+            Err(CodegenError::UnknownVariable(_))
+        ));
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        assert_eq!(engine.call("two", &[]), Ok(2.0));
+    }
+
+    #[test]
+    fn call_arity_works() {
+        let source = r#"🜹mypow🜄x🜌y🜂 = "pow";🜙f🜄x🜂x;🜙f🜄x🜌y🜂y;"#;
+        let ast = Parser::default().parse_str(source).unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        for (call, name, expected, found) in [
+            ("f🜄1🜌2🜂;", "f", 1, 2),
+            ("mypow🜄1🜂;", "mypow", 2, 1),
+            ("pi🜄1🜂;", "pi", 0, 1),
+        ] {
+            let ast = Parser::default().parse_str(call).unwrap();
+            assert!(matches!(
+                codegen.codegen(&ast),
+                Err(CodegenError::InvalidCall(n, e, f)) if n == name && e == expected && f == found
+            ));
+        }
+        assert_eq!(run("🜙f🜄x🜂x;🜙f🜄x🜌y🜂y;f🜄4🜂;", AngleUnit::Radians), 4.0);
+    }
+
+    /// the number of float arithmetic instructions source compiles to
+    fn arithmetic(source: &str, peephole: bool, fast_math: bool) -> usize {
+        let ast = Parser::default().parse_str(source).unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.peephole = peephole;
+        codegen.fast_math = fast_math;
+        codegen.codegen(&ast).unwrap();
+        let ir = codegen.module.print_to_string().to_string();
+        ir.lines()
+            .filter(|line| {
+                ["fadd", "fsub", "fmul", "fdiv"]
+                    .iter()
+                    .any(|op| line.contains(&format!("= {} ", op)))
+            })
+            .count()
+    }
+
+    #[test]
+    fn intrinsics_work() {
+        let source = "🜙lim🜄x🜂clamp🜄x🜌0🜌1🜂;\
+                      min🜄2🜌3🜂;max🜄2🜌3🜂;abs🜄0 - 3🜂;lim🜄2🜂;lim🜄0.5🜂;\
+                      min🜄nan🜄🜂🜌1🜂;max🜄2🜌nan🜄🜂🜂;lim🜄nan🜄🜂🜂;abs🜄nan🜄🜂🜂;";
+        let ast = Parser::default().parse_str(source).unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        let ir = codegen.module.print_to_string().to_string();
+        assert!(ir.contains("call double @llvm.maxnum.f64(double %x, double 0.000000e+00)"));
+        assert!(ir.contains("call double @llvm.minnum.f64"));
+        assert!(ir.contains("call double @llvm.fabs.f64"));
+        assert!(!ir.contains(" br ") && !ir.contains("select"));
+
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        let results: Vec<f64> = engine
+            .run_all(40)
+            .into_iter()
+            .map(|(_, value)| value.unwrap())
+            .collect();
+        assert_eq!(results[..8], [2.0, 3.0, 3.0, 1.0, 0.5, 1.0, 2.0, 0.0]);
+        assert!(results[8].is_nan());
+
+        // a definition of its own replaces the builtin
+        assert_eq!(run("🜙abs🜄x🜂x;abs🜄0 - 3🜂;", AngleUnit::Radians), -3.0);
+    }
+
+    #[test]
+    fn peephole_works() {
+        // the body, then the instruction count when strict and with fast math
+        let cases = [
+            ("x * 1", 0, 0),
+            ("1 * x", 0, 0),
+            ("x + 0", 1, 0),
+            ("0 + x", 1, 0),
+            ("x + -0", 0, 0),
+            ("x - 0", 0, 0),
+            ("x - -0", 1, 0),
+            ("0 - x", 1, 1),
+            ("x / 1", 0, 0),
+            ("1 / x", 1, 1),
+            ("x * 2", 1, 1),
+            ("2 * 🜄x + 1🜂", 2, 2),
+            ("x * 0", 1, 0),
+            ("0 * sin🜄x🜂", 1, 0),
+            ("sin🜄1🜂 * 0", 1, 0),
+            ("🜄sin🜄1🜂 + 2🜂 * 0", 0, 0),
+            ("printd🜄x🜂 * 0", 1, 1),
+        ];
+        for (body, strict, fast) in cases.iter() {
+            let source = format!("🜹sin🜄x🜂;🜹printd🜄x🜂;🜙f🜄x🜂{};", body);
+            assert_eq!(arithmetic(&source, true, false), *strict, "{}", body);
+            assert_eq!(arithmetic(&source, true, true), *fast, "{}", body);
+        }
+        assert_eq!(arithmetic("🜙f🜄x🜂x * 1;", false, false), 1);
+
+        let context = Context::create();
+        let mut outputs = Vec::new();
+        for peephole in [false, true].iter() {
+            // the parser nests to the right, so the outer ops need parens
+            let ast = Parser::default()
+                .parse_str(
+                    "🜹sin🜄x🜂;🜙f🜄x🜂🜄x * 1🜂 + 0;🜙g🜄x🜂🜄2 * x🜂 - 0;🜙h🜄x🜂🜄x * 0🜂 / 1;\
+                     🜙i🜄x🜂0 + 🜄x / 1🜂;🜙j🜄x🜂🜄x + -0🜂 - -0;\
+                     🜙k🜄x🜂🜄sin🜄1🜂 - 2🜂 * 0 + x * 0;🜙l🜄x🜂🜄sin🜄1🜂 + 2🜂 * -0 - x;",
+                )
+                .unwrap();
+            let mut codegen = Codegen::new(&context);
+            codegen.peephole = *peephole;
+            codegen.codegen(&ast).unwrap();
+            let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+            let mut values = Vec::new();
+            for name in ["f", "g", "h", "i", "j", "k", "l"].iter() {
+                for x in [1.5, -1.5, 0.0, -0.0, f64::INFINITY, f64::NAN].iter() {
+                    values.push(engine.call(name, &[*x]).unwrap().to_string());
+                }
+            }
+            outputs.push(values);
+        }
+        assert_eq!(outputs[0], outputs[1]);
+    }
 }
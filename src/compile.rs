@@ -0,0 +1,952 @@
+use std::cell::RefCell;
+
+use inkwell::context::Context;
+use inkwell::execution_engine::ExecutionEngine;
+use inkwell::memory_buffer::MemoryBuffer;
+use inkwell::module::Module;
+use inkwell::passes::{PassManager, PassManagerBuilder};
+use inkwell::{AddressSpace, IntPredicate};
+
+use crate::ast::ASTNode;
+use crate::codegen::{Codegen, CodegenError, Failures, ItemError};
+use crate::diagnostic::{Diagnostic, Sink};
+use crate::engine::{self, Engine, EngineError, EngineKind, OptLevel};
+use crate::lexer;
+use crate::parser::{ParserError, SyntaxError};
+use crate::plan::{self, CompileOptions, Plan, PlanError};
+use crate::resolve::Resolver;
+use crate::runtime;
+use crate::span::LineIndex;
+use crate::table::{self, LoadTables};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CompileError {
+    #[error(transparent)]
+    Plan(#[from] PlanError),
+    #[error(transparent)]
+    Parse(#[from] ParserError),
+    #[error(transparent)]
+    Codegen(#[from] CodegenError),
+    /// a top level expression didn't compile, with everything else that
+    /// didn't
+    #[error("{}", lines(.0))]
+    Items(Vec<ItemError>),
+    /// every item of a streamed source that didn't parse, when more than
+    /// one didn't - streaming keeps no token positions to say where
+    #[error("{path}: {} syntax errors{}", .errors.len(), list_errors(.errors))]
+    Syntax {
+        path: String,
+        errors: Vec<ParserError>,
+    },
+}
+
+fn list_errors(errors: &[ParserError]) -> String {
+    errors.iter().map(|err| format!("\n  {}", err)).collect()
+}
+
+fn lines(errors: &[ItemError]) -> String {
+    let lines: Vec<String> = errors
+        .iter()
+        .map(|err| match &err.span {
+            Some(span) => format!("{}: {}", span, err),
+            None => err.to_string(),
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// every failure rendered like the rest of the diagnostics and fitted to
+/// width, at where its item was written when that's known, with the line
+/// from sources it starts on
+pub fn render_failures(failures: &[ItemError], sources: &[(&str, &str)], width: usize) -> String {
+    let mut sink = Sink::default();
+    sink.width = width;
+    for failure in failures {
+        let span = match &failure.span {
+            Some(span) => span,
+            None => {
+                sink.report("", failure.diagnostic());
+                continue;
+            }
+        };
+        let line = sources
+            .iter()
+            .find(|(path, _)| *path == span.path)
+            .and_then(|(_, source)| LineIndex::new(source).line(span.start.line));
+        match line {
+            Some(line) => sink.report_in_line(
+                &span.to_string(),
+                failure.diagnostic(),
+                line,
+                span.start.column as usize,
+            ),
+            None => sink.report(&span.to_string(), failure.diagnostic()),
+        }
+    }
+    sink.render()
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SymbolKind {
+    Function,
+    Extern,
+    Table,
+}
+
+/// something a program defines, for outlines and symbol lists
+#[derive(Debug, PartialEq, Clone)]
+pub struct SymbolInfo {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub params: Vec<String>,
+}
+
+/// what node defines, if it's something symbol lists show
+fn symbol(node: &ASTNode) -> Option<SymbolInfo> {
+    match node {
+        ASTNode::Function(func) if func.prototype.name == "lambda" => None,
+        ASTNode::Function(func) => Some(SymbolInfo {
+            name: func.prototype.name.clone(),
+            kind: SymbolKind::Function,
+            params: func.prototype.args.clone(),
+        }),
+        ASTNode::Extern(proto) => Some(SymbolInfo {
+            name: proto.name.clone(),
+            kind: SymbolKind::Extern,
+            params: proto.args.clone(),
+        }),
+        ASTNode::Table(table) => Some(SymbolInfo {
+            name: table.name.clone(),
+            kind: SymbolKind::Table,
+            params: vec!["i".to_string()],
+        }),
+        // configure has already dropped or unwrapped every conditional item
+        ASTNode::When(..) => None,
+        ASTNode::InlineIr(_) | ASTNode::Test(_) => None,
+    }
+}
+
+/// everything one compile produces, so tools can show the ast, diagnostics
+/// and ir of a program and then run it without compiling it again
+pub struct Compilation<'ctx> {
+    /// the path and text of each source, in order
+    sources: Vec<(String, String)>,
+    ast: Vec<ASTNode>,
+    diagnostics: Vec<Diagnostic>,
+    failures: Vec<ItemError>,
+    symbols: Vec<SymbolInfo>,
+    /// how many definitions the passes turned into calls to another
+    merged: usize,
+    codegen: Codegen<'ctx>,
+}
+
+/// plan the sources with the options and codegen them into codegen, whose
+/// settings are kept
+///
+/// ```
+/// use inkwell::context::Context;
+/// use wizarding::{codegen::Codegen, compile, plan::CompileOptions};
+///
+/// let context = Context::create();
+/// let source = "🜙double🜄x🜂x*2;double🜄4🜂;";
+/// let compilation = compile::compile(
+///     Codegen::new(&context),
+///     &[("double.wiz", source)],
+///     &mut CompileOptions::default(),
+/// )
+/// .unwrap();
+/// assert_eq!(compilation.ast().len(), 2);
+/// assert_eq!(compilation.symbols()[0].name, "double");
+/// assert!(compilation.ir().contains("define double @double(double %x)"));
+/// ```
+pub fn compile<'ctx>(
+    codegen: Codegen<'ctx>,
+    sources: &[(&str, &str)],
+    options: &mut CompileOptions,
+) -> Result<Compilation<'ctx>, CompileError> {
+    let plan = Plan::from_sources(sources, options)?;
+    Compilation::new(codegen, plan)
+}
+
+impl<'ctx> Compilation<'ctx> {
+    /// codegen a plan that was already made, like one that was printed first,
+    /// reporting every item that fails - those only fail the whole compile
+    /// when a top level expression needs them
+    pub fn new(mut codegen: Codegen<'ctx>, plan: Plan) -> Result<Compilation<'ctx>, CompileError> {
+        let (summary, failures) = codegen.codegen_all(&plan.ast, &plan.item_spans());
+        if summary.entries.len() < plan.entries() {
+            return Err(CompileError::Items(failures));
+        }
+        let mut diagnostics = plan.diagnostics();
+        diagnostics.extend(failures.iter().map(ItemError::diagnostic));
+        Ok(Compilation {
+            sources: plan
+                .files
+                .into_iter()
+                .map(|file| (file.path, file.source))
+                .collect(),
+            symbols: plan.ast.iter().filter_map(symbol).collect(),
+            diagnostics,
+            failures,
+            merged: plan.merged,
+            ast: plan.ast,
+            codegen,
+        })
+    }
+
+    /// codegen each item as soon as it is lexed and parsed, without
+    /// planning, and drop it once it's compiled - only its symbol is kept,
+    /// so the ast of the compilation is empty. an item that doesn't parse
+    /// stops the codegen, but the rest of its source is still parsed to
+    /// report every syntax error in it. items that fail to compile are
+    /// reported like new does, and only fail the compile when a top level
+    /// expression does. items are parsed with the parser options of options
+    /// and resolved against the ones before them, making implicit calls if
+    /// options says to, and tables are loaded with tables, but nothing is
+    /// configured, derived or linted and the passes of options aren't run
+    pub fn streaming(
+        mut codegen: Codegen<'ctx>,
+        sources: &[(&str, &str)],
+        options: &CompileOptions,
+        tables: &LoadTables,
+    ) -> Result<Compilation<'ctx>, CompileError> {
+        let parser = plan::parser(options);
+        let mut resolver = Resolver::default();
+        resolver.implicit_calls = options.implicit_calls;
+        let mut symbols = Vec::new();
+        let entries = codegen.entries.len();
+        let mut failures = Failures::default();
+        for (name, source) in sources {
+            let mut errors = Vec::new();
+            for node in parser.parse_items(lexer::Lexer::new(source)) {
+                let mut node = match node {
+                    Ok(node) => node,
+                    Err(err) => {
+                        errors.push(err);
+                        continue;
+                    }
+                };
+                // the rest is only parsed, for its errors
+                if !errors.is_empty() {
+                    continue;
+                }
+                resolver.resolve(&mut node).map_err(PlanError::from)?;
+                if let ASTNode::Table(table) = &mut node {
+                    table::load_table(table, tables.loader.as_ref(), tables.format)
+                        .map_err(|err| PlanError::Pass(err.into()))?;
+                }
+                codegen.codegen_item(&node, None, &mut failures);
+                symbols.extend(symbol(&node));
+            }
+            match errors.len() {
+                0 => (),
+                1 => {
+                    return Err(match errors.remove(0) {
+                        ParserError::Lex(err) => {
+                            PlanError::ParseIn(name.to_string(), SyntaxError::lex(source, err))
+                                .into()
+                        }
+                        err => err.into(),
+                    })
+                }
+                _ => {
+                    return Err(CompileError::Syntax {
+                        path: name.to_string(),
+                        errors,
+                    })
+                }
+            }
+        }
+        if codegen.entries.len() - entries < failures.entries() {
+            return Err(CompileError::Items(failures.errors));
+        }
+        Ok(Compilation {
+            sources: sources
+                .iter()
+                .map(|(name, source)| (name.to_string(), source.to_string()))
+                .collect(),
+            symbols,
+            ast: Vec::new(),
+            diagnostics: failures.errors.iter().map(ItemError::diagnostic).collect(),
+            failures: failures.errors,
+            merged: 0,
+            codegen,
+        })
+    }
+
+    pub fn sources(&self) -> &[(String, String)] {
+        &self.sources
+    }
+
+    /// the planned ast, empty when the compilation was streamed
+    pub fn ast(&self) -> &[ASTNode] {
+        &self.ast
+    }
+
+    /// the notes of the passes and about unchecked externs, the lint
+    /// warnings and the failures
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// the items that didn't compile, which nothing that runs needs
+    pub fn failures(&self) -> &[ItemError] {
+        &self.failures
+    }
+
+    pub fn symbols(&self) -> &[SymbolInfo] {
+        &self.symbols
+    }
+
+    /// how many definitions merge-functions turned into calls to another
+    pub fn merged(&self) -> usize {
+        self.merged
+    }
+
+    pub fn module(&self) -> &Module<'ctx> {
+        &self.codegen.module
+    }
+
+    /// the llvm ir of the module, only rendered when asked for
+    pub fn ir(&self) -> String {
+        self.codegen.module.print_to_string().to_string()
+    }
+
+    /// the llvm ir of a copy of the module after llvm's standard pipeline at
+    /// opt, which shows what the optimizer makes of it - the jit only
+    /// optimizes while generating machine code, so it never runs this
+    pub fn optimized_ir(&self, opt: OptLevel) -> String {
+        let _span = tracing::info_span!("optimize", opt = ?opt).entered();
+        let module = self.codegen.module.clone();
+        optimize(&module, opt);
+        module.print_to_string().to_string()
+    }
+
+    /// the module as llvm bitcode
+    pub fn bitcode(&self) -> Vec<u8> {
+        self.codegen
+            .module
+            .write_bitcode_to_memory()
+            .as_slice()
+            .to_vec()
+    }
+
+    /// link a library's bitcode into the module, so the externs of its
+    /// interface resolve to it - its own top level expressions are dropped,
+    /// and a library that can trap is refused, since its trap sites
+    /// index a codegen that isn't this one
+    pub fn link_bitcode(&self, bitcode: &[u8]) -> Result<(), String> {
+        let buffer = MemoryBuffer::create_from_memory_range_copy(bitcode, "library");
+        let library = Module::parse_bitcode_from_buffer(&buffer, self.codegen.context)
+            .map_err(|err| err.to_string())?;
+        if library.get_function(runtime::TRAP).is_some() {
+            return Err(
+                "the library was compiled with --check-bounds or --check-overflow".to_string(),
+            );
+        }
+        let entries: Vec<_> = library
+            .get_functions()
+            .filter(|function| {
+                let name = function.get_name().to_string_lossy();
+                name == "lambda" || name.starts_with("lambda.")
+            })
+            .collect();
+        for entry in entries {
+            unsafe { entry.delete() };
+        }
+        self.codegen
+            .module
+            .link_in_module(library)
+            .map_err(|err| err.to_string())
+    }
+
+    pub fn into_program(self) -> CompiledProgram<'ctx> {
+        CompiledProgram {
+            codegen: self.codegen,
+            batches: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+/// run llvm's standard pipeline at opt over module
+fn optimize(module: &Module, opt: OptLevel) {
+    let builder = PassManagerBuilder::create();
+    builder.set_optimization_level(opt.into());
+    let passes = PassManager::<Module>::create(());
+    builder.populate_module_pass_manager(&passes);
+    passes.run_on(module);
+}
+
+/// the level map_unary optimizes its copy of the module at
+const BATCH_OPT: OptLevel = OptLevel::Default;
+
+/// a jit for a copy of the module that map_unary calls one function through
+struct Batch<'ctx> {
+    name: String,
+    /// the address of the name__batch wrapper, or of the function itself when
+    /// the wrapper couldn't be made
+    address: usize,
+    batched: bool,
+    // the engine goes before the module it runs
+    _engine: ExecutionEngine<'ctx>,
+    _module: Module<'ctx>,
+}
+
+type BatchFn = unsafe extern "C" fn(*const f64, *mut f64, i64);
+type ScalarFn = unsafe extern "C" fn(f64) -> f64;
+
+/// add wrapper to module, a function of an input and output pointer and a
+/// count that stores name of each input to the output at the same index -
+/// false if it can't be
+fn build_batch(context: &Context, module: &Module, name: &str, wrapper: &str) -> bool {
+    let callee = match module.get_function(name) {
+        Some(callee) if module.get_function(wrapper).is_none() => callee,
+        _ => return false,
+    };
+    let f64_type = context.f64_type();
+    let i64_type = context.i64_type();
+    let ptr_type = f64_type.ptr_type(AddressSpace::Generic);
+    let fn_type = context
+        .void_type()
+        .fn_type(&[ptr_type.into(), ptr_type.into(), i64_type.into()], false);
+    let func = module.add_function(wrapper, fn_type, None);
+    let input = func.get_nth_param(0).unwrap().into_pointer_value();
+    let output = func.get_nth_param(1).unwrap().into_pointer_value();
+    let count = func.get_nth_param(2).unwrap().into_int_value();
+
+    let builder = context.create_builder();
+    let entry = context.append_basic_block(func, "entry");
+    let body = context.append_basic_block(func, "loop");
+    let done = context.append_basic_block(func, "done");
+    builder.position_at_end(entry);
+    let zero = i64_type.const_int(0, false);
+    let empty = builder.build_int_compare(IntPredicate::SLE, count, zero, "empty");
+    builder.build_conditional_branch(empty, done, body);
+
+    builder.position_at_end(body);
+    let i = builder.build_phi(i64_type, "i");
+    let index = i.as_basic_value().into_int_value();
+    let x = unsafe { builder.build_in_bounds_gep(input, &[index], "x") };
+    let x = builder.build_load(x, "tmpx");
+    let y = builder
+        .build_call(callee, &[x], "tmpy")
+        .try_as_basic_value()
+        .left()
+        .unwrap();
+    let out = unsafe { builder.build_in_bounds_gep(output, &[index], "out") };
+    builder.build_store(out, y);
+    let next = builder.build_int_add(index, i64_type.const_int(1, false), "next");
+    let more = builder.build_int_compare(IntPredicate::SLT, next, count, "more");
+    builder.build_conditional_branch(more, body, done);
+    i.add_incoming(&[(&zero, entry), (&next, body)]);
+
+    builder.position_at_end(done);
+    builder.build_return(None);
+    if func.verify(false) {
+        return true;
+    }
+    unsafe { func.delete() };
+    false
+}
+
+/// a compiled program that is ready to run
+pub struct CompiledProgram<'ctx> {
+    codegen: Codegen<'ctx>,
+    /// what map_unary made for each function it was asked to run
+    batches: RefCell<Vec<Batch<'ctx>>>,
+}
+
+impl<'ctx> CompiledProgram<'ctx> {
+    /// a program from what Compilation::bitcode wrote, like a session
+    /// snapshot keeps - it has no top level expressions to run
+    pub fn from_bitcode(
+        context: &'ctx Context,
+        bitcode: &[u8],
+    ) -> Result<CompiledProgram<'ctx>, String> {
+        let buffer = MemoryBuffer::create_from_memory_range_copy(bitcode, "bitcode");
+        let module =
+            Module::parse_bitcode_from_buffer(&buffer, context).map_err(|err| err.to_string())?;
+        let mut codegen = Codegen::new(context);
+        codegen.module = module;
+        Ok(CompiledProgram {
+            codegen,
+            batches: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn module(&self) -> &Module<'ctx> {
+        &self.codegen.module
+    }
+
+    /// an engine of the given kind, flushing denormals if the program was
+    /// compiled to
+    pub fn engine(&self, kind: EngineKind, opt: OptLevel) -> Result<Engine<'_>, EngineError> {
+        let mut engine = Engine::new(&self.codegen, kind, opt)?;
+        engine.denormals_are_zero = self.codegen.denormals_are_zero;
+        Ok(engine)
+    }
+
+    /// call the unary function name on each of inputs, storing the results
+    /// to outputs - one call runs a name__batch wrapper with the loop in ir,
+    /// which llvm can inline the body into and vectorize, made the first time
+    /// name is mapped in a jit for a copy of the module, so externs link
+    /// like in a new engine
+    ///
+    /// when the wrapper can't be made, because the program defines a
+    /// function by that name say, each input is a call of its own instead
+    pub fn map_unary(
+        &self,
+        name: &str,
+        inputs: &[f64],
+        outputs: &mut [f64],
+    ) -> Result<(), EngineError> {
+        if inputs.len() != outputs.len() {
+            return Err(EngineError::LengthMismatch(inputs.len(), outputs.len()));
+        }
+        engine::checked_function(
+            &self.codegen.module,
+            self.codegen.context.f64_type(),
+            name,
+            1,
+        )?;
+        let _span = tracing::debug_span!("map_unary", name, len = inputs.len()).entered();
+        let mut batches = self.batches.borrow_mut();
+        let batch = match batches.iter().position(|batch| batch.name == name) {
+            Some(i) => &batches[i],
+            None => {
+                batches.push(self.batch(name)?);
+                batches.last().unwrap()
+            }
+        };
+
+        let saved_csr = match self.codegen.denormals_are_zero {
+            true => Some(engine::set_denormals_are_zero()),
+            false => None,
+        };
+        unsafe {
+            if batch.batched {
+                let batch: BatchFn = std::mem::transmute(batch.address);
+                batch(inputs.as_ptr(), outputs.as_mut_ptr(), inputs.len() as i64);
+            } else {
+                let scalar: ScalarFn = std::mem::transmute(batch.address);
+                for (input, output) in inputs.iter().zip(outputs) {
+                    *output = scalar(*input);
+                }
+            }
+        }
+        if let Some(csr) = saved_csr {
+            engine::restore_csr(csr);
+        }
+        Ok(())
+    }
+
+    /// a jit for a copy of the module with name__batch added and optimized,
+    /// or for the copy as it is when the wrapper can't be added
+    fn batch(&self, name: &str) -> Result<Batch<'ctx>, EngineError> {
+        let module = self.codegen.module.clone();
+        let wrapper = format!("{}__batch", name);
+        let batched = build_batch(self.codegen.context, &module, name, &wrapper);
+        if batched {
+            optimize(&module, BATCH_OPT);
+        } else {
+            tracing::debug!(name, "calling each input on its own");
+        }
+        let engine = module
+            .create_jit_execution_engine(BATCH_OPT.into())
+            .map_err(|e| EngineError::Create(e.to_string()))?;
+        let symbol = if batched { wrapper.as_str() } else { name };
+        let address = engine
+            .get_function_address(symbol)
+            .map_err(|_| EngineError::UnknownFunction(symbol.to_string()))?;
+        Ok(Batch {
+            name: name.to_string(),
+            address,
+            batched,
+            _engine: engine,
+            _module: module,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use inkwell::context::Context;
+    use tracing::{
+        span::{Attributes, Id, Record},
+        Event, Metadata, Subscriber,
+    };
+
+    use super::*;
+    use crate::diagnostic::Severity;
+    use crate::lint::Lint;
+    use crate::table::{Loader, TableFormat};
+
+    /// serves the same csv for every table
+    struct Csv(&'static str);
+
+    impl Loader for Csv {
+        fn load(&self, _: &str) -> std::io::Result<Vec<u8>> {
+            Ok(self.0.as_bytes().to_vec())
+        }
+    }
+
+    #[test]
+    fn compile_works() {
+        let context = Context::create();
+        let source = "🜹sqrt🜄x🜂;🜙hyp🜄a🜌b🜂sqrt🜄🜄a*a🜂 + b*b🜂;sqrt🜄0-1🜂;hyp🜄3🜌4🜂;";
+        let mut options = CompileOptions {
+            lints: vec![Lint::DomainCheck],
+            ..CompileOptions::default()
+        };
+        let compilation =
+            compile(Codegen::new(&context), &[("hyp.wiz", source)], &mut options).unwrap();
+        assert_eq!(compilation.ast().len(), 4);
+        assert_eq!(
+            compilation.symbols(),
+            [
+                SymbolInfo {
+                    name: "sqrt".to_string(),
+                    kind: SymbolKind::Extern,
+                    params: vec!["x".to_string()],
+                },
+                SymbolInfo {
+                    name: "hyp".to_string(),
+                    kind: SymbolKind::Function,
+                    params: vec!["a".to_string(), "b".to_string()],
+                },
+            ]
+        );
+        assert_eq!(compilation.diagnostics().len(), 1);
+        assert_eq!(compilation.diagnostics()[0].severity, Severity::Warning);
+        let ir = compilation.ir();
+        assert!(ir.contains("define double @hyp(double %a, double %b)"));
+        assert_eq!(ir, compilation.module().print_to_string().to_string());
+        let symbols = compilation.symbols().to_vec();
+
+        let program = compilation.into_program();
+        let engine = program.engine(EngineKind::Jit, OptLevel::None).unwrap();
+        assert_eq!(engine.call("hyp", &[3.0, 4.0]), Ok(5.0));
+
+        let tables = LoadTables {
+            loader: Box::new(Csv("4, 5")),
+            format: TableFormat::Csv,
+        };
+        let streamed = Compilation::streaming(
+            Codegen::new(&context),
+            &[("hyp.wiz", source)],
+            &options,
+            &tables,
+        )
+        .unwrap();
+        assert_eq!(streamed.ir(), ir);
+        assert!(streamed.ast().is_empty());
+        assert_eq!(streamed.symbols(), symbols);
+        let source = r#"table t from "t.csv";t🜄1🜂;"#;
+        let streamed = Compilation::streaming(
+            Codegen::new(&context),
+            &[("t.wiz", source)],
+            &options,
+            &tables,
+        );
+        let program = streamed.unwrap().into_program();
+        let engine = program.engine(EngineKind::Jit, OptLevel::None).unwrap();
+        assert_eq!(engine.run_all(10)[0].1, Ok(5.0));
+        assert!(matches!(
+            compile(Codegen::new(&context), &[("bad.wiz", "1 +")], &mut options),
+            Err(CompileError::Plan(_))
+        ));
+    }
+
+    #[test]
+    fn streaming_reports_every_syntax_error() {
+        let context = Context::create();
+        let tables = LoadTables {
+            loader: Box::new(Csv("")),
+            format: TableFormat::Csv,
+        };
+        let options = CompileOptions::default();
+        let stream = |source| {
+            Compilation::streaming(
+                Codegen::new(&context),
+                &[("s.wiz", source)],
+                &options,
+                &tables,
+            )
+        };
+        let err = stream("🜙f🜄x🜂x + * 2;1;🜙g x;2;").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "s.wiz: 2 syntax errors\n  expected an expression, found '*'\n  expected '🜄', found 'x'"
+        );
+        // a lone lex error still says where it is
+        let err = stream("1;\"x").err().unwrap();
+        assert_eq!(err.to_string(), "s.wiz:1:3: unterminated string");
+    }
+
+    #[test]
+    fn every_failure_is_reported() {
+        let context = Context::create();
+        let source = "🜙f🜄x🜂nope🜄x🜂;🜙g🜄x🜂missing🜄x🜂;🜙h🜄x🜂f🜄x🜂 + 1;🜙k🜄x🜂x*2;k🜄2🜂;";
+        let compile_source = |source: &str| {
+            let sources = [("broken.wiz", source)];
+            compile(
+                Codegen::new(&context),
+                &sources,
+                &mut CompileOptions::default(),
+            )
+        };
+        let compilation = compile_source(source).unwrap();
+        let items: Vec<&str> = compilation
+            .failures()
+            .iter()
+            .map(|failure| failure.item.as_str())
+            .collect();
+        assert_eq!(items, ["f", "g", "h"]);
+        let spans: Vec<String> = compilation
+            .failures()
+            .iter()
+            .map(|failure| failure.span.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            spans,
+            ["broken.wiz:1:1", "broken.wiz:1:14", "broken.wiz:1:30"]
+        );
+        let severities: Vec<Severity> = compilation
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.severity)
+            .collect();
+        assert_eq!(
+            severities,
+            [Severity::Error, Severity::Error, Severity::Note]
+        );
+        assert_eq!(
+            compilation.diagnostics()[2].message,
+            "h: not compiled because f failed to compile"
+        );
+        let program = compilation.into_program();
+        let engine = program.engine(EngineKind::Jit, OptLevel::None).unwrap();
+        assert_eq!(engine.call("k", &[2.0]), Ok(4.0));
+
+        // streaming reports the same failures, just without spans
+        let tables = LoadTables {
+            loader: Box::new(Csv("")),
+            format: TableFormat::Csv,
+        };
+        let options = CompileOptions::default();
+        let stream = |source| {
+            Compilation::streaming(
+                Codegen::new(&context),
+                &[("broken.wiz", source)],
+                &options,
+                &tables,
+            )
+        };
+        let streamed = stream(source).unwrap();
+        let failures: Vec<String> = streamed
+            .failures()
+            .iter()
+            .map(ItemError::to_string)
+            .collect();
+        assert_eq!(
+            failures,
+            [
+                "f: unknown function nope",
+                "g: unknown function missing",
+                "h: not compiled because f failed to compile"
+            ]
+        );
+        assert_eq!(streamed.diagnostics().len(), 3);
+
+        // an entry that needs h can't run
+        let with_entry = format!("{}h🜄1🜂;", source);
+        assert!(matches!(
+            stream(&with_entry).err().unwrap(),
+            CompileError::Items(failures) if failures.len() == 4
+        ));
+        let err = compile_source(&with_entry).err().unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("broken.wiz:1:1: f: unknown function nope\n"));
+        let failures = match err {
+            CompileError::Items(failures) => failures,
+            err => panic!("{}", err),
+        };
+        assert_eq!(failures.len(), 4);
+        let rendered = render_failures(&failures, &[("broken.wiz", &with_entry)], 60);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(
+            lines[..3],
+            [
+                "broken.wiz:1:1: error[codegen]: f: unknown function nope",
+                "🜙f🜄x🜂nope🜄x🜂;🜙g🜄x🜂missing🜄x🜂;🜙h🜄x🜂f🜄x🜂 + 1;🜙k🜄x🜂x*2;k🜄2🜂;h🜄…",
+                "^",
+            ]
+        );
+        // g starts 13 columns in
+        assert_eq!(lines[4], format!("{}^", " ".repeat(13)));
+        assert!(rendered.ends_with("2 errors, 0 warnings, 2 notes"));
+
+        // bad inline ir is reported where its block is, with llvm's message
+        let mut options = CompileOptions {
+            allow_inline_ir: true,
+            ..CompileOptions::default()
+        };
+        let sources = [("ir.wiz", "1;\n  llvm \"define double\";")];
+        let compilation = compile(Codegen::new(&context), &sources, &mut options).unwrap();
+        let failure = &compilation.failures()[0];
+        assert_eq!(failure.span.as_ref().unwrap().to_string(), "ir.wiz:2:3");
+        assert!(matches!(&failure.error, CodegenError::InvalidIr(_)));
+    }
+
+    /// the name of every span opened with the index of the one it's in, and
+    /// the spans that are entered now, innermost last
+    #[derive(Default)]
+    struct Spans {
+        opened: Vec<(&'static str, Option<usize>)>,
+        entered: Vec<usize>,
+    }
+
+    impl Spans {
+        /// the names of the span and every span it's in, outermost first
+        fn path(&self, mut i: usize) -> String {
+            let mut names = vec![self.opened[i].0];
+            while let Some(parent) = self.opened[i].1 {
+                names.push(self.opened[parent].0);
+                i = parent;
+            }
+            names.reverse();
+            names.join("/")
+        }
+    }
+
+    /// a subscriber that collects the spans opened while it's the default
+    struct Collect(Arc<Mutex<Spans>>);
+
+    impl Subscriber for Collect {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.0.lock().unwrap();
+            let parent = match span.parent() {
+                Some(parent) => Some(parent.into_u64() as usize - 1),
+                None if span.is_contextual() => spans.entered.last().copied(),
+                None => None,
+            };
+            spans.opened.push((span.metadata().name(), parent));
+            Id::from_u64(spans.opened.len() as u64)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, span: &Id) {
+            let index = span.into_u64() as usize - 1;
+            self.0.lock().unwrap().entered.push(index);
+        }
+
+        fn exit(&self, _: &Id) {
+            self.0.lock().unwrap().entered.pop();
+        }
+    }
+
+    #[test]
+    fn map_unary_matches_calls() {
+        let context = Context::create();
+        let source = "🜹sin🜄x🜂;🜙wave🜄t🜂sin🜄t*2🜂*t + 1;🜙add🜄a🜌b🜂a+b;";
+        let program = compile(
+            Codegen::new(&context),
+            &[("wave.wiz", source)],
+            &mut CompileOptions::default(),
+        )
+        .unwrap()
+        .into_program();
+        let inputs: Vec<f64> = (0..1000).map(|i| f64::from(i) / 10.0 - 50.0).collect();
+        let mut outputs = vec![0.0; inputs.len()];
+        program.map_unary("wave", &inputs, &mut outputs).unwrap();
+        // the second batch reuses the wrapper
+        program.map_unary("wave", &[], &mut []).unwrap();
+        assert_eq!(program.batches.borrow().len(), 1);
+        assert!(program.batches.borrow()[0].batched);
+        let engine = program.engine(EngineKind::Jit, OptLevel::None).unwrap();
+        for (input, output) in inputs.iter().zip(&outputs) {
+            assert_eq!(engine.call("wave", &[*input]).unwrap(), *output);
+        }
+
+        assert_eq!(
+            program.map_unary("wave", &inputs, &mut outputs[..1]),
+            Err(EngineError::LengthMismatch(1000, 1))
+        );
+        assert_eq!(
+            program.map_unary("add", &[1.0], &mut [0.0]),
+            Err(EngineError::InvalidCall("add".to_string(), 2, 1))
+        );
+        assert_eq!(
+            program.map_unary("nope", &[1.0], &mut [0.0]),
+            Err(EngineError::UnknownFunction("nope".to_string()))
+        );
+    }
+
+    #[test]
+    fn map_unary_falls_back_to_calls() {
+        let context = Context::create();
+        let source = "🜙sq🜄x🜂x*x;🜙sq__batch🜄x🜂x;";
+        let program = compile(
+            Codegen::new(&context),
+            &[("sq.wiz", source)],
+            &mut CompileOptions::default(),
+        )
+        .unwrap()
+        .into_program();
+        let mut outputs = [0.0; 3];
+        program
+            .map_unary("sq", &[1.0, -2.0, 3.0], &mut outputs)
+            .unwrap();
+        assert_eq!(outputs, [1.0, 4.0, 9.0]);
+        assert!(!program.batches.borrow()[0].batched);
+    }
+
+    #[test]
+    fn compile_opens_a_span_for_each_phase() {
+        let spans = Arc::new(Mutex::new(Spans::default()));
+        let context = Context::create();
+        let source = "🜙sq🜄x🜂x*x;sq🜄pi🜄🜂🜂;";
+        let results = tracing::subscriber::with_default(Collect(spans.clone()), || {
+            let mut options = CompileOptions::default();
+            let compilation =
+                compile(Codegen::new(&context), &[("sq.wiz", source)], &mut options).unwrap();
+            let program = compilation.into_program();
+            let engine = program.engine(EngineKind::Jit, OptLevel::None).unwrap();
+            engine.run_all(40)
+        });
+        assert_eq!(results.len(), 1);
+
+        let spans = spans.lock().unwrap();
+        let paths: Vec<String> = (0..spans.opened.len()).map(|i| spans.path(i)).collect();
+        assert_eq!(
+            paths,
+            [
+                "plan",
+                "plan/parse",
+                "plan/parse/lex",
+                "plan/configure",
+                "plan/resolve",
+                "plan/check_externs",
+                "plan/derive",
+                "plan/lint",
+                "codegen",
+                "codegen/item",
+                "codegen/item",
+                "engine",
+                "run",
+            ]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::ASTNode;
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum ConfigureError {
+    #[error("{0} is defined more than once in this configuration")]
+    Duplicate(String),
+}
+
+/// the name a top level item defines, if any
+fn defined_name(node: &ASTNode) -> Option<&str> {
+    match node {
+        ASTNode::Function(func) if func.prototype.name != "lambda" => Some(&func.prototype.name),
+        ASTNode::Function(_) => None,
+        ASTNode::Extern(proto) => Some(&proto.name),
+        ASTNode::Table(table) => Some(&table.name),
+        ASTNode::When(_, item) => defined_name(item),
+        ASTNode::InlineIr(_) | ASTNode::Test(_) => None,
+    }
+}
+
+/// the item a conditional wraps if every condition on the way holds
+fn select(node: ASTNode, defines: &HashSet<String>) -> Option<ASTNode> {
+    match node {
+        ASTNode::When(cond, item) if cond.holds(&|name| defines.contains(name)) => {
+            select(*item, defines)
+        }
+        ASTNode::When(..) => None,
+        node => Some(node),
+    }
+}
+
+/// keep the conditional items whose condition holds with the given flags
+/// defined and drop the rest - a kept conditional item can't share its name
+/// with any other item
+pub fn configure(
+    ast: Vec<ASTNode>,
+    defines: &HashSet<String>,
+) -> Result<Vec<ASTNode>, ConfigureError> {
+    let mut kept = Vec::with_capacity(ast.len());
+    let mut conditional = Vec::new();
+    for node in ast {
+        let is_conditional = matches!(node, ASTNode::When(..));
+        if let Some(node) = select(node, defines) {
+            if is_conditional {
+                conditional.extend(defined_name(&node).map(str::to_string));
+            }
+            kept.push(node);
+        }
+    }
+
+    let mut counts = HashMap::new();
+    for name in kept.iter().filter_map(defined_name) {
+        *counts.entry(name).or_insert(0) += 1;
+    }
+    match conditional
+        .into_iter()
+        .find(|name| counts[name.as_str()] > 1)
+    {
+        Some(name) => Err(ConfigureError::Duplicate(name)),
+        None => Ok(kept),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use inkwell::context::Context;
+
+    use super::*;
+    use crate::{codegen::Codegen, parser::Parser};
+
+    const SOURCE: &str = "🜹checked🜄x🜂;\
+                          when FAST 🜙norm🜄x🜂x;\
+                          when not FAST and not 🜄ARM or WASM🜂 🜙norm🜄x🜂checked🜄x🜂;\
+                          norm🜄2🜂;";
+
+    fn ir(defines: &[&str]) -> Result<String, String> {
+        let ast = Parser::default().parse_str(SOURCE).unwrap();
+        let defines = defines.iter().map(|name| name.to_string()).collect();
+        let ast = configure(ast, &defines).map_err(|e| e.to_string())?;
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).map_err(|e| e.to_string())?;
+        Ok(codegen.module.print_to_string().to_string())
+    }
+
+    #[test]
+    fn configure_works() {
+        let fast = ir(&["FAST"]).unwrap();
+        assert!(fast.contains("ret double %x"));
+        assert!(!fast.contains("call double @checked"));
+        let checked = ir(&[]).unwrap();
+        assert!(checked.contains("call double @checked"));
+        assert_ne!(fast, checked);
+        assert_eq!(ir(&["ARM"]), Err("unknown function norm".to_string()));
+
+        let ast = Parser::default().parse_str(SOURCE).unwrap();
+        assert_eq!(
+            ast[2].to_string(),
+            "when not FAST and not 🜄ARM or WASM🜂 🜙norm🜄x🜂 checked🜄x🜂"
+        );
+        assert_eq!(
+            Parser::default().parse_str(&ast[2].to_string()).unwrap(),
+            [ast[2].clone()]
+        );
+        let when: Vec<ASTNode> = Parser::default().parse_str("when🜄1🜂;when;").unwrap();
+        assert!(when
+            .iter()
+            .all(|node| matches!(node, ASTNode::Function(f) if f.prototype.name == "lambda")));
+    }
+
+    #[test]
+    fn configure_rejects_duplicates() {
+        let source = "🜙norm🜄x🜂x;when A 🜙norm🜄x🜂x*2;when B when A 🜹sin🜄x🜂;🜹sin🜄y🜂;";
+        let ast = Parser::default().parse_str(source).unwrap();
+        let defines = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        assert_eq!(
+            configure(ast.clone(), &defines(&["A"])),
+            Err(ConfigureError::Duplicate("norm".to_string()))
+        );
+        assert_eq!(
+            configure(ast.clone(), &defines(&["B"])).map(|ast| ast.len()),
+            Ok(2)
+        );
+        let dup = Parser::default().parse_str("🜙f🜄🜂1;🜙f🜄🜂2;").unwrap();
+        assert!(configure(dup, &defines(&[])).is_ok());
+    }
+}
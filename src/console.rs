@@ -0,0 +1,184 @@
+use std::{env, fmt};
+
+/// the width output is fitted to when neither --width nor $COLUMNS say
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// the columns between tab stops when a source line is shown
+pub const TAB_WIDTH: usize = 4;
+
+/// the width asked for, else $COLUMNS, else DEFAULT_WIDTH
+pub fn width(requested: Option<usize>) -> usize {
+    requested
+        .or_else(|| env::var("COLUMNS").ok()?.parse().ok())
+        .filter(|width| *width > 0)
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// the columns a character takes up in a terminal - combining marks and
+/// control characters take none and east asian wide characters and most
+/// emoji take two
+pub fn char_width(c: char) -> usize {
+    match c as u32 {
+        0..=0x1f | 0x7f..=0x9f => 0,
+        0x300..=0x36f | 0x1ab0..=0x1aff | 0x1dc0..=0x1dff | 0x200b..=0x200f => 0,
+        0x20d0..=0x20ff | 0xfe00..=0xfe0f | 0xfe20..=0xfe2f => 0,
+        0x1100..=0x115f | 0x2e80..=0x303e | 0x3041..=0x33ff | 0x3400..=0x4dbf => 2,
+        0x4e00..=0x9fff | 0xa000..=0xa4cf | 0xac00..=0xd7a3 | 0xf900..=0xfaff => 2,
+        0xfe30..=0xfe4f | 0xff00..=0xff60 | 0xffe0..=0xffe6 => 2,
+        0x1f300..=0x1f64f | 0x1f900..=0x1f9ff | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+pub fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// text cut down to at most width columns, ending in … when it was cut
+pub fn fit(text: &str, width: usize) -> String {
+    if display_width(text) <= width {
+        return text.to_string();
+    }
+    let mut res = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        if used + char_width(c) + 1 > width {
+            break;
+        }
+        used += char_width(c);
+        res.push(c);
+    }
+    res.push('…');
+    res
+}
+
+/// a source line cut down around a column so it fits the terminal, with a
+/// caret under that column
+#[derive(Debug, PartialEq, Clone)]
+pub struct Excerpt {
+    /// the visible part of the line, tabs expanded and … where it was cut
+    pub text: String,
+    /// the columns before the caret
+    pub caret: usize,
+}
+
+impl Excerpt {
+    /// the excerpt of line at a 1 based column, counted in unicode scalar
+    /// values like span::Location, fitted to width columns - a third of the
+    /// width goes before the caret when the line has to be cut, and whatever
+    /// is left after the end of the line goes there too
+    pub fn new(line: &str, column: usize, width: usize) -> Excerpt {
+        let mut cells = Vec::new();
+        let mut position = 0;
+        for c in line.chars() {
+            let cell = match c {
+                '\t' => " ".repeat(TAB_WIDTH - position % TAB_WIDTH),
+                c if char_width(c) == 0 => String::new(),
+                c => c.to_string(),
+            };
+            position += display_width(&cell);
+            cells.push(cell);
+        }
+        let caret = column.saturating_sub(1).min(cells.len());
+        let mut offsets = vec![0];
+        for cell in &cells {
+            offsets.push(offsets[offsets.len() - 1] + display_width(cell));
+        }
+
+        let len = cells.len();
+        let fits = |start: usize, end: usize| {
+            offsets[end] - offsets[start] + (start > 0) as usize + (end < len) as usize <= width
+        };
+        let mut start = caret;
+        let mut end = (caret + 1).min(len);
+        while start > 0 && offsets[caret] - offsets[start - 1] <= width / 3 && fits(start - 1, end)
+        {
+            start -= 1;
+        }
+        while end < len && fits(start, end + 1) {
+            end += 1;
+        }
+        while start > 0 && fits(start - 1, end) {
+            start -= 1;
+        }
+
+        let mut text = String::new();
+        if start > 0 {
+            text.push('…');
+        }
+        text.extend(cells[start..end].iter().map(String::as_str));
+        if end < len {
+            text.push('…');
+        }
+        Excerpt {
+            text,
+            caret: (start > 0) as usize + offsets[caret] - offsets[start],
+        }
+    }
+}
+
+impl fmt::Display for Excerpt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n{}^", self.text, " ".repeat(self.caret))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn fit_works() {
+        assert_eq!(fit("sq🜄1🜂 + 2", 20), "sq🜄1🜂 + 2");
+        assert_eq!(fit("sq🜄1🜂 + 2", 5), "sq🜄1…");
+        assert_eq!(fit("数学函数", 5), "数学…");
+        assert_eq!(display_width("e\u{301}\t数"), 3);
+    }
+
+    #[test]
+    fn excerpt_works() {
+        let excerpt = Excerpt::new("\tx + 数 * y", 6, 80);
+        assert_eq!(excerpt.to_string(), "    x + 数 * y\n        ^");
+
+        let line: String = (0..40).map(|i| format!("f{}🜄x🜂+", i)).collect::<String>() + "oops";
+        let column = line.chars().count() - 3;
+        assert!(column > 200);
+        let excerpts: Vec<Excerpt> = [60, 80, 120]
+            .iter()
+            .map(|width| Excerpt::new(&line, column, *width))
+            .collect();
+        let snapshots: Vec<(&str, usize)> = excerpts
+            .iter()
+            .map(|excerpt| (excerpt.text.as_str(), excerpt.caret))
+            .collect();
+        assert_eq!(
+            snapshots,
+            [
+                ("…32🜄x🜂+f33🜄x🜂+f34🜄x🜂+f35🜄x🜂+f36🜄x🜂+f37🜄x🜂+f38🜄x🜂+f39🜄x🜂+oops", 56),
+                (
+                    "…9🜄x🜂+f30🜄x🜂+f31🜄x🜂+f32🜄x🜂+f33🜄x🜂+f34🜄x🜂+f35🜄x🜂+f36🜄x🜂+f37🜄x🜂+f38🜄x🜂+f39🜄x🜂+oops",
+                    76
+                ),
+                (
+                    "…x🜂+f24🜄x🜂+f25🜄x🜂+f26🜄x🜂+f27🜄x🜂+f28🜄x🜂+f29🜄x🜂+f30🜄x🜂+f31🜄x🜂+f32🜄x🜂+f33🜄x🜂+f34🜄x🜂+f35🜄x🜂+f36🜄x🜂+f37🜄x🜂+f38🜄x🜂+f39🜄x🜂+oops",
+                    116
+                ),
+            ]
+        );
+
+        let excerpt = Excerpt::new(&line, 10, 60);
+        assert_eq!(excerpt.caret, 9);
+        assert!(excerpt.text.ends_with('…') && !excerpt.text.starts_with('…'));
+        for width in [60, 80, 120].iter() {
+            let excerpt = Excerpt::new(&line, 120, *width);
+            assert_eq!(display_width(&excerpt.text), *width);
+            let before: String = excerpt.text.chars().take(excerpt.caret).collect();
+            assert_eq!(display_width(&before), excerpt.caret);
+            assert_eq!(
+                excerpt.text.chars().nth(excerpt.caret),
+                line.chars().nth(119)
+            );
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use crate::ast::{ASTNode, Expression, Function, MathMode, Prototype};
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum DeriveError {
+    #[error("unknown function {0}")]
+    UnknownFunction(String),
+    #[error("can only differentiate unary functions, {0} takes {1} args")]
+    NotUnary(String, usize),
+    #[error("cannot differentiate call to {0}")]
+    UnsupportedCall(String),
+    #[error("cannot differentiate {0} with a non constant exponent")]
+    NonConstantExponent(String),
+    #[error("cannot differentiate unknown variable {0}")]
+    UnknownVariable(String),
+    #[error("cannot differentiate an expression with a syntax error in it")]
+    SyntaxError,
+}
+
+/// math functions a derivative may call, with their arity
+const MATH_FUNCTIONS: &[(&str, usize)] = &[
+    ("sin", 1),
+    ("cos", 1),
+    ("exp", 1),
+    ("log", 1),
+    ("sqrt", 1),
+    ("pow", 2),
+];
+
+/// the name the derivative of a function is registered under
+pub fn derivative_name(name: &str) -> String {
+    format!("{}__dx", name)
+}
+
+fn literal(value: f64) -> Expression {
+    Expression::Literal(value)
+}
+
+fn call(callee: &str, args: Vec<Expression>) -> Expression {
+    Expression::Call(callee.to_string(), args)
+}
+
+fn binary(op: &str, lhs: Expression, rhs: Expression) -> Expression {
+    Expression::Binary(op.to_string(), Box::new(lhs), Box::new(rhs))
+}
+
+// the constructors below fold away the zeros and ones the rules produce
+
+fn add(lhs: Expression, rhs: Expression) -> Expression {
+    match (lhs, rhs) {
+        (Expression::Literal(l), Expression::Literal(r)) => literal(l + r),
+        (Expression::Literal(0.0), rhs) => rhs,
+        (lhs, Expression::Literal(0.0)) => lhs,
+        (lhs, rhs) => binary("+", lhs, rhs),
+    }
+}
+
+fn sub(lhs: Expression, rhs: Expression) -> Expression {
+    match (lhs, rhs) {
+        (Expression::Literal(l), Expression::Literal(r)) => literal(l - r),
+        (lhs, Expression::Literal(0.0)) => lhs,
+        (lhs, rhs) => binary("-", lhs, rhs),
+    }
+}
+
+fn mul(lhs: Expression, rhs: Expression) -> Expression {
+    match (lhs, rhs) {
+        (Expression::Literal(l), Expression::Literal(r)) => literal(l * r),
+        (Expression::Literal(0.0), _) | (_, Expression::Literal(0.0)) => literal(0.0),
+        (Expression::Literal(1.0), rhs) => rhs,
+        (lhs, Expression::Literal(1.0)) => lhs,
+        (lhs, rhs) => binary("*", lhs, rhs),
+    }
+}
+
+fn div(lhs: Expression, rhs: Expression) -> Expression {
+    match (lhs, rhs) {
+        (Expression::Literal(0.0), _) => literal(0.0),
+        (lhs, Expression::Literal(1.0)) => lhs,
+        (lhs, rhs) => binary("/", lhs, rhs),
+    }
+}
+
+/// forward mode derivative of expr with respect to var
+pub fn derive_expr(expr: &Expression, var: &str) -> Result<Expression, DeriveError> {
+    match expr {
+        Expression::Literal(_) => Ok(literal(0.0)),
+        Expression::Variable(name) if name == var => Ok(literal(1.0)),
+        Expression::Variable(name) => Err(DeriveError::UnknownVariable(name.clone())),
+        Expression::Error(_) => Err(DeriveError::SyntaxError),
+        Expression::Binary(op, lhs, rhs) => {
+            let (u, v) = (lhs.as_ref().clone(), rhs.as_ref().clone());
+            let du = derive_expr(lhs, var)?;
+            let dv = derive_expr(rhs, var)?;
+            match op.as_str() {
+                "+" => Ok(add(du, dv)),
+                "-" => Ok(sub(du, dv)),
+                "*" => Ok(add(mul(du, v), mul(u, dv))),
+                "/" => Ok(div(sub(mul(du, v.clone()), mul(u, dv)), mul(v.clone(), v))),
+                _ => Err(DeriveError::UnsupportedCall(op.clone())),
+            }
+        }
+        Expression::Call(callee, args) => {
+            let (u, du) = match args.as_slice() {
+                [u] => (u.clone(), derive_expr(u, var)?),
+                [u, Expression::Literal(n)] if callee == "pow" => {
+                    let du = derive_expr(u, var)?;
+                    let outer = mul(literal(*n), call("pow", vec![u.clone(), literal(n - 1.0)]));
+                    return Ok(mul(outer, du));
+                }
+                [_, _] if callee == "pow" => {
+                    return Err(DeriveError::NonConstantExponent(callee.clone()))
+                }
+                _ => return Err(DeriveError::UnsupportedCall(callee.clone())),
+            };
+            let outer = match callee.as_str() {
+                "sin" => call("cos", vec![u]),
+                "cos" => sub(literal(0.0), call("sin", vec![u])),
+                "exp" => call("exp", vec![u]),
+                "log" => div(literal(1.0), u),
+                "sqrt" => div(literal(1.0), mul(literal(2.0), call("sqrt", vec![u]))),
+                _ => return Err(DeriveError::UnsupportedCall(callee.clone())),
+            };
+            Ok(mul(outer, du))
+        }
+        Expression::NamedCall(callee, _) => Err(DeriveError::UnsupportedCall(callee.clone())),
+    }
+}
+
+/// synthesize the derivative of a unary function as a new function
+pub fn derive(function: &Function) -> Result<Function, DeriveError> {
+    let proto = &function.prototype;
+    let var = match proto.args.as_slice() {
+        [var] => var,
+        args => return Err(DeriveError::NotUnary(proto.name.clone(), args.len())),
+    };
+
+    Ok(Function {
+        prototype: Prototype {
+            name: derivative_name(&proto.name),
+            args: proto.args.clone(),
+            link_name: None,
+            defaults: proto.defaults.clone(),
+            void: false,
+            math: proto.math,
+        },
+        body: derive_expr(&function.body, var)?,
+    })
+}
+
+pub(crate) fn calls_in(expr: &Expression, res: &mut Vec<String>) {
+    match expr {
+        Expression::Literal(_) | Expression::Variable(_) | Expression::Error(_) => (),
+        Expression::Binary(_, lhs, rhs) => {
+            calls_in(lhs, res);
+            calls_in(rhs, res);
+        }
+        Expression::Call(callee, args) => {
+            res.push(callee.clone());
+            for arg in args {
+                calls_in(arg, res);
+            }
+        }
+        Expression::NamedCall(callee, args) => {
+            res.push(callee.clone());
+            for (_, arg) in args {
+                calls_in(arg, res);
+            }
+        }
+    }
+}
+
+/// add the derivative of the named function right after its definition,
+/// declaring any math functions it needs that aren't declared yet
+pub fn register_derivative(ast: &mut Vec<ASTNode>, name: &str) -> Result<(), DeriveError> {
+    let (index, derived) = match ast.iter().enumerate().find_map(|(i, node)| match node {
+        ASTNode::Function(func) if func.prototype.name == name => Some((i, func)),
+        _ => None,
+    }) {
+        Some((index, func)) => (index, derive(func)?),
+        None => return Err(DeriveError::UnknownFunction(name.to_string())),
+    };
+
+    let mut calls = Vec::new();
+    calls_in(&derived.body, &mut calls);
+    let mut externs = Vec::new();
+    for (math, arity) in MATH_FUNCTIONS {
+        let declared = ast.iter().any(|node| match node {
+            ASTNode::Extern(proto) => proto.name == *math,
+            ASTNode::Function(func) => func.prototype.name == *math,
+            ASTNode::Table(table) => table.name == *math,
+            ASTNode::When(..) | ASTNode::InlineIr(_) | ASTNode::Test(_) => false,
+        });
+        if calls.iter().any(|callee| callee == math) && !declared {
+            externs.push(ASTNode::Extern(Prototype {
+                name: math.to_string(),
+                args: (0..*arity).map(|i| format!("x{}", i)).collect(),
+                link_name: None,
+                defaults: vec![None; *arity],
+                void: false,
+                math: MathMode::default(),
+            }));
+        }
+    }
+
+    ast.insert(index + 1, ASTNode::Function(derived));
+    for (i, node) in externs.into_iter().enumerate() {
+        ast.insert(index + 1 + i, node);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use inkwell::context::Context;
+
+    use super::*;
+    use crate::{
+        codegen::Codegen,
+        engine::{Engine, EngineKind, OptLevel},
+        parser::Parser,
+    };
+
+    /// compare the derivative against central finite differences
+    fn check_derivative(source: &str, points: &[f64]) {
+        let mut ast = Parser::default().parse_str(source).unwrap();
+        register_derivative(&mut ast, "f").unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        let h = 1e-6;
+        for &x in points {
+            let exact = engine.call("f__dx", &[x]).unwrap();
+            let approx = (engine.call("f", &[x + h]).unwrap()
+                - engine.call("f", &[x - h]).unwrap())
+                / (2.0 * h);
+            assert!((exact - approx).abs() < 1e-4, "{} at {}", source, x);
+        }
+    }
+
+    #[test]
+    fn derive_polynomial_works() {
+        check_derivative("🜙f🜄x🜂x*x*x - 2*x/4 + 7;", &[-2.0, 0.0, 0.5, 3.0]);
+        let ast = Parser::default().parse_str("🜙f🜄x🜂3*x+1;").unwrap();
+        let derived = match &ast[0] {
+            ASTNode::Function(func) => derive(func).unwrap(),
+            _ => unreachable!(),
+        };
+        assert_eq!(derived.prototype.name, "f__dx");
+        assert_eq!(derived.body, Expression::Literal(3.0));
+    }
+
+    #[test]
+    fn derive_composite_works() {
+        let source = "🜹sin🜄x🜂;🜹exp🜄x🜂;🜹pow🜄x🜌y🜂;\
+                      🜙f🜄x🜂sin🜄exp🜄x🜂🜂*pow🜄x🜌3🜂/🜄1+x*x🜂;";
+        check_derivative(source, &[-1.0, 0.25, 1.5]);
+    }
+
+    #[test]
+    fn derive_errors_work() {
+        let mut ast = Parser::default()
+            .parse_str("🜙g🜄x🜂x;🜙f🜄x🜂g🜄x🜂;🜙h🜄x🜂pow🜄x🜌x🜂;🜙two🜄x🜌y🜂x;")
+            .unwrap();
+        assert_eq!(
+            register_derivative(&mut ast, "f"),
+            Err(DeriveError::UnsupportedCall("g".to_string()))
+        );
+        assert_eq!(
+            register_derivative(&mut ast, "h"),
+            Err(DeriveError::NonConstantExponent("pow".to_string()))
+        );
+        assert_eq!(
+            register_derivative(&mut ast, "two"),
+            Err(DeriveError::NotUnary("two".to_string(), 2))
+        );
+        assert_eq!(
+            register_derivative(&mut ast, "nope"),
+            Err(DeriveError::UnknownFunction("nope".to_string()))
+        );
+    }
+}
@@ -0,0 +1,413 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::console::{self, Excerpt};
+use crate::lint::Warning;
+use crate::span::SourceSpan;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// what kind of problem it is, like the name of a lint or the stage that
+    /// failed - runs of the same code are collapsed
+    pub code: &'static str,
+    pub message: String,
+    /// an edit that fixes it, sure enough to be applied without asking
+    pub suggestion: Option<Suggestion>,
+}
+
+/// replace the source at span with replacement
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Suggestion {
+    pub span: SourceSpan,
+    pub replacement: String,
+}
+
+impl Suggestion {
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"location\":{:?},\"start\":{},\"end\":{},\"replacement\":{:?}}}",
+            self.span.to_string(),
+            self.span.span.start,
+            self.span.span.end,
+            self.replacement
+        )
+    }
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl ToString) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.to_string(),
+            suggestion: None,
+        }
+    }
+
+    pub fn note(code: &'static str, message: impl ToString) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Note,
+            code,
+            message: message.to_string(),
+            suggestion: None,
+        }
+    }
+
+    pub fn with_suggestion(self, suggestion: Option<Suggestion>) -> Diagnostic {
+        Diagnostic { suggestion, ..self }
+    }
+
+    pub fn to_json(&self) -> String {
+        let suggestion = match &self.suggestion {
+            Some(suggestion) => format!(",\"suggestion\":{}", suggestion.to_json()),
+            None => String::new(),
+        };
+        format!(
+            "{{\"severity\":\"{}\",\"code\":\"{}\",\"message\":{:?}{}}}",
+            self.severity, self.code, self.message, suggestion
+        )
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            code: warning.lint.name(),
+            message: format!("{} in {}", warning.message, warning.function),
+            suggestion: None,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+/// what happens to a diagnostic when the sink is rendered
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Shown {
+    Yes,
+    /// similar_limit others with its code came before it
+    Similar,
+    /// error_limit diagnostics were already shown
+    OverLimit,
+}
+
+/// collects the diagnostics of every stage for one run, dropping exact
+/// duplicates, and renders them for people with long runs cut short
+#[derive(Debug, Clone)]
+pub struct Sink {
+    /// the number of diagnostics shown before the rest are only counted, 0
+    /// for no limit
+    pub error_limit: usize,
+    /// the number of diagnostics with one code shown before the rest are
+    /// collapsed into a single line
+    pub similar_limit: usize,
+    /// the columns each diagnostic is fitted to, 0 for no limit
+    pub width: usize,
+    /// the location each diagnostic is at, like the path of its file
+    entries: Vec<(String, Diagnostic)>,
+    seen: HashSet<(String, Diagnostic)>,
+    /// the source line and 1 based column shown under an entry, by its index
+    excerpts: HashMap<usize, (String, usize)>,
+}
+
+impl Default for Sink {
+    fn default() -> Self {
+        Sink {
+            error_limit: 50,
+            similar_limit: 10,
+            width: 0,
+            entries: Vec::new(),
+            seen: HashSet::new(),
+            excerpts: HashMap::new(),
+        }
+    }
+}
+
+/// "error" or "errors" and so on for the count
+fn noun(count: usize, severity: Severity) -> String {
+    match count {
+        1 => severity.to_string(),
+        _ => format!("{}s", severity),
+    }
+}
+
+fn plural(count: usize, severity: Severity) -> String {
+    format!("{} {}", count, noun(count, severity))
+}
+
+impl Sink {
+    pub fn report(&mut self, location: &str, diagnostic: Diagnostic) {
+        let entry = (location.to_string(), diagnostic);
+        if self.seen.insert(entry.clone()) {
+            self.entries.push(entry);
+        }
+    }
+
+    /// report a diagnostic along with the source line it's about, which is
+    /// shown under it cut down around the column, with a caret there
+    pub fn report_in_line(
+        &mut self,
+        location: &str,
+        diagnostic: Diagnostic,
+        line: &str,
+        column: usize,
+    ) {
+        let entry = (location.to_string(), diagnostic);
+        if self.seen.insert(entry.clone()) {
+            self.excerpts
+                .insert(self.entries.len(), (line.to_string(), column));
+            self.entries.push(entry);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// every diagnostic reported, without duplicates
+    pub fn diagnostics(&self) -> impl Iterator<Item = (&str, &Diagnostic)> {
+        self.entries
+            .iter()
+            .map(|(location, diagnostic)| (location.as_str(), diagnostic))
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.diagnostics()
+            .filter(|(_, diagnostic)| diagnostic.severity == severity)
+            .count()
+    }
+
+    fn shown(&self) -> Vec<Shown> {
+        let mut per_code = HashMap::new();
+        let mut shown = 0;
+        self.diagnostics()
+            .map(|(_, diagnostic)| {
+                let code = per_code.entry(diagnostic.code).or_insert(0);
+                *code += 1;
+                if *code > self.similar_limit {
+                    Shown::Similar
+                } else if self.error_limit != 0 && shown >= self.error_limit {
+                    Shown::OverLimit
+                } else {
+                    shown += 1;
+                    Shown::Yes
+                }
+            })
+            .collect()
+    }
+
+    /// the diagnostics that are shown one per line, each fitted to the width
+    /// and followed by its source line if it has one, a line after the last one
+    /// shown of every code that was collapsed, a footer if the limit was hit
+    /// and the totals of each severity
+    pub fn render(&self) -> String {
+        let shown = self.shown();
+        let mut similar: HashMap<&str, usize> = HashMap::new();
+        let mut last_shown = HashMap::new();
+        for (i, ((_, diagnostic), shown)) in self.diagnostics().zip(&shown).enumerate() {
+            match shown {
+                Shown::Yes => drop(last_shown.insert(diagnostic.code, i)),
+                Shown::Similar => *similar.entry(diagnostic.code).or_insert(0) += 1,
+                Shown::OverLimit => (),
+            }
+        }
+
+        let width = match self.width {
+            0 => usize::MAX,
+            width => width,
+        };
+        let mut res = String::new();
+        for (i, ((location, diagnostic), shown)) in self.diagnostics().zip(&shown).enumerate() {
+            if *shown != Shown::Yes {
+                continue;
+            }
+            let line = match location {
+                "" => diagnostic.to_string(),
+                location => format!("{}: {}", location, diagnostic),
+            };
+            res += &console::fit(&line, width);
+            res.push('\n');
+            if let Some((line, column)) = self.excerpts.get(&i) {
+                res += &format!("{}\n", Excerpt::new(line, *column, width));
+            }
+            match similar.get(diagnostic.code) {
+                Some(more) if last_shown[diagnostic.code] == i => {
+                    res += &format!(
+                        "… and {} more similar {}\n",
+                        more,
+                        noun(*more, diagnostic.severity)
+                    );
+                }
+                _ => (),
+            }
+        }
+        let over = shown.iter().filter(|s| **s == Shown::OverLimit).count();
+        if over > 0 {
+            res += &format!(
+                "stopped after {} diagnostics, {} more not shown - raise --error-limit to see them\n",
+                self.error_limit, over
+            );
+        }
+        res += &format!(
+            "{}, {}, {}",
+            plural(self.count(Severity::Error), Severity::Error),
+            plural(self.count(Severity::Warning), Severity::Warning),
+            plural(self.count(Severity::Note), Severity::Note)
+        );
+        res
+    }
+
+    /// every diagnostic, however many there are
+    pub fn to_json(&self) -> String {
+        let diagnostics: Vec<String> = self
+            .diagnostics()
+            .map(|(location, diagnostic)| {
+                let json = diagnostic.to_json();
+                format!("{{\"location\":{:?},{}", location, &json[1..])
+            })
+            .collect();
+        format!("[{}]", diagnostics.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn sink_works() {
+        let mut sink = Sink {
+            error_limit: 5,
+            similar_limit: 2,
+            ..Sink::default()
+        };
+        for _ in 0..300 {
+            sink.report(
+                "a.wiz",
+                Diagnostic::error("parse", "unexpected end of file"),
+            );
+        }
+        for i in 0..200 {
+            sink.report(
+                "b.wiz",
+                Diagnostic::error("resolve", format!("unknown function f{}", i)),
+            );
+        }
+        sink.report(
+            "b.wiz",
+            Diagnostic::error("parse", "unexpected end of file"),
+        );
+        for i in 0..4 {
+            sink.report("", Diagnostic::note("pass", format!("folded {}", i)));
+        }
+        assert_eq!(
+            sink.render(),
+            "a.wiz: error[parse]: unexpected end of file\n\
+             b.wiz: error[resolve]: unknown function f0\n\
+             b.wiz: error[resolve]: unknown function f1\n\
+             … and 198 more similar errors\n\
+             b.wiz: error[parse]: unexpected end of file\n\
+             note[pass]: folded 0\n\
+             … and 2 more similar notes\n\
+             stopped after 5 diagnostics, 1 more not shown - raise --error-limit to see them\n\
+             202 errors, 0 warnings, 4 notes"
+        );
+
+        let json = sink.to_json();
+        assert_eq!(json.matches("\"severity\":").count(), 206);
+        assert!(json.starts_with(
+            "[{\"location\":\"a.wiz\",\"severity\":\"error\",\"code\":\"parse\",\
+             \"message\":\"unexpected end of file\"}"
+        ));
+        assert!(json.contains("unknown function f199"));
+
+        let unlimited = Sink {
+            error_limit: 0,
+            similar_limit: 300,
+            ..sink
+        };
+        assert_eq!(unlimited.render().lines().count(), 207);
+        assert_eq!(Sink::default().render(), "0 errors, 0 warnings, 0 notes");
+    }
+
+    #[test]
+    fn sink_fits_the_width() {
+        let line: String = (0..40).map(|i| format!("f{}🜄x🜂+", i)).collect::<String>() + "oops🜄1🜂;";
+        let column = line.chars().count() - 7;
+        assert!(column > 200);
+        let renders: Vec<String> = [60, 80, 120]
+            .iter()
+            .map(|width| {
+                let mut sink = Sink {
+                    width: *width,
+                    ..Sink::default()
+                };
+                sink.report_in_line(
+                    "generated.wiz:1:1",
+                    Diagnostic::error(
+                        "codegen",
+                        "top level expression 1: unknown function oops, which nothing defines",
+                    ),
+                    &line,
+                    column,
+                );
+                sink.report(
+                    "generated.wiz",
+                    Diagnostic::note("pass", "folded 2 constants"),
+                );
+                sink.render()
+            })
+            .collect();
+        assert_eq!(
+            renders,
+            [
+                "generated.wiz:1:1: error[codegen]: top level expression 1: …\n\
+                 …🜂+f33🜄x🜂+f34🜄x🜂+f35🜄x🜂+f36🜄x🜂+f37🜄x🜂+f38🜄x🜂+f39🜄x🜂+oops🜄1🜂;\n\
+                 \u{20}                                                   ^\n\
+                 generated.wiz: note[pass]: folded 2 constants\n\
+                 1 error, 0 warnings, 1 note",
+                "generated.wiz:1:1: error[codegen]: top level expression 1: unknown function oop…\n\
+                 …+f30🜄x🜂+f31🜄x🜂+f32🜄x🜂+f33🜄x🜂+f34🜄x🜂+f35🜄x🜂+f36🜄x🜂+f37🜄x🜂+f38🜄x🜂+f39🜄x🜂+oops🜄1🜂;\n\
+                 \u{20}                                                                       ^\n\
+                 generated.wiz: note[pass]: folded 2 constants\n\
+                 1 error, 0 warnings, 1 note",
+                "generated.wiz:1:1: error[codegen]: top level expression 1: unknown function oops, which nothing defines\n\
+                 …24🜄x🜂+f25🜄x🜂+f26🜄x🜂+f27🜄x🜂+f28🜄x🜂+f29🜄x🜂+f30🜄x🜂+f31🜄x🜂+f32🜄x🜂+f33🜄x🜂+f34🜄x🜂+f35🜄x🜂+f36🜄x🜂+f37🜄x🜂+f38🜄x🜂+f39🜄x🜂+oops🜄1🜂;\n\
+                 \u{20}                                                                                                               ^\n\
+                 generated.wiz: note[pass]: folded 2 constants\n\
+                 1 error, 0 warnings, 1 note",
+            ]
+        );
+        for (render, width) in renders.iter().zip(&[60, 80, 120]) {
+            assert!(render
+                .lines()
+                .all(|line| console::display_width(line) <= *width));
+        }
+    }
+}
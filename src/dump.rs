@@ -0,0 +1,282 @@
+use std::{path::PathBuf, str::FromStr};
+
+use crate::compile::{Compilation, SymbolKind};
+use crate::engine::OptLevel;
+use crate::interface;
+use crate::lexer;
+use crate::span::{LineIndex, SourceMap};
+
+/// the bytes of bitcode on each line of its hex dump
+const HEX_WIDTH: usize = 32;
+
+/// what --dump can show of a compilation - the ir and bitcode of the same
+/// sources are the same bytes every time, whatever their paths, since the
+/// module is always named wizarding and nothing is emitted in hash order
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DumpKind {
+    Source,
+    Tokens,
+    Ast,
+    Ir,
+    OptimizedIr,
+    /// the module as llvm bitcode
+    Bytecode,
+    Symbols,
+    /// the externs a program needs to call what this one defines, see the
+    /// interface module
+    Interface,
+}
+
+impl DumpKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DumpKind::Source => "source",
+            DumpKind::Tokens => "tokens",
+            DumpKind::Ast => "ast",
+            DumpKind::Ir => "ir",
+            DumpKind::OptimizedIr => "optimized-ir",
+            DumpKind::Bytecode => "bytecode",
+            DumpKind::Symbols => "symbols",
+            DumpKind::Interface => "interface",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+#[error(
+    "unknown dump {0}, expected one of source, tokens, ast, ir, optimized-ir, bytecode, symbols or interface"
+)]
+pub struct UnknownDump(String);
+
+impl FromStr for DumpKind {
+    type Err = UnknownDump;
+
+    fn from_str(kind: &str) -> Result<DumpKind, UnknownDump> {
+        match kind {
+            "source" => Ok(DumpKind::Source),
+            "tokens" => Ok(DumpKind::Tokens),
+            "ast" => Ok(DumpKind::Ast),
+            "ir" => Ok(DumpKind::Ir),
+            "optimized-ir" => Ok(DumpKind::OptimizedIr),
+            "bytecode" => Ok(DumpKind::Bytecode),
+            "symbols" => Ok(DumpKind::Symbols),
+            "interface" => Ok(DumpKind::Interface),
+            _ => Err(UnknownDump(kind.to_string())),
+        }
+    }
+}
+
+/// a dump and the file it's written to, a section of stderr without one
+#[derive(Debug, PartialEq, Clone)]
+pub struct Dump {
+    pub kind: DumpKind,
+    pub path: Option<PathBuf>,
+}
+
+/// parses kind[=path]
+impl FromStr for Dump {
+    type Err = UnknownDump;
+
+    fn from_str(dump: &str) -> Result<Dump, UnknownDump> {
+        let (kind, path) = match dump.split_once('=') {
+            Some((kind, path)) => (kind, Some(PathBuf::from(path))),
+            None => (dump, None),
+        };
+        Ok(Dump {
+            kind: kind.parse()?,
+            path,
+        })
+    }
+}
+
+/// what dumps that need more than the compilation are rendered with
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DumpOptions {
+    /// the levels of each item the ast shows
+    pub max_depth: usize,
+    /// the children of each node the ast shows
+    pub max_children: usize,
+    /// the level the optimized ir is optimized at
+    pub opt: OptLevel,
+}
+
+/// every source as one program, each after a comment with its path
+fn source(compilation: &Compilation) -> String {
+    let sources = compilation
+        .sources()
+        .iter()
+        .map(|(path, source)| (path.as_str(), source.as_str()));
+    SourceMap::new(sources).text().to_string()
+}
+
+/// each token on its own line, after the path, line and column it starts at
+fn tokens(compilation: &Compilation) -> String {
+    let mut res = String::new();
+    for (path, source) in compilation.sources() {
+        let lines = LineIndex::new(source);
+        // a compilation's sources all lexed
+        for (tok, span) in lexer::lex_spanned(source).unwrap_or_default() {
+            let location = lines
+                .location(span.start as u32)
+                .expect("tokens start on a character");
+            res += &format!("{}:{} {}\n", path, location, tok);
+        }
+    }
+    res
+}
+
+fn symbols(compilation: &Compilation) -> String {
+    let mut res = String::new();
+    for symbol in compilation.symbols() {
+        let kind = match symbol.kind {
+            SymbolKind::Function => "function",
+            SymbolKind::Extern => "extern",
+            SymbolKind::Table => "table",
+        };
+        res += &format!("{} {}🜄{}🜂\n", kind, symbol.name, symbol.params.join("🜌"));
+    }
+    res
+}
+
+/// the artifact itself, as it's written to a file
+pub fn render(compilation: &Compilation, kind: DumpKind, options: &DumpOptions) -> Vec<u8> {
+    match kind {
+        DumpKind::Source => source(compilation).into_bytes(),
+        DumpKind::Tokens => tokens(compilation).into_bytes(),
+        DumpKind::Ast => {
+            let trees: Vec<String> = compilation
+                .ast()
+                .iter()
+                .map(|node| node.display_tree(options.max_depth, options.max_children))
+                .collect();
+            (trees.join("\n") + "\n").into_bytes()
+        }
+        DumpKind::Ir => compilation.ir().into_bytes(),
+        DumpKind::OptimizedIr => compilation.optimized_ir(options.opt).into_bytes(),
+        DumpKind::Bytecode => compilation.bitcode(),
+        DumpKind::Symbols => symbols(compilation).into_bytes(),
+        DumpKind::Interface => interface::render(compilation.symbols()).into_bytes(),
+    }
+}
+
+/// the artifact between lines naming it, for dumps that share stderr -
+/// bitcode is shown in hex
+pub fn section(kind: DumpKind, artifact: &[u8]) -> String {
+    let body = match kind {
+        DumpKind::Bytecode => {
+            let lines: Vec<String> = artifact
+                .chunks(HEX_WIDTH)
+                .map(|line| line.iter().map(|byte| format!("{:02x}", byte)).collect())
+                .collect();
+            lines.join("\n")
+        }
+        _ => String::from_utf8_lossy(artifact).into_owned(),
+    };
+    format!(
+        "--- {} ---\n{}\n--- end {} ---",
+        kind.name(),
+        body.trim_end_matches('\n'),
+        kind.name()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use inkwell::context::Context;
+
+    use super::*;
+    use crate::codegen::Codegen;
+    use crate::compile;
+    use crate::plan::CompileOptions;
+
+    #[test]
+    fn dump_parses() {
+        assert_eq!(
+            "optimized-ir=out/opt.ll".parse(),
+            Ok(Dump {
+                kind: DumpKind::OptimizedIr,
+                path: Some(PathBuf::from("out/opt.ll")),
+            })
+        );
+        assert_eq!(
+            "ast".parse(),
+            Ok(Dump {
+                kind: DumpKind::Ast,
+                path: None
+            })
+        );
+        assert_eq!(
+            "bitcode=a.bc".parse::<Dump>(),
+            Err(UnknownDump("bitcode".to_string()))
+        );
+    }
+
+    #[test]
+    fn render_works() {
+        let context = Context::create();
+        let source = "🜹sin🜄x🜂;\n🜙twice🜄x🜂x*2;twice🜄3🜂;";
+        let compilation = compile::compile(
+            Codegen::new(&context),
+            &[("twice.wiz", source)],
+            &mut CompileOptions::default(),
+        )
+        .unwrap();
+        let options = DumpOptions {
+            max_depth: 12,
+            max_children: 16,
+            opt: OptLevel::Aggressive,
+        };
+        let dump = |kind| String::from_utf8(render(&compilation, kind, &options)).unwrap();
+
+        assert_eq!(
+            dump(DumpKind::Source),
+            format!("# --- twice.wiz ---\n{}", source)
+        );
+        assert!(dump(DumpKind::Tokens).starts_with("twice.wiz:1:1 🜹\ntwice.wiz:1:2 sin\n"));
+        assert!(dump(DumpKind::Tokens).contains("\ntwice.wiz:2:2 twice\n"));
+        assert_eq!(
+            dump(DumpKind::Symbols),
+            "extern sin🜄x🜂\nfunction twice🜄x🜂\n"
+        );
+        assert_eq!(
+            dump(DumpKind::Interface),
+            "# wizarding interface 1\n🜹twice🜄x🜂;\n"
+        );
+        assert_eq!(dump(DumpKind::Ir), compilation.ir());
+        assert!(dump(DumpKind::OptimizedIr).contains("define double @twice(double %x)"));
+        let bitcode = render(&compilation, DumpKind::Bytecode, &options);
+        assert_eq!(&bitcode[..4], b"BC\xc0\xde");
+
+        assert_eq!(
+            section(DumpKind::Symbols, b"extern sin\n"),
+            "--- symbols ---\nextern sin\n--- end symbols ---"
+        );
+        assert!(section(DumpKind::Bytecode, &bitcode).starts_with("--- bytecode ---\n4243c0de"));
+    }
+
+    #[test]
+    fn ir_and_bitcode_are_reproducible() {
+        let source = "🜹sin🜄x🜂;🜙wave🜄t🜂sin🜄t*2🜂 + 1;🜙twice🜄x🜂wave🜄x🜂*2;twice🜄0🜂;";
+        let options = DumpOptions {
+            max_depth: 12,
+            max_children: 16,
+            opt: OptLevel::Default,
+        };
+        let dumps = |path: &str| {
+            let context = Context::create();
+            let compilation = compile::compile(
+                Codegen::new(&context),
+                &[(path, source), ("<eval-1>", "wave🜄1🜂;")],
+                &mut CompileOptions::default(),
+            )
+            .unwrap();
+            [DumpKind::Ir, DumpKind::OptimizedIr, DumpKind::Bytecode]
+                .iter()
+                .map(|kind| render(&compilation, *kind, &options))
+                .collect::<Vec<_>>()
+        };
+        let first = dumps("/home/a/wave.wiz");
+        assert_eq!(first, dumps("/home/a/wave.wiz"));
+        assert_eq!(first, dumps("b/wave.wiz"));
+    }
+}
@@ -0,0 +1,625 @@
+use std::str::FromStr;
+
+use inkwell::{
+    execution_engine::{ExecutionEngine, JitFunction},
+    module::Module,
+    types::{BasicTypeEnum, FloatType},
+    values::FunctionValue,
+    OptimizationLevel,
+};
+
+use crate::ast::Expression;
+use crate::codegen::Codegen;
+use crate::console;
+use crate::runtime::{self, RuntimeError};
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum EngineError {
+    #[error("failed to create execution engine: {0}")]
+    Create(String),
+    #[error("unknown function {0}")]
+    UnknownFunction(String),
+    #[error("invalid number of args in call {0} expected {1} found {2}")]
+    InvalidCall(String, usize, usize),
+    #[error("jit calls support at most 4 args, {0} takes {1}")]
+    UnsupportedArity(String, usize),
+    #[error("{0} returns nothing, so it can't be called for a value")]
+    ReturnsNothing(String),
+    #[error("{0} isn't a void extern")]
+    NotAProcedure(String),
+    /// calling it as a function of doubles would be undefined behaviour
+    #[error("{0} is {1}, not a function of doubles returning a double")]
+    Signature(String, String),
+    #[error("{0} inputs but room for {1} outputs")]
+    LengthMismatch(usize, usize),
+    #[error(transparent)]
+    Trap(#[from] RuntimeError),
+}
+
+/// a host function that void externs can call through
+#[derive(Debug, Clone, Copy)]
+pub enum HostProcedure {
+    Nullary(extern "C" fn()),
+    Unary(extern "C" fn(f64)),
+}
+
+impl HostProcedure {
+    fn arity(&self) -> usize {
+        match self {
+            HostProcedure::Nullary(_) => 0,
+            HostProcedure::Unary(_) => 1,
+        }
+    }
+
+    fn address(&self) -> usize {
+        match *self {
+            HostProcedure::Nullary(procedure) => procedure as usize,
+            HostProcedure::Unary(procedure) => procedure as usize,
+        }
+    }
+}
+
+/// how hard llvm optimizes jitted code, on the usual 0 to 3 scale
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OptLevel {
+    None,
+    Less,
+    Default,
+    Aggressive,
+}
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+#[error(
+    "unknown optimization level {0}, expected one of 0, 1, 2, 3, none, less, default or aggressive"
+)]
+pub struct UnknownOptLevel(String);
+
+impl FromStr for OptLevel {
+    type Err = UnknownOptLevel;
+
+    fn from_str(level: &str) -> Result<OptLevel, UnknownOptLevel> {
+        match level.to_lowercase().as_str() {
+            "0" | "none" => Ok(OptLevel::None),
+            "1" | "less" => Ok(OptLevel::Less),
+            "2" | "default" => Ok(OptLevel::Default),
+            "3" | "aggressive" => Ok(OptLevel::Aggressive),
+            _ => Err(UnknownOptLevel(level.to_string())),
+        }
+    }
+}
+
+impl From<OptLevel> for OptimizationLevel {
+    fn from(level: OptLevel) -> OptimizationLevel {
+        match level {
+            OptLevel::None => OptimizationLevel::None,
+            OptLevel::Less => OptimizationLevel::Less,
+            OptLevel::Default => OptimizationLevel::Default,
+            OptLevel::Aggressive => OptimizationLevel::Aggressive,
+        }
+    }
+}
+
+/// which llvm execution engine to run compiled code with
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EngineKind {
+    Jit,
+    Interpreter,
+}
+
+type Fn0 = unsafe extern "C" fn() -> f64;
+type Fn1 = unsafe extern "C" fn(f64) -> f64;
+type Fn2 = unsafe extern "C" fn(f64, f64) -> f64;
+type Fn3 = unsafe extern "C" fn(f64, f64, f64) -> f64;
+type Fn4 = unsafe extern "C" fn(f64, f64, f64, f64) -> f64;
+
+/// set the flush to zero and denormals are zero bits of the host's mxcsr,
+/// returning the previous value
+#[cfg(target_arch = "x86_64")]
+#[allow(deprecated)]
+pub(crate) fn set_denormals_are_zero() -> u32 {
+    use std::arch::x86_64::{_mm_getcsr, _mm_setcsr};
+    const FTZ_DAZ: u32 = 0x8040;
+    unsafe {
+        let csr = _mm_getcsr();
+        _mm_setcsr(csr | FTZ_DAZ);
+        csr
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[allow(deprecated)]
+pub(crate) fn restore_csr(csr: u32) {
+    unsafe { std::arch::x86_64::_mm_setcsr(csr) }
+}
+
+// other hosts rely on the function attributes alone
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) fn set_denormals_are_zero() -> u32 {
+    0
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) fn restore_csr(_csr: u32) {}
+
+/// the function with the given symbol in module, checked against the one
+/// type everything is called as, arity doubles to a double - declarations
+/// from inline ir can have any type
+pub(crate) fn checked_function<'a>(
+    module: &Module<'a>,
+    f64_type: FloatType<'a>,
+    name: &str,
+    arity: usize,
+) -> Result<FunctionValue<'a>, EngineError> {
+    let func = match module.get_function(name) {
+        Some(func) => func,
+        None => return Err(EngineError::UnknownFunction(name.to_string())),
+    };
+    if func.get_type().get_return_type().is_none() {
+        return Err(EngineError::ReturnsNothing(name.to_string()));
+    }
+    let count = func.count_params() as usize;
+    if count != arity {
+        return Err(EngineError::InvalidCall(name.to_string(), count, arity));
+    }
+    let double = BasicTypeEnum::from(f64_type);
+    let doubles = func.get_type().get_return_type() == Some(double)
+        && func
+            .get_param_iter()
+            .all(|param| param.get_type() == double);
+    if !doubles || func.get_type().is_var_arg() {
+        return Err(EngineError::Signature(
+            name.to_string(),
+            func.get_type().print_to_string().to_string(),
+        ));
+    }
+    Ok(func)
+}
+
+/// an execution engine that hides whether code is jitted or interpreted
+pub struct Engine<'a> {
+    pub kind: EngineKind,
+    /// run calls with the host flushing denormals to zero
+    pub denormals_are_zero: bool,
+    module: &'a Module<'a>,
+    ee: ExecutionEngine<'a>,
+    f64_type: FloatType<'a>,
+    entries: Vec<(String, Expression)>,
+    trap_sites: Vec<String>,
+}
+
+/// the source of expr, cut down to at most width columns
+pub fn snippet(expr: &Expression, width: usize) -> String {
+    console::fit(&expr.to_string(), width)
+}
+
+impl<'a> Engine<'a> {
+    pub fn new(
+        codegen: &'a Codegen<'a>,
+        kind: EngineKind,
+        opt: OptLevel,
+    ) -> Result<Engine<'a>, EngineError> {
+        let _span = tracing::info_span!("engine", kind = ?kind, opt = ?opt).entered();
+        let module = &codegen.module;
+        let ee = match kind {
+            EngineKind::Jit => module.create_jit_execution_engine(opt.into()),
+            EngineKind::Interpreter => module.create_interpreter_execution_engine(),
+        }
+        .map_err(|e| EngineError::Create(e.to_string()))?;
+
+        Ok(Engine {
+            kind,
+            denormals_are_zero: false,
+            module,
+            ee,
+            f64_type: codegen.context.f64_type(),
+            entries: codegen.entries.clone(),
+            trap_sites: codegen.trap_sites.clone(),
+        })
+    }
+
+    /// the symbol of each top level expression's entry function with the
+    /// expression, in source order
+    pub fn entries(&self) -> &[(String, Expression)] {
+        &self.entries
+    }
+
+    /// call the zero argument entry function - the jit finishes compiling
+    /// the module the first time anything is looked up, so the first run's
+    /// span includes that
+    pub fn run_entry(&self, name: &str) -> Result<f64, EngineError> {
+        let _span = tracing::debug_span!("run", entry = name).entered();
+        self.call(name, &[])
+    }
+
+    /// run every top level expression in source order, pairing each result
+    /// with a snippet of its source - a failing entry doesn't stop the rest
+    pub fn run_all(&self, width: usize) -> Vec<(String, Result<f64, EngineError>)> {
+        self.entries
+            .iter()
+            .map(|(symbol, expr)| (snippet(expr, width), self.run_entry(symbol)))
+            .collect()
+    }
+
+    /// make calls to the void extern with the given symbol run procedure,
+    /// before anything is run - only the jit calls through it
+    pub fn register_procedure(
+        &self,
+        name: &str,
+        procedure: HostProcedure,
+    ) -> Result<(), EngineError> {
+        let func = match self.module.get_function(name) {
+            Some(func) => func,
+            None => return Err(EngineError::UnknownFunction(name.to_string())),
+        };
+        if func.get_first_basic_block().is_some() || func.get_type().get_return_type().is_some() {
+            return Err(EngineError::NotAProcedure(name.to_string()));
+        }
+        let arity = func.count_params() as usize;
+        if arity != procedure.arity() {
+            return Err(EngineError::InvalidCall(
+                name.to_string(),
+                arity,
+                procedure.arity(),
+            ));
+        }
+        self.ee.add_global_mapping(&func, procedure.address());
+        Ok(())
+    }
+
+    /// the function with the given symbol, checked against the one type
+    /// everything is called as
+    fn function(&self, name: &str, arity: usize) -> Result<FunctionValue<'a>, EngineError> {
+        checked_function(self.module, self.f64_type, name, arity)
+    }
+
+    /// make calls to the extern with the given symbol run the host function
+    /// at address, before anything is run - only the jit calls through it
+    ///
+    /// # Safety
+    ///
+    /// address has to be an `extern "C"` function taking arity doubles and
+    /// returning a double, and stay valid as long as the engine is used
+    pub unsafe fn register_function(
+        &self,
+        name: &str,
+        arity: usize,
+        address: usize,
+    ) -> Result<(), EngineError> {
+        let func = self.function(name, arity)?;
+        self.ee.add_global_mapping(&func, address);
+        Ok(())
+    }
+
+    /// the number of parameters of the function with the given symbol that
+    /// the program defines, externs aside
+    pub fn arity(&self, name: &str) -> Result<usize, EngineError> {
+        match self.module.get_function(name) {
+            Some(func) if func.get_first_basic_block().is_some() => {
+                Ok(func.count_params() as usize)
+            }
+            _ => Err(EngineError::UnknownFunction(name.to_string())),
+        }
+    }
+
+    /// the address of the jitted function with the given symbol once its type
+    /// is checked like call checks it, so it can be called as a function of
+    /// arity doubles returning a double
+    pub fn function_address(&self, name: &str, arity: usize) -> Result<usize, EngineError> {
+        self.function(name, arity)?;
+        self.address(name)
+    }
+
+    /// the address of the jitted function with the given symbol, which stays
+    /// valid as long as the engine
+    pub fn address(&self, name: &str) -> Result<usize, EngineError> {
+        if self.kind != EngineKind::Jit {
+            return Err(EngineError::UnknownFunction(name.to_string()));
+        }
+        self.ee
+            .get_function_address(name)
+            .map_err(|_| EngineError::UnknownFunction(name.to_string()))
+    }
+
+    /// call the function with the given symbol, once its type is checked -
+    /// if it trapped, the trap is the error
+    pub fn call(&self, name: &str, args: &[f64]) -> Result<f64, EngineError> {
+        let func = self.function(name, args.len())?;
+
+        let saved_csr = if self.denormals_are_zero {
+            Some(set_denormals_are_zero())
+        } else {
+            None
+        };
+        // a trap from a call made around the engine isn't this call's
+        let res = self
+            .take_trap()
+            .and_then(|_| self.call_unchecked(func, name, args))
+            .and_then(|value| self.take_trap().map(|_| value));
+        if let Some(csr) = saved_csr {
+            restore_csr(csr);
+        }
+        res
+    }
+
+    /// the trap in the module's trap slot, clearing it - a module that
+    /// can't trap has no slot
+    fn take_trap(&self) -> Result<(), EngineError> {
+        let read = |name| match self.module.get_function(name) {
+            Some(func) => self.call_unchecked(func, name, &[]).map(Some),
+            None => Ok(None),
+        };
+        match read(runtime::TAKE)? {
+            Some(kind) if kind != 0.0 => {
+                let site = read(runtime::SITE)?.unwrap();
+                let value = read(runtime::VALUE)?.unwrap();
+                Err(RuntimeError::decode(kind, site, value, &self.trap_sites).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn call_unchecked(
+        &self,
+        func: FunctionValue<'a>,
+        name: &str,
+        args: &[f64],
+    ) -> Result<f64, EngineError> {
+        match self.kind {
+            EngineKind::Jit => self.call_jit(name, args),
+            EngineKind::Interpreter => {
+                let gened_args: Vec<_> = args
+                    .iter()
+                    .map(|&arg| self.f64_type.create_generic_value(arg))
+                    .collect();
+                let arg_refs: Vec<_> = gened_args.iter().collect();
+                let res = unsafe { self.ee.run_function(func, &arg_refs) };
+                Ok(res.as_float(&self.f64_type))
+            }
+        }
+    }
+
+    fn call_jit(&self, name: &str, args: &[f64]) -> Result<f64, EngineError> {
+        let unknown = |_| EngineError::UnknownFunction(name.to_string());
+        unsafe {
+            match *args {
+                [] => {
+                    let func: JitFunction<Fn0> = self.ee.get_function(name).map_err(unknown)?;
+                    Ok(func.call())
+                }
+                [a] => {
+                    let func: JitFunction<Fn1> = self.ee.get_function(name).map_err(unknown)?;
+                    Ok(func.call(a))
+                }
+                [a, b] => {
+                    let func: JitFunction<Fn2> = self.ee.get_function(name).map_err(unknown)?;
+                    Ok(func.call(a, b))
+                }
+                [a, b, c] => {
+                    let func: JitFunction<Fn3> = self.ee.get_function(name).map_err(unknown)?;
+                    Ok(func.call(a, b, c))
+                }
+                [a, b, c, d] => {
+                    let func: JitFunction<Fn4> = self.ee.get_function(name).map_err(unknown)?;
+                    Ok(func.call(a, b, c, d))
+                }
+                _ => Err(EngineError::UnsupportedArity(name.to_string(), args.len())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use inkwell::{context::Context, OptimizationLevel};
+
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+    use super::{snippet, Engine, EngineError, EngineKind, HostProcedure, OptLevel};
+    use crate::{codegen::Codegen, parser::Parser, resolve};
+
+    fn run_all(source: &str, kind: EngineKind) -> (f64, f64) {
+        let ast = Parser::default().parse_str(source).unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        let engine = Engine::new(&codegen, kind, OptLevel::None).unwrap();
+        let entry = engine.run_entry("lambda").unwrap();
+        let called = engine.call("add", &[1.5, 2.0]).unwrap();
+        (entry, called)
+    }
+
+    #[test]
+    fn interpreter_matches_jit() {
+        let source = "🜙add🜄x🜌y🜂x+y;🜙sq🜄x🜂x*x;add🜄sq🜄3🜂🜌1🜂/2;";
+        let jit = run_all(source, EngineKind::Jit);
+        assert_eq!(jit, (5.0, 3.5));
+        assert_eq!(run_all(source, EngineKind::Interpreter), jit);
+    }
+
+    #[test]
+    fn call_checks_arity() {
+        let ast = Parser::default().parse_str("🜙one🜄x🜂x;").unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        assert!(matches!(
+            engine.call("one", &[]),
+            Err(EngineError::InvalidCall(_, 1, 0))
+        ));
+        assert!(matches!(
+            engine.run_entry("lambda"),
+            Err(EngineError::UnknownFunction(_))
+        ));
+    }
+
+    #[test]
+    fn call_checks_signatures() {
+        let parser = Parser {
+            allow_inline_ir: true,
+            ..Parser::default()
+        };
+        // calling fabs from the ir is fine, calling its f32 intrinsic is not
+        let source = "🜙half🜄x🜂x/2;🜹void beep🜄🜂;llvm \"declare float @llvm.fabs.f32(float)
+define double @fabs(double %x) {
+  %f = fptrunc double %x to float
+  %a = call float @llvm.fabs.f32(float %f)
+  %r = fpext float %a to double
+  ret double %r
+}\";";
+        let ast = parser.parse_str(source).unwrap();
+        for kind in [EngineKind::Jit, EngineKind::Interpreter].iter() {
+            let context = Context::create();
+            let mut codegen = Codegen::new(&context);
+            codegen.codegen(&ast).unwrap();
+            let engine = Engine::new(&codegen, *kind, OptLevel::None).unwrap();
+            assert_eq!(
+                engine.run_entry("half"),
+                Err(EngineError::InvalidCall("half".to_string(), 1, 0))
+            );
+            assert_eq!(
+                engine.run_entry("beep"),
+                Err(EngineError::ReturnsNothing("beep".to_string()))
+            );
+            let err = engine.call("llvm.fabs.f32", &[-1.0]).unwrap_err();
+            assert_eq!(
+                err,
+                EngineError::Signature("llvm.fabs.f32".to_string(), "float (float)".to_string())
+            );
+            assert_eq!(
+                err.to_string(),
+                "llvm.fabs.f32 is float (float), not a function of doubles returning a double"
+            );
+            // the interpreter can't run the intrinsic at all
+            if *kind == EngineKind::Jit {
+                assert_eq!(engine.call("fabs", &[-1.5]), Ok(1.5));
+            }
+        }
+    }
+
+    #[test]
+    fn denormals_are_zero_works() {
+        let ast = Parser::default().parse_str("🜙half🜄x🜂x/2;").unwrap();
+        for kind in [EngineKind::Jit, EngineKind::Interpreter].iter() {
+            let context = Context::create();
+            let mut codegen = Codegen::new(&context);
+            codegen.denormals_are_zero = true;
+            codegen.codegen(&ast).unwrap();
+            let mut engine = Engine::new(&codegen, *kind, OptLevel::None).unwrap();
+            assert!(engine.call("half", &[f64::MIN_POSITIVE]).unwrap() > 0.0);
+            engine.denormals_are_zero = true;
+            if cfg!(target_arch = "x86_64") {
+                assert_eq!(engine.call("half", &[f64::MIN_POSITIVE]).unwrap(), 0.0);
+            }
+            engine.denormals_are_zero = false;
+            assert!(engine.call("half", &[f64::MIN_POSITIVE]).unwrap() > 0.0);
+        }
+    }
+
+    #[test]
+    fn opt_level_parse_works() {
+        let spellings = [
+            ("0", OptLevel::None, OptimizationLevel::None),
+            ("1", OptLevel::Less, OptimizationLevel::Less),
+            ("2", OptLevel::Default, OptimizationLevel::Default),
+            ("3", OptLevel::Aggressive, OptimizationLevel::Aggressive),
+            ("none", OptLevel::None, OptimizationLevel::None),
+            ("Less", OptLevel::Less, OptimizationLevel::Less),
+            ("DEFAULT", OptLevel::Default, OptimizationLevel::Default),
+            (
+                "aggressive",
+                OptLevel::Aggressive,
+                OptimizationLevel::Aggressive,
+            ),
+        ];
+        for (spelling, level, llvm) in spellings.iter() {
+            assert_eq!(spelling.parse::<OptLevel>().as_ref(), Ok(level));
+            assert_eq!(OptimizationLevel::from(*level), *llvm);
+        }
+        let err = "4".parse::<OptLevel>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown optimization level 4, expected one of 0, 1, 2, 3, none, less, default or aggressive"
+        );
+    }
+
+    #[test]
+    fn run_all_works() {
+        let source = "🜙sq🜄x🜂x*x;sq🜄3🜂;1 + 2;sq🜄1🜂 + sq🜄2🜂 + sq🜄3🜂;";
+        let ast = Parser::default().parse_str(source).unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        let mut engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        let results = engine.run_all(12);
+        assert_eq!(
+            results,
+            [
+                ("sq🜄3🜂".to_string(), Ok(9.0)),
+                ("1 + 2".to_string(), Ok(3.0)),
+                ("sq🜄1🜂 + sq🜄…".to_string(), Ok(14.0)),
+            ]
+        );
+        let missing = ("missing".to_string(), engine.entries[0].1.clone());
+        engine.entries.insert(1, missing);
+        let results = engine.run_all(40);
+        assert_eq!(results.len(), 4);
+        assert!(matches!(results[1].1, Err(EngineError::UnknownFunction(_))));
+        assert_eq!(results[2].1, Ok(3.0));
+        assert_eq!(results[3].1, Ok(14.0));
+        assert_eq!(snippet(&engine.entries[0].1, 5), "sq🜄3🜂");
+        assert_eq!(snippet(&engine.entries[0].1, 4), "sq🜄…");
+    }
+
+    static BEEPS: AtomicUsize = AtomicUsize::new(0);
+    static LOGGED: AtomicU64 = AtomicU64::new(0);
+
+    extern "C" fn beep() {
+        BEEPS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    extern "C" fn log_value(x: f64) {
+        LOGGED.store(x.to_bits(), Ordering::SeqCst);
+    }
+
+    #[test]
+    fn host_procedures_work() {
+        let source = "🜹void beep🜄🜂;🜹void log_value🜄x🜂;🜙twice🜄x🜂x*2;\
+                      beep🜄🜂;log_value🜄twice🜄1.25🜂🜂;beep🜄🜂;";
+        let mut ast = Parser::default().parse_str(source).unwrap();
+        resolve::resolve(&mut ast).unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        assert!(codegen
+            .module
+            .print_to_string()
+            .to_string()
+            .contains("declare void @log_value(double)"));
+
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        engine
+            .register_procedure("beep", HostProcedure::Nullary(beep))
+            .unwrap();
+        engine
+            .register_procedure("log_value", HostProcedure::Unary(log_value))
+            .unwrap();
+        let results: Vec<_> = engine.run_all(40).into_iter().map(|(_, r)| r).collect();
+        assert_eq!(results, [Ok(0.0), Ok(0.0), Ok(0.0)]);
+        assert_eq!(BEEPS.load(Ordering::SeqCst), 2);
+        assert_eq!(f64::from_bits(LOGGED.load(Ordering::SeqCst)), 2.5);
+
+        assert_eq!(
+            engine.call("beep", &[]),
+            Err(EngineError::ReturnsNothing("beep".to_string()))
+        );
+        assert_eq!(
+            engine.register_procedure("twice", HostProcedure::Unary(log_value)),
+            Err(EngineError::NotAProcedure("twice".to_string()))
+        );
+        assert_eq!(
+            engine.register_procedure("beep", HostProcedure::Unary(log_value)),
+            Err(EngineError::InvalidCall("beep".to_string(), 0, 1))
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! a library's interface - the extern declarations a program compiled on
+//! its own needs to call into it, written with --dump interface and read
+//! back with --with-interface, so calls are checked against it without the
+//! library's source and its bitcode is linked in with --link
+//!
+//! it's plain source under a header naming the format's version, one
+//! extern for each function and table the library defines
+//!
+//! both files are found where they were named, or else on the library
+//! path, so a library can be shared between projects without copying it
+use std::path::{Path, PathBuf};
+
+use crate::ast::ASTNode;
+use crate::compile::{SymbolInfo, SymbolKind};
+use crate::parser::Parser;
+
+/// the layout this build writes and reads, bumped whenever it changes
+pub const VERSION: u32 = 1;
+const HEADER: &str = "# wizarding interface ";
+/// the variable whose directories are searched after the --lib-path ones
+pub const PATH_VAR: &str = "WIZARDING_PATH";
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum InterfaceError {
+    #[error("not an interface, it has to start with {}{}", HEADER, VERSION)]
+    NotAnInterface,
+    #[error("interface format {0}, this build reads format {current}", current = VERSION)]
+    Version(u32),
+    #[error("interfaces only declare externs, this one has {0}")]
+    Defines(String),
+    #[error("the interface doesn't parse: {0}")]
+    Parse(String),
+    #[error("{0} isn't where it was named or on the library path")]
+    NotFound(String),
+    #[error("{0} is only on the library path, which isn't searched when frozen")]
+    Frozen(String),
+}
+
+/// the directories to look for a library file in when it isn't where it
+/// was named, in order
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LibPath {
+    pub dirs: Vec<PathBuf>,
+    /// only find files where they were named, for reproducible builds
+    pub frozen: bool,
+}
+
+/// a library file and the other directories on the path that have one by
+/// the same name, which lost to it
+#[derive(Debug, PartialEq, Clone)]
+pub struct Found {
+    pub path: PathBuf,
+    pub shadowed: Vec<PathBuf>,
+}
+
+impl LibPath {
+    /// dirs followed by the directories of PATH_VAR
+    pub fn with_env(dirs: impl IntoIterator<Item = PathBuf>, frozen: bool) -> LibPath {
+        let mut dirs: Vec<_> = dirs.into_iter().collect();
+        if let Some(var) = std::env::var_os(PATH_VAR) {
+            dirs.extend(std::env::split_paths(&var));
+        }
+        LibPath { dirs, frozen }
+    }
+
+    /// name where it was named, or else its absolute path in the first
+    /// directory that has it
+    pub fn find(&self, name: &Path) -> Result<Found, InterfaceError> {
+        if name.is_file() {
+            return Ok(Found {
+                path: name.to_path_buf(),
+                shadowed: Vec::new(),
+            });
+        }
+        let missing = || name.display().to_string();
+        if name.is_absolute() {
+            return Err(InterfaceError::NotFound(missing()));
+        }
+        let mut candidates = self
+            .dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .filter(|path| path.is_file());
+        let path = candidates
+            .next()
+            .ok_or_else(|| InterfaceError::NotFound(missing()))?;
+        if self.frozen {
+            return Err(InterfaceError::Frozen(missing()));
+        }
+        Ok(Found {
+            path: path.canonicalize().unwrap_or(path),
+            shadowed: candidates.collect(),
+        })
+    }
+}
+
+/// the interface of a library with symbols - what it declares extern
+/// itself isn't part of it
+pub fn render(symbols: &[SymbolInfo]) -> String {
+    let mut res = format!("{}{}\n", HEADER, VERSION);
+    for symbol in symbols {
+        match symbol.kind {
+            SymbolKind::Function => {
+                res += &format!("🜹{}🜄{}🜂;\n", symbol.name, symbol.params.join("🜌"))
+            }
+            SymbolKind::Table => res += &format!("🜹{}🜄i🜂;\n🜹{}_len🜄🜂;\n", symbol.name, symbol.name),
+            SymbolKind::Extern => (),
+        }
+    }
+    res
+}
+
+/// check source is an interface this build can read, before it's compiled
+/// with the program like any other source
+pub fn check(source: &str) -> Result<(), InterfaceError> {
+    let version = source
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix(HEADER))
+        .and_then(|version| version.trim().parse().ok())
+        .ok_or(InterfaceError::NotAnInterface)?;
+    if version != VERSION {
+        return Err(InterfaceError::Version(version));
+    }
+    let ast = Parser::default()
+        .parse_str(source)
+        .map_err(|err| InterfaceError::Parse(err.to_string()))?;
+    match ast.iter().find(|node| !matches!(node, ASTNode::Extern(_))) {
+        Some(node) => Err(InterfaceError::Defines(node.summary())),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn libraries_are_found() {
+        let root = std::env::temp_dir().join(format!("wizarding-libpath-{}", std::process::id()));
+        let (first, second) = (root.join("first"), root.join("second"));
+        for dir in &[&root, &first, &second] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        let here = root.join("lib.wizi");
+        for path in &[&here, &first.join("lib.wizi"), &second.join("lib.wizi")] {
+            std::fs::write(path, "").unwrap();
+        }
+        std::fs::write(second.join("other.wizi"), "").unwrap();
+        let lib_path = LibPath {
+            dirs: vec![first.clone(), second.clone()],
+            frozen: false,
+        };
+
+        // where it was named wins over the library path
+        let found = lib_path.find(&here).unwrap();
+        assert_eq!((found.path, found.shadowed), (here.clone(), Vec::new()));
+        let found = lib_path.find(Path::new("other.wizi")).unwrap();
+        assert_eq!(
+            found.path,
+            second.join("other.wizi").canonicalize().unwrap()
+        );
+        // the first directory wins, and the others are named
+        let found = lib_path.find(Path::new("lib.wizi")).unwrap();
+        assert_eq!(found.path, first.join("lib.wizi").canonicalize().unwrap());
+        assert_eq!(found.shadowed, [second.join("lib.wizi")]);
+        assert_eq!(
+            lib_path.find(Path::new("nope.wizi")),
+            Err(InterfaceError::NotFound("nope.wizi".to_string()))
+        );
+
+        let frozen = LibPath {
+            frozen: true,
+            ..lib_path
+        };
+        assert_eq!(frozen.find(&here).unwrap().path, here);
+        assert_eq!(
+            frozen.find(Path::new("other.wizi")),
+            Err(InterfaceError::Frozen("other.wizi".to_string()))
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn interfaces_round_trip() {
+        let symbol = |name: &str, kind, params: &[&str]| SymbolInfo {
+            name: name.to_string(),
+            kind,
+            params: params.iter().map(|param| param.to_string()).collect(),
+        };
+        let symbols = [
+            symbol("sin", SymbolKind::Extern, &["x"]),
+            symbol("hyp", SymbolKind::Function, &["a", "b"]),
+            symbol("coeffs", SymbolKind::Table, &["i"]),
+            symbol("two", SymbolKind::Function, &[]),
+        ];
+        let interface = render(&symbols);
+        assert_eq!(
+            interface,
+            "# wizarding interface 1\n🜹hyp🜄a🜌b🜂;\n🜹coeffs🜄i🜂;\n🜹coeffs_len🜄🜂;\n🜹two🜄🜂;\n"
+        );
+        assert_eq!(check(&interface), Ok(()));
+
+        assert_eq!(check("🜹hyp🜄a🜌b🜂;"), Err(InterfaceError::NotAnInterface));
+        assert_eq!(
+            check("# wizarding interface 2\n🜹hyp🜄a🜌b🜂;"),
+            Err(InterfaceError::Version(2))
+        );
+        assert!(matches!(
+            check("# wizarding interface 1\n🜙hyp🜄a🜌b🜂a+b;"),
+            Err(InterfaceError::Defines(_))
+        ));
+        assert!(matches!(
+            check("# wizarding interface 1\n🜹hyp🜄a🜌"),
+            Err(InterfaceError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,173 @@
+use std::ops;
+
+use crate::ast::Expression;
+use crate::builtins;
+
+/// a closed range of values an expression is known to lie in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Interval {
+    pub lo: f64,
+    pub hi: f64,
+}
+
+impl Interval {
+    pub fn new(lo: f64, hi: f64) -> Interval {
+        if lo.is_nan() || hi.is_nan() || lo > hi {
+            Interval::unbounded()
+        } else {
+            Interval { lo, hi }
+        }
+    }
+
+    pub fn point(value: f64) -> Interval {
+        Interval::new(value, value)
+    }
+
+    pub fn unbounded() -> Interval {
+        Interval {
+            lo: f64::NEG_INFINITY,
+            hi: f64::INFINITY,
+        }
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        self.lo <= value && value <= self.hi
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.lo == 0.0 && self.hi == 0.0
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.lo.is_finite() && self.hi.is_finite()
+    }
+
+    /// the smallest interval holding every candidate bound, unbounded if any is nan
+    fn hull(bounds: &[f64]) -> Interval {
+        if bounds.iter().any(|bound| bound.is_nan()) {
+            return Interval::unbounded();
+        }
+        let lo = bounds.iter().cloned().fold(f64::INFINITY, f64::min);
+        let hi = bounds.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        Interval::new(lo, hi)
+    }
+
+    /// apply a non decreasing function to both bounds
+    pub fn map_monotonic(self, func: fn(f64) -> f64) -> Interval {
+        Interval::new(func(self.lo), func(self.hi))
+    }
+}
+
+impl ops::Add for Interval {
+    type Output = Interval;
+
+    fn add(self, other: Interval) -> Interval {
+        Interval::hull(&[self.lo + other.lo, self.hi + other.hi])
+    }
+}
+
+impl ops::Sub for Interval {
+    type Output = Interval;
+
+    fn sub(self, other: Interval) -> Interval {
+        Interval::hull(&[self.lo - other.hi, self.hi - other.lo])
+    }
+}
+
+impl ops::Mul for Interval {
+    type Output = Interval;
+
+    fn mul(self, other: Interval) -> Interval {
+        Interval::hull(&[
+            self.lo * other.lo,
+            self.lo * other.hi,
+            self.hi * other.lo,
+            self.hi * other.hi,
+        ])
+    }
+}
+
+impl ops::Div for Interval {
+    type Output = Interval;
+
+    fn div(self, other: Interval) -> Interval {
+        if other.contains(0.0) {
+            Interval::unbounded()
+        } else {
+            Interval::hull(&[
+                self.lo / other.lo,
+                self.lo / other.hi,
+                self.hi / other.lo,
+                self.hi / other.hi,
+            ])
+        }
+    }
+}
+
+/// bounds on an expression that can never be nan or infinite - unlike the
+/// domain lint's intervals, anything that might be is None
+pub fn finite_bounds(expr: &Expression) -> Option<Interval> {
+    let bounds = match expr {
+        Expression::Literal(value) => Interval::point(*value),
+        Expression::Variable(_) | Expression::Error(_) => return None,
+        Expression::Binary(op, lhs, rhs) => {
+            let (lhs, rhs) = (finite_bounds(lhs)?, finite_bounds(rhs)?);
+            match op.as_str() {
+                "+" => lhs + rhs,
+                "-" => lhs - rhs,
+                "*" => lhs * rhs,
+                "/" if !rhs.contains(0.0) => lhs / rhs,
+                _ => return None,
+            }
+        }
+        Expression::Call(callee, args) => match (callee.as_str(), args.as_slice()) {
+            ("sin" | "cos", [arg]) => {
+                finite_bounds(arg)?;
+                Interval::new(-1.0, 1.0)
+            }
+            (name, []) => Interval::point(builtins::constant(name)?),
+            _ => return None,
+        },
+        Expression::NamedCall(..) => return None,
+    };
+    Some(bounds).filter(Interval::is_finite)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{finite_bounds, Interval};
+    use crate::parser::Parser;
+
+    #[test]
+    fn interval_arithmetic_works() {
+        let a = Interval::new(-1.0, 2.0);
+        let b = Interval::new(3.0, 4.0);
+        assert_eq!(a + b, Interval::new(2.0, 6.0));
+        assert_eq!(a - b, Interval::new(-5.0, -1.0));
+        assert_eq!(a * b, Interval::new(-4.0, 8.0));
+        assert_eq!(b / Interval::new(1.0, 2.0), Interval::new(1.5, 4.0));
+        assert_eq!(b / a, Interval::unbounded());
+        assert_eq!(
+            Interval::point(0.0) * Interval::unbounded(),
+            Interval::unbounded()
+        );
+        assert_eq!(
+            Interval::new(4.0, 9.0).map_monotonic(f64::sqrt),
+            Interval::new(2.0, 3.0)
+        );
+        assert!(Interval::point(0.0).is_zero());
+        assert!(!a.is_zero());
+    }
+
+    #[test]
+    fn finite_bounds_works() {
+        let bounds =
+            |source: &str| finite_bounds(&Parser::default().parse_expression_str(source).unwrap());
+        assert_eq!(bounds("1 + sin🜄pi🜄🜂🜂"), Some(Interval::new(0.0, 2.0)));
+        assert_eq!(bounds("2 / 🜄1 - 1🜂"), None);
+        assert_eq!(bounds(&format!("1{} * 10", "0".repeat(308))), None);
+        for source in ["x", "sin🜄inf🜄🜂🜂", "nan🜄🜂", "sqrt🜄4🜂"].iter() {
+            assert_eq!(bounds(source), None, "{}", source);
+        }
+    }
+}
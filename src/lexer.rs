@@ -1,12 +1,96 @@
-use std::fmt;
+use std::{fmt, ops::Range};
 
 use lazy_static::lazy_static;
 use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
 
+use crate::numfmt;
+
+/// a token borrowing its text from the source it was lexed from
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Token<'src> {
+    Def,
+    Extern,
+    /// kept for control flow, which doesn't exist yet
+    If,
+    Then,
+    Else,
+    For,
+    In,
+    Delimiter,
+    OpenParen,
+    CloseParen,
+    Comma,
+    Ident(&'src str),
+    Operator(&'src str),
+    Number(f64),
+    /// true or false, which are keywords rather than identifiers
+    Bool(bool),
+    /// the text between the quotes as it's written, escapes and all
+    Str(&'src str),
+    /// the text after the #, only kept by lex_with_trivia
+    Comment(&'src str),
+}
+
+impl Token<'_> {
+    pub fn into_owned(self) -> OwnedToken {
+        match self {
+            Token::Def => OwnedToken::Def,
+            Token::Extern => OwnedToken::Extern,
+            Token::If => OwnedToken::If,
+            Token::Then => OwnedToken::Then,
+            Token::Else => OwnedToken::Else,
+            Token::For => OwnedToken::For,
+            Token::In => OwnedToken::In,
+            Token::Delimiter => OwnedToken::Delimiter,
+            Token::OpenParen => OwnedToken::OpenParen,
+            Token::CloseParen => OwnedToken::CloseParen,
+            Token::Comma => OwnedToken::Comma,
+            Token::Ident(ident) => OwnedToken::Ident(ident.to_string()),
+            Token::Operator(op) => OwnedToken::Operator(op.to_string()),
+            Token::Number(num) => OwnedToken::Number(num),
+            Token::Bool(value) => OwnedToken::Bool(value),
+            Token::Str(string) => OwnedToken::Str(string.to_string()),
+            Token::Comment(text) => OwnedToken::Comment(text.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Def => write!(f, "🜙"),
+            Token::Extern => write!(f, "🜹"),
+            Token::If => write!(f, "if"),
+            Token::Then => write!(f, "then"),
+            Token::Else => write!(f, "else"),
+            Token::For => write!(f, "for"),
+            Token::In => write!(f, "in"),
+            Token::Delimiter => write!(f, ";"),
+            Token::OpenParen => write!(f, "🜄"),
+            Token::CloseParen => write!(f, "🜂"),
+            Token::Comma => write!(f, "🜌"),
+            Token::Ident(ident) => write!(f, "{}", ident),
+            Token::Operator(op) => write!(f, "{}", op),
+            Token::Number(num) => write!(f, "{}", numfmt::shortest(*num)),
+            Token::Bool(value) => write!(f, "{}", value),
+            // already escaped as written, so it lexes back to the same token
+            Token::Str(string) => write!(f, "\"{}\"", string),
+            Token::Comment(text) => write!(f, "#{}", text),
+        }
+    }
+}
+
+/// a token that owns its text, for errors that outlive the source
 #[derive(Debug, PartialEq, Clone)]
-pub enum Token {
+pub enum OwnedToken {
     Def,
     Extern,
+    If,
+    Then,
+    Else,
+    For,
+    In,
     Delimiter,
     OpenParen,
     CloseParen,
@@ -14,52 +98,658 @@ pub enum Token {
     Ident(String),
     Operator(String),
     Number(f64),
+    Bool(bool),
+    Str(String),
+    Comment(String),
+}
+
+impl OwnedToken {
+    pub fn as_token(&self) -> Token<'_> {
+        match self {
+            OwnedToken::Def => Token::Def,
+            OwnedToken::Extern => Token::Extern,
+            OwnedToken::If => Token::If,
+            OwnedToken::Then => Token::Then,
+            OwnedToken::Else => Token::Else,
+            OwnedToken::For => Token::For,
+            OwnedToken::In => Token::In,
+            OwnedToken::Delimiter => Token::Delimiter,
+            OwnedToken::OpenParen => Token::OpenParen,
+            OwnedToken::CloseParen => Token::CloseParen,
+            OwnedToken::Comma => Token::Comma,
+            OwnedToken::Ident(ident) => Token::Ident(ident),
+            OwnedToken::Operator(op) => Token::Operator(op),
+            OwnedToken::Number(num) => Token::Number(*num),
+            OwnedToken::Bool(value) => Token::Bool(*value),
+            OwnedToken::Str(string) => Token::Str(string),
+            OwnedToken::Comment(text) => Token::Comment(text),
+        }
+    }
 }
 
-impl fmt::Display for Token {
+impl fmt::Display for OwnedToken {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_token().fmt(f)
+    }
+}
+
+/// a part of the input that isn't a token, with its byte range
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum LexError {
+    #[error("invalid number {text}")]
+    InvalidNumber { text: String, span: Range<usize> },
+    /// anything that isn't part of a token, like @ or a smart quote
+    #[error("unexpected character {character:?}")]
+    UnexpectedCharacter { character: char, span: Range<usize> },
+    #[error("unknown escape {text} in a string")]
+    InvalidEscape { text: String, span: Range<usize> },
+    /// at the quote that's never closed
+    #[error("unterminated string")]
+    UnterminatedString { span: Range<usize> },
+}
+
+impl LexError {
+    pub fn span(&self) -> &Range<usize> {
         match self {
-            tok => write!(f, "{:?}", tok),
+            LexError::InvalidNumber { span, .. }
+            | LexError::UnexpectedCharacter { span, .. }
+            | LexError::InvalidEscape { span, .. }
+            | LexError::UnterminatedString { span } => span,
+        }
+    }
+}
+
+/// the glyphs that stand for the keywords and punctuation - the ascii
+/// spellings like def and ( are always there too
+#[derive(Debug, PartialEq, Clone)]
+pub struct Glyphs {
+    pub def: char,
+    pub extern_: char,
+    pub open_paren: char,
+    pub close_paren: char,
+    pub comma: char,
+}
+
+impl Default for Glyphs {
+    fn default() -> Self {
+        Glyphs {
+            def: '🜙',
+            extern_: '🜹',
+            open_paren: '🜄',
+            close_paren: '🜂',
+            comma: '🜌',
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum GlyphError {
+    #[error("{0:?} can't stand for more than one token")]
+    Shared(char),
+    /// the glyph would be read as part of an identifier, number, operator or
+    /// some other token already
+    #[error("{0:?} is already part of another token")]
+    Taken(char),
+}
+
+/// the characters the lexer already has a use for
+const TAKEN: &str = ";,()\"#._+-*/<>=!%^&|~?:";
+
+/// the glyphs a lexer reads, checked so they can't be mistaken for anything
+/// else
+#[derive(Debug, PartialEq, Clone)]
+pub struct LexerConfig {
+    glyphs: Glyphs,
+    /// end a top level item at the end of its line, as if there was a ;
+    /// there - see ItemEnds for which lines go on
+    pub newline_delimiters: bool,
+}
+
+impl LexerConfig {
+    pub fn new(glyphs: Glyphs) -> Result<LexerConfig, GlyphError> {
+        let all = [
+            glyphs.def,
+            glyphs.extern_,
+            glyphs.open_paren,
+            glyphs.close_paren,
+            glyphs.comma,
+        ];
+        for (i, glyph) in all.iter().enumerate() {
+            if all[..i].contains(glyph) {
+                return Err(GlyphError::Shared(*glyph));
+            }
+            if TAKEN.contains(*glyph) || is_word(*glyph) || glyph.is_whitespace() {
+                return Err(GlyphError::Taken(*glyph));
+            }
         }
+        Ok(LexerConfig {
+            glyphs,
+            newline_delimiters: false,
+        })
+    }
+
+    pub fn glyphs(&self) -> &Glyphs {
+        &self.glyphs
+    }
+}
+
+impl Default for LexerConfig {
+    fn default() -> Self {
+        LexerConfig::new(Glyphs::default()).expect("the default glyphs are distinct")
     }
 }
 
 lazy_static! {
-    static ref IGNORE_RE: Regex = Regex::new(r"(?m)#.*$").unwrap();
-    static ref TOKEN_RE: Regex = Regex::new(&[
-        r"(?P<ident>\p{Alphabetic}\w*)",
-        r"(?P<extern>🜹)",
-        r"(?P<def>🜙)",
-        r"(?P<number>\d+\.?\d*)",
-        r"(?P<delimiter>;)",
-        r"(?P<oppar>🜄)",
-        r"(?P<clpar>🜂)",
-        r"(?P<comma>🜌)",
-        r"(?P<operator>\S)"
-    ].join("|"))
-    .unwrap();
+    static ref WORD_RE: Regex = Regex::new(r"^\w$").unwrap();
+    static ref DIGIT_RE: Regex = Regex::new(r"^\d$").unwrap();
+}
+
+/// a character that can be part of an identifier, \w in a regex
+fn is_word(c: char) -> bool {
+    match c.is_ascii() {
+        true => c.is_ascii_alphanumeric() || c == '_',
+        false => WORD_RE.is_match(c.encode_utf8(&mut [0; 4])),
+    }
+}
+
+/// a decimal digit in any script, \d in a regex
+fn is_digit(c: char) -> bool {
+    match c.is_ascii() {
+        true => c.is_ascii_digit(),
+        false => DIGIT_RE.is_match(c.encode_utf8(&mut [0; 4])),
+    }
+}
+
+/// an identifier's text in NFC, so a name written with a combining accent is
+/// the same name as one written precomposed - tokens keep the text as it's
+/// written, and the parser normalizes every name it keeps
+pub fn normalize(ident: &str) -> String {
+    match ident.is_ascii() {
+        true => ident.to_string(),
+        false => ident.nfc().collect(),
+    }
+}
+
+/// the characters a run of them is one operator
+const OPERATOR_CHARS: &str = "+*/<>=!%^&|~?:";
+
+/// the escapes a string can have and what they stand for
+const ESCAPES: &[(char, char)] = &[('n', '\n'), ('t', '\t'), ('\\', '\\'), ('"', '"')];
+
+/// every backslash in the string at start has to be one of the escapes
+fn check_escapes(string: &str, start: usize) -> Result<(), LexError> {
+    let mut chars = string.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            continue;
+        }
+        match chars.next() {
+            Some((_, escaped)) if ESCAPES.iter().any(|(e, _)| *e == escaped) => (),
+            escaped => {
+                let end = escaped.map_or(string.len(), |(j, escaped)| j + escaped.len_utf8());
+                return Err(LexError::InvalidEscape {
+                    text: string[i..end].to_string(),
+                    span: start + i..start + end,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// the text of a string token with its escapes replaced
+pub fn unescape(string: &str) -> String {
+    let mut res = String::with_capacity(string.len());
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let escaped = chars.next();
+                let (_, value) = ESCAPES
+                    .iter()
+                    .find(|(e, _)| Some(*e) == escaped)
+                    .expect("the lexer checked the escapes");
+                res.push(*value);
+            }
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// text written as the inside of a string token, the inverse of unescape
+pub fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match ESCAPES.iter().find(|(_, value)| *value == c) {
+            Some((escaped, _)) => {
+                res.push('\\');
+                res.push(*escaped);
+            }
+            None => res.push(c),
+        }
+    }
+    res
 }
 
-fn preprocess(input: &str) -> String {
-    IGNORE_RE.replace_all(input, "").to_string()
+/// the digits of a number without the underscores separating them, None if
+/// an underscore isn't between two digits
+fn separated(number: &str, is_digit: impl Fn(char) -> bool) -> Option<String> {
+    let chars: Vec<char> = number.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        let between = i > 0
+            && is_digit(chars[i - 1])
+            && chars.get(i + 1).is_some_and(|next| is_digit(*next));
+        if *c == '_' && !between {
+            return None;
+        }
+    }
+    Some(number.replace('_', ""))
 }
 
 /// lex the given input string - returns a stack, so first-on last-off
-pub fn lex(input: &str) -> Vec<Token> {
-    let preprocessed = preprocess(input);
+pub fn lex(input: &str) -> Result<Vec<Token<'_>>, LexError> {
+    let mut res = Lexer::new(input).collect::<Result<Vec<_>, _>>()?;
+    res.reverse();
+    Ok(res)
+}
+
+/// the tokens of the input in source order, lexed as they're asked for -
+/// after an error it carries on with the token after the bad one
+pub struct Lexer<'src> {
+    input: &'src str,
+    pos: usize,
+    glyphs: Glyphs,
+}
+
+impl<'src> Lexer<'src> {
+    pub fn new(input: &'src str) -> Lexer<'src> {
+        Lexer::with_glyphs(input, Glyphs::default())
+    }
+
+    fn with_glyphs(input: &'src str, glyphs: Glyphs) -> Lexer<'src> {
+        Lexer {
+            input,
+            pos: 0,
+            glyphs,
+        }
+    }
+
+    fn rest(&self) -> &'src str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    /// step over the characters that match
+    fn eat_while(&mut self, matches: impl Fn(char) -> bool) {
+        let rest = self.rest();
+        self.pos += rest.find(|c| !matches(c)).unwrap_or(rest.len());
+    }
+
+    /// step over the next character if it matches
+    fn eat_if(&mut self, matches: impl Fn(char) -> bool) -> bool {
+        match self.peek() {
+            Some(c) if matches(c) => {
+                self.pos += c.len_utf8();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// the next token and its byte range, comments included - comments are
+    /// tokens like any other here, so a # in a string is part of the string
+    fn next_spanned(&mut self) -> Option<Result<(Token<'src>, Range<usize>), LexError>> {
+        self.eat_while(char::is_whitespace);
+        let start = self.pos;
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        let tok = match c {
+            '#' => {
+                self.eat_while(|c| c != '\n');
+                Token::Comment(&self.input[start + 1..self.pos])
+            }
+            c if c == self.glyphs.extern_ => Token::Extern,
+            c if c == self.glyphs.def => Token::Def,
+            c if c.is_alphabetic() => {
+                self.eat_while(is_word);
+                // the keywords are spelled out too for keyboards without the
+                // glyphs, and they're only keywords as whole words
+                match &self.input[start..self.pos] {
+                    "extern" => Token::Extern,
+                    "def" => Token::Def,
+                    "if" => Token::If,
+                    "then" => Token::Then,
+                    "else" => Token::Else,
+                    "for" => Token::For,
+                    "in" => Token::In,
+                    "true" => Token::Bool(true),
+                    "false" => Token::Bool(false),
+                    ident => Token::Ident(ident),
+                }
+            }
+            '0' if matches!(self.peek(), Some('x') | Some('b')) => return Some(self.radix(start)),
+            c if is_digit(c) || c == '.' || self.starts_number(c) => {
+                return Some(self.number(start))
+            }
+            ';' => Token::Delimiter,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            ',' => Token::Comma,
+            c if c == self.glyphs.open_paren => Token::OpenParen,
+            c if c == self.glyphs.close_paren => Token::CloseParen,
+            c if c == self.glyphs.comma => Token::Comma,
+            '"' => return Some(self.string(start)),
+            // minus is never part of a run, since it also negates what comes
+            // after it, as in x*-1
+            '-' => Token::Operator("-"),
+            c if OPERATOR_CHARS.contains(c) => {
+                self.eat_while(|c| OPERATOR_CHARS.contains(c));
+                Token::Operator(&self.input[start..self.pos])
+            }
+            character => {
+                return Some(Err(LexError::UnexpectedCharacter {
+                    character,
+                    span: start..self.pos,
+                }))
+            }
+        };
+        Some(Ok((tok, start..self.pos)))
+    }
+
+    /// whether the underscore c is the start of a number like _1, which is
+    /// lexed whole to be reported as a bad one
+    fn starts_number(&self, c: char) -> bool {
+        c == '_' && {
+            let digits = self.rest().trim_start_matches('_');
+            digits.starts_with(|c| is_digit(c) || c == '.')
+        }
+    }
+
+    /// anything number-like is taken whole so 1.2.3 and 1e are one bad number
+    /// rather than something else after a good one - underscores are taken
+    /// anywhere in it so a misplaced one is part of the bad number too, and
+    /// one too big for an f64, like 1e400, is bad as well
+    fn number(&mut self, start: usize) -> Result<(Token<'src>, Range<usize>), LexError> {
+        self.eat_while(|c| is_digit(c) || c == '.' || c == '_');
+        if self.eat_if(|c| c == 'e' || c == 'E') {
+            self.eat_if(|c| c == '+' || c == '-');
+            self.eat_while(|c| is_digit(c) || c == '_');
+        }
+        let text = &self.input[start..self.pos];
+        match separated(text, |c| c.is_ascii_digit()).map(|digits| digits.parse()) {
+            Some(Ok(num)) if f64::is_finite(num) => Ok((Token::Number(num), start..self.pos)),
+            _ => Err(LexError::InvalidNumber {
+                text: text.to_string(),
+                span: start..self.pos,
+            }),
+        }
+    }
+
+    /// the same goes for everything after 0x and 0b
+    fn radix(&mut self, start: usize) -> Result<(Token<'src>, Range<usize>), LexError> {
+        let radix = match self.peek() {
+            Some('x') => 16,
+            _ => 2,
+        };
+        self.pos += 1;
+        self.eat_while(|c| is_word(c) || c == '.');
+        let text = &self.input[start..self.pos];
+        let digits = separated(&text[2..], |c| c.is_ascii_hexdigit());
+        match digits.map(|digits| u64::from_str_radix(&digits, radix)) {
+            Some(Ok(num)) => Ok((Token::Number(num as f64), start..self.pos)),
+            _ => Err(LexError::InvalidNumber {
+                text: text.to_string(),
+                span: start..self.pos,
+            }),
+        }
+    }
+
+    /// a string after its opening quote - when it's never closed only the
+    /// quote is taken, and lexing carries on just after it
+    fn string(&mut self, start: usize) -> Result<(Token<'src>, Range<usize>), LexError> {
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    let quoted = &self.input[start..self.pos];
+                    check_escapes(quoted, start)?;
+                    return Ok((Token::Str(&quoted[1..quoted.len() - 1]), start..self.pos));
+                }
+                '\\' if chars.next().is_none() => break,
+                _ => (),
+            }
+        }
+        Err(LexError::UnterminatedString {
+            span: start..start + 1,
+        })
+    }
+
+    /// the tokens with their byte ranges, comments included
+    fn spanned(mut self) -> impl Iterator<Item = Result<(Token<'src>, Range<usize>), LexError>> {
+        std::iter::from_fn(move || self.next_spanned())
+    }
+}
 
+impl<'src> Iterator for Lexer<'src> {
+    type Item = Result<Token<'src>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_spanned()? {
+                Ok((Token::Comment(_), _)) => continue,
+                tok => return Some(tok.map(|(tok, _)| tok)),
+            }
+        }
+    }
+}
+
+/// the byte ranges of every comment in the input
+pub fn comments(input: &str) -> Vec<Range<usize>> {
+    Lexer::new(input)
+        .spanned()
+        .filter_map(|tok| match tok {
+            Ok((Token::Comment(_), span)) => Some(span),
+            _ => None,
+        })
+        .collect()
+}
+
+/// lex with the glyphs in config rather than the default ones - returns a
+/// stack like lex
+pub fn lex_with_config<'src>(
+    input: &'src str,
+    config: &LexerConfig,
+) -> Result<Vec<Token<'src>>, LexError> {
     let mut res = Vec::new();
-    for cap in TOKEN_RE.captures_iter(&preprocessed) {
-        let token = if let Some(ident) = cap.name("ident") {
-            Token::Ident(ident.as_str().to_string())
+    let mut ends = ItemEnds::default();
+    let mut end = 0;
+    for tok in Lexer::with_glyphs(input, config.glyphs.clone()).spanned() {
+        let (tok, span) = match tok? {
+            (Token::Comment(_), _) => continue,
+            tok => tok,
+        };
+        let newline = input[end..span.start].contains('\n');
+        if config.newline_delimiters && newline && ends.is_complete() {
+            res.push(Token::Delimiter);
+            ends = ItemEnds::default();
+        }
+        ends.push(&tok);
+        end = span.end;
+        res.push(tok);
+    }
+    res.reverse();
+    Ok(res)
+}
+
+/// follows the tokens of a top level item closely enough to tell whether a
+/// newline after them ends it - a line goes on when it's inside parentheses,
+/// ends in an operator or comma, or is a function's prototype
+#[derive(Debug, Default)]
+struct ItemEnds {
+    depth: usize,
+    /// the last token can end an expression
+    can_end: bool,
+    /// after a def, up to the parenthesis closing its parameters
+    in_prototype: bool,
+    /// after a function's prototype, before its body
+    before_body: bool,
+}
+
+impl ItemEnds {
+    fn push(&mut self, tok: &Token) {
+        match tok {
+            Token::Delimiter => *self = ItemEnds::default(),
+            Token::Def => self.in_prototype = true,
+            Token::OpenParen => self.depth += 1,
+            Token::CloseParen => {
+                self.depth = self.depth.saturating_sub(1);
+                if self.depth == 0 && self.in_prototype {
+                    self.in_prototype = false;
+                    self.before_body = true;
+                    self.can_end = false;
+                    return;
+                }
+            }
+            _ => (),
+        }
+        if self.depth == 0 {
+            self.before_body = false;
+        }
+        self.can_end = matches!(
+            tok,
+            Token::Ident(_) | Token::Number(_) | Token::Bool(_) | Token::Str(_) | Token::CloseParen
+        );
+    }
+
+    fn is_complete(&self) -> bool {
+        self.depth == 0 && self.can_end && !self.in_prototype && !self.before_body
+    }
+}
+
+/// lex the given input string in source order, keeping the byte range of each token
+pub fn lex_spanned(input: &str) -> Result<Vec<(Token<'_>, Range<usize>)>, LexError> {
+    let mut res = lex_with_trivia(input)?;
+    res.retain(|(tok, _)| !matches!(tok, Token::Comment(_)));
+    Ok(res)
+}
+
+/// lex_spanned keeping the comments as tokens too, for tools that have to
+/// put them back
+pub fn lex_with_trivia(input: &str) -> Result<Vec<(Token<'_>, Range<usize>)>, LexError> {
+    Lexer::new(input).spanned().collect()
+}
+
+/// the regex lexer the scanner replaced, kept to check the scanner against
+/// and to benchmark it with
+#[cfg(any(test, feature = "reference-lexer"))]
+pub mod reference {
+    use lazy_static::lazy_static;
+    use regex::{Captures, Regex};
+
+    use super::*;
+
+    /// the regex matching every token, with the given glyphs
+    fn token_re(glyphs: &Glyphs) -> Regex {
+        let glyph = |c: char| regex::escape(&c.to_string());
+        // comments are matched like tokens and skipped, so every token can borrow
+        // straight from the input - a # in a string is part of the string, since
+        // the string starts first
+        let pattern = [
+            r"(?P<comment>#.*)".to_string(),
+            // the keywords are spelled out too for keyboards without the glyphs,
+            // so they have to come before identifiers
+            format!(r"(?P<extern>{}|extern\b)", glyph(glyphs.extern_)),
+            format!(r"(?P<def>{}|def\b)", glyph(glyphs.def)),
+            r"(?P<ident>\p{Alphabetic}\w*)".to_string(),
+            // anything number-like is matched whole so 1.2.3 and 1e are one bad
+            // number rather than something else after a good one, the same goes
+            // for digits after 0x and 0b - underscores are matched anywhere in
+            // them so a misplaced one is part of the bad number too
+            r"(?P<radix>0[xb][\w.]*)".to_string(),
+            r"(?P<number>_*[\d.][\d._]*(?:[eE][+-]?[\d_]*)?)".to_string(),
+            r"(?P<delimiter>;)".to_string(),
+            format!(r"(?P<oppar>{}|\()", glyph(glyphs.open_paren)),
+            format!(r"(?P<clpar>{}|\))", glyph(glyphs.close_paren)),
+            format!(r"(?P<comma>{}|,)", glyph(glyphs.comma)),
+            r#"(?P<string>"(?:[^"\\]|\\(?s:.))*")"#.to_string(),
+            r#"(?P<unterminated>")"#.to_string(),
+            // a run of operator characters is one operator, like <= - minus is
+            // never part of a run, since it also negates what comes after it, as
+            // in x*-1
+            r"(?P<operator>[+*/<>=!%^&|~?:]+|-)".to_string(),
+            r"(?P<unexpected>\S)".to_string(),
+        ]
+        .join("|");
+        Regex::new(&pattern).unwrap()
+    }
+
+    lazy_static! {
+        static ref TOKEN_RE: Regex = token_re(&Glyphs::default());
+    }
+
+    /// the token a match is
+    fn to_token<'src>(cap: &Captures<'src>) -> Result<Token<'src>, LexError> {
+        let whole = cap.get(0).unwrap();
+        let tok = if let Some(comment) = cap.name("comment") {
+            Token::Comment(&comment.as_str()[1..])
+        } else if let Some(ident) = cap.name("ident") {
+            // the word keywords are told apart from identifiers by their
+            // text, so they're only keywords as whole words
+            match ident.as_str() {
+                "if" => Token::If,
+                "then" => Token::Then,
+                "else" => Token::Else,
+                "for" => Token::For,
+                "in" => Token::In,
+                "true" => Token::Bool(true),
+                "false" => Token::Bool(false),
+                ident => Token::Ident(ident),
+            }
         } else if let Some(_) = cap.name("extern") {
             Token::Extern
         } else if let Some(_) = cap.name("def") {
             Token::Def
         } else if let Some(inner) = cap.name("number") {
-            Token::Number(inner.as_str().parse().expect("failed to parse number!"))
+            let digits = separated(inner.as_str(), |c| c.is_ascii_digit());
+            match digits.map(|digits| digits.parse()) {
+                Some(Ok(num)) if f64::is_finite(num) => Token::Number(num),
+                _ => {
+                    return Err(LexError::InvalidNumber {
+                        text: inner.as_str().to_string(),
+                        span: inner.range(),
+                    })
+                }
+            }
+        } else if let Some(inner) = cap.name("radix") {
+            let radix = match &inner.as_str()[..2] {
+                "0x" => 16,
+                _ => 2,
+            };
+            let digits = separated(&inner.as_str()[2..], |c| c.is_ascii_hexdigit());
+            match digits.map(|digits| u64::from_str_radix(&digits, radix)) {
+                Some(Ok(num)) => Token::Number(num as f64),
+                _ => {
+                    return Err(LexError::InvalidNumber {
+                        text: inner.as_str().to_string(),
+                        span: inner.range(),
+                    })
+                }
+            }
+        } else if let Some(string) = cap.name("string") {
+            let quoted = string.as_str();
+            check_escapes(quoted, string.start())?;
+            Token::Str(&quoted[1..quoted.len() - 1])
+        } else if let Some(quote) = cap.name("unterminated") {
+            return Err(LexError::UnterminatedString {
+                span: quote.range(),
+            });
         } else if let Some(op) = cap.name("operator") {
-            Token::Operator(op.as_str().to_string())
+            Token::Operator(op.as_str())
         } else if let Some(_) = cap.name("comma") {
             Token::Comma
         } else if let Some(_) = cap.name("oppar") {
@@ -69,22 +759,49 @@ pub fn lex(input: &str) -> Vec<Token> {
         } else if let Some(_) = cap.name("delimiter") {
             Token::Delimiter
         } else {
-            panic!("unknown token!");
+            return Err(LexError::UnexpectedCharacter {
+                character: whole.as_str().chars().next().unwrap(),
+                span: whole.range(),
+            });
         };
+        Ok(tok)
+    }
 
-        res.push(token);
+    /// every token and comment in the input with its byte range, carrying on
+    /// after errors
+    pub fn tokens(input: &str) -> Vec<Result<(Token<'_>, Range<usize>), LexError>> {
+        TOKEN_RE
+            .captures_iter(input)
+            .map(|cap| Ok((to_token(&cap)?, cap.get(0).unwrap().range())))
+            .collect()
+    }
+
+    /// lex the given input string - returns a stack, so first-on last-off
+    pub fn lex(input: &str) -> Result<Vec<Token<'_>>, LexError> {
+        let mut res = Vec::new();
+        for tok in tokens(input) {
+            match tok? {
+                (Token::Comment(_), _) => (),
+                (tok, _) => res.push(tok),
+            }
+        }
+        res.reverse();
+        Ok(res)
     }
-    res.reverse();
-    res
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn lex_ok(input: &str) -> Vec<Token<'_>> {
+        lex(input).unwrap()
+    }
+
     #[test]
     fn ignore_works() {
-        assert_eq!(preprocess("# somebody \na"), "\na");
+        assert_eq!(lex_ok("# somebody \na"), [Token::Ident("a")]);
+        assert_eq!(lex_ok("a#b\"c\"\r\n;"), [Token::Delimiter, Token::Ident("a")]);
     }
 
     #[test]
@@ -93,14 +810,469 @@ mod tests {
         let tokenized = [
             Token::Delimiter,
             Token::Number(1.0),
-            Token::Operator("+".to_string()),
-            Token::Ident("x".to_string()),
+            Token::Operator("+"),
+            Token::Ident("x"),
             Token::CloseParen,
-            Token::Ident("x".to_string()),
+            Token::Ident("x"),
             Token::OpenParen,
-            Token::Ident("add".to_string()),
+            Token::Ident("add"),
             Token::Def,
         ];
-        assert_eq!(lex(input), tokenized);
+        assert_eq!(lex_ok(input), tokenized);
+        for tok in tokenized.iter() {
+            assert_eq!(tok.into_owned().as_token(), *tok);
+            assert_eq!(tok.into_owned().to_string(), tok.to_string());
+        }
+    }
+
+    #[test]
+    fn tokens_display_as_written() {
+        let tokens = [
+            (Token::Def, "🜙"),
+            (Token::Extern, "🜹"),
+            (Token::Delimiter, ";"),
+            (Token::OpenParen, "🜄"),
+            (Token::CloseParen, "🜂"),
+            (Token::Comma, "🜌"),
+            (Token::Ident("foo"), "foo"),
+            (Token::Operator("<="), "<="),
+            (Token::Number(2.5), "2.5"),
+            (Token::Number(1e300), "1e300"),
+            (Token::Bool(true), "true"),
+            (Token::If, "if"),
+            (Token::In, "in"),
+            (Token::Bool(false), "false"),
+            (Token::Str("a\\n\\\"b\\\""), "\"a\\n\\\"b\\\"\""),
+            (Token::Str("\\\\ \\t # \\\""), "\"\\\\ \\t # \\\"\""),
+        ];
+        for (tok, text) in tokens.iter() {
+            assert_eq!(tok.to_string(), *text);
+            assert_eq!(lex_ok(text), [*tok]);
+        }
+
+        // text escaped into a string token comes back out of it unchanged
+        for text in ["say \"hi\"", "C:\\dir\\", "tab\tand\nline"].iter() {
+            let written = format!("\"{}\"", escape(text));
+            let tokens = lex_ok(&written);
+            assert_eq!(tokens[0].to_string(), written);
+            assert!(matches!(tokens[0], Token::Str(raw) if unescape(raw) == *text));
+        }
+    }
+
+    #[test]
+    fn trivia_is_kept() {
+        let input = "# twice\n🜙twice🜄x🜂 # doubles\n  x*2; # done";
+        let tokens = lex_with_trivia(input).unwrap();
+        let comments: Vec<_> = tokens
+            .iter()
+            .filter(|(tok, _)| matches!(tok, Token::Comment(_)))
+            .map(|(tok, span)| (*tok, span.start))
+            .collect();
+        assert_eq!(
+            comments,
+            [
+                (Token::Comment(" twice"), 0),
+                (Token::Comment(" doubles"), 27),
+                (Token::Comment(" done"), 44)
+            ]
+        );
+        // the pieces between the tokens are only ever whitespace
+        let mut copied = 0;
+        let mut res = String::new();
+        for (tok, span) in &tokens {
+            res += &input[copied..span.start];
+            res += &tok.to_string();
+            copied = span.end;
+        }
+        assert_eq!(res + &input[copied..], input);
+        assert_eq!(lex_spanned(input).unwrap().len(), tokens.len() - 3);
+    }
+
+    #[test]
+    fn ascii_spellings_work() {
+        assert_eq!(lex_ok("def add(x, y) x+y;"), lex_ok("🜙add🜄x🜌 y🜂x+y;"));
+        assert_eq!(lex_ok("extern sin(x);"), lex_ok("🜹sin🜄x🜂;"));
+        // only whole words are keywords
+        assert_eq!(
+            lex_ok("define externs def_1"),
+            [Token::Ident("def_1"), Token::Ident("externs"), Token::Ident("define")]
+        );
+    }
+
+    #[test]
+    fn glyphs_can_be_remapped() {
+        // runic punctuation and brackets - the runes themselves are letters
+        let config = LexerConfig::new(Glyphs {
+            def: '᛭',
+            extern_: '⚚',
+            open_paren: '⟨',
+            close_paren: '⟩',
+            comma: '᛫',
+        })
+        .unwrap();
+        assert_eq!(
+            lex_with_config("᛭add⟨x᛫ y⟩ x+y; # ᛭\n⚚sin(x);", &config),
+            lex("🜙add🜄x🜌 y🜂 x+y;\n🜹sin🜄x🜂;")
+        );
+        // the default glyphs are just unexpected now
+        assert!(matches!(
+            lex_with_config("🜙f x;", &config),
+            Err(LexError::UnexpectedCharacter {
+                character: '🜙', ..
+            })
+        ));
+        assert_eq!(
+            lex_with_config("🜙f🜄x🜂 x;", &LexerConfig::default()),
+            lex("🜙f🜄x🜂 x;")
+        );
+
+        let with = |comma| {
+            LexerConfig::new(Glyphs {
+                comma,
+                ..Glyphs::default()
+            })
+        };
+        assert_eq!(with('🜄').unwrap_err(), GlyphError::Shared('🜄'));
+        for taken in ['x', '7', '_', ' ', '+', ';', '(', '"', '#', '.', 'é', 'ᚠ'].iter() {
+            assert_eq!(with(*taken).unwrap_err(), GlyphError::Taken(*taken));
+        }
+    }
+
+    /// the scanner and the regex lexer it replaced agree on input, errors and
+    /// all
+    fn check_against_regex(input: &str) {
+        let scanned: Vec<_> = Lexer::new(input).spanned().collect();
+        assert_eq!(scanned, reference::tokens(input), "{:?}", input);
+    }
+
+    #[test]
+    fn scanner_matches_the_regex_lexer() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+        let files = [
+            "kaleidoscope/chapter3.wiz",
+            "kaleidoscope/chapter4.wiz",
+            "annotate/worksheet.wiz",
+        ];
+        for file in files.iter() {
+            check_against_regex(&std::fs::read_to_string(dir.join(file)).unwrap());
+        }
+        let snippets = [
+            "",
+            "  \t\n",
+            "🜙add🜄x🜌 y🜂 x+y; # sum\r\nadd🜄1🜌 2🜂;",
+            "def f(x, y) x*-y; extern sin(x); definitely externs def_1 xdef",
+            "true false truest True _true",
+            "1 1.5 .5 1. 1e3 1E+4 6.02e-23 1e 1else 1.2.3 1__0 1_000 _1 __ _ . ..",
+            "0x1F 0b101 0x 0b 0x_1 0xfg 0b2 0b1.0 0xé 00x1",
+            "<= < = ** != --2 x*-1 a+.5 =\"x\" ?: ~|&^%",
+            r##""hi" "# not a comment" "a\tb\n\\ \"q\"" "\q" "open"##,
+            "\"line\nbreak\" \"trailing\\",
+            "a ¬ b @ ' ` $ \\ { } [ ]",
+            "café cafe\u{301} ٣ x٣ ² x² a‿b Ⅻ 𝑥",
+            "# only a comment",
+            "x # note\n+ 12 #",
+        ];
+        for input in snippets.iter() {
+            check_against_regex(input);
+        }
+    }
+
+    #[test]
+    fn scanner_matches_the_regex_lexer_on_noise() {
+        let pieces = [
+            "🜙", "🜹", "🜄", "🜂", "🜌", "def", "extern", "true", "x", "é", "e\u{301}", "0x", "0b",
+            "1", "9", "_", ".", "e", "E", "+", "-", "*", "<", "=", "#", "\"", "\\", "n", "\n",
+            " ", ";", "(", ")", ",", "@", "٣", "²", "‿", "\t",
+        ];
+        // a fixed xorshift, so a failure always comes back the same
+        let mut state: u32 = 0x2545_f491;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as usize
+        };
+        for _ in 0..5000 {
+            let len = next() % 24;
+            let input: String = (0..len).map(|_| pieces[next() % pieces.len()]).collect();
+            check_against_regex(&input);
+        }
+    }
+
+    #[test]
+    fn newlines_can_delimit() {
+        let mut config = LexerConfig::default();
+        let input = "🜙f🜄x🜌\n  y🜂 # sum\n  x +\n  y\nf🜄1🜌 2🜂; f🜄\n 3🜌 4🜂\n\n# done\n";
+        assert_eq!(lex_with_config(input, &config), lex(input));
+        config.newline_delimiters = true;
+        assert_eq!(
+            lex_with_config(input, &config),
+            lex("🜙f🜄x🜌 y🜂 x + y; f🜄1🜌 2🜂; f🜄3🜌 4🜂;")
+                .map(|tokens| tokens[1..].to_vec())
+        );
+        assert_eq!(
+            lex_with_config("🜹sin🜄x🜂\ntable t from \"t.csv\"\n-1\n", &config),
+            lex("🜹sin🜄x🜂; table t from \"t.csv\"; -1")
+        );
+    }
+
+    #[test]
+    fn normalize_works() {
+        assert_eq!(normalize("cafe\u{301}"), "café");
+        assert_eq!(normalize("café"), "café");
+        assert_eq!(normalize("x_1"), "x_1");
+        // tokens are left as they're written
+        assert_eq!(lex_ok("cafe\u{301}"), [Token::Ident("cafe\u{301}")]);
+    }
+
+    #[test]
+    fn control_words_are_keywords() {
+        assert_eq!(
+            lex_ok("if then else for in iffy Else"),
+            [
+                Token::Ident("Else"),
+                Token::Ident("iffy"),
+                Token::In,
+                Token::For,
+                Token::Else,
+                Token::Then,
+                Token::If,
+            ]
+        );
+    }
+
+    #[test]
+    fn bools_are_keywords() {
+        assert_eq!(
+            lex_ok("true+false*truest"),
+            [
+                Token::Ident("truest"),
+                Token::Operator("*"),
+                Token::Bool(false),
+                Token::Operator("+"),
+                Token::Bool(true),
+            ]
+        );
+        assert_eq!(lex_ok("True false_"), [Token::Ident("false_"), Token::Ident("True")]);
+    }
+
+    #[test]
+    fn lexer_is_lazy() {
+        let mut lexer = Lexer::new("1 # one\n; \"open");
+        assert_eq!(lexer.next(), Some(Ok(Token::Number(1.0))));
+        assert_eq!(lexer.next(), Some(Ok(Token::Delimiter)));
+        assert!(matches!(lexer.next(), Some(Err(LexError::UnterminatedString { .. }))));
+        assert_eq!(lexer.next(), Some(Ok(Token::Ident("open"))));
+        assert_eq!(lexer.next(), None);
+
+        let input = "🜙f🜄x🜌y🜂x*y; f🜄0x2🜌\"3\"🜂";
+        let mut tokens = Lexer::new(input).collect::<Result<Vec<_>, _>>().unwrap();
+        tokens.reverse();
+        assert_eq!(tokens, lex_ok(input));
+    }
+
+    #[test]
+    fn string_escapes_work() {
+        let strings = [
+            (r#""hi""#, "hi"),
+            (r##""# not a comment""##, "# not a comment"),
+            (r#""🜄x🜂""#, "🜄x🜂"),
+            (r#""a\tb\nc \\ \"quoted\"""#, "a\tb\nc \\ \"quoted\""),
+        ];
+        for (input, text) in strings.iter() {
+            let raw = match lex_ok(input)[..] {
+                [Token::Str(raw)] => raw,
+                ref tokens => panic!("{} lexed to {:?}", input, tokens),
+            };
+            assert_eq!(unescape(raw), *text);
+            assert_eq!(escape(text), raw);
+        }
+        assert_eq!(
+            lex("x = \"a\\qb\""),
+            Err(LexError::InvalidEscape {
+                text: "\\q".to_string(),
+                span: 6..8
+            })
+        );
+        assert_eq!(
+            lex("1;\"open # 🜄;"),
+            Err(LexError::UnterminatedString { span: 2..3 })
+        );
+        assert_eq!(lex_ok("\"a\";# \""), [Token::Delimiter, Token::Str("a")]);
+    }
+
+    #[test]
+    fn lex_string_works() {
+        let tokenized = [
+            Token::Str("eng_sin"),
+            Token::Operator("="),
+        ];
+        assert_eq!(lex_ok(r#"= "eng_sin""#), tokenized);
+    }
+
+    #[test]
+    fn lex_spanned_works() {
+        let input = "x # note\n+ 12";
+        let tokenized = vec![
+            (Token::Ident("x"), 0..1),
+            (Token::Operator("+"), 9..10),
+            (Token::Number(12.0), 11..13),
+        ];
+        assert_eq!(lex_spanned(input), Ok(tokenized));
+        assert_eq!(comments(input), vec![2..8]);
+    }
+
+    #[test]
+    fn operator_runs_work() {
+        let ops = |input| -> Vec<Token> {
+            lex_ok(input)
+                .into_iter()
+                .rev()
+                .filter(|tok| matches!(tok, Token::Operator(_)))
+                .collect()
+        };
+        assert_eq!(ops("a <= b"), [Token::Operator("<=")]);
+        assert_eq!(ops("a < = b"), [Token::Operator("<"), Token::Operator("=")]);
+        assert_eq!(ops("a**b!=c"), [Token::Operator("**"), Token::Operator("!=")]);
+        assert_eq!(ops("x*-1"), [Token::Operator("*"), Token::Operator("-")]);
+        assert_eq!(ops("--2"), [Token::Operator("-"), Token::Operator("-")]);
+        // the glyphs, delimiters and strings around an operator aren't part of it
+        assert_eq!(
+            lex_ok("🜄x🜂==🜄y🜂;"),
+            [
+                Token::Delimiter,
+                Token::CloseParen,
+                Token::Ident("y"),
+                Token::OpenParen,
+                Token::Operator("=="),
+                Token::CloseParen,
+                Token::Ident("x"),
+                Token::OpenParen,
+            ]
+        );
+        assert_eq!(ops("=\"x\""), [Token::Operator("=")]);
+        assert_eq!(ops("a+.5"), [Token::Operator("+")]);
+        assert_eq!(
+            lex("a ¬ b"),
+            Err(LexError::UnexpectedCharacter {
+                character: '¬',
+                span: 2..4
+            })
+        );
+    }
+
+    #[test]
+    fn scientific_numbers_work() {
+        let numbers = [("1e3", 1e3), ("1.5e-2", 1.5e-2), ("1E+4", 1e4), ("6.02E23", 6.02e23)];
+        for (text, value) in numbers.iter() {
+            assert_eq!(lex_ok(text), [Token::Number(*value)]);
+        }
+        assert_eq!(
+            lex("2*1e"),
+            Err(LexError::InvalidNumber {
+                text: "1e".to_string(),
+                span: 2..4
+            })
+        );
+        assert!(lex("1e+ 2").is_err());
+        let huge = format!("1{}", "0".repeat(400));
+        for text in ["1e400", "1.5E+309", huge.as_str()].iter() {
+            assert_eq!(
+                lex(text),
+                Err(LexError::InvalidNumber {
+                    text: text.to_string(),
+                    span: 0..text.len()
+                })
+            );
+        }
+        // the exponent ends at the first thing that isn't a digit
+        assert_eq!(lex_ok("2e2x"), [Token::Ident("x"), Token::Number(200.0)]);
+    }
+
+    #[test]
+    fn leading_dot_numbers_work() {
+        assert_eq!(lex_ok(".5"), [Token::Number(0.5)]);
+        assert_eq!(lex_ok(".25e1"), [Token::Number(2.5)]);
+        assert_eq!(lex_ok("1."), [Token::Number(1.0)]);
+        // a dot with no digits on either side is no number
+        assert_eq!(
+            lex("1 + ."),
+            Err(LexError::InvalidNumber {
+                text: ".".to_string(),
+                span: 4..5
+            })
+        );
+    }
+
+    #[test]
+    fn digit_separators_work() {
+        let numbers = [
+            ("1_000_000", 1e6),
+            ("3.141_592", 3.141_592),
+            ("1_0e1_0", 10e10),
+            ("0xff_ff", 65535.0),
+        ];
+        for (text, value) in numbers.iter() {
+            assert_eq!(lex_ok(text), [Token::Number(*value)]);
+        }
+        for text in ["_100", "100_", "1._5", "1_.5", "1__0", "1e_5", "0x_1"].iter() {
+            assert_eq!(
+                lex(text),
+                Err(LexError::InvalidNumber {
+                    text: text.to_string(),
+                    span: 0..text.len()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn radix_numbers_work() {
+        let numbers = [("0x1F", 31.0), ("0xff", 255.0), ("0b1010", 10.0), ("0b0", 0.0)];
+        for (text, value) in numbers.iter() {
+            assert_eq!(lex_ok(text), [Token::Number(*value)]);
+        }
+        for text in ["0xZZ", "0x", "0b12", "0x1.8", "0x10000000000000000"].iter() {
+            assert_eq!(
+                lex(text),
+                Err(LexError::InvalidNumber {
+                    text: text.to_string(),
+                    span: 0..text.len()
+                })
+            );
+        }
+        assert_eq!(lex_ok("0 x1F"), [Token::Ident("x1F"), Token::Number(0.0)]);
+    }
+
+    #[test]
+    fn lex_errors_work() {
+        let number = |text: &str, span| LexError::InvalidNumber {
+            text: text.to_string(),
+            span,
+        };
+        assert_eq!(lex("🜙f🜄🜂 1.2.3;"), Err(number("1.2.3", 14..19)));
+        assert_eq!(lex("1 + ."), Err(number(".", 4..5)));
+        assert_eq!(
+            lex_ok("1. + .5"),
+            [Token::Number(0.5), Token::Operator("+"), Token::Number(1.0)]
+        );
+        let err = lex_spanned("x\n+ \u{7}").unwrap_err();
+        assert_eq!(
+            err,
+            LexError::UnexpectedCharacter {
+                character: '\u{7}',
+                span: 4..5
+            }
+        );
+        assert_eq!(err.to_string(), "unexpected character '\\u{7}'");
+        for garbage in ["@", "$", "\\", "“"].iter() {
+            assert!(matches!(
+                lex(garbage),
+                Err(LexError::UnexpectedCharacter { character, .. })
+                    if character.to_string() == *garbage
+            ));
+        }
+        // whitespace control characters are still just whitespace
+        assert_eq!(lex_ok("1\t\r\n"), [Token::Number(1.0)]);
     }
 }
@@ -0,0 +1,35 @@
+pub mod annotate;
+pub mod ast;
+pub mod batch;
+pub mod builtins;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod classify;
+pub mod codegen;
+pub mod compile;
+pub mod configure;
+pub mod console;
+pub mod derive;
+pub mod diagnostic;
+pub mod dump;
+pub mod engine;
+pub mod interface;
+pub mod interval;
+pub mod lexer;
+pub mod lint;
+pub mod numfmt;
+pub mod parser;
+pub mod pass;
+pub mod plan;
+pub mod report;
+pub mod resolve;
+pub mod runtime;
+pub mod session;
+pub mod snapshot;
+pub mod span;
+pub mod step;
+pub mod table;
+pub mod testing;
+pub mod verify;
+
+pub use classify::{classify, Classification, Classified};
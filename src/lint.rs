@@ -0,0 +1,310 @@
+use std::{fmt, iter, str::FromStr};
+
+use crate::ast::{ASTNode, Expression};
+use crate::builtins;
+use crate::interval::Interval;
+use crate::parser::{self, Binding};
+
+/// optional analyses, all off unless enabled with -W
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Lint {
+    DomainCheck,
+    UnusedResult,
+    /// names that --allow-reserved let through
+    ReservedWord,
+}
+
+impl Lint {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::DomainCheck => "domain_check",
+            Lint::UnusedResult => "unused_result",
+            Lint::ReservedWord => "reserved_word",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+#[error("unknown lint {0}")]
+pub struct UnknownLint(String);
+
+impl FromStr for Lint {
+    type Err = UnknownLint;
+
+    fn from_str(name: &str) -> Result<Lint, UnknownLint> {
+        match name {
+            "domain_check" => Ok(Lint::DomainCheck),
+            "unused_result" => Ok(Lint::UnusedResult),
+            "reserved_word" => Ok(Lint::ReservedWord),
+            _ => Err(UnknownLint(name.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Warning {
+    pub lint: Lint,
+    /// the function the warning was found in
+    pub function: String,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "warning[{}]: {} in {}",
+            self.lint.name(),
+            self.message,
+            self.function
+        )
+    }
+}
+
+/// run the given lints over every function
+pub fn check(ast: &[ASTNode], lints: &[Lint]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    for lint in lints {
+        match lint {
+            Lint::DomainCheck => domain_check(ast, &mut warnings),
+            Lint::UnusedResult => unused_result(ast, &mut warnings),
+            Lint::ReservedWord => reserved_word(ast, &mut warnings),
+        }
+    }
+    warnings
+}
+
+fn domain_check(ast: &[ASTNode], warnings: &mut Vec<Warning>) {
+    for node in ast {
+        if let ASTNode::Function(func) = node {
+            let mut messages = Vec::new();
+            propagate(&func.body, &mut messages);
+            warnings.extend(messages.into_iter().map(|message| Warning {
+                lint: Lint::DomainCheck,
+                function: func.prototype.name.clone(),
+                message,
+            }));
+        }
+    }
+}
+
+/// whether expr only calls functions that are known to be pure and that the
+/// file doesn't define itself
+pub fn is_constant(expr: &Expression, defined: &[&str]) -> bool {
+    match expr {
+        Expression::Literal(_) => true,
+        Expression::Variable(_) | Expression::Error(_) => false,
+        Expression::Binary(_, lhs, rhs) => is_constant(lhs, defined) && is_constant(rhs, defined),
+        Expression::Call(callee, args) => {
+            !defined.contains(&callee.as_str())
+                && builtins::is_pure(callee)
+                && args.iter().all(|arg| is_constant(arg, defined))
+        }
+        Expression::NamedCall(..) => false,
+    }
+}
+
+/// top level expressions that compute a constant are usually left over from
+/// debugging or meant to be part of a def
+fn unused_result(ast: &[ASTNode], warnings: &mut Vec<Warning>) {
+    let defined: Vec<&str> = ast
+        .iter()
+        .filter_map(|node| match node {
+            ASTNode::Function(func) if func.prototype.name != "lambda" => {
+                Some(func.prototype.name.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+    for node in ast {
+        let func = match node {
+            ASTNode::Function(func) if func.prototype.name == "lambda" => func,
+            _ => continue,
+        };
+        let message = match &func.body {
+            Expression::Literal(value) => {
+                format!("top level expression is only the literal {}", value)
+            }
+            body if is_constant(body, &defined) => "top level expression is constant".to_string(),
+            _ => continue,
+        };
+        warnings.push(Warning {
+            lint: Lint::UnusedResult,
+            function: func.prototype.name.clone(),
+            message,
+        });
+    }
+}
+
+fn reserved_word(ast: &[ASTNode], warnings: &mut Vec<Warning>) {
+    for node in ast {
+        let (name, binding, params): (&String, Binding, &[String]) = match node {
+            ASTNode::Function(func) => (
+                &func.prototype.name,
+                Binding::Function,
+                &func.prototype.args,
+            ),
+            ASTNode::Extern(proto) => (&proto.name, Binding::Function, &proto.args),
+            ASTNode::Table(table) => (&table.name, Binding::Table, &[]),
+            ASTNode::When(..) | ASTNode::InlineIr(_) | ASTNode::Test(_) => continue,
+        };
+        let names =
+            iter::once((name, binding)).chain(params.iter().map(|p| (p, Binding::Parameter)));
+        for (word, binding) in names.filter(|(word, _)| parser::is_reserved(word)) {
+            warnings.push(Warning {
+                lint: Lint::ReservedWord,
+                function: name.clone(),
+                message: format!("{} {} is reserved for future syntax", binding, word),
+            });
+        }
+    }
+}
+
+/// the interval an expression lies in, noting arguments that are provably out
+/// of their function's domain - variables are unknown so they are unbounded
+fn propagate(expr: &Expression, messages: &mut Vec<String>) -> Interval {
+    match expr {
+        Expression::Literal(value) => Interval::point(*value),
+        Expression::Variable(_) | Expression::Error(_) => Interval::unbounded(),
+        Expression::Binary(op, lhs, rhs) => {
+            let lhs = propagate(lhs, messages);
+            let rhs = propagate(rhs, messages);
+            match op.as_str() {
+                "+" => lhs + rhs,
+                "-" => lhs - rhs,
+                "*" => lhs * rhs,
+                "/" => {
+                    if rhs.is_zero() {
+                        messages.push("divisor is always zero".to_string());
+                    }
+                    lhs / rhs
+                }
+                _ => Interval::unbounded(),
+            }
+        }
+        Expression::Call(callee, args) => {
+            let args: Vec<Interval> = args.iter().map(|arg| propagate(arg, messages)).collect();
+            let arg = match args.as_slice() {
+                [arg] => *arg,
+                _ => return Interval::unbounded(),
+            };
+            let violation = match callee.as_str() {
+                "sqrt" if arg.hi < 0.0 => Some("always negative"),
+                "log" if arg.hi < 0.0 => Some("always negative"),
+                "log" if arg.is_zero() => Some("always zero"),
+                "asin" | "acos" if arg.lo > 1.0 || arg.hi < -1.0 => Some("always outside [-1, 1]"),
+                _ => None,
+            };
+            if let Some(violation) = violation {
+                messages.push(format!("{} argument is {}", callee, violation));
+            }
+            match callee.as_str() {
+                "sin" | "cos" => Interval::new(-1.0, 1.0),
+                "exp" => arg.map_monotonic(f64::exp),
+                "sqrt" => Interval::new(arg.lo.max(0.0), arg.hi).map_monotonic(f64::sqrt),
+                "log" => Interval::new(arg.lo.max(0.0), arg.hi).map_monotonic(f64::ln),
+                _ => Interval::unbounded(),
+            }
+        }
+        Expression::NamedCall(_, args) => {
+            for (_, arg) in args {
+                propagate(arg, messages);
+            }
+            Interval::unbounded()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn messages_for(source: &str, lint: Lint) -> Vec<String> {
+        let ast = Parser::default().parse_str(source).unwrap();
+        check(&ast, &[lint])
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect()
+    }
+
+    fn messages(source: &str) -> Vec<String> {
+        messages_for(source, Lint::DomainCheck)
+    }
+
+    #[test]
+    fn domain_check_works() {
+        assert_eq!(
+            messages("🜙f🜄x🜂sqrt🜄0 - 2 - sin🜄x🜂*sin🜄x🜂🜂;"),
+            ["sqrt argument is always negative"]
+        );
+        assert_eq!(messages("log🜄0🜂;"), ["log argument is always zero"]);
+        assert_eq!(messages("🜙f🜄x🜂x/🜄2-2🜂;"), ["divisor is always zero"]);
+        assert_eq!(
+            messages("asin🜄1+exp🜄2🜂🜂;"),
+            ["asin argument is always outside [-1, 1]"]
+        );
+        let warnings = check(
+            &Parser::default().parse_str("🜙g🜄🜂sqrt🜄0-4🜂;").unwrap(),
+            &[Lint::DomainCheck],
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "warning[domain_check]: sqrt argument is always negative in g"
+        );
+    }
+
+    #[test]
+    fn domain_check_has_no_false_positives() {
+        let sources = [
+            "🜙f🜄x🜂sqrt🜄x🜂;",
+            "🜙f🜄x🜂sqrt🜄x - 1🜂;",
+            "🜙f🜄x🜂log🜄x*x🜂;",
+            "🜙f🜄x🜂1/x;",
+            "🜙f🜄x🜂1/🜄x-x🜂;",
+            "🜙f🜄x🜂asin🜄sin🜄x🜂🜂;",
+            "sqrt🜄0🜂+log🜄1🜂;",
+        ];
+        for source in sources.iter() {
+            assert!(messages(source).is_empty(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn unused_result_works() {
+        let messages = |source| messages_for(source, Lint::UnusedResult);
+        assert_eq!(
+            messages("3;"),
+            ["top level expression is only the literal 3"]
+        );
+        assert_eq!(
+            messages("🜹sin🜄x🜂;1 + sin🜄pi🜄🜂/2🜂;"),
+            ["top level expression is constant"]
+        );
+        assert_eq!(messages("🜙f🜄x🜂x+1;3;f🜄2🜂;").len(), 1);
+        assert!(messages("🜹printd🜄x🜂;printd🜄1🜂;").is_empty());
+        assert!(messages("🜙sin🜄x🜂x;sin🜄1🜂;").is_empty());
+        assert!(messages("🜙f🜄x🜂3;").is_empty());
+        assert_eq!("unused_result".parse(), Ok(Lint::UnusedResult));
+    }
+
+    #[test]
+    fn reserved_word_works() {
+        let parser = Parser {
+            allow_reserved: true,
+            ..Parser::default()
+        };
+        let source = r#"🜙while🜄x🜌var🜂var;🜹sin🜄const🜂;table var from "t.f64";🜙f🜄x🜂x;"#;
+        let warnings = check(&parser.parse_str(source).unwrap(), &[Lint::ReservedWord]);
+        let messages: Vec<String> = warnings.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            [
+                "warning[reserved_word]: function while is reserved for future syntax in while",
+                "warning[reserved_word]: parameter var is reserved for future syntax in while",
+                "warning[reserved_word]: parameter const is reserved for future syntax in sin",
+                "warning[reserved_word]: table var is reserved for future syntax in var",
+            ]
+        );
+    }
+}
@@ -1,88 +1,978 @@
-mod ast;
-mod codegen;
-mod lexer;
-mod parser;
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
 
-use std::{env, fs};
+use anyhow::{bail, Context as _};
+use clap::{
+    crate_authors, crate_description, crate_name, crate_version, App, AppSettings, Arg, ArgMatches,
+    SubCommand,
+};
+use inkwell::context::Context;
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+use wizarding::{
+    annotate,
+    ast::ASTNode,
+    batch::{self, BatchOptions, Status},
+    builtins::AngleUnit,
+    codegen::Codegen,
+    compile::{self, Compilation, CompileError},
+    console,
+    diagnostic::Sink,
+    dump::{self, Dump, DumpKind, DumpOptions},
+    engine::{EngineKind, OptLevel},
+    interface::{self, LibPath},
+    lint::Lint,
+    numfmt::NumberFormat,
+    plan::{CompileOptions, Plan},
+    report::SizeReport,
+    step::{StepError, Stepper},
+    table::{FsLoader, LoadTables, TableFormat},
+    testing::{self, TestOptions},
+    verify::{self, Verdict},
+};
 
-use anyhow::{anyhow, bail};
-use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg};
-use codegen::Codegen;
-use inkwell::{context::Context, execution_engine::JitFunction, OptimizationLevel};
-use parser::Parser;
+/// the columns a result line takes up besides its snippet, with room for a
+/// value printed in full
+const RESULT_OVERHEAD: usize = 32;
 
-type EntryFunc = unsafe extern "C" fn() -> f64;
+/// the characters of an entry's source shown with its result, unless the
+/// width only fits fewer
+const DEFAULT_SNIPPET_WIDTH: usize = 40;
+
+/// the children of each node --dump-ast shows before eliding the rest
+const DUMP_MAX_CHILDREN: usize = 16;
+
+fn warn_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("warn")
+        .short("W")
+        .value_name("LINT")
+        .help("Enables the given lint, one of domain_check, unused_result or reserved_word")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+}
+
+fn lints(matches: &ArgMatches) -> anyhow::Result<Vec<Lint>> {
+    match matches.values_of("warn") {
+        Some(names) => Ok(names
+            .map(|name| name.parse())
+            .collect::<Result<Vec<Lint>, _>>()?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// the dumps asked for, the old flags first
+fn dumps(matches: &ArgMatches) -> anyhow::Result<Vec<Dump>> {
+    let legacy = [
+        ("dump source", DumpKind::Source),
+        ("dump ast", DumpKind::Ast),
+        ("dump ir", DumpKind::Ir),
+    ];
+    let mut res: Vec<Dump> = legacy
+        .iter()
+        .filter(|(flag, _)| matches.is_present(flag))
+        .map(|(_, kind)| Dump {
+            kind: *kind,
+            path: None,
+        })
+        .collect();
+    for dump in matches.values_of("dump").into_iter().flatten() {
+        res.push(dump.parse()?);
+    }
+    Ok(res)
+}
+
+fn build(matches: &ArgMatches) -> anyhow::Result<()> {
+    let options = BatchOptions {
+        jobs: matches.value_of("jobs").unwrap().parse()?,
+        run: matches.is_present("run"),
+        lints: lints(matches)?,
+    };
+    let reports = batch::build(Path::new(matches.value_of("DIR").unwrap()), &options)?;
+    let width = match matches.value_of("width") {
+        Some(width) => console::width(Some(width.parse()?)),
+        None => console::width(None),
+    };
+    match matches.value_of("error format") {
+        Some("json") => println!("{}", batch::to_json(&reports)),
+        _ => println!(
+            "{}",
+            batch::summarize(
+                &reports,
+                matches.value_of("error limit").unwrap().parse()?,
+                width
+            )
+        ),
+    }
+
+    let failed = reports
+        .iter()
+        .filter(|report| report.status == Status::Failed)
+        .count();
+    if failed > 0 {
+        bail!("{} of {} files failed", failed, reports.len());
+    }
+    Ok(())
+}
+
+fn test(matches: &ArgMatches) -> anyhow::Result<()> {
+    let path = matches.value_of("FILE").unwrap();
+    let source = fs::read_to_string(path)?;
+    let mut compile_options = CompileOptions::default();
+    compile_options.add_pass(Box::new(LoadTables {
+        loader: Box::new(FsLoader::beside(Path::new(path))),
+        format: TableFormat::F64,
+    }))?;
+    let plan = Plan::new(path, &source, &mut compile_options)?;
+    let mut options = TestOptions {
+        filter: matches.value_of("filter").map(str::to_string),
+        ..TestOptions::default()
+    };
+    if let Some(epsilon) = matches.value_of("epsilon") {
+        options.epsilon = epsilon.parse()?;
+    }
+    let reports = testing::run(&plan, &options)?;
+    match matches.value_of("output") {
+        Some("json") => println!("{}", testing::to_json(&reports)),
+        _ => println!("{}", testing::summarize(path, &reports)),
+    }
+
+    let (_, failed, errored) = testing::counts(&reports);
+    if failed + errored > 0 {
+        bail!(
+            "{} of {} tests didn't pass",
+            failed + errored,
+            reports.len()
+        );
+    }
+    Ok(())
+}
+
+fn annotate(matches: &ArgMatches) -> anyhow::Result<()> {
+    let path = matches.value_of("FILE").unwrap();
+    let source = fs::read_to_string(path)?;
+    let mut options = CompileOptions::default();
+    options.add_pass(Box::new(LoadTables {
+        loader: Box::new(FsLoader::beside(Path::new(path))),
+        format: TableFormat::F64,
+    }))?;
+    let plan = Plan::new(path, &source, &mut options)?;
+    let annotated = annotate::annotate(&source, &annotate::evaluate(&plan)?);
+    fs::write(matches.value_of("output").unwrap_or(path), annotated)?;
+    Ok(())
+}
+
+/// print the steps of each top level expression of a file
+fn step(matches: &ArgMatches) -> anyhow::Result<()> {
+    let path = matches.value_of("FILE").unwrap();
+    let source = fs::read_to_string(path)?;
+    let mut options = CompileOptions::default();
+    options.add_pass(Box::new(LoadTables {
+        loader: Box::new(FsLoader::beside(Path::new(path))),
+        format: TableFormat::F64,
+    }))?;
+    let plan = Plan::new(path, &source, &mut options)?;
+    let mut stepper = Stepper::new(&plan.ast);
+    if let Some(count) = matches.value_of("max calls") {
+        stepper.max_calls = count.parse()?;
+    }
+
+    let mut wait = !matches.is_present("all");
+    let stdin = io::stdin();
+    let entries = plan.ast.iter().filter_map(|node| match node {
+        ASTNode::Function(func) if func.prototype.name == "lambda" => Some(&func.body),
+        _ => None,
+    });
+    for (i, entry) in entries.enumerate() {
+        println!("#{}", i + 1);
+        let res = stepper.trace(entry, |step| {
+            println!("{}", step);
+            if wait && step.redex().is_some() {
+                let mut line = String::new();
+                // the end of the input runs the rest like c
+                if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+                    wait = false;
+                }
+                match line.trim() {
+                    "c" => wait = false,
+                    "q" => return false,
+                    _ => (),
+                }
+            }
+            true
+        });
+        match res {
+            Ok(_) => (),
+            Err(StepError::Aborted) => return Ok(()),
+            Err(err) => bail!("#{}: {}", i + 1, err),
+        }
+    }
+    Ok(())
+}
+
+/// a codegen with the settings asked for
+fn codegen<'ctx>(context: &'ctx Context, matches: &ArgMatches) -> anyhow::Result<Codegen<'ctx>> {
+    let mut codegen = Codegen::new(context);
+    if matches.is_present("degrees") {
+        codegen.angle_unit = AngleUnit::Degrees;
+    }
+    codegen.denormals_are_zero = matches.is_present("denormals are zero");
+    codegen.fast_math = matches.is_present("fast math");
+    codegen.strict_fp = matches.is_present("strict fp");
+    codegen.peephole = !matches.is_present("no peephole");
+    codegen.check_bounds = matches.is_present("check bounds");
+    codegen.check_overflow = matches.is_present("check overflow");
+    if let Some(count) = matches.value_of("max arity") {
+        codegen.max_arity = count.parse()?;
+    }
+    if let Some(epsilon) = matches.value_of("epsilon") {
+        codegen.epsilon = epsilon.parse()?;
+    }
+    Ok(codegen)
+}
+
+/// loads tables from beside the input, or the current directory without one
+fn load_tables(matches: &ArgMatches, input: Option<&str>) -> anyhow::Result<LoadTables> {
+    let loader = match input {
+        Some(input) => FsLoader::beside(Path::new(input)),
+        None => FsLoader {
+            dir: PathBuf::new(),
+        },
+    };
+    Ok(LoadTables {
+        loader: Box::new(loader),
+        format: matches.value_of("table format").unwrap().parse()?,
+    })
+}
+
+/// the library file name stands for, warning when more than one directory
+/// on the library path has it
+fn find_library(lib_path: &LibPath, name: &str) -> anyhow::Result<PathBuf> {
+    let found = lib_path.find(Path::new(name))?;
+    for shadowed in &found.shadowed {
+        eprintln!(
+            "warning: {} is also {}, using {}",
+            name,
+            shadowed.display(),
+            found.path.display()
+        );
+    }
+    Ok(found.path)
+}
+
+/// render the failures of a compile that failed because of them, so they
+/// show up like every other diagnostic
+fn report(err: CompileError, sources: &[(&str, &str)], width: usize) -> anyhow::Error {
+    match err {
+        CompileError::Items(failures) => {
+            eprintln!("{}", compile::render_failures(&failures, sources, width));
+            anyhow::anyhow!("{} items failed to compile", failures.len())
+        }
+        err => err.into(),
+    }
+}
+
+/// log the spans and events of the library that the filter lets through to
+/// stderr, with how long each span took when it closes
+fn init_logging(filter: &str) -> anyhow::Result<()> {
+    let filter =
+        EnvFilter::try_new(filter).with_context(|| format!("invalid log filter {}", filter))?;
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(false)
+        .with_writer(std::io::stderr)
+        .init();
+    Ok(())
+}
 
 fn main() -> anyhow::Result<()> {
     let matches = App::new(crate_name!())
         .version(crate_version!())
         .author(crate_authors!())
         .about(crate_description!())
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Checks every .wiz file in a directory")
+                .arg(
+                    Arg::with_name("jobs")
+                        .short("j")
+                        .long("jobs")
+                        .value_name("N")
+                        .help("Sets the number of files compiled in parallel")
+                        .takes_value(true)
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::with_name("run")
+                        .long("run")
+                        .help("If set will also run the entry of each file"),
+                )
+                .arg(
+                    Arg::with_name("error format")
+                        .long("error-format")
+                        .value_name("FORMAT")
+                        .help("Sets how the results are printed")
+                        .possible_values(&["human", "json"])
+                        .default_value("human"),
+                )
+                .arg(
+                    Arg::with_name("error limit")
+                        .long("error-limit")
+                        .value_name("N")
+                        .help("Sets how many diagnostics are printed before the rest are only counted, 0 for all of them")
+                        .takes_value(true)
+                        .default_value("50"),
+                )
+                .arg(warn_arg())
+                .arg(
+                    Arg::with_name("DIR")
+                        .help("Sets the directory to search for .wiz files")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("test")
+                .about("Runs the tests a .wiz file defines")
+                .arg(
+                    Arg::with_name("filter")
+                        .long("filter")
+                        .value_name("SUBSTRING")
+                        .help("Only runs the tests whose name contains SUBSTRING")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("FORMAT")
+                        .help("Sets how the results are printed")
+                        .possible_values(&["human", "json"])
+                        .default_value("human"),
+                )
+                .arg(
+                    Arg::with_name("epsilon")
+                        .long("epsilon")
+                        .value_name("TOLERANCE")
+                        .help("Sets the tolerance results are compared with, by default 1e-9")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("Sets the file whose tests are run")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("annotate")
+                .about("Writes what each top level expression comes out to beside it as a comment")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("OUT")
+                        .help("Writes the annotated source to OUT instead of back to FILE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("Sets the file to annotate")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("step")
+                .about("Evaluates each top level expression one step at a time, waiting for enter between steps - c runs the rest without waiting and q stops")
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("If set prints every step without waiting"),
+                )
+                .arg(
+                    Arg::with_name("max calls")
+                        .long("max-calls")
+                        .value_name("N")
+                        .help("Sets how many calls to definitions an expression can expand before it's stopped")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("Sets the file to step through")
+                        .required(true)
+                        .index(1),
+                ),
+        )
         .arg(
             Arg::with_name("optimization")
                 .short("o")
                 .long("opt")
                 .value_name("LEVEL")
-                .help("Sets the amount of optimization of the jit compiler")
+                .help("Sets the optimization level of the jit compiler, 0 to 3 or none, less, default or aggressive")
                 .takes_value(true)
                 .default_value("0"),
         )
+        .arg(
+            Arg::with_name("table format")
+                .long("table-format")
+                .value_name("FORMAT")
+                .help("Sets how the files read by table items are encoded")
+                .possible_values(&["f64", "csv"])
+                .default_value("f64"),
+        )
+        .arg(
+            Arg::with_name("snippet width")
+                .long("snippet-width")
+                .value_name("WIDTH")
+                .help("Sets how many characters of each entry's source are printed with its result, by default 40 or as many as fit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("width")
+                .long("width")
+                .value_name("COLUMNS")
+                .help("Sets the width tables, results and diagnostics are fitted to, by default $COLUMNS or 80")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("log level")
+                .long("log-level")
+                .value_name("FILTER")
+                .help("Logs what the compiler does to stderr, at a level like debug or with a filter like wizarding::codegen=trace")
+                .env("WIZARDING_LOG")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("output format")
+                .long("output-format")
+                .value_name("FORMAT")
+                .help("Sets how results are printed, hex being the exact c99 hex float")
+                .possible_values(&["shortest", "fixed", "scientific", "hex"])
+                .default_value("shortest"),
+        )
+        .arg(
+            Arg::with_name("output precision")
+                .long("output-precision")
+                .value_name("N")
+                .help("Sets the number of decimals results are printed with")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("legacy opt levels")
+                .long("legacy-opt-levels")
+                .help("If set --opt 2 means aggressive, as it did before level 3 existed"),
+        )
+        .arg(
+            Arg::with_name("dump")
+                .long("dump")
+                .value_name("KIND[=PATH]")
+                .help("Writes source, tokens, ast, ir, optimized-ir, bytecode, symbols or interface to PATH, or to a section of stderr without one")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
         .arg(
             Arg::with_name("dump source")
                 .short("s")
                 .long("dump-source")
-                .help("If set will dump wizarding source to stdout"),
+                .help("The same as --dump source"),
         )
         .arg(
             Arg::with_name("dump ir")
                 .short("i")
                 .long("dump-ir")
-                .help("If set will dump llvm ir to stdout"),
+                .help("The same as --dump ir"),
+        )
+        .arg(
+            Arg::with_name("dump ast")
+                .long("dump-ast")
+                .help("The same as --dump ast")
+                .conflicts_with("streaming"),
+        )
+        .arg(
+            Arg::with_name("max depth")
+                .long("max-depth")
+                .value_name("LEVELS")
+                .help("Sets how many levels of each item --dump-ast shows")
+                .takes_value(true)
+                .default_value("12"),
+        )
+        .arg(
+            Arg::with_name("degrees")
+                .long("degrees")
+                .help("If set trig functions will take and return angles in degrees"),
+        )
+        .arg(
+            Arg::with_name("derive")
+                .long("derive")
+                .value_name("FUNCTION")
+                .help("Defines FUNCTION__dx as the derivative of the unary FUNCTION")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with("streaming"),
+        )
+        .arg(warn_arg().conflicts_with("streaming"))
+        .arg(
+            Arg::with_name("define")
+                .short("D")
+                .long("define")
+                .value_name("FLAG")
+                .help("Sets a flag for when items to test")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with("streaming"),
+        )
+        .arg(
+            Arg::with_name("pass")
+                .long("pass")
+                .value_name("NAME")
+                .help("Runs the given pass over the ast before codegen, const-fold or merge-functions")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with("streaming"),
+        )
+        .arg(
+            Arg::with_name("merge functions")
+                .long("merge-functions")
+                .help("If set definitions with the same body as an earlier one become calls to it, after any other passes")
+                .conflicts_with("streaming"),
+        )
+        .arg(
+            Arg::with_name("allow reserved")
+                .long("allow-reserved")
+                .help("If set reserved words can be used as names, with a warning, for code written before they were reserved")
+                .conflicts_with("streaming"),
+        )
+        .arg(
+            Arg::with_name("implicit call")
+                .long("implicit-call")
+                .help("If set functions without parameters, like pi, can be used without the parens that call them"),
+        )
+        .arg(
+            Arg::with_name("allow inline ir")
+                .long("allow-inline-ir")
+                .help("If set llvm \"...\" items link the ir they hold into the program, which can do anything"),
+        )
+        .arg(
+            Arg::with_name("denormals are zero")
+                .long("denormals-are-zero")
+                .conflicts_with("strict fp")
+                .help("If set denormal inputs and results are flushed to zero"),
+        )
+        .arg(
+            Arg::with_name("fast math")
+                .long("fast-math")
+                .conflicts_with("strict fp")
+                .help("If set expressions are simplified assuming values are finite, except in strict definitions"),
+        )
+        .arg(
+            Arg::with_name("strict fp")
+                .long("strict-fp")
+                .help("If set arithmetic is rounded as the host asks and never reordered or folded"),
+        )
+        .arg(
+            Arg::with_name("no peephole")
+                .long("no-peephole")
+                .help("If set binary ops with a literal operand are compiled as written"),
+        )
+        .arg(
+            Arg::with_name("check bounds")
+                .long("check-bounds")
+                .help("If set indexing a table out of bounds traps instead of giving nan"),
+        )
+        .arg(
+            Arg::with_name("check overflow")
+                .long("check-overflow")
+                .help("If set arithmetic making an infinity from finite operands traps, and fast math is off"),
+        )
+        .arg(
+            Arg::with_name("max arity")
+                .long("max-arity")
+                .value_name("COUNT")
+                .help("Sets the most parameters or call arguments a function may have")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("epsilon")
+                .long("epsilon")
+                .value_name("TOLERANCE")
+                .help("Sets the tolerance approx_eq compares with, by default 1e-9")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("verify opt")
+                .long("verify-opt")
+                .help("If set every entry is also run unoptimized and without fast math, reporting results that differ")
+                .conflicts_with("streaming"),
+        )
+        .arg(
+            Arg::with_name("ulp tolerance")
+                .long("ulp-tolerance")
+                .value_name("ULPS")
+                .help("Sets how many ulps apart --verify-opt lets results be, by default 0")
+                .takes_value(true)
+                .requires("verify opt"),
+        )
+        .arg(
+            Arg::with_name("streaming")
+                .long("streaming")
+                .help("If set each item is compiled as soon as it is parsed"),
+        )
+        .arg(
+            Arg::with_name("engine")
+                .long("engine")
+                .value_name("ENGINE")
+                .help("Forces the execution engine, otherwise the jit is tried first")
+                .possible_values(&["jit", "interp-ee"]),
+        )
+        .arg(
+            Arg::with_name("size report")
+                .long("size-report")
+                .value_name("FORMAT")
+                .help("If set will print the llvm ir instruction count of each function")
+                .possible_values(&["text", "json"]),
+        )
+        .arg(
+            Arg::with_name("dry run")
+                .long("dry-run")
+                .value_name("FORMAT")
+                .help("If set will print the compile plan and exit without compiling")
+                .possible_values(&["text", "json"])
+                .conflicts_with("streaming"),
+        )
+        .arg(
+            Arg::with_name("eval")
+                .short("e")
+                .long("eval")
+                .value_name("SOURCE")
+                .help("Runs SOURCE after the input file, if any, in the order given")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("with interface")
+                .long("with-interface")
+                .value_name("FILE")
+                .help("Declares the functions of a library from its --dump interface, before the input")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("link")
+                .long("link")
+                .value_name("FILE")
+                .help("Links a library's --dump bytecode into the program before it runs")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("lib path")
+                .long("lib-path")
+                .value_name("DIR")
+                .help("Looks for --with-interface and --link files in DIR when they aren't where they were named, before the directories of WIZARDING_PATH")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("frozen")
+                .long("frozen")
+                .help("If set --with-interface and --link files have to be where they were named"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .help("If set will only print the value of the last entry"),
         )
         .arg(
             Arg::with_name("INPUT")
                 .help("Sets the input file(s) to use")
-                .required(true)
+                .required_unless("eval")
                 .index(1),
         )
         .get_matches();
 
+    let (_, subcommand) = matches.subcommand();
+    if let Some(filter) = subcommand.unwrap_or(&matches).value_of("log level") {
+        init_logging(filter)?;
+    }
+    if let Some(matches) = matches.subcommand_matches("build") {
+        return build(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("test") {
+        return test(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("annotate") {
+        return annotate(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("step") {
+        return step(matches);
+    }
+
+    let width = match matches.value_of("width") {
+        Some(width) => console::width(Some(width.parse()?)),
+        None => console::width(None),
+    };
+
     let opt_amount = match matches.value_of("optimization").unwrap() {
-        "0" => OptimizationLevel::None,
-        "1" => OptimizationLevel::Less,
-        "2" => OptimizationLevel::Aggressive,
-        amount => bail!("unknown optimization amount: {}", amount),
+        "2" if matches.is_present("legacy opt levels") => OptLevel::Aggressive,
+        "2" => {
+            eprintln!(
+                "note: --opt 2 now means the default level, use 3 or pass --legacy-opt-levels for aggressive"
+            );
+            OptLevel::Default
+        }
+        level => level.parse()?,
     };
 
-    let source = fs::read_to_string(matches.value_of("INPUT").unwrap())?;
-    if matches.is_present("dump source") {
-        println!("Source:");
-        println!("{}", source);
-        println!()
+    let input = matches.value_of("INPUT");
+    let mut sources = Vec::new();
+    let lib_path = LibPath::with_env(
+        matches
+            .values_of("lib path")
+            .into_iter()
+            .flatten()
+            .map(PathBuf::from),
+        matches.is_present("frozen"),
+    );
+    for name in matches.values_of("with interface").into_iter().flatten() {
+        let path = find_library(&lib_path, name)?;
+        let source = fs::read_to_string(&path)?;
+        interface::check(&source).with_context(|| format!("couldn't use {}", path.display()))?;
+        sources.push((path.display().to_string(), source));
+    }
+    if let Some(input) = input {
+        sources.push((input.to_string(), fs::read_to_string(input)?));
+    }
+    for (i, snippet) in matches.values_of("eval").into_iter().flatten().enumerate() {
+        sources.push((format!("<eval-{}>", i + 1), snippet.to_string()));
+    }
+    let sources: Vec<(&str, &str)> = sources
+        .iter()
+        .map(|(name, source)| (name.as_str(), source.as_str()))
+        .collect();
+    let mut options = CompileOptions {
+        derivatives: matches
+            .values_of("derive")
+            .into_iter()
+            .flatten()
+            .map(str::to_string)
+            .collect(),
+        lints: lints(&matches)?,
+        allow_reserved: matches.is_present("allow reserved"),
+        allow_inline_ir: matches.is_present("allow inline ir"),
+        implicit_calls: matches.is_present("implicit call"),
+        ..CompileOptions::default()
+    };
+    let plan = if matches.is_present("streaming") {
+        // each item is dropped once it's compiled
+        if dumps(&matches)?
+            .iter()
+            .any(|dump| dump.kind == DumpKind::Ast)
+        {
+            bail!("--streaming doesn't keep the ast to dump");
+        }
+        None
+    } else {
+        for flag in matches.values_of("define").into_iter().flatten() {
+            options.define(flag);
+        }
+        // tables are loaded first so every other pass sees their values
+        options.add_pass(Box::new(load_tables(&matches, input)?))?;
+        for name in matches.values_of("pass").into_iter().flatten() {
+            options.passes.add_builtin(name)?;
+        }
+        if matches.is_present("merge functions")
+            && !options.passes.names().contains(&"merge-functions")
+        {
+            options.passes.add_builtin("merge-functions")?;
+        }
+        let plan = Plan::from_sources(&sources, &mut options)?;
+        let mut sink = Sink::default();
+        sink.width = width;
+        for diagnostic in plan.diagnostics() {
+            sink.report("", diagnostic);
+        }
+        if !sink.is_empty() {
+            eprintln!("{}", sink.render());
+        }
+        Some(plan)
+    };
+
+    match (matches.value_of("dry run"), &plan) {
+        (Some("json"), Some(plan)) => {
+            println!("{}", plan.to_json());
+            return Ok(());
+        }
+        (Some(_), Some(plan)) => {
+            println!("Plan:");
+            println!("{}", plan);
+            return Ok(());
+        }
+        _ => (),
     }
 
-    let parser = Parser::default();
-    let ast = parser.parse_str(&source)?;
     let context = Context::create();
 
-    let mut codegen = Codegen::new(&context);
-    codegen.codegen(&ast)?;
-    if matches.is_present("dump ir") {
-        println!("IR:");
-        println!("{}", codegen.module.print_to_string().to_str()?);
+    // the same program again without fast math, to run unoptimized
+    // verify needs the ast, which streaming doesn't keep
+    let baseline = match &plan {
+        Some(plan) if matches.is_present("verify opt") => {
+            let mut codegen = codegen(&context, &matches)?;
+            codegen.fast_math = false;
+            Some(
+                Compilation::new(codegen, plan.clone())
+                    .map_err(|err| report(err, &sources, width))?,
+            )
+        }
+        _ => None,
+    };
+    let codegen = codegen(&context, &matches)?;
+    let compilation = match plan {
+        Some(plan) => {
+            Compilation::new(codegen, plan).map_err(|err| report(err, &sources, width))?
+        }
+        None => Compilation::streaming(codegen, &sources, &options, &load_tables(&matches, input)?)
+            .map_err(|err| report(err, &sources, width))?,
+    };
+    // what did compile still runs, but the run fails at the end
+    let failures = compilation.failures().len();
+    if failures > 0 {
+        eprintln!(
+            "{}",
+            compile::render_failures(compilation.failures(), &sources, width)
+        );
+    }
+    let dump_options = DumpOptions {
+        max_depth: matches.value_of("max depth").unwrap().parse()?,
+        max_children: DUMP_MAX_CHILDREN,
+        opt: opt_amount,
+    };
+    for dump in dumps(&matches)? {
+        let artifact = dump::render(&compilation, dump.kind, &dump_options);
+        match &dump.path {
+            Some(path) => fs::write(path, artifact).with_context(|| {
+                format!(
+                    "couldn't write the {} to {}",
+                    dump.kind.name(),
+                    path.display()
+                )
+            })?,
+            None => eprintln!("{}", dump::section(dump.kind, &artifact)),
+        }
     }
 
-    let ee = codegen
-        .module
-        .create_jit_execution_engine(opt_amount)
-        .map_err(|e| anyhow!("{}", e.to_str().unwrap()))?;
+    let size_report = || SizeReport::new(compilation.module(), compilation.merged());
+    match matches.value_of("size report") {
+        Some("json") => println!("{}", size_report().to_json()),
+        Some(_) => {
+            println!("Size report:");
+            println!("{}", size_report().render(width));
+            println!()
+        }
+        None => (),
+    }
 
-    let entry: JitFunction<EntryFunc> = unsafe { ee.get_function("lambda") }?;
+    for name in matches.values_of("link").into_iter().flatten() {
+        let path = find_library(&lib_path, name)?;
+        compilation
+            .link_bitcode(&fs::read(&path)?)
+            .map_err(|err| anyhow::anyhow!("couldn't link {}: {}", path.display(), err))?;
+    }
+    let program = compilation.into_program();
+
+    let engine = match matches.value_of("engine") {
+        Some("jit") => program.engine(EngineKind::Jit, opt_amount)?,
+        Some("interp-ee") => program.engine(EngineKind::Interpreter, opt_amount)?,
+        Some(engine) => bail!("unknown engine: {}", engine),
+        None => match program.engine(EngineKind::Jit, opt_amount) {
+            Ok(engine) => engine,
+            Err(err) => {
+                eprintln!("note: {}, falling back to the interpreter", err);
+                program.engine(EngineKind::Interpreter, opt_amount)?
+            }
+        },
+    };
+
+    // leave room for the entry's number and its value
+    let fitting = width.saturating_sub(RESULT_OVERHEAD).max(1);
+    let snippet_width = match matches.value_of("snippet width") {
+        Some(snippet_width) => match snippet_width.parse()? {
+            snippet_width if (1..=fitting).contains(&snippet_width) => snippet_width,
+            snippet_width => bail!(
+                "--snippet-width {} doesn't fit a width of {}, it has to be from 1 to {}",
+                snippet_width,
+                width,
+                fitting
+            ),
+        },
+        None => fitting.min(DEFAULT_SNIPPET_WIDTH),
+    };
+    let precision = match matches.value_of("output precision") {
+        Some(precision) => Some(precision.parse()?),
+        None => None,
+    };
+    let format = NumberFormat::from_args(matches.value_of("output format").unwrap(), precision)?;
+    let results = engine.run_all(snippet_width);
+    let quiet = matches.is_present("quiet");
+    if !results.is_empty() && !quiet {
+        println!("Result:");
+    }
+    let mut failed = 0;
+    for (i, (snippet, value)) in results.iter().enumerate() {
+        match value {
+            Ok(value) if quiet => {
+                if i + 1 == results.len() {
+                    println!("{}", format.format(*value));
+                }
+            }
+            Ok(value) => println!("#{} ({}) = {}", i + 1, snippet, format.format(*value)),
+            Err(err) => {
+                eprintln!("#{} ({}) failed: {}", i + 1, snippet, err);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{} of {} entries failed", failed, results.len());
+    }
 
-    println!("Result:");
-    unsafe {
-        println!("{}", entry.call());
+    if let Some(baseline) = baseline {
+        let ast = baseline.ast().to_vec();
+        let baseline = baseline.into_program();
+        let baseline_engine = baseline.engine(engine.kind, OptLevel::None)?;
+        let tolerance = match matches.value_of("ulp tolerance") {
+            Some(tolerance) => tolerance.parse()?,
+            None => verify::DEFAULT_ULP_TOLERANCE,
+        };
+        let checks = verify::verify(&ast, &baseline_engine, &engine, tolerance, snippet_width);
+        println!("Verify opt:");
+        let mut diverged = 0;
+        for (i, check) in checks.iter().enumerate() {
+            println!("#{} {}", i + 1, check);
+            if let Verdict::Diverged { .. } = check.verdict {
+                diverged += 1;
+            }
+        }
+        if diverged > 0 {
+            bail!("{} of {} entries diverged", diverged, checks.len());
+        }
     }
 
+    if failures > 0 {
+        bail!("{} items failed to compile", failures);
+    }
     Ok(())
 }
@@ -0,0 +1,191 @@
+/// how results are printed
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum NumberFormat {
+    /// the fewest digits that parse back to the same value
+    #[default]
+    Shortest,
+    /// a fixed number of decimals
+    Fixed(usize),
+    /// one digit before the point, then the given number of decimals or as
+    /// few as round trip
+    Scientific(Option<usize>),
+    /// the c99 hex float, exact down to the bit
+    Hex,
+}
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum NumberFormatError {
+    #[error("unknown number format {0}, expected shortest, fixed, scientific or hex")]
+    UnknownFormat(String),
+    #[error("a precision can't be used with the {0} format")]
+    PrecisionUnsupported(String),
+}
+
+impl NumberFormat {
+    /// the format for a format name and an optional precision - a precision on
+    /// its own asks for that many fixed decimals
+    pub fn from_args(name: &str, precision: Option<usize>) -> Result<Self, NumberFormatError> {
+        match (name, precision) {
+            ("shortest", None) => Ok(NumberFormat::Shortest),
+            ("shortest", Some(precision)) | ("fixed", Some(precision)) => {
+                Ok(NumberFormat::Fixed(precision))
+            }
+            ("fixed", None) => Ok(NumberFormat::Fixed(6)),
+            ("scientific", precision) => Ok(NumberFormat::Scientific(precision)),
+            ("hex", None) => Ok(NumberFormat::Hex),
+            ("hex", Some(_)) => Err(NumberFormatError::PrecisionUnsupported(name.to_string())),
+            _ => Err(NumberFormatError::UnknownFormat(name.to_string())),
+        }
+    }
+
+    pub fn format(&self, value: f64) -> String {
+        match self {
+            NumberFormat::Shortest => shortest(value),
+            NumberFormat::Fixed(precision) => format!("{:.*}", precision, value),
+            NumberFormat::Scientific(Some(precision)) => format!("{:.*e}", precision, value),
+            NumberFormat::Scientific(None) => format!("{:e}", value),
+            NumberFormat::Hex => hex(value),
+        }
+    }
+}
+
+/// rust's shortest round trip digits, switching to an exponent at the same
+/// magnitudes debug formatting does so huge and tiny values stay readable
+pub fn shortest(value: f64) -> String {
+    let magnitude = value.abs();
+    if value != 0.0 && !(1e-4..1e16).contains(&magnitude) {
+        format!("{:e}", value)
+    } else {
+        format!("{}", value)
+    }
+}
+
+/// a count with its digits in groups of three, like 14,203
+pub fn grouped(count: usize) -> String {
+    let digits = count.to_string();
+    let head = match digits.len() % 3 {
+        0 => 3,
+        len => len,
+    };
+    let mut res = digits[..head].to_string();
+    for group in digits.as_bytes()[head..].chunks(3) {
+        res.push(',');
+        res.push_str(std::str::from_utf8(group).unwrap());
+    }
+    res
+}
+
+/// the value as printf's %a would print it, like 0x1.8p+1 for 3
+pub fn hex(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    }
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    if value.is_infinite() {
+        return format!("{}inf", sign);
+    }
+
+    let bits = value.to_bits();
+    let mantissa = bits & ((1 << 52) - 1);
+    // subnormals have no implicit leading one and share the smallest exponent
+    let (lead, exponent) = match (bits >> 52) & 0x7ff {
+        0 if mantissa == 0 => (0, 0),
+        0 => (0, -1022),
+        biased => (1, biased as i64 - 1023),
+    };
+    let digits = format!("{:013x}", mantissa);
+    let digits = digits.trim_end_matches('0');
+    if digits.is_empty() {
+        format!("{}0x{}p{:+}", sign, lead, exponent)
+    } else {
+        format!("{}0x{}.{}p{:+}", sign, lead, digits, exponent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn number_format_works() {
+        // the value, then how it prints as shortest, fixed(2), scientific and hex
+        let cases = [
+            (2.75, "2.75", "2.75", "2.75e0", "0x1.6p+1"),
+            (1.0, "1", "1.00", "1e0", "0x1p+0"),
+            (0.1, "0.1", "0.10", "1e-1", "0x1.999999999999ap-4"),
+            (-0.0, "-0", "-0.00", "-0e0", "-0x0p+0"),
+            (
+                1e20,
+                "1e20",
+                "100000000000000000000.00",
+                "1e20",
+                "0x1.5af1d78b58c4p+66",
+            ),
+            (1e-5, "1e-5", "0.00", "1e-5", "0x1.4f8b588e368f1p-17"),
+            (
+                5e-324,
+                "5e-324",
+                "0.00",
+                "5e-324",
+                "0x0.0000000000001p-1022",
+            ),
+            (
+                f64::MIN_POSITIVE,
+                "2.2250738585072014e-308",
+                "0.00",
+                "2.2250738585072014e-308",
+                "0x1p-1022",
+            ),
+            (f64::INFINITY, "inf", "inf", "inf", "inf"),
+            (f64::NEG_INFINITY, "-inf", "-inf", "-inf", "-inf"),
+            (f64::NAN, "NaN", "NaN", "NaN", "nan"),
+        ];
+        for (value, short, fixed, scientific, hex) in cases.iter() {
+            assert_eq!(NumberFormat::Shortest.format(*value), *short);
+            assert_eq!(NumberFormat::Fixed(2).format(*value), *fixed);
+            assert_eq!(NumberFormat::Scientific(None).format(*value), *scientific);
+            assert_eq!(NumberFormat::Hex.format(*value), *hex);
+            if !value.is_nan() {
+                let parsed: f64 = short.parse().unwrap();
+                assert_eq!(parsed.to_bits(), value.to_bits(), "{}", short);
+            }
+        }
+        assert_eq!(NumberFormat::Scientific(Some(3)).format(1234.5), "1.234e3");
+    }
+
+    #[test]
+    fn grouped_works() {
+        assert_eq!(grouped(0), "0");
+        assert_eq!(grouped(999), "999");
+        assert_eq!(grouped(14203), "14,203");
+        assert_eq!(grouped(1234567), "1,234,567");
+    }
+
+    #[test]
+    fn from_args_works() {
+        assert_eq!(
+            NumberFormat::from_args("shortest", None),
+            Ok(NumberFormat::Shortest)
+        );
+        assert_eq!(
+            NumberFormat::from_args("shortest", Some(3)),
+            Ok(NumberFormat::Fixed(3))
+        );
+        assert_eq!(
+            NumberFormat::from_args("fixed", None),
+            Ok(NumberFormat::Fixed(6))
+        );
+        assert_eq!(
+            NumberFormat::from_args("scientific", Some(2)),
+            Ok(NumberFormat::Scientific(Some(2)))
+        );
+        assert_eq!(
+            NumberFormat::from_args("hex", Some(2)),
+            Err(NumberFormatError::PrecisionUnsupported("hex".to_string()))
+        );
+        assert_eq!(
+            NumberFormat::from_args("octal", None),
+            Err(NumberFormatError::UnknownFormat("octal".to_string()))
+        );
+    }
+}
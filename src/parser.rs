@@ -1,25 +1,248 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, ops::Range};
 
 use super::ast::*;
-use super::lexer::{self, Token};
+use super::lexer::{self, LexError, OwnedToken, Token};
+use super::span::{LineIndex, Location};
+
+/// the kinds of token the parser can expect at a position
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TokenCategory {
+    Def,
+    Extern,
+    If,
+    Then,
+    Else,
+    For,
+    In,
+    Delimiter,
+    OpenParen,
+    CloseParen,
+    Comma,
+    Identifier,
+    Operator,
+    Number,
+    Bool,
+    Str,
+    Comment,
+    Expression,
+}
+
+impl TokenCategory {
+    pub fn of(tok: &Token) -> TokenCategory {
+        match tok {
+            Token::Def => TokenCategory::Def,
+            Token::Extern => TokenCategory::Extern,
+            Token::If => TokenCategory::If,
+            Token::Then => TokenCategory::Then,
+            Token::Else => TokenCategory::Else,
+            Token::For => TokenCategory::For,
+            Token::In => TokenCategory::In,
+            Token::Delimiter => TokenCategory::Delimiter,
+            Token::OpenParen => TokenCategory::OpenParen,
+            Token::CloseParen => TokenCategory::CloseParen,
+            Token::Comma => TokenCategory::Comma,
+            Token::Ident(_) => TokenCategory::Identifier,
+            Token::Operator(_) => TokenCategory::Operator,
+            Token::Number(_) => TokenCategory::Number,
+            Token::Bool(_) => TokenCategory::Bool,
+            Token::Str(_) => TokenCategory::Str,
+            Token::Comment(_) => TokenCategory::Comment,
+        }
+    }
+}
+
+impl fmt::Display for TokenCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenCategory::Def => write!(f, "'{}'", Token::Def),
+            TokenCategory::Extern => write!(f, "'{}'", Token::Extern),
+            TokenCategory::If => write!(f, "'{}'", Token::If),
+            TokenCategory::Then => write!(f, "'{}'", Token::Then),
+            TokenCategory::Else => write!(f, "'{}'", Token::Else),
+            TokenCategory::For => write!(f, "'{}'", Token::For),
+            TokenCategory::In => write!(f, "'{}'", Token::In),
+            TokenCategory::Delimiter => write!(f, "'{}'", Token::Delimiter),
+            TokenCategory::OpenParen => write!(f, "'{}'", Token::OpenParen),
+            TokenCategory::CloseParen => write!(f, "'{}'", Token::CloseParen),
+            TokenCategory::Comma => write!(f, "'{}'", Token::Comma),
+            TokenCategory::Identifier => write!(f, "an identifier"),
+            TokenCategory::Operator => write!(f, "an operator"),
+            TokenCategory::Number => write!(f, "a number"),
+            TokenCategory::Bool => write!(f, "true or false"),
+            TokenCategory::Str => write!(f, "a string"),
+            TokenCategory::Comment => write!(f, "a comment"),
+            TokenCategory::Expression => write!(f, "an expression"),
+        }
+    }
+}
+
+/// render a list of categories as "a, b or c"
+fn describe_expected(expected: &[TokenCategory]) -> String {
+    let mut described: Vec<String> = expected.iter().map(|cat| cat.to_string()).collect();
+    match described.pop() {
+        Some(last) if described.is_empty() => last,
+        Some(last) => format!("{} or {}", described.join(", "), last),
+        None => "nothing".to_string(),
+    }
+}
 
 #[derive(Debug, PartialEq, Clone, thiserror::Error)]
 pub enum ParserError {
-    // TODO: add more context information
-    #[error("invalid token {0}")]
-    InvalidToken(Token),
+    #[error("expected {}, found '{found}'", describe_expected(.expected))]
+    UnexpectedToken {
+        found: OwnedToken,
+        expected: Vec<TokenCategory>,
+    },
     #[error("invalid operator {0}")]
     InvalidOperator(String),
     #[error("unexpected end of file")]
     UnexpectedEOF,
+    #[error("expected an expression, found the start of an item '{0}'")]
+    UnexpectedItem(OwnedToken),
+    #[error("expected the end of the expression, found '{found}' at {}", .span.start)]
+    TrailingTokens {
+        found: OwnedToken,
+        span: Range<usize>,
+    },
+    #[error("inline llvm ir isn't allowed, it can do anything the host process can")]
+    InlineIrNotAllowed,
+    #[error("test \"{0}\" is missing expect and the value it should come out to")]
+    MissingExpect(String),
+    #[error("missing '🜌' between arguments, before '{0}'")]
+    MissingComma(OwnedToken),
+    /// span is where the word is, known when the parse lexed the source
+    /// itself
+    #[error("{word} is reserved for future syntax and can't name a {context}")]
+    ReservedWord {
+        word: String,
+        span: Option<Range<usize>>,
+        context: Binding,
+    },
+    /// a keyword like true or if where a name goes, which allow_reserved
+    /// can't let through since it was never lexed as an identifier
+    #[error("{word} is a keyword and can't name a {binding}")]
+    KeywordName { word: String, binding: Binding },
+    /// a token the lexer can't produce, from a token stream made some other
+    /// way
+    #[error("malformed token '{found}', {reason}")]
+    MalformedToken {
+        found: OwnedToken,
+        reason: &'static str,
+    },
+    #[error("expressions can only be nested {0} deep")]
+    TooDeep(usize),
+    /// an error a recovering parse stood a placeholder in for, with the
+    /// number of tokens that were left when it was found
+    #[error("{1}")]
+    Recovered(usize, Box<ParserError>),
+    #[error(transparent)]
+    Lex(#[from] LexError),
 }
 
+/// a syntax error and where in the source it is
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+#[error("{location}: {error}")]
+pub struct SyntaxError {
+    pub location: Location,
+    pub error: ParserError,
+}
+
+impl SyntaxError {
+    /// the error input couldn't be lexed with, at the start of what isn't a
+    /// token
+    pub fn lex(input: &str, err: LexError) -> SyntaxError {
+        SyntaxError {
+            location: LineIndex::new(input)
+                .location(err.span().start as u32)
+                .expect("tokens start on a character"),
+            error: ParserError::Lex(err),
+        }
+    }
+}
+
+impl ParserError {
+    fn unexpected(found: &Token, expected: &[TokenCategory]) -> ParserError {
+        ParserError::UnexpectedToken {
+            found: found.into_owned(),
+            expected: expected.to_vec(),
+        }
+    }
+
+    /// the error with the span of the token on top of the stack filled in,
+    /// for the errors found before their token is taken
+    fn at(self, top: Option<&Range<usize>>) -> ParserError {
+        match self {
+            ParserError::ReservedWord {
+                word,
+                span: None,
+                context,
+            } => ParserError::ReservedWord {
+                word,
+                span: top.cloned(),
+                context,
+            },
+            err => err,
+        }
+    }
+}
+
+/// words kept free for syntax that doesn't exist yet - the keywords like
+/// if and true can't be identifiers anyway, and the contextual ones like
+/// table and when stay usable as names by design
+pub const RESERVED_WORDS: &[&str] = &["while", "var", "const"];
+
+pub fn is_reserved(word: &str) -> bool {
+    RESERVED_WORDS.contains(&word)
+}
+
+/// keywords spelled as words, which go where a name might and are reported
+/// as a keyword standing in for one
+fn is_keyword(tok: &Token) -> bool {
+    matches!(
+        tok,
+        Token::If | Token::Then | Token::Else | Token::For | Token::In | Token::Bool(_)
+    )
+}
+
+/// an identifier, or a keyword standing where one would be
+fn is_name_like(tok: &Token) -> bool {
+    matches!(tok, Token::Ident(_)) || is_keyword(tok)
+}
+
+/// the kinds of name a definition introduces
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Binding {
+    Function,
+    Parameter,
+    Table,
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Function => write!(f, "function"),
+            Binding::Parameter => write!(f, "parameter"),
+            Binding::Table => write!(f, "table"),
+        }
+    }
+}
+
+/// the default limit on how deep parens, call arguments and negations can
+/// nest, well within what the recursive descent can take on a small thread
+/// stack
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// what may follow an argument in a call or prototype
+const ARG_SEPARATORS: &[TokenCategory] = &[TokenCategory::Comma, TokenCategory::CloseParen];
+
 pub type PartialParseResult = Result<Expression, ParserError>;
 
 macro_rules! ensure_next {
     ($input:ident, $($next:expr),+) => {
         match $input.last() {
-            Some(tok) if $(*tok != $next)||+ => return Err(ParserError::InvalidToken(tok.clone())),
+            Some(tok) if $(*tok != $next)||+ => {
+                return Err(ParserError::unexpected(tok, &[$(TokenCategory::of(&$next)),+]))
+            }
             None => return Err(ParserError::UnexpectedEOF),
             _ => (),
         }
@@ -27,27 +250,191 @@ macro_rules! ensure_next {
     };
 }
 
+/// take the next token when it matches, leaving anything else on top of the
+/// stack like ensure_next does, so an error is always at the top token
 macro_rules! extract_token {
-    ($input:expr) => {
-        match $input {
-            Some(tok) => tok,
+    ($input:ident, $next:pat, $inner:expr, $category:expr) => {
+        match $input.last().copied() {
+            Some($next) => {
+                $input.pop();
+                $inner
+            }
+            Some(tok) => return Err(ParserError::unexpected(&tok, &[$category])),
             None => return Err(ParserError::UnexpectedEOF),
         }
     };
-    ($input:expr, $next:pat, $inner:expr) => {
-        match $input {
-            Some(tok) => match tok {
-                $next => $inner,
-                tok => return Err(ParserError::InvalidToken(tok.clone())),
-            },
-            None => return Err(ParserError::UnexpectedEOF),
+}
+
+/// reads a when condition without consuming anything, so the parser can
+/// back out when `when` turns out to be an ordinary identifier
+struct ConditionParser<'a> {
+    /// the token stack, next token last
+    tokens: &'a [Token<'a>],
+    used: usize,
+}
+
+impl<'a> ConditionParser<'a> {
+    fn peek(&self) -> Option<&'a Token<'a>> {
+        self.tokens
+            .len()
+            .checked_sub(self.used + 1)
+            .map(|i| &self.tokens[i])
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(ident)) if *ident == word)
+    }
+
+    fn or(&mut self) -> Option<Condition> {
+        let mut lhs = self.and()?;
+        while self.is_word("or") {
+            self.used += 1;
+            lhs = Condition::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Some(lhs)
+    }
+
+    fn and(&mut self) -> Option<Condition> {
+        let mut lhs = self.not()?;
+        while self.is_word("and") {
+            self.used += 1;
+            lhs = Condition::And(Box::new(lhs), Box::new(self.not()?));
+        }
+        Some(lhs)
+    }
+
+    fn not(&mut self) -> Option<Condition> {
+        if self.is_word("not") {
+            self.used += 1;
+            return Some(Condition::Not(Box::new(self.not()?)));
+        }
+        match self.peek()? {
+            Token::Ident(name) if *name != "and" && *name != "or" => {
+                self.used += 1;
+                Some(Condition::Flag(lexer::normalize(name)))
+            }
+            Token::OpenParen => {
+                self.used += 1;
+                let cond = self.or()?;
+                match self.peek()? {
+                    Token::CloseParen => {
+                        self.used += 1;
+                        Some(cond)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn is_blank(text: &str) -> bool {
+    text.is_empty() || text.chars().any(char::is_whitespace)
+}
+
+fn check_token(tok: &Token) -> Result<(), ParserError> {
+    let reason = match tok {
+        Token::Ident(ident) if is_blank(ident) => "identifiers can't be empty or hold whitespace",
+        Token::Operator(op) if is_blank(op) => "operators can't be empty or hold whitespace",
+        Token::Number(num) if !num.is_finite() => "numbers have to be finite",
+        _ => return Ok(()),
+    };
+    Err(ParserError::MalformedToken {
+        found: tok.into_owned(),
+        reason,
+    })
+}
+
+/// tokens that close or separate what an expression is part of, which
+/// recovery stops at rather than skipping
+fn is_structural(tok: &Token) -> bool {
+    matches!(
+        tok,
+        Token::CloseParen | Token::Comma | Token::Delimiter | Token::Def | Token::Extern
+    )
+}
+
+/// tokens that start an expression, so one right after an argument means a
+/// comma was left out
+fn starts_primary(tok: &Token) -> bool {
+    matches!(
+        tok,
+        Token::Number(_) | Token::Bool(_) | Token::Ident(_) | Token::OpenParen
+    )
+}
+
+/// drop tokens up to the next one where a broken expression can be picked
+/// up again, stepping over whole parenthesized groups - operators are a place
+/// to stop too when at_operators is set
+fn skip_to_boundary(input: &mut Vec<Token>, at_operators: bool) {
+    let mut depth = 0;
+    while let Some(tok) = input.last() {
+        match tok {
+            Token::Delimiter | Token::Def | Token::Extern => return,
+            Token::CloseParen | Token::Comma if depth == 0 => return,
+            Token::Operator(_) if depth == 0 && at_operators => return,
+            Token::OpenParen => depth += 1,
+            Token::CloseParen => depth -= 1,
+            _ => (),
+        }
+        input.pop();
+    }
+}
+
+/// an error recovered from, standing in for what couldn't be parsed - left
+/// is how many tokens were left when it was found, the first of them being
+/// where it is
+fn placeholder(left: usize, err: ParserError) -> Expression {
+    Expression::Error(Box::new(ParserError::Recovered(left, Box::new(err))))
+}
+
+/// the errors recovered from in expr, in source order
+fn errors_in(expr: &Expression, errors: &mut Vec<ParserError>) {
+    match expr {
+        Expression::Literal(_) | Expression::Variable(_) => (),
+        Expression::Error(err) => errors.push(err.as_ref().clone()),
+        Expression::Binary(_, lhs, rhs) => {
+            errors_in(lhs, errors);
+            errors_in(rhs, errors);
+        }
+        Expression::Call(_, args) => args.iter().for_each(|arg| errors_in(arg, errors)),
+        Expression::NamedCall(_, args) => args.iter().for_each(|(_, arg)| errors_in(arg, errors)),
+    }
+}
+
+fn item_errors(node: &ASTNode, errors: &mut Vec<ParserError>) {
+    let proto = match node {
+        ASTNode::Function(func) => &func.prototype,
+        ASTNode::Extern(proto) => proto,
+        ASTNode::Table(_) | ASTNode::InlineIr(_) => return,
+        ASTNode::When(_, item) => return item_errors(item, errors),
+        ASTNode::Test(test) => {
+            errors_in(&test.expr, errors);
+            return errors_in(&test.expected, errors);
         }
     };
+    for default in proto.defaults.iter().flatten() {
+        errors_in(default, errors);
+    }
+    if let ASTNode::Function(func) = node {
+        errors_in(&func.body, errors);
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Parser {
     pub operator_precedence: HashMap<String, u32>,
+    /// accept reserved words as names, for code written before they were
+    /// reserved - the reserved_word lint still points them out
+    pub allow_reserved: bool,
+    /// accept `llvm "..."` items
+    pub allow_inline_ir: bool,
+    /// stand an Expression::Error in for a broken part of an expression and
+    /// carry on, rather than failing the whole item - see parse_recovering
+    pub recover: bool,
+    /// how deep expressions can nest before parsing gives up
+    pub max_depth: usize,
 }
 
 impl std::default::Default for Parser {
@@ -59,117 +446,289 @@ impl std::default::Default for Parser {
         operator_precedence.insert("-".to_string(), 20);
         Self {
             operator_precedence,
+            allow_reserved: false,
+            allow_inline_ir: false,
+            recover: false,
+            max_depth: DEFAULT_MAX_DEPTH,
         }
     }
 }
 
 impl Parser {
+    /// the name word gives, normalized like every other name so a parameter
+    /// matches its uses however its accents are written
+    fn bind(&self, word: &str, binding: Binding) -> Result<String, ParserError> {
+        if is_reserved(word) && !self.allow_reserved {
+            return Err(ParserError::ReservedWord {
+                word: word.to_string(),
+                span: None,
+                context: binding,
+            });
+        }
+        Ok(lexer::normalize(word))
+    }
+
+    /// the identifier on top of the stack as a name, only taken when it can
+    /// be one
+    fn bind_next(&self, input: &mut Vec<Token>, binding: Binding) -> Result<String, ParserError> {
+        let name = match input.last() {
+            Some(Token::Ident(word)) => self.bind(word, binding)?,
+            Some(tok) if is_keyword(tok) => {
+                return Err(ParserError::KeywordName {
+                    word: tok.to_string(),
+                    binding,
+                })
+            }
+            Some(tok) => return Err(ParserError::unexpected(tok, &[TokenCategory::Identifier])),
+            None => return Err(ParserError::UnexpectedEOF),
+        };
+        input.pop();
+        Ok(name)
+    }
+
     fn parse_number(&self, input: &mut Vec<Token>) -> PartialParseResult {
-        let num = extract_token!(input.pop(), Token::Number(extract), extract);
+        let num = extract_token!(
+            input,
+            Token::Number(extract),
+            extract,
+            TokenCategory::Number
+        );
         Ok(Expression::Literal(num))
     }
 
-    fn parse_identifier(&self, input: &mut Vec<Token>) -> PartialParseResult {
-        let ident = extract_token!(input.pop(), Token::Ident(extract), extract);
+    /// true and false are just 1 and 0
+    fn parse_bool(&self, input: &mut Vec<Token>) -> PartialParseResult {
+        let value = extract_token!(input, Token::Bool(extract), extract, TokenCategory::Bool);
+        Ok(Expression::Literal(if value { 1.0 } else { 0.0 }))
+    }
+
+    fn parse_identifier(&self, input: &mut Vec<Token>, depth: usize) -> PartialParseResult {
+        let ident = extract_token!(
+            input,
+            Token::Ident(extract),
+            extract,
+            TokenCategory::Identifier
+        );
         if let Some(Token::OpenParen) = input.last() {
             let mut args = Vec::new();
             ensure_next!(input, Token::OpenParen);
             // TODO: try to prevent code duplication with argument parsing
             if input.last() != Some(&Token::CloseParen) {
                 loop {
-                    args.push(self.parse_expr(input)?);
+                    let name = match input.as_slice() {
+                        [.., Token::Operator(colon), Token::Ident(name)] if *colon == ":" => {
+                            Some(lexer::normalize(name))
+                        }
+                        _ => None,
+                    };
+                    if name.is_some() {
+                        input.truncate(input.len() - 2);
+                    }
+                    args.push((name, self.parse_expr_at(input, depth)?));
                     if input.last() != Some(&Token::Comma) {
                         if input.last() == Some(&Token::CloseParen) {
                             break;
-                        } else if let Some(tok) = input.last() {
-                            return Err(ParserError::InvalidToken(tok.clone()));
-                        } else {
-                            return Err(ParserError::UnexpectedEOF);
+                        }
+                        let err = match input.last() {
+                            Some(tok) if starts_primary(tok) => {
+                                ParserError::MissingComma(tok.into_owned())
+                            }
+                            Some(tok) => ParserError::unexpected(tok, ARG_SEPARATORS),
+                            None => ParserError::UnexpectedEOF,
+                        };
+                        if !self.recover {
+                            return Err(err);
+                        }
+                        // the next argument has already started, so it's
+                        // parsed as though the comma was there
+                        let left = input.len();
+                        if let ParserError::MissingComma(_) = err {
+                            args.push((None, placeholder(left, err)));
+                            continue;
+                        }
+                        // the rest of the argument is dropped, and isn't
+                        // reported again when the argument already was
+                        skip_to_boundary(input, false);
+                        if !matches!(args.last(), Some((_, arg)) if arg.has_error()) {
+                            args.push((None, placeholder(left, err)));
+                        }
+                        if input.last() != Some(&Token::Comma) {
+                            break;
                         }
                     }
                     input.pop();
                 }
             }
-            ensure_next!(input, Token::CloseParen);
-            Ok(Expression::Call(ident.to_string(), args))
+            if self.recover && input.last() != Some(&Token::CloseParen) {
+                // an unclosed call that was already reported
+            } else {
+                ensure_next!(input, Token::CloseParen);
+            }
+            if args.iter().all(|(name, _)| name.is_none()) {
+                let args = args.into_iter().map(|(_, arg)| arg).collect();
+                Ok(Expression::Call(lexer::normalize(ident), args))
+            } else {
+                Ok(Expression::NamedCall(lexer::normalize(ident), args))
+            }
         } else {
-            Ok(Expression::Variable(ident.to_string()))
+            Ok(Expression::Variable(lexer::normalize(ident)))
         }
     }
 
-    fn parse_nested(&self, input: &mut Vec<Token>) -> PartialParseResult {
+    fn parse_nested(&self, input: &mut Vec<Token>, depth: usize) -> PartialParseResult {
         ensure_next!(input, Token::OpenParen);
-        let res = self.parse_expr(input)?;
+        let res = self.parse_expr_at(input, depth)?;
+        if self.recover && input.last() != Some(&Token::CloseParen) {
+            let err = match input.last() {
+                Some(tok) => ParserError::unexpected(tok, &[TokenCategory::CloseParen]),
+                None => ParserError::UnexpectedEOF,
+            };
+            let left = input.len();
+            skip_to_boundary(input, false);
+            if input.last() == Some(&Token::CloseParen) {
+                input.pop();
+            }
+            // a missing paren after an error is usually caused by that error
+            return match res.has_error() {
+                true => Ok(res),
+                false => Ok(placeholder(left, err)),
+            };
+        }
         ensure_next!(input, Token::CloseParen);
         Ok(res)
     }
 
-    fn parse_primary(&self, input: &mut Vec<Token>) -> PartialParseResult {
-        match extract_token!(input.last()) {
-            Token::Number(_) => self.parse_number(input),
-            Token::Ident(_) => self.parse_identifier(input),
-            Token::OpenParen => self.parse_nested(input),
-            tok => return Err(ParserError::InvalidToken(tok.clone())),
+    fn parse_primary(&self, input: &mut Vec<Token>, depth: usize) -> PartialParseResult {
+        let tok = match input.last() {
+            Some(Token::Number(_)) => return self.parse_number(input),
+            Some(Token::Bool(_)) => return self.parse_bool(input),
+            Some(Token::Ident(_)) => return self.parse_identifier(input, depth),
+            Some(Token::OpenParen) => return self.parse_nested(input, depth),
+            Some(Token::Operator("-")) => return self.parse_negation(input, depth),
+            Some(tok) => *tok,
+            None => return self.recovered(input, 0, ParserError::UnexpectedEOF),
+        };
+        let err = ParserError::unexpected(&tok, &[TokenCategory::Expression]);
+        let left = input.len();
+        // a token that ends something around the expression is left for that
+        if self.recover && !is_structural(&tok) {
+            input.pop();
+        }
+        self.recovered(input, left, err)
+    }
+
+    /// a leading minus, binding tighter than any binary operator - a negated
+    /// number is a negative literal and anything else is multiplied by -1,
+    /// which keeps the sign of zero and prints back as it parses
+    fn parse_negation(&self, input: &mut Vec<Token>, depth: usize) -> PartialParseResult {
+        ensure_next!(input, Token::Operator("-"));
+        match self.parse_primary(input, self.deeper(depth)?)? {
+            Expression::Literal(value) => Ok(Expression::Literal(-value)),
+            Expression::Error(err) => Ok(Expression::Error(err)),
+            operand => Ok(Expression::Binary(
+                "*".to_string(),
+                Box::new(Expression::Literal(-1.0)),
+                Box::new(operand),
+            )),
         }
     }
 
-    fn parse_rhs(
+    /// the error as a placeholder when recovering, with everything up to
+    /// where the expression can go on skipped
+    fn recovered(
         &self,
         input: &mut Vec<Token>,
-        expr_precedence: u32,
-        lhs: &Expression,
+        left: usize,
+        err: ParserError,
     ) -> PartialParseResult {
-        let mut result = lhs.clone();
-
-        loop {
-            let (operator, precedence) = match input.last() {
-                Some(&Token::Operator(ref op)) => match self.operator_precedence.get(op) {
-                    Some(pr) if *pr >= expr_precedence => (op.clone(), *pr),
-                    None => return Err(ParserError::InvalidOperator(op.to_string())),
-                    _ => break,
-                },
-                _ => break,
-            };
-            input.pop();
-
-            let mut rhs = self.parse_expr(input)?;
+        if !self.recover {
+            return Err(err);
+        }
+        skip_to_boundary(input, true);
+        Ok(placeholder(left, err))
+    }
 
-            match input.last() {
-                Some(&Token::Operator(ref op)) => match self.operator_precedence.get(op) {
-                    Some(next_precedence) if precedence < *next_precedence => {
-                        rhs = self.parse_rhs(input, precedence + 1, &rhs)?
-                    }
-                    None => return Err(ParserError::InvalidOperator(op.to_string())),
-                    _ => (),
-                },
-                _ => (),
-            };
+    /// the operators and operands following lhs - every operator groups to
+    /// the right, and the chain is read in a loop so a long one doesn't nest
+    /// any deeper
+    fn parse_rhs(
+        &self,
+        input: &mut Vec<Token>,
+        lhs: Expression,
+        depth: usize,
+    ) -> PartialParseResult {
+        let mut operands = vec![lhs];
+        let mut operators = Vec::new();
 
-            result = Expression::Binary(operator, Box::new(result), Box::new(rhs));
+        while let Some(Token::Operator(op)) = input.last() {
+            let operator = op.to_string();
+            if self.operator_precedence.contains_key(&operator) {
+                input.pop();
+                operators.push(operator);
+                operands.push(self.parse_primary(input, depth)?);
+            } else if self.recover {
+                // the placeholder stands in for the operator's right operand,
+                // which is skipped, and binds to the operand before it
+                let err = ParserError::InvalidOperator(operator.clone());
+                let left = input.len();
+                input.pop();
+                let rhs = self.recovered(input, left, err)?;
+                let lhs = operands.pop().expect("there is always an operand");
+                operands.push(Expression::Binary(operator, Box::new(lhs), Box::new(rhs)));
+            } else {
+                return Err(ParserError::InvalidOperator(operator));
+            }
         }
 
+        let mut result = operands.pop().expect("there is always an operand");
+        for (operator, lhs) in operators.into_iter().zip(operands).rev() {
+            result = Expression::Binary(operator, Box::new(lhs), Box::new(result));
+        }
         Ok(result)
     }
 
     fn parse_expr(&self, input: &mut Vec<Token>) -> PartialParseResult {
-        let lhs = self.parse_primary(input)?;
+        self.parse_expr_at(input, 0)
+    }
 
-        let expr = self.parse_rhs(input, 0, &lhs)?;
-        Ok(expr)
+    /// the depth an expression nested in one at depth is at, as long as
+    /// that's within the limit
+    fn deeper(&self, depth: usize) -> Result<usize, ParserError> {
+        match depth < self.max_depth {
+            true => Ok(depth + 1),
+            false => Err(ParserError::TooDeep(self.max_depth)),
+        }
+    }
+
+    /// an expression inside depth others, counting each argument, negation
+    /// and parenthesized expression as one deeper than what it is in - the
+    /// operands of a chain of binary operators are all at the same depth
+    fn parse_expr_at(&self, input: &mut Vec<Token>, depth: usize) -> PartialParseResult {
+        let depth = self.deeper(depth)?;
+        let lhs = self.parse_primary(input, depth)?;
+        self.parse_rhs(input, lhs, depth)
     }
 
     fn parse_prototype(&self, input: &mut Vec<Token>) -> Result<Prototype, ParserError> {
-        let name = extract_token!(input.pop(), Token::Ident(ident), ident);
+        let name = self.bind_next(input, Binding::Function)?;
         ensure_next!(input, Token::OpenParen);
         let mut args = Vec::new();
+        let mut defaults = Vec::new();
         if input.last() != Some(&Token::CloseParen) {
-            while let Some(Token::Ident(ident)) = input.pop() {
-                args.push(ident);
+            while input.last().map_or(false, is_name_like) {
+                args.push(self.bind_next(input, Binding::Parameter)?);
+                defaults.push(match input.last() {
+                    Some(Token::Operator(op)) if *op == "=" => {
+                        input.pop();
+                        Some(self.parse_expr(input)?)
+                    }
+                    _ => None,
+                });
                 if input.last() != Some(&Token::Comma) {
                     if input.last() == Some(&Token::CloseParen) {
                         break;
                     } else if let Some(tok) = input.last() {
-                        return Err(ParserError::InvalidToken(tok.clone()));
+                        return Err(ParserError::unexpected(tok, ARG_SEPARATORS));
                     } else {
                         return Err(ParserError::UnexpectedEOF);
                     }
@@ -178,12 +737,30 @@ impl Parser {
             }
         }
         ensure_next!(input, Token::CloseParen);
-        Ok(Prototype { name, args })
+        Ok(Prototype {
+            name,
+            args,
+            link_name: None,
+            defaults,
+            void: false,
+            math: MathMode::default(),
+        })
     }
 
+    /// like void, fast and strict are only keywords between the glyph and the
+    /// name
     fn parse_function(&self, input: &mut Vec<Token>) -> Result<ASTNode, ParserError> {
         input.pop();
-        let proto = self.parse_prototype(input)?;
+        let math = match input.as_slice() {
+            [.., Token::Ident(_), Token::Ident("fast")] => MathMode::Fast,
+            [.., Token::Ident(_), Token::Ident("strict")] => MathMode::Strict,
+            _ => MathMode::Inherit,
+        };
+        if math != MathMode::Inherit {
+            input.pop();
+        }
+        let mut proto = self.parse_prototype(input)?;
+        proto.math = math;
         let body = self.parse_expr(input)?;
         Ok(ASTNode::Function(Function {
             prototype: proto,
@@ -191,9 +768,28 @@ impl Parser {
         }))
     }
 
+    /// void is only a keyword between the extern glyph and the name, so it
+    /// can still name an extern itself
     fn parse_extern(&self, input: &mut Vec<Token>) -> Result<ASTNode, ParserError> {
         input.pop();
-        Ok(ASTNode::Extern(self.parse_prototype(input)?))
+        let void = matches!(
+            input.as_slice(),
+            [.., Token::Ident(_), Token::Ident("void")]
+        );
+        if void {
+            input.pop();
+        }
+        let mut proto = self.parse_prototype(input)?;
+        proto.void = void;
+        if let Some(Token::Operator(op)) = input.last() {
+            if *op == "=" {
+                input.pop();
+                let link_name =
+                    extract_token!(input, Token::Str(extract), extract, TokenCategory::Str);
+                proto.link_name = Some(lexer::unescape(link_name));
+            }
+        }
+        Ok(ASTNode::Extern(proto))
     }
 
     fn parse_lambda(&self, input: &mut Vec<Token>) -> Result<ASTNode, ParserError> {
@@ -201,33 +797,389 @@ impl Parser {
             prototype: Prototype {
                 name: "lambda".to_string(),
                 args: vec![],
+                link_name: None,
+                defaults: vec![],
+                void: false,
+                math: MathMode::default(),
             },
             body: self.parse_expr(input)?,
         }))
     }
 
+    /// table and from are only keywords in `table name from "path"`, so they
+    /// stay usable as identifiers everywhere else
+    fn is_table(input: &[Token]) -> bool {
+        match input {
+            [.., Token::Ident(from), name, Token::Ident(table)] => {
+                *table == "table" && *from == "from" && is_name_like(name)
+            }
+            _ => false,
+        }
+    }
+
+    fn is_inline_ir(input: &[Token]) -> bool {
+        matches!(input, [.., Token::Str(_), Token::Ident("llvm")])
+    }
+
+    /// llvm followed by the ir as a string, which can't hold a # like any
+    /// other, so attribute groups have to be written out in place
+    fn parse_inline_ir(&self, input: &mut Vec<Token>) -> Result<ASTNode, ParserError> {
+        if !self.allow_inline_ir {
+            return Err(ParserError::InlineIrNotAllowed);
+        }
+        input.pop();
+        let ir = extract_token!(input, Token::Str(ir), ir, TokenCategory::Str);
+        Ok(ASTNode::InlineIr(lexer::unescape(ir)))
+    }
+
+    /// test and expect are only keywords in `test "name" expr expect value`
+    fn is_test(input: &[Token]) -> bool {
+        matches!(input, [.., Token::Str(_), Token::Ident("test")])
+    }
+
+    fn parse_test(&self, input: &mut Vec<Token>) -> Result<ASTNode, ParserError> {
+        input.pop();
+        let name = lexer::unescape(extract_token!(
+            input,
+            Token::Str(name),
+            name,
+            TokenCategory::Str
+        ));
+        let expr = self.parse_expr(input)?;
+        match input.last() {
+            Some(Token::Ident("expect")) => input.pop(),
+            _ => return Err(ParserError::MissingExpect(name)),
+        };
+        Ok(ASTNode::Test(Test {
+            name,
+            expr,
+            expected: self.parse_expr(input)?,
+        }))
+    }
+
+    /// the condition of a `when` at the top of the stack and how many tokens
+    /// it takes up, if a definition, extern or table follows it
+    fn when_condition(input: &[Token]) -> Option<(Condition, usize)> {
+        match input.last() {
+            Some(Token::Ident(when)) if *when == "when" => (),
+            _ => return None,
+        }
+        let mut cond = ConditionParser {
+            tokens: &input[..input.len() - 1],
+            used: 0,
+        };
+        let condition = cond.or()?;
+        let rest = &input[..input.len() - 1 - cond.used];
+        match rest.last() {
+            Some(Token::Def) | Some(Token::Extern) => Some((condition, cond.used + 1)),
+            _ if Parser::is_table(rest) || Parser::when_condition(rest).is_some() => {
+                Some((condition, cond.used + 1))
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_when(
+        &self,
+        input: &mut Vec<Token>,
+        (condition, used): (Condition, usize),
+    ) -> Result<ASTNode, ParserError> {
+        input.truncate(input.len() - used);
+        let item = match input.last() {
+            Some(Token::Def) => self.parse_function(input)?,
+            Some(Token::Extern) => self.parse_extern(input)?,
+            _ if Parser::is_table(input) => self.parse_table(input)?,
+            _ => {
+                let nested = Parser::when_condition(input).expect("checked by when_condition");
+                self.parse_when(input, nested)?
+            }
+        };
+        Ok(ASTNode::When(condition, Box::new(item)))
+    }
+
+    fn parse_table(&self, input: &mut Vec<Token>) -> Result<ASTNode, ParserError> {
+        input.pop();
+        let name = self.bind_next(input, Binding::Table)?;
+        input.pop();
+        let path = extract_token!(input, Token::Str(path), path, TokenCategory::Str);
+        Ok(ASTNode::Table(Table {
+            name,
+            path: lexer::unescape(path),
+            values: None,
+        }))
+    }
+
+    /// parse the next top level item, skipping any delimiters before it -
+    /// the tokens up to the next delimiter are checked first, since a token
+    /// stream doesn't have to come from the lexer
+    fn parse_item(&self, input: &mut Vec<Token>) -> Result<Option<ASTNode>, ParserError> {
+        while input.last() == Some(&Token::Delimiter) {
+            input.pop();
+        }
+        let cur_tok = match input.last() {
+            Some(tok) => *tok,
+            None => return Ok(None),
+        };
+        let start = input
+            .iter()
+            .rposition(|tok| *tok == Token::Delimiter)
+            .map_or(0, |i| i + 1);
+        for i in (start..input.len()).rev() {
+            if let Err(err) = check_token(&input[i]) {
+                // the tokens before it are dropped, so it's on top like the
+                // token any other error is at
+                input.truncate(i + 1);
+                return Err(err);
+            }
+        }
+
+        let before = input.len();
+        let item = match cur_tok {
+            Token::Def => self.parse_function(input)?,
+            Token::Extern => self.parse_extern(input)?,
+            Token::Ident(_) if Parser::is_table(input) => self.parse_table(input)?,
+            Token::Ident(_) if Parser::is_inline_ir(input) => self.parse_inline_ir(input)?,
+            Token::Ident(_) if Parser::is_test(input) => self.parse_test(input)?,
+            _ => match Parser::when_condition(input) {
+                Some(when) => self.parse_when(input, when)?,
+                None => self.parse_lambda(input)?,
+            },
+        };
+        // recovering stands an error in for a stray paren or comma without
+        // taking it, which would leave it there forever when the error
+        // didn't make the caller skip it
+        if input.len() == before {
+            return Err(ParserError::unexpected(
+                &cur_tok,
+                &[TokenCategory::Expression],
+            ));
+        }
+        Ok(Some(item))
+    }
+
+    /// comments in the input are skipped
     pub fn parse(&self, input: &mut Vec<Token>) -> Result<Vec<ASTNode>, ParserError> {
+        input.retain(|tok| !matches!(tok, Token::Comment(_)));
         let mut ast = Vec::new();
 
-        while !input.is_empty() {
-            let cur_tok = input.last().unwrap();
+        let mut left = input.len();
+        while let Some(item) = self.parse_item(input)? {
+            debug_assert!(input.len() < left, "parsing an item took no tokens");
+            left = input.len();
+            ast.push(item);
+        }
+
+        Ok(ast)
+    }
 
-            match cur_tok {
-                Token::Def => ast.push(self.parse_function(input)?),
-                Token::Extern => ast.push(self.parse_extern(input)?),
-                Token::Delimiter => {
-                    input.pop();
+    /// lazily parse one top level item at a time as the lexer gets to it -
+    /// after an error the iterator resumes at the next delimiter
+    pub fn parse_items<'a, 'src: 'a>(
+        &'a self,
+        tokens: lexer::Lexer<'src>,
+    ) -> impl Iterator<Item = Result<ASTNode, ParserError>> + 'a {
+        self.parse_iter(tokens)
+    }
+
+    /// parse the tokens one item at a time, like a Peekable over a Lexer,
+    /// so only the tokens up to the next delimiter are ever held - an error
+    /// is yielded in place of the item it's in and the items after it still
+    /// parse
+    pub fn parse_iter<'a, 'src: 'a, I>(
+        &'a self,
+        mut tokens: I,
+    ) -> impl Iterator<Item = Result<ASTNode, ParserError>> + 'a
+    where
+        I: Iterator<Item = Result<Token<'src>, LexError>> + 'a,
+    {
+        let mut chunk = Vec::new();
+        let mut parsed = Vec::new().into_iter();
+        std::iter::from_fn(move || loop {
+            if let Some(item) = parsed.next() {
+                return Some(Ok(item));
+            }
+            chunk.clear();
+            for tok in tokens.by_ref() {
+                match tok {
+                    Ok(Token::Comment(_)) => continue,
+                    Ok(Token::Delimiter) => {
+                        chunk.push(Token::Delimiter);
+                        break;
+                    }
+                    Ok(tok) => chunk.push(tok),
+                    Err(err) => {
+                        // the rest of the item the bad token is in goes too
+                        tokens.find(|tok| matches!(tok, Ok(Token::Delimiter)));
+                        return Some(Err(err.into()));
+                    }
                 }
-                _ => ast.push(self.parse_lambda(input)?),
+            }
+            if chunk.is_empty() {
+                return None;
+            }
+            chunk.reverse();
+            match self.parse(&mut chunk) {
+                Ok(items) => parsed = items.into_iter(),
+                Err(err) => return Some(Err(err)),
+            }
+        })
+    }
+
+    /// parse every item, finding as many errors as possible - an error in an
+    /// expression leaves an Expression::Error in the ast and the rest of the
+    /// item is still parsed, any other error skips to the next delimiter
+    pub fn parse_recovering(&self, input: &str) -> (Vec<ASTNode>, Vec<SyntaxError>) {
+        let parser = Parser {
+            recover: true,
+            ..self.clone()
+        };
+        let span = tracing::debug_span!("lex", tokens = tracing::field::Empty);
+        let spanned = match span.in_scope(|| lexer::lex_spanned(input)) {
+            Ok(spanned) => spanned,
+            Err(err) => return (Vec::new(), vec![SyntaxError::lex(input, err)]),
+        };
+        span.record("tokens", &spanned.len());
+        let lines = LineIndex::new(input);
+        // where the first of the tokens left is, the end when there are none
+        let locate = |left: usize| {
+            let offset = match spanned.get(spanned.len() - left) {
+                Some((_, span)) => span.start,
+                None => input.len(),
             };
-        }
+            lines
+                .location(offset as u32)
+                .expect("tokens start on a character")
+        };
+        let located = |err: ParserError, left: usize| {
+            let (left, error) = match err {
+                ParserError::Recovered(left, error) => (left, *error),
+                error => (left, error),
+            };
+            let top = spanned.get(spanned.len() - left).map(|(_, span)| span);
+            SyntaxError {
+                location: locate(left),
+                error: error.at(top),
+            }
+        };
 
-        Ok(ast)
+        let mut tokens: Vec<Token> = spanned.iter().rev().map(|(tok, _)| *tok).collect();
+        let mut ast = Vec::new();
+        let mut errors = Vec::new();
+        let mut clean = true;
+        loop {
+            // a stray paren or comma right after a broken item is usually
+            // where the error was, so it's only reported after a clean one
+            if let Some(tok @ Token::CloseParen) | Some(tok @ Token::Comma) = tokens.last() {
+                if clean {
+                    let err = ParserError::unexpected(tok, &[TokenCategory::Expression]);
+                    errors.push(located(err, tokens.len()));
+                }
+                tokens.pop();
+                clean = false;
+                continue;
+            }
+            let (before, left) = (errors.len(), tokens.len());
+            match parser.parse_item(&mut tokens) {
+                Ok(Some(item)) => {
+                    let mut found = Vec::new();
+                    item_errors(&item, &mut found);
+                    errors.extend(found.into_iter().map(|err| located(err, left)));
+                    ast.push(item);
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    errors.push(located(err, tokens.len()));
+                    while let Some(tok) = tokens.pop() {
+                        if tok == Token::Delimiter {
+                            break;
+                        }
+                    }
+                }
+            }
+            debug_assert!(tokens.len() < left, "parsing an item took no tokens");
+            clean = errors.len() == before;
+        }
+        (ast, errors)
     }
 
     pub fn parse_str(&self, input: &str) -> Result<Vec<ASTNode>, ParserError> {
-        let mut tokens = lexer::lex(input);
+        let (mut tokens, spans): (Vec<Token>, Vec<Range<usize>>) =
+            lexer::lex_spanned(input)?.into_iter().rev().unzip();
         self.parse(&mut tokens)
+            .map_err(|err| err.at(tokens.len().checked_sub(1).map(|top| &spans[top])))
+    }
+
+    /// parse a whole file, the same as parse_str
+    pub fn parse_program_str(&self, input: &str) -> Result<Vec<ASTNode>, ParserError> {
+        self.parse_str(input)
+    }
+
+    /// parse exactly one expression, optionally followed by delimiters -
+    /// definitions, externs and anything after the expression are rejected
+    pub fn parse_expression_str(&self, input: &str) -> PartialParseResult {
+        let (mut tokens, spans): (Vec<Token>, Vec<Range<usize>>) =
+            lexer::lex_spanned(input)?.into_iter().rev().unzip();
+        if let Some(tok @ Token::Def) | Some(tok @ Token::Extern) = tokens.last() {
+            return Err(ParserError::UnexpectedItem(tok.into_owned()));
+        }
+
+        let expr = self.parse_expr(&mut tokens)?;
+        while tokens.last() == Some(&Token::Delimiter) {
+            tokens.pop();
+        }
+        match tokens.pop() {
+            Some(found) => Err(ParserError::TrailingTokens {
+                found: found.into_owned(),
+                span: spans[tokens.len()].clone(),
+            }),
+            None => Ok(expr),
+        }
+    }
+
+    /// print the expression with the parentheses this parser's operator
+    /// table needs and a reader expects - in tests every node printed is
+    /// parsed back to check the two agree
+    pub fn print_expression(&self, expr: &Expression) -> String {
+        #[cfg(test)]
+        self.verify_printed(expr);
+        self.print(expr)
+    }
+
+    fn print(&self, expr: &Expression) -> String {
+        let precedence = |op: &str| self.operator_precedence.get(op).copied().unwrap_or(0);
+        WithPrecedence {
+            expr,
+            precedence: &precedence,
+        }
+        .to_string()
+    }
+
+    #[cfg(test)]
+    fn verify_printed(&self, expr: &Expression) {
+        let printed = self.print(expr);
+        let reparsed = self.parse_expression_str(&printed);
+        // the trees are cut down, a generated one can be huge
+        assert!(
+            reparsed.as_ref() == Ok(expr),
+            "{} printed as {}, which reparses as {}",
+            expr.summary(),
+            printed,
+            match &reparsed {
+                Ok(reparsed) => reparsed.display_tree(6, 8),
+                Err(err) => err.to_string(),
+            }
+        );
+        match expr {
+            Expression::Literal(_) | Expression::Variable(_) | Expression::Error(_) => (),
+            Expression::Call(_, args) => args.iter().for_each(|arg| self.verify_printed(arg)),
+            Expression::NamedCall(_, args) => {
+                args.iter().for_each(|(_, arg)| self.verify_printed(arg))
+            }
+            Expression::Binary(_, lhs, rhs) => {
+                self.verify_printed(lhs);
+                self.verify_printed(rhs);
+            }
+        }
     }
 }
 
@@ -239,12 +1191,16 @@ mod tests {
     #[test]
     fn lamda_parse_works() {
         let parser = Parser::default();
-        let mut tokens = lexer::lex("1;");
+        let mut tokens = lexer::lex("1;").unwrap();
         let res = parser.parse(&mut tokens).unwrap();
         let target = vec![ASTNode::Function(Function {
             prototype: Prototype {
                 name: "lambda".to_string(),
                 args: vec![],
+                link_name: None,
+                defaults: vec![],
+                void: false,
+                math: MathMode::default(),
             },
             body: Expression::Literal(1.0),
         })];
@@ -254,24 +1210,84 @@ mod tests {
     #[test]
     fn extern_parse_works() {
         let parser = Parser::default();
-        let mut tokens = lexer::lex("🜹sin🜄x🜂;");
+        let mut tokens = lexer::lex("🜹sin🜄x🜂;").unwrap();
         let res = parser.parse(&mut tokens).unwrap();
         let target = vec![ASTNode::Extern(Prototype {
             name: "sin".to_string(),
             args: vec!["x".to_string()],
+            link_name: None,
+            defaults: vec![None],
+            void: false,
+            math: MathMode::default(),
         })];
         assert_eq!(res, target);
     }
 
+    #[test]
+    fn void_extern_parse_works() {
+        let parser = Parser::default();
+        let res = parser.parse_str("🜹void log_value🜄x🜂;🜹void🜄x🜂;").unwrap();
+        let target = vec![
+            ASTNode::Extern(Prototype {
+                name: "log_value".to_string(),
+                args: vec!["x".to_string()],
+                link_name: None,
+                defaults: vec![None],
+                void: true,
+                math: MathMode::default(),
+            }),
+            ASTNode::Extern(Prototype {
+                name: "void".to_string(),
+                args: vec!["x".to_string()],
+                link_name: None,
+                defaults: vec![None],
+                void: false,
+                math: MathMode::default(),
+            }),
+        ];
+        assert_eq!(res, target);
+        assert_eq!(res[0].to_string(), "🜹void log_value🜄x🜂");
+        assert!(parser.parse_str("🜙void f🜄🜂1;").is_err());
+    }
+
+    #[test]
+    fn extern_alias_parse_works() {
+        let parser = Parser::default();
+        let mut tokens = lexer::lex(r#"🜹circle🜄x🜌y🜌r🜂 = "eng_draw_circle_f64";"#).unwrap();
+        let res = parser.parse(&mut tokens).unwrap();
+        let target = vec![ASTNode::Extern(Prototype {
+            name: "circle".to_string(),
+            args: vec!["x".to_string(), "y".to_string(), "r".to_string()],
+            link_name: Some("eng_draw_circle_f64".to_string()),
+            defaults: vec![None; 3],
+            void: false,
+            math: MathMode::default(),
+        })];
+        assert_eq!(res, target);
+        let mut tokens = lexer::lex("🜹circle🜄x🜂 = 1;").unwrap();
+        let res = parser.parse(&mut tokens);
+        assert_eq!(
+            res,
+            Err(ParserError::unexpected(
+                &Token::Number(1.0),
+                &[TokenCategory::Str]
+            ))
+        );
+    }
+
     #[test]
     fn def_parse_works() {
         let parser = Parser::default();
-        let mut tokens = lexer::lex("🜙add🜄x🜌y🜂x+y;");
+        let mut tokens = lexer::lex("🜙add🜄x🜌y🜂x+y;").unwrap();
         let res = parser.parse(&mut tokens).unwrap();
         let target = vec![ASTNode::Function(Function {
             prototype: Prototype {
                 name: "add".to_string(),
                 args: vec!["x".to_string(), "y".to_string()],
+                link_name: None,
+                defaults: vec![None; 2],
+                void: false,
+                math: MathMode::default(),
             },
             body: Expression::Binary(
                 "+".to_string(),
@@ -280,30 +1296,121 @@ mod tests {
             ),
         })];
         assert_eq!(res, target);
-        let mut tokens = lexer::lex("🜙one🜄🜂1.0;");
+        let mut tokens = lexer::lex("🜙one🜄🜂1.0;").unwrap();
         let res = parser.parse(&mut tokens).unwrap();
         let target = vec![ASTNode::Function(Function {
             prototype: Prototype {
                 name: "one".to_string(),
                 args: vec![],
+                link_name: None,
+                defaults: vec![],
+                void: false,
+                math: MathMode::default(),
             },
             body: Expression::Literal(1.0),
         })];
         assert_eq!(res, target);
     }
 
+    #[test]
+    fn ascii_spellings_parse() {
+        let parser = Parser::default();
+        assert_eq!(
+            parser.parse_str("def add(x, y) x + y;"),
+            parser.parse_str("🜙add🜄x🜌y🜂x + y;")
+        );
+        let mixed = "extern sin🜄x);🜙add(x🜌 y = 2🜂x + y;\ndef twice(x) add🜄x, x);add(sin(1));";
+        let glyphs = "🜹sin🜄x🜂;🜙add🜄x🜌y = 2🜂x + y;🜙twice🜄x🜂add🜄x🜌x🜂;add🜄sin🜄1🜂🜂;";
+        assert_eq!(parser.parse_str(mixed), parser.parse_str(glyphs));
+        assert_eq!(parser.parse_str(mixed).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn math_modes_parse() {
+        let parser = Parser::default();
+        let ast = parser
+            .parse_str("🜙fast kernel🜄x🜂x*x;🜙strict sum🜄x🜂x;🜙fast🜄x🜂x;🜙plain🜄🜂1;")
+            .unwrap();
+        let modes: Vec<(&str, MathMode)> = ast
+            .iter()
+            .map(|node| match node {
+                ASTNode::Function(func) => (func.prototype.name.as_str(), func.prototype.math),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            modes,
+            [
+                ("kernel", MathMode::Fast),
+                ("sum", MathMode::Strict),
+                ("fast", MathMode::Inherit),
+                ("plain", MathMode::Inherit),
+            ]
+        );
+        assert_eq!(ast[0].to_string(), "🜙fast kernel🜄x🜂 x * x");
+        assert_eq!(parser.parse_str(&ast[1].to_string()).unwrap(), ast[1..2]);
+    }
+
+    #[test]
+    fn test_parses() {
+        let parser = Parser::default();
+        assert_eq!(
+            parser.parse_str("test \"sum\" f🜄1🜂 + 2 expect 3;test \"bare\" 1 2;"),
+            Err(ParserError::MissingExpect("bare".to_string()))
+        );
+        let ast = parser
+            .parse_str("test \"sum\" f🜄1🜂 + 2 expect pi🜄🜂 / 2;test🜄1🜂;")
+            .unwrap();
+        assert_eq!(
+            ast[0],
+            ASTNode::Test(Test {
+                name: "sum".to_string(),
+                expr: parser.parse_expression_str("f🜄1🜂 + 2").unwrap(),
+                expected: parser.parse_expression_str("pi🜄🜂 / 2").unwrap(),
+            })
+        );
+        assert!(matches!(&ast[1], ASTNode::Function(func) if func.prototype.name == "lambda"));
+        assert_eq!(ast[0].to_string(), "test \"sum\" f🜄1🜂 + 2 expect pi🜄🜂 / 2");
+        assert_eq!(parser.parse_str(&ast[0].to_string()).unwrap(), ast[..1]);
+
+        let ast = parser
+            .parse_str("test \"a \\\"quoted\\\"\\tname\" 1 expect 1;")
+            .unwrap();
+        assert!(matches!(&ast[0], ASTNode::Test(test) if test.name == "a \"quoted\"\tname"));
+        assert_eq!(parser.parse_str(&ast[0].to_string()).unwrap(), ast);
+    }
+
+    #[test]
+    fn inline_ir_parses() {
+        let source = "llvm \"define double @one() {\n  ret double 1.0\n}\";one🜄🜂;";
+        assert_eq!(
+            Parser::default().parse_str(source),
+            Err(ParserError::InlineIrNotAllowed)
+        );
+        let parser = Parser {
+            allow_inline_ir: true,
+            ..Parser::default()
+        };
+        let ast = parser.parse_str(source).unwrap();
+        assert_eq!(
+            ast[0],
+            ASTNode::InlineIr("define double @one() {\n  ret double 1.0\n}".to_string())
+        );
+        assert_eq!(parser.parse_str(&ast[0].to_string()).unwrap(), ast[..1]);
+    }
+
     #[test]
     fn parse_call_works() {
         let parser = Parser::default();
         let input = "add🜄1🜌2🜂";
-        let mut tokens = lexer::lex(input);
+        let mut tokens = lexer::lex(input).unwrap();
         let res = parser.parse_expr(&mut tokens).unwrap();
         let target = Expression::Call(
             "add".to_string(),
             vec![Expression::Literal(1.0), Expression::Literal(2.0)],
         );
         assert_eq!(res, target);
-        let mut tokens = lexer::lex("one🜄🜂");
+        let mut tokens = lexer::lex("one🜄🜂").unwrap();
         let res = parser.parse_expr(&mut tokens).unwrap();
         let target = Expression::Call("one".to_string(), vec![]);
         assert_eq!(res, target);
@@ -313,7 +1420,7 @@ mod tests {
     fn parse_expr_works() {
         let input = "x + 1 * 🜄2 - 3🜂";
         let parser = Parser::default();
-        let mut tokens = lexer::lex(input);
+        let mut tokens = lexer::lex(input).unwrap();
         let res = parser.parse_expr(&mut tokens).unwrap();
         let target = Expression::Binary(
             "+".to_string(),
@@ -331,11 +1438,38 @@ mod tests {
         assert_eq!(res, target);
     }
 
+    #[test]
+    fn fractional_literals_parse() {
+        let parser = Parser::default();
+        let res = parser.parse_str(".5 + .25;").unwrap();
+        let target = vec![ASTNode::Function(Function {
+            prototype: Prototype {
+                name: "lambda".to_string(),
+                args: vec![],
+                link_name: None,
+                defaults: vec![],
+                void: false,
+                math: MathMode::default(),
+            },
+            body: Expression::Binary(
+                "+".to_string(),
+                Box::new(Expression::Literal(0.5)),
+                Box::new(Expression::Literal(0.25)),
+            ),
+        })];
+        assert_eq!(res, target);
+        assert_eq!(parser.parse_str("1. * 2;"), parser.parse_str("1 * 2;"));
+        assert!(matches!(
+            parser.parse_str("1 + . 5;"),
+            Err(ParserError::Lex(LexError::InvalidNumber { .. }))
+        ));
+    }
+
     #[test]
     fn invalid_operator_works() {
         let input = "x : 1";
         let parser = Parser::default();
-        let mut tokens = lexer::lex(input);
+        let mut tokens = lexer::lex(input).unwrap();
         let res = parser.parse_expr(&mut tokens);
         assert_eq!(res, Err(ParserError::InvalidOperator(":".to_string())));
     }
@@ -344,16 +1478,711 @@ mod tests {
     fn invalid_token_works() {
         let input = "🜄1 + 🜂";
         let parser = Parser::default();
-        let mut tokens = lexer::lex(input);
+        let mut tokens = lexer::lex(input).unwrap();
         let res = parser.parse_expr(&mut tokens);
-        assert_eq!(res, Err(ParserError::InvalidToken(Token::CloseParen)));
+        assert_eq!(
+            res,
+            Err(ParserError::unexpected(
+                &Token::CloseParen,
+                &[TokenCategory::Expression]
+            ))
+        );
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "expected an expression, found '🜂'"
+        );
+    }
+
+    #[test]
+    fn expected_tokens_work() {
+        let parser = Parser::default();
+        let cases: &[(&str, Token, &[TokenCategory])] = &[
+            ("🜙🜄x🜂x;", Token::OpenParen, &[TokenCategory::Identifier]),
+            ("🜙f x;", Token::Ident("x"), &[TokenCategory::OpenParen]),
+            ("🜙f🜄x y🜂x;", Token::Ident("y"), ARG_SEPARATORS),
+            ("f🜄1;", Token::Delimiter, ARG_SEPARATORS),
+            ("🜄1 2", Token::Number(2.0), &[TokenCategory::CloseParen]),
+            ("1 + 🜌", Token::Comma, &[TokenCategory::Expression]),
+        ];
+        for (source, found, expected) in cases {
+            assert_eq!(
+                parser.parse_str(source),
+                Err(ParserError::unexpected(found, expected)),
+                "{}",
+                source
+            );
+        }
+        let err = parser.parse_str("🜙f🜄x y🜂x;").unwrap_err();
+        assert_eq!(err.to_string(), "expected '🜌' or '🜂', found 'y'");
+    }
+
+    #[test]
+    fn negation_works() {
+        let parser = Parser::default();
+        let neg = |expr| {
+            Expression::Binary(
+                "*".to_string(),
+                Box::new(Expression::Literal(-1.0)),
+                Box::new(expr),
+            )
+        };
+        let x = || Expression::Variable("x".to_string());
+        assert_eq!(
+            parser.parse_expression_str("f🜄-1🜂"),
+            Ok(Expression::Call(
+                "f".to_string(),
+                vec![Expression::Literal(-1.0)]
+            ))
+        );
+        let sum = Expression::Binary(
+            "+".to_string(),
+            Box::new(x()),
+            Box::new(Expression::Literal(1.0)),
+        );
+        assert_eq!(
+            parser.parse_expression_str("f🜄-🜄x + 1🜂🜌 -2🜂"),
+            Ok(Expression::Call(
+                "f".to_string(),
+                vec![neg(sum), Expression::Literal(-2.0)]
+            ))
+        );
+        // it binds tighter than the binary operators
+        assert_eq!(
+            parser.parse_expression_str("-x * 2"),
+            Ok(Expression::Binary(
+                "*".to_string(),
+                Box::new(neg(x())),
+                Box::new(Expression::Literal(2.0))
+            ))
+        );
+        assert_eq!(
+            parser.parse_expression_str("1 - -x"),
+            parser.parse_expression_str("1 - -1 * x")
+        );
+        assert_eq!(
+            parser.parse_expression_str("--2"),
+            Ok(Expression::Literal(2.0))
+        );
+        assert_eq!(parser.print_expression(&neg(x())), "-1 * x");
+    }
+
+    #[test]
+    fn missing_comma_works() {
+        let parser = Parser::default();
+        let cases: &[(&str, OwnedToken)] = &[
+            ("f🜄1 2🜂;", OwnedToken::Number(2.0)),
+            ("f🜄x🜌g🜄🜂 y🜂;", OwnedToken::Ident("y".to_string())),
+            ("f🜄1 🜄2🜂🜂;", OwnedToken::OpenParen),
+        ];
+        for (source, found) in cases {
+            assert_eq!(
+                parser.parse_str(source),
+                Err(ParserError::MissingComma(found.clone())),
+                "{}",
+                source
+            );
+        }
+        let err = parser.parse_str("f🜄1 2🜂;").unwrap_err();
+        assert_eq!(err.to_string(), "missing '🜌' between arguments, before '2'");
+        // parameters still get the generic error
+        assert_eq!(
+            parser.parse_str("🜙f🜄x y🜂x;"),
+            Err(ParserError::unexpected(&Token::Ident("y"), ARG_SEPARATORS))
+        );
     }
 
     #[test]
     fn unexpected_eof_works() {
         let parser = Parser::default();
-        let mut tokens = lexer::lex("1 + ");
+        let mut tokens = lexer::lex("1 + ").unwrap();
         let res = parser.parse_expr(&mut tokens);
         assert_eq!(res, Err(ParserError::UnexpectedEOF));
     }
+
+    #[test]
+    fn parse_items_works() {
+        let parser = Parser::default();
+        let tokens = lexer::Lexer::new("🜹sin🜄x🜂;🜙bad🜄🜂+;1;");
+        let res: Vec<_> = parser.parse_items(tokens).collect();
+        assert_eq!(res.len(), 3);
+        assert!(matches!(res[0], Ok(ASTNode::Extern(_))));
+        assert_eq!(
+            res[1],
+            Err(ParserError::unexpected(
+                &Token::Operator("+"),
+                &[TokenCategory::Expression]
+            ))
+        );
+        assert_eq!(res[2], parser.parse_str("1;").map(|mut ast| ast.remove(0)));
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        let parser = Parser::default();
+        let source = "# squares\n🜙sq🜄x🜂 # of x\n x*x;\nsq🜄2🜂; # four";
+        let trivia = lexer::lex_with_trivia(source).unwrap();
+        let mut tokens: Vec<Token> = trivia.iter().rev().map(|(tok, _)| *tok).collect();
+        assert_eq!(parser.parse(&mut tokens), parser.parse_str(source));
+        let items: Result<Vec<_>, _> = parser.parse_items(lexer::Lexer::new(source)).collect();
+        assert_eq!(items, parser.parse_str(source));
+        let tokens = trivia.into_iter().map(|(tok, _)| Ok(tok));
+        let items: Result<Vec<_>, _> = parser.parse_iter(tokens).collect();
+        assert_eq!(items, parser.parse_str(source));
+    }
+
+    #[test]
+    fn remapped_glyphs_parse_the_same() {
+        let parser = Parser::default();
+        let config = lexer::LexerConfig::new(lexer::Glyphs {
+            def: '⊢',
+            extern_: '⊣',
+            open_paren: '⟦',
+            close_paren: '⟧',
+            comma: '⁏',
+        })
+        .unwrap();
+        let mut tokens =
+            lexer::lex_with_config("⊣cos⟦x⟧; ⊢f⟦x⁏ y⟧ cos⟦x⟧*y; f⟦0⁏ 2⟧;", &config).unwrap();
+        assert_eq!(
+            parser.parse(&mut tokens),
+            parser.parse_str("🜹cos🜄x🜂; 🜙f🜄x🜌 y🜂 cos🜄x🜂*y; f🜄0🜌 2🜂;")
+        );
+    }
+
+    #[test]
+    fn newline_delimiters_work() {
+        let parser = Parser::default();
+        let mut config = lexer::LexerConfig::default();
+        config.newline_delimiters = true;
+        let source = "🜹sin🜄x🜂\n🜙f🜄x🜌 y🜂\n  🜄sin🜄x🜂 *\n   y🜂 - 1; f🜄1🜌 2🜂\nf🜄\n  3🜌\n  4\n🜂\n";
+        let mut tokens = lexer::lex_with_config(source, &config).unwrap();
+        assert_eq!(
+            parser.parse(&mut tokens),
+            parser.parse_str("🜹sin🜄x🜂; 🜙f🜄x🜌 y🜂 🜄sin🜄x🜂*y🜂 - 1; f🜄1🜌 2🜂; f🜄3🜌 4🜂;")
+        );
+        // a line that could go on with the next one still ends there
+        let source = "🜙f🜄x🜂 x\n-1\n";
+        let mut tokens = lexer::lex_with_config(source, &config).unwrap();
+        assert_eq!(parser.parse(&mut tokens), parser.parse_str("🜙f🜄x🜂 x; -1;"));
+        // without it the newlines are just whitespace
+        let mut tokens = lexer::lex_with_config(source, &lexer::LexerConfig::default()).unwrap();
+        assert_eq!(parser.parse(&mut tokens), parser.parse_str("🜙f🜄x🜂 x - 1;"));
+    }
+
+    #[test]
+    fn names_are_normalized() {
+        let parser = Parser::default();
+        let decomposed = "🜙cafe\u{301}🜄cre\u{300}me🜂 crème*2; café🜄cre\u{300}me: 1🜂;";
+        assert_eq!(
+            parser.parse_str(decomposed),
+            parser.parse_str("🜙café🜄crème🜂 crème*2; café🜄crème: 1🜂;")
+        );
+    }
+
+    #[test]
+    fn parse_iter_works() {
+        let parser = Parser::default();
+        let source = "🜹sin🜄x🜂;🜙f🜄x🜂sin🜄x🜂*2;f🜄1🜂;f🜄2🜂";
+        let mut tokens = lexer::Lexer::new(source).peekable();
+        let items: Result<Vec<_>, _> = parser.parse_iter(&mut tokens).collect();
+        assert_eq!(items, parser.parse_str(source));
+        assert_eq!(tokens.next(), None);
+
+        let tokens = lexer::Lexer::new("1;2;🜙bad🜄🜂+;\"x;3;");
+        let items: Vec<_> = parser.parse_iter(tokens).collect();
+        assert_eq!(items.len(), 5);
+        let item = |source| parser.parse_str(source).map(|mut ast| ast.remove(0));
+        assert_eq!(items[0], item("1;"));
+        assert_eq!(items[1], item("2;"));
+        assert_eq!(
+            items[2],
+            Err(ParserError::unexpected(
+                &Token::Operator("+"),
+                &[TokenCategory::Expression]
+            ))
+        );
+        assert!(matches!(
+            items[3],
+            Err(ParserError::Lex(LexError::UnterminatedString { .. }))
+        ));
+        assert_eq!(items[4], item("3;"));
+    }
+
+    #[test]
+    fn parse_expression_str_works() {
+        let parser = Parser::default();
+        let expr = parser.parse_expression_str("f🜄x🜌2🜂*3;").unwrap();
+        assert_eq!(
+            expr,
+            Expression::Binary(
+                "*".to_string(),
+                Box::new(Expression::Call(
+                    "f".to_string(),
+                    vec![
+                        Expression::Variable("x".to_string()),
+                        Expression::Literal(2.0)
+                    ]
+                )),
+                Box::new(Expression::Literal(3.0))
+            )
+        );
+        assert_eq!(
+            parser.parse_expression_str("1 + 2; 3"),
+            Err(ParserError::TrailingTokens {
+                found: OwnedToken::Number(3.0),
+                span: 7..8,
+            })
+        );
+        assert_eq!(
+            parser.parse_expression_str("x y").unwrap_err().to_string(),
+            "expected the end of the expression, found 'y' at 2"
+        );
+        assert_eq!(
+            parser.parse_expression_str("🜙f🜄x🜂x;"),
+            Err(ParserError::UnexpectedItem(OwnedToken::Def))
+        );
+        assert_eq!(
+            parser.parse_expression_str("🜹sin🜄x🜂"),
+            Err(ParserError::UnexpectedItem(OwnedToken::Extern))
+        );
+        assert_eq!(parser.parse_program_str("1;"), parser.parse_str("1;"));
+    }
+
+    #[test]
+    fn parse_recovering_works() {
+        let parser = Parser::default();
+        let source = "🜙f🜄x🜂x + * 2 - g🜄1 2🜌x🜂 * 🜄x : x🜂 + 🜄1 + 🜂;f🜄1🜂;";
+        let (ast, located) = parser.parse_recovering(source);
+        let errors: Vec<ParserError> = located.iter().map(|err| err.error.clone()).collect();
+        assert_eq!(
+            errors,
+            [
+                ParserError::unexpected(&Token::Operator("*"), &[TokenCategory::Expression]),
+                ParserError::MissingComma(OwnedToken::Number(2.0)),
+                ParserError::InvalidOperator(":".to_string()),
+                ParserError::unexpected(&Token::CloseParen, &[TokenCategory::Expression]),
+            ]
+        );
+        assert_eq!(ast.len(), 2);
+        assert_eq!(
+            ast[0].to_string(),
+            "🜙f🜄x🜂 x + <error> - g🜄1🜌<error>🜌2🜌x🜂 * 🜄🜄x : <error>🜂 + 1 + <error>🜂"
+        );
+        // columns count characters, not the bytes of the glyphs
+        let columns: Vec<u32> = located.iter().map(|err| err.location.column).collect();
+        assert_eq!(columns, [10, 20, 30, 42]);
+        assert_eq!(
+            located[0].to_string(),
+            "1:10: expected an expression, found '*'"
+        );
+        assert_eq!(parser.parse_str("🜙f🜄x🜂x + * 2;"), Err(errors[0].clone()));
+
+        let (ast, located) = parser.parse_recovering("🜙f🜄x🜂🜄x + 1;\n🜙g x;\nf🜄2🜂🜂;1;");
+        let errors: Vec<ParserError> = located.iter().map(|err| err.error.clone()).collect();
+        let locations: Vec<String> = located.iter().map(|err| err.location.to_string()).collect();
+        assert_eq!(locations, ["1:12", "2:4", "3:5"]);
+        assert_eq!(
+            errors,
+            [
+                ParserError::unexpected(&Token::Delimiter, &[TokenCategory::CloseParen]),
+                ParserError::unexpected(&Token::Ident("x"), &[TokenCategory::OpenParen]),
+                ParserError::unexpected(&Token::CloseParen, &[TokenCategory::Expression]),
+            ]
+        );
+        assert_eq!(ast.len(), 3);
+
+        let (ast, located) = parser.parse_recovering("1;\n🜙f🜄x🜂x + 1.2.3;");
+        assert!(ast.is_empty());
+        assert_eq!(located.len(), 1);
+        assert_eq!(located[0].to_string(), "2:10: invalid number 1.2.3");
+        assert!(matches!(
+            parser.parse_str("1 + 1.2.3"),
+            Err(ParserError::Lex(LexError::InvalidNumber { .. }))
+        ));
+        let (_, located) = parser.parse_recovering("🜙f🜄x🜂x;\nf🜄“hi”🜂;");
+        assert_eq!(located[0].to_string(), "2:3: unexpected character '“'");
+
+        let source = "🜹sin🜄x🜂;🜙f🜄x🜌y = 2🜂sin🜄x🜂 * 🜄y - 1🜂;f🜄1🜂;";
+        let (ast, errors) = parser.parse_recovering(source);
+        assert!(errors.is_empty());
+        assert_eq!(ast, parser.parse_str(source).unwrap());
+        let bodies = ast.iter().filter_map(|node| match node {
+            ASTNode::Function(func) => Some(&func.body),
+            _ => None,
+        });
+        assert!(bodies.clone().count() == 2 && !bodies.into_iter().any(Expression::has_error));
+    }
+
+    #[test]
+    fn bools_work() {
+        let parser = Parser::default();
+        assert_eq!(
+            parser.parse_str("🜙f🜄x🜂 x*true + false; f🜄true🜂;"),
+            parser.parse_str("🜙f🜄x🜂 x*1 + 0; f🜄1🜂;")
+        );
+        assert_eq!(parser.parse_str("-true;"), parser.parse_str("-1;"));
+
+        let allowed = Parser {
+            allow_reserved: true,
+            ..Parser::default()
+        };
+        let cases = [
+            ("🜙true🜄🜂 1;", "true", Binding::Function),
+            ("🜹sin🜄x🜌 false🜂;", "false", Binding::Parameter),
+            (r#"table false from "f.csv";"#, "false", Binding::Table),
+        ];
+        for (source, word, binding) in cases.iter() {
+            let err = ParserError::KeywordName {
+                word: word.to_string(),
+                binding: *binding,
+            };
+            assert_eq!(parser.parse_str(source), Err(err.clone()), "{}", source);
+            assert_eq!(allowed.parse_str(source), Err(err), "{}", source);
+        }
+        assert_eq!(
+            parser.parse_str("🜙true🜄🜂 1;").unwrap_err().to_string(),
+            "true is a keyword and can't name a function"
+        );
+    }
+
+    #[test]
+    fn control_words_cant_be_names() {
+        let parser = Parser::default();
+        let allowed = Parser {
+            allow_reserved: true,
+            ..Parser::default()
+        };
+        let cases = [
+            ("def if(x) x;", "if", Binding::Function),
+            ("🜙f🜄x🜌 then🜂 x;", "then", Binding::Parameter),
+            ("🜹g🜄else🜌 x🜂;", "else", Binding::Parameter),
+            ("🜙for🜄🜂 1;", "for", Binding::Function),
+            (r#"table in from "f.csv";"#, "in", Binding::Table),
+        ];
+        for (source, word, binding) in cases.iter() {
+            let err = ParserError::KeywordName {
+                word: word.to_string(),
+                binding: *binding,
+            };
+            assert_eq!(parser.parse_str(source), Err(err.clone()), "{}", source);
+            assert_eq!(allowed.parse_str(source), Err(err), "{}", source);
+        }
+        assert_eq!(
+            parser.parse_str("def if(x) x;").unwrap_err().to_string(),
+            "if is a keyword and can't name a function"
+        );
+        // names that only start with one are fine
+        assert_eq!(
+            parser
+                .parse_str("def iffy(x) x; iffy(in_x);")
+                .map(|ast| ast.len()),
+            Ok(2)
+        );
+        assert_eq!(
+            parser.parse_str("f🜄if🜂;"),
+            Err(ParserError::unexpected(
+                &Token::If,
+                &[TokenCategory::Expression]
+            ))
+        );
+    }
+
+    #[test]
+    fn reserved_words_work() {
+        let parser = Parser::default();
+        let cases = [
+            ("🜙while🜄x🜂x;", "while", Binding::Function),
+            ("🜙f🜄x🜌var🜂x;", "var", Binding::Parameter),
+            ("🜙f🜄x🜌const = 2🜂x;", "const", Binding::Parameter),
+            ("🜹while🜄x🜂;", "while", Binding::Function),
+            ("🜹void const🜄🜂;", "const", Binding::Function),
+            ("🜹sin🜄var🜂;", "var", Binding::Parameter),
+            (r#"table var from "f.csv";"#, "var", Binding::Table),
+        ];
+        for (source, word, context) in cases.iter() {
+            let start = source.find(word).unwrap();
+            assert_eq!(
+                parser.parse_str(source),
+                Err(ParserError::ReservedWord {
+                    word: word.to_string(),
+                    span: Some(start..start + word.len()),
+                    context: *context,
+                }),
+                "{}",
+                source
+            );
+        }
+        assert_eq!(
+            parser.parse_str("🜙f🜄var🜂1;").unwrap_err().to_string(),
+            "var is reserved for future syntax and can't name a parameter"
+        );
+        let source = "1;\n🜙f🜄var🜂1;";
+        let (_, errors) = parser.parse_recovering(source);
+        let start = source.find("var").unwrap();
+        assert_eq!(
+            errors[0].error,
+            ParserError::ReservedWord {
+                word: "var".to_string(),
+                span: Some(start..start + 3),
+                context: Binding::Parameter,
+            }
+        );
+        assert_eq!(errors[0].location.to_string(), "2:4");
+
+        let allowed = Parser {
+            allow_reserved: true,
+            ..Parser::default()
+        };
+        for (source, ..) in cases.iter() {
+            assert!(allowed.parse_str(source).is_ok(), "{}", source);
+        }
+        // only names that are bound are checked, and contextual keywords
+        // stay usable as names
+        assert!(parser.parse_str("f🜄while🜂 + g🜄x: var🜂;").is_ok());
+        assert!(parser.parse_str("🜙when🜄table🜌and🜂table;🜹void🜄🜂;").is_ok());
+    }
+
+    #[test]
+    fn print_expression_works() {
+        let mut parser = Parser::default();
+        let cases = [
+            ("x + 1 * 🜄2 - 3🜂", "x + 1 * 🜄2 - 3🜂"),
+            ("add🜄1🜌2🜂", "add🜄1🜌2🜂"),
+            ("f🜄x🜌2🜂*3;", "f🜄x🜌2🜂 * 3"),
+            ("🜄1 + 2🜂 * 3", "🜄1 + 2🜂 * 3"),
+            ("x*2+1", "x * 🜄2 + 1🜂"),
+            ("a - b - c", "a - 🜄b - c🜂"),
+            ("a + b - c", "a + b - c"),
+            ("a * b / c", "a * b / c"),
+            ("a / b * c", "a / 🜄b * c🜂"),
+            ("f🜄🜄a🜂🜌n: 🜄b + c🜂🜂", "f🜄a🜌n: b + c🜂"),
+        ];
+        for (source, printed) in cases.iter() {
+            let expr = parser.parse_expression_str(source).unwrap();
+            assert_eq!(parser.print_expression(&expr), *printed);
+        }
+
+        parser.operator_precedence.insert("%".to_string(), 60);
+        let expr = parser.parse_expression_str("a % b + c % d").unwrap();
+        assert_eq!(parser.print_expression(&expr), "a % 🜄b + c % d🜂");
+    }
+
+    #[test]
+    #[should_panic(expected = "printed as a @ b")]
+    fn print_expression_checks_the_table() {
+        let expr = Expression::Binary(
+            "@".to_string(),
+            Box::new(Expression::Variable("a".to_string())),
+            Box::new(Expression::Variable("b".to_string())),
+        );
+        Parser::default().print_expression(&expr);
+    }
+
+    /// xorshift, so a failing case reproduces from its seed
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    fn random_expression(rng: &mut Rng, ops: &[String], depth: u32) -> Expression {
+        let leaf = depth == 0 || rng.below(4) == 0;
+        match rng.below(if leaf { 2 } else { 6 }) {
+            0 => Expression::Literal(rng.below(40) as f64 / 4.0),
+            1 => Expression::Variable(["x", "y", "t"][rng.below(3)].to_string()),
+            2 => {
+                let args = (0..rng.below(3))
+                    .map(|_| random_expression(rng, ops, depth - 1))
+                    .collect();
+                Expression::Call("f".to_string(), args)
+            }
+            3 => {
+                let mut args = Vec::new();
+                for i in 0..=rng.below(2) {
+                    let name = match rng.below(2) {
+                        0 => None,
+                        _ => Some(format!("n{}", i)),
+                    };
+                    args.push((name, random_expression(rng, ops, depth - 1)));
+                }
+                let last = random_expression(rng, ops, depth - 1);
+                args.push((Some("last".to_string()), last));
+                Expression::NamedCall("g".to_string(), args)
+            }
+            _ => Expression::Binary(
+                ops[rng.below(ops.len())].clone(),
+                Box::new(random_expression(rng, ops, depth - 1)),
+                Box::new(random_expression(rng, ops, depth - 1)),
+            ),
+        }
+    }
+
+    #[test]
+    fn print_expression_round_trips_random_tables() {
+        let operators = ["+", "-", "*", "/", "%", "^", "&", "|", "<", ">"];
+        for seed in 1..200 {
+            let mut rng = Rng(0x9e37_79b9_7f4a_7c15_u64.wrapping_mul(seed));
+            let mut parser = Parser {
+                operator_precedence: HashMap::new(),
+                ..Parser::default()
+            };
+            for op in operators.iter() {
+                if rng.below(2) == 0 {
+                    let precedence = 10 * (rng.below(4) as u32 + 1);
+                    parser
+                        .operator_precedence
+                        .insert(op.to_string(), precedence);
+                }
+            }
+            let ops: Vec<String> = parser.operator_precedence.keys().cloned().collect();
+            if ops.is_empty() {
+                continue;
+            }
+            for _ in 0..20 {
+                let expr = random_expression(&mut rng, &ops, 4);
+                parser.print_expression(&expr);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let parser = Parser::default();
+        for tok in [
+            Token::Ident(""),
+            Token::Ident("a b"),
+            Token::Operator(""),
+            Token::Number(f64::NAN),
+            Token::Number(f64::INFINITY),
+        ]
+        .iter()
+        {
+            let mut input = vec![Token::Number(1.0), Token::Operator("+"), *tok];
+            assert!(matches!(
+                parser.parse(&mut input),
+                Err(ParserError::MalformedToken { found, .. }) if found.to_string() == tok.to_string()
+            ));
+        }
+        let mut input = vec![Token::Ident("x"), Token::Delimiter, Token::Ident("")];
+        let items: Vec<_> = parser
+            .parse_iter(input.iter().rev().map(|tok| Ok(*tok)))
+            .collect();
+        assert_eq!(items.len(), 2);
+        assert!(matches!(items[0], Err(ParserError::MalformedToken { .. })));
+        assert_eq!(items[1], parser.parse_str("x").map(|mut ast| ast.remove(0)));
+        assert_eq!(
+            parser.parse(&mut input).unwrap_err().to_string(),
+            "malformed token '', identifiers can't be empty or hold whitespace"
+        );
+
+        // a recovering parse still takes a stray paren
+        let recovering = Parser {
+            recover: true,
+            ..Parser::default()
+        };
+        let tokens = vec![Ok(Token::CloseParen), Ok(Token::Number(1.0))];
+        let items: Vec<_> = recovering.parse_iter(tokens.into_iter()).collect();
+        assert_eq!(items.len(), 1);
+        assert!(items[0].is_err());
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| {
+            let mut input = vec![Token::CloseParen; depth];
+            input.push(Token::Number(1.0));
+            input.extend(vec![Token::OpenParen; depth]);
+            input
+        };
+        let parser = Parser {
+            max_depth: 3,
+            ..Parser::default()
+        };
+        assert!(parser.parse(&mut nested(2)).is_ok());
+        assert_eq!(parser.parse(&mut nested(3)), Err(ParserError::TooDeep(3)));
+        // a chain of operators doesn't nest
+        assert_eq!(parser.parse_str("1 + 2 + 3 + 4 + 5").unwrap().len(), 1);
+        assert_eq!(parser.parse_str("f🜄🜄1 + 2🜂🜂 * 3").unwrap().len(), 1);
+        assert_eq!(parser.parse_str("f🜄🜄🜄1🜂🜂🜂"), Err(ParserError::TooDeep(3)));
+        assert_eq!(parser.parse_str("- - - 4"), Err(ParserError::TooDeep(3)));
+
+        let parser = Parser::default();
+        assert!(parser.parse(&mut nested(DEFAULT_MAX_DEPTH - 1)).is_ok());
+        assert_eq!(
+            parser.parse(&mut nested(50_000)),
+            Err(ParserError::TooDeep(DEFAULT_MAX_DEPTH))
+        );
+        let sum = vec!["1"; 4 * DEFAULT_MAX_DEPTH].join(" + ");
+        assert_eq!(parser.parse_str(&sum).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn random_tokens_parse_or_fail_cleanly() {
+        let tokens = [
+            Token::Def,
+            Token::Extern,
+            Token::Delimiter,
+            Token::OpenParen,
+            Token::OpenParen,
+            Token::CloseParen,
+            Token::CloseParen,
+            Token::Comma,
+            Token::Ident("x"),
+            Token::Ident("f"),
+            Token::Ident("when"),
+            Token::Ident("not"),
+            Token::Ident("table"),
+            Token::Ident("from"),
+            Token::Ident("test"),
+            Token::Ident("expect"),
+            Token::Ident("llvm"),
+            Token::Ident("fast"),
+            Token::Ident("void"),
+            Token::Ident(""),
+            Token::Operator("+"),
+            Token::Operator("-"),
+            Token::Operator("*"),
+            Token::Operator("="),
+            Token::Operator(":"),
+            Token::Operator("@"),
+            Token::Operator(" "),
+            Token::Number(1.5),
+            Token::Number(f64::NAN),
+            Token::Number(f64::INFINITY),
+            Token::Str("a"),
+        ];
+        let parser = Parser {
+            allow_inline_ir: true,
+            ..Parser::default()
+        };
+        let recovering = Parser {
+            recover: true,
+            ..parser.clone()
+        };
+        for seed in 1..5000 {
+            let mut rng = Rng(0x2545_f491_4f6c_dd1d_u64.wrapping_mul(seed));
+            let input: Vec<Token> = (0..rng.below(24))
+                .map(|_| tokens[rng.below(tokens.len())])
+                .collect();
+            let _ = recovering.parse(&mut input.clone());
+            let ast = match parser.parse(&mut input.clone()) {
+                Ok(ast) => ast,
+                Err(_) => continue,
+            };
+            let printed: Vec<String> = ast.iter().map(ASTNode::to_string).collect();
+            let printed = printed.join(";");
+            assert_eq!(
+                parser.parse_str(&printed).as_ref(),
+                Ok(&ast),
+                "{:?} printed as {}",
+                input,
+                printed
+            );
+        }
+    }
 }
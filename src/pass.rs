@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+
+use crate::ast::{ASTNode, Expression};
+use crate::table::TableError;
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum PassError {
+    #[error("unknown pass {0}")]
+    UnknownPass(String),
+    #[error("pass {0} was added twice")]
+    DuplicatePass(String),
+    #[error("pass {0} has to run after {1}, but was added before it")]
+    OrderConflict(String, String),
+    #[error("pass {0} failed: {1}")]
+    Failed(String, String),
+    #[error(transparent)]
+    Table(#[from] TableError),
+}
+
+/// messages a pass wants shown to the user, they don't stop the compile
+pub type Diagnostics = Vec<String>;
+
+/// a transformation of the whole ast, run after parsing and before codegen
+pub trait Pass {
+    fn name(&self) -> &str;
+
+    fn run(&mut self, ast: &mut Vec<ASTNode>, diags: &mut Diagnostics) -> Result<(), PassError>;
+
+    /// passes that have to run before this one if they run at all
+    fn after(&self) -> &[&str] {
+        &[]
+    }
+
+    /// how many definitions the last run turned into calls to another
+    fn merged(&self) -> usize {
+        0
+    }
+}
+
+/// the passes a compile runs, in the order they were added
+#[derive(Default)]
+pub struct Passes {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Passes {
+    /// the built in passes with the given names, in the given order
+    pub fn from_names<S: AsRef<str>>(names: &[S]) -> Result<Passes, PassError> {
+        let mut passes = Passes::default();
+        for name in names {
+            passes.add_builtin(name.as_ref())?;
+        }
+        Ok(passes)
+    }
+
+    /// append the built in pass with the given name
+    pub fn add_builtin(&mut self, name: &str) -> Result<(), PassError> {
+        match builtin(name) {
+            Some(pass) => self.add_pass(pass),
+            None => Err(PassError::UnknownPass(name.to_string())),
+        }
+    }
+
+    /// append a pass, rejecting duplicate names and passes that another pass
+    /// already added has to run after
+    pub fn add_pass(&mut self, pass: Box<dyn Pass>) -> Result<(), PassError> {
+        for added in &self.passes {
+            if added.name() == pass.name() {
+                return Err(PassError::DuplicatePass(pass.name().to_string()));
+            }
+            if added.after().contains(&pass.name()) {
+                return Err(PassError::OrderConflict(
+                    added.name().to_string(),
+                    pass.name().to_string(),
+                ));
+            }
+        }
+        self.passes.push(pass);
+        Ok(())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    /// how many definitions the passes turned into calls to another
+    pub fn merged(&self) -> usize {
+        self.passes.iter().map(|pass| pass.merged()).sum()
+    }
+
+    pub fn run(
+        &mut self,
+        ast: &mut Vec<ASTNode>,
+        diags: &mut Diagnostics,
+    ) -> Result<(), PassError> {
+        for pass in self.passes.iter_mut() {
+            let _span = tracing::debug_span!("pass", name = pass.name()).entered();
+            pass.run(ast, diags)?;
+        }
+        Ok(())
+    }
+}
+
+/// look up a built in pass by the name --pass takes
+pub fn builtin(name: &str) -> Option<Box<dyn Pass>> {
+    match name {
+        "const-fold" => Some(Box::new(ConstFold)),
+        "merge-functions" => Some(Box::new(MergeFunctions::default())),
+        _ => None,
+    }
+}
+
+/// replace arithmetic on two literals with its result, unless that
+/// overflows
+pub struct ConstFold;
+
+fn fold(expr: &mut Expression) {
+    match expr {
+        Expression::Literal(_) | Expression::Variable(_) | Expression::Error(_) => (),
+        Expression::Call(_, args) => args.iter_mut().for_each(fold),
+        Expression::NamedCall(_, args) => args.iter_mut().for_each(|(_, arg)| fold(arg)),
+        Expression::Binary(op, lhs, rhs) => {
+            fold(lhs);
+            fold(rhs);
+            if let (Expression::Literal(l), Expression::Literal(r)) = (lhs.as_ref(), rhs.as_ref()) {
+                let value = match op.as_str() {
+                    "+" => l + r,
+                    "-" => l - r,
+                    "*" => l * r,
+                    "/" => l / r,
+                    _ => return,
+                };
+                // left to overflow at run time, where check_overflow can
+                // say which op it was
+                if value.is_infinite() && l.is_finite() && r.is_finite() {
+                    return;
+                }
+                *expr = Expression::Literal(value);
+            }
+        }
+    }
+}
+
+impl Pass for ConstFold {
+    fn name(&self) -> &str {
+        "const-fold"
+    }
+
+    fn run(&mut self, ast: &mut Vec<ASTNode>, _: &mut Diagnostics) -> Result<(), PassError> {
+        for node in ast.iter_mut() {
+            if let ASTNode::Function(func) = node {
+                fold(&mut func.body);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// turn every definition whose body is the same as an earlier one's, up to
+/// the names of their parameters and of the function itself, into a call to
+/// that earlier definition, so the module has one body for each
+#[derive(Default)]
+pub struct MergeFunctions {
+    merged: usize,
+}
+
+/// a key that's the same for two bodies exactly when they only differ in the
+/// names of their parameters and in calls to themselves
+fn structure(expr: &Expression, name: &str, params: &[String], res: &mut String) {
+    match expr {
+        Expression::Literal(value) => res.push_str(&format!("{:x};", value.to_bits())),
+        Expression::Variable(var) => match params.iter().position(|param| param == var) {
+            Some(i) => res.push_str(&format!("${};", i)),
+            None => res.push_str(&format!("{};", var)),
+        },
+        Expression::Binary(op, lhs, rhs) => {
+            res.push_str(&format!("({}", op));
+            structure(lhs, name, params, res);
+            structure(rhs, name, params, res);
+            res.push(')');
+        }
+        Expression::Call(callee, args) => {
+            match callee == name {
+                true => res.push_str("@self("),
+                false => res.push_str(&format!("@{}(", callee)),
+            }
+            for arg in args {
+                structure(arg, name, params, res);
+            }
+            res.push(')');
+        }
+        // resolve has turned every named call into a call and parsing would
+        // have failed on an error, neither is worth merging
+        Expression::NamedCall(..) | Expression::Error(_) => res.push_str(&format!("{:p};", expr)),
+    }
+}
+
+impl Pass for MergeFunctions {
+    fn name(&self) -> &str {
+        "merge-functions"
+    }
+
+    fn run(&mut self, ast: &mut Vec<ASTNode>, diags: &mut Diagnostics) -> Result<(), PassError> {
+        self.merged = merge_functions(ast);
+        if self.merged > 0 {
+            diags.push(format!(
+                "merge-functions turned {} duplicate functions into calls",
+                self.merged
+            ));
+        }
+        Ok(())
+    }
+
+    fn after(&self) -> &[&str] {
+        &["const-fold"]
+    }
+
+    fn merged(&self) -> usize {
+        self.merged
+    }
+}
+
+/// what MergeFunctions does, returning how many definitions it turned into
+/// calls
+pub fn merge_functions(ast: &mut [ASTNode]) -> usize {
+    let mut canonical: HashMap<String, String> = HashMap::new();
+    let mut merged = 0;
+    for node in ast.iter_mut() {
+        let func = match node {
+            // a body that's no bigger than a call isn't worth forwarding
+            ASTNode::Function(func)
+                if func.prototype.name != "lambda"
+                    && !matches!(func.body, Expression::Literal(_) | Expression::Variable(_)) =>
+            {
+                func
+            }
+            _ => continue,
+        };
+        let proto = &func.prototype;
+        let mut key = format!("{}:{:?}:", proto.args.len(), proto.math);
+        structure(&func.body, &proto.name, &proto.args, &mut key);
+        match canonical.get(&key) {
+            Some(name) => {
+                let args = proto.args.iter().cloned().map(Expression::Variable);
+                func.body = Expression::Call(name.clone(), args.collect());
+                merged += 1;
+            }
+            None => {
+                canonical.insert(key, proto.name.clone());
+            }
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use inkwell::context::Context;
+
+    use super::*;
+    use crate::{
+        codegen::Codegen,
+        engine::{Engine, EngineKind, OptLevel},
+        parser::Parser,
+    };
+
+    struct Rename(&'static str, &'static str);
+
+    impl Pass for Rename {
+        fn name(&self) -> &str {
+            "rename"
+        }
+
+        fn run(
+            &mut self,
+            ast: &mut Vec<ASTNode>,
+            diags: &mut Diagnostics,
+        ) -> Result<(), PassError> {
+            for node in ast.iter_mut() {
+                if let ASTNode::Function(func) = node {
+                    if func.prototype.name == self.0 {
+                        func.prototype.name = self.1.to_string();
+                    }
+                }
+            }
+            diags.push(self.name().to_string());
+            Ok(())
+        }
+    }
+
+    struct Log(&'static str, &'static [&'static str]);
+
+    impl Pass for Log {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn run(&mut self, _: &mut Vec<ASTNode>, diags: &mut Diagnostics) -> Result<(), PassError> {
+            diags.push(self.0.to_string());
+            Ok(())
+        }
+
+        fn after(&self) -> &[&str] {
+            self.1
+        }
+    }
+
+    #[test]
+    fn custom_pass_works() {
+        let mut ast = Parser::default().parse_str("🜙f🜄x🜂x*🜄2+3🜂;").unwrap();
+        let mut passes = Passes::from_names(&["const-fold"]).unwrap();
+        passes.add_pass(Box::new(Rename("f", "g"))).unwrap();
+        let mut diags = Diagnostics::new();
+        passes.run(&mut ast, &mut diags).unwrap();
+        assert_eq!(diags, ["rename"]);
+
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        assert!(codegen.module.get_function("f").is_none());
+        let ir = codegen.module.print_to_string().to_string();
+        assert!(ir.contains("define double @g(double %x)"));
+        assert!(ir.contains("fmul double %x, 5.000000e+00"));
+    }
+
+    #[test]
+    fn const_fold_leaves_overflows() {
+        let mut ast = Parser::default()
+            .parse_str("🜙f🜄🜂🜄2 * 3🜂 - 🜄1e200 * 1e200🜂;🜙g🜄🜂1e308 * 10 - 1e308 * 10;")
+            .unwrap();
+        let before = ast[1].clone();
+        let mut passes = Passes::from_names(&["const-fold"]).unwrap();
+        passes.run(&mut ast, &mut Diagnostics::new()).unwrap();
+        let folded = Parser::default()
+            .parse_str("🜙f🜄🜂6 - 🜄1e200 * 1e200🜂;")
+            .unwrap();
+        assert_eq!(ast[0], folded[0]);
+        assert_eq!(ast[1], before);
+    }
+
+    #[test]
+    fn pass_order_works() {
+        let mut passes = Passes::default();
+        passes.add_pass(Box::new(Log("b", &[]))).unwrap();
+        passes.add_pass(Box::new(Log("a", &["b"]))).unwrap();
+        passes.add_pass(Box::new(Log("c", &["b"]))).unwrap();
+        assert_eq!(passes.names(), ["b", "a", "c"]);
+        let mut diags = Diagnostics::new();
+        passes.run(&mut Vec::new(), &mut diags).unwrap();
+        assert_eq!(diags, ["b", "a", "c"]);
+
+        assert_eq!(
+            passes.add_pass(Box::new(Log("a", &[]))),
+            Err(PassError::DuplicatePass("a".to_string()))
+        );
+        let mut passes = Passes::default();
+        passes.add_pass(Box::new(Log("a", &["b"]))).unwrap();
+        assert_eq!(
+            passes.add_pass(Box::new(Log("b", &[]))),
+            Err(PassError::OrderConflict("a".to_string(), "b".to_string()))
+        );
+        assert_eq!(
+            Passes::from_names(&["const-fold", "const-fold"]).err(),
+            Some(PassError::DuplicatePass("const-fold".to_string()))
+        );
+        assert_eq!(
+            Passes::from_names(&["cse"]).err(),
+            Some(PassError::UnknownPass("cse".to_string()))
+        );
+    }
+
+    #[test]
+    fn merge_functions_works() {
+        let source = "🜹sin🜄x🜂;\
+                      🜙area🜄w🜌h🜂w * h / 2;\
+                      🜙tri🜄base🜌height🜂base * height / 2;\
+                      🜙wave🜄t🜂sin🜄t * 2🜂 + 1;\
+                      🜙half🜄a🜌b🜂a * b / 3;\
+                      🜙swapped🜄a🜌b🜂b * a / 2;\
+                      🜙ripple🜄x🜂sin🜄x * 2🜂 + 1;\
+                      🜙strict twice🜄t🜂sin🜄t * 2🜂 + 1;\
+                      🜙loop🜄x🜂loop🜄x🜂 + 1;🜙again🜄y🜂again🜄y🜂 + 1;\
+                      tri🜄3🜌4🜂;ripple🜄2🜂;half🜄3🜌4🜂;";
+        let mut ast = Parser::default().parse_str(source).unwrap();
+        let mut diags = Diagnostics::new();
+        let mut pass = MergeFunctions::default();
+        pass.run(&mut ast, &mut diags).unwrap();
+        assert_eq!(
+            diags,
+            ["merge-functions turned 3 duplicate functions into calls"]
+        );
+        assert_eq!(pass.merged(), 3);
+        let bodies: Vec<String> = ast
+            .iter()
+            .filter_map(|node| match node {
+                ASTNode::Function(func) if func.prototype.name != "lambda" => {
+                    Some(format!("{} {}", func.prototype.name, func.body))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            bodies,
+            [
+                "area w * h / 2",
+                "tri area🜄base🜌height🜂",
+                "wave sin🜄t * 2🜂 + 1",
+                "half a * b / 3",
+                "swapped b * a / 2",
+                "ripple wave🜄x🜂",
+                "twice sin🜄t * 2🜂 + 1",
+                "loop loop🜄x🜂 + 1",
+                "again loop🜄y🜂",
+            ]
+        );
+
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        let ir = codegen.module.print_to_string().to_string();
+        assert!(ir.contains("call double @area(double %base, double %height)"));
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        let results: Vec<f64> = engine
+            .run_all(40)
+            .into_iter()
+            .map(|(_, value)| value.unwrap())
+            .collect();
+        assert_eq!(results, [6.0, (4.0f64).sin() + 1.0, 4.0]);
+    }
+}
@@ -0,0 +1,654 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+};
+
+use crate::ast::ASTNode;
+use crate::classify;
+use crate::configure::{self, ConfigureError};
+use crate::derive::{self, DeriveError};
+use crate::diagnostic::{Diagnostic, Suggestion};
+use crate::lexer::{self, Token};
+use crate::lint::{self, Lint, Warning};
+use crate::parser::{Parser, ParserError, SyntaxError};
+use crate::pass::{Diagnostics, Pass, PassError, Passes};
+use crate::resolve::{self, ResolveError};
+use crate::span::{FileId, LineIndex, SourceSpan, Span};
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum PlanError {
+    #[error(transparent)]
+    Parse(#[from] ParserError),
+    #[error(transparent)]
+    Derive(#[from] DeriveError),
+    #[error(transparent)]
+    Pass(#[from] PassError),
+    #[error(transparent)]
+    Configure(#[from] ConfigureError),
+    #[error(transparent)]
+    Resolve(#[from] ResolveError),
+    /// a resolve error in an item one of the sources holds, at the name it's
+    /// about, with the edit that fixes it when there's one
+    #[error("{span}: {error}")]
+    ResolveIn {
+        span: SourceSpan,
+        error: Box<ResolveError>,
+        suggestion: Option<Box<Suggestion>>,
+    },
+    /// the syntax error in the only source
+    #[error(transparent)]
+    Located(SyntaxError),
+    /// a syntax error in one of several sources, with the source's name
+    #[error("{0}:{1}")]
+    ParseIn(String, SyntaxError),
+    /// every syntax error parsing found in a source, when there was more
+    /// than one
+    #[error("{path}: {} syntax errors{}", .errors.len(), list_errors(.errors))]
+    Syntax {
+        path: String,
+        errors: Vec<SyntaxError>,
+    },
+}
+
+fn list_errors(errors: &[SyntaxError]) -> String {
+    errors.iter().map(|err| format!("\n  {}", err)).collect()
+}
+
+impl PlanError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let suggestion = match self {
+            PlanError::ResolveIn { suggestion, .. } => suggestion.as_deref().cloned(),
+            _ => None,
+        };
+        Diagnostic::error("plan", self).with_suggestion(suggestion)
+    }
+}
+
+/// parse with recovery, so all of a source's syntax errors are reported at
+/// once - a lone error is reported the way it always was
+fn parse(
+    parser: &Parser,
+    name: &str,
+    source: &str,
+    named: bool,
+) -> Result<Vec<ASTNode>, PlanError> {
+    let span = tracing::debug_span!("parse", path = name, items = tracing::field::Empty);
+    let (ast, mut errors) = span.in_scope(|| parser.parse_recovering(source));
+    span.record("items", &ast.len());
+    match (errors.len(), named) {
+        (0, _) => Ok(ast),
+        (1, false) => Err(PlanError::Located(errors.remove(0))),
+        (1, true) => Err(PlanError::ParseIn(name.to_string(), errors.remove(0))),
+        _ => Err(PlanError::Syntax {
+            path: name.to_string(),
+            errors,
+        }),
+    }
+}
+
+/// everything that changes what a plan compiles besides the source itself
+#[derive(Default)]
+pub struct CompileOptions {
+    /// functions to register derivatives of
+    pub derivatives: Vec<String>,
+    pub lints: Vec<Lint>,
+    /// flags that are set for when items
+    pub defines: HashSet<String>,
+    pub passes: Passes,
+    /// accept reserved words as names, warning about them instead
+    pub allow_reserved: bool,
+    /// accept `llvm "..."` items
+    pub allow_inline_ir: bool,
+    /// let functions without parameters be used without parens
+    pub implicit_calls: bool,
+    /// the functions an embedder registers with the engine, with their
+    /// arity, checked against the externs that link against them
+    pub host_functions: Vec<(String, usize)>,
+}
+
+impl CompileOptions {
+    pub fn define(&mut self, flag: &str) -> &mut CompileOptions {
+        self.defines.insert(flag.to_string());
+        self
+    }
+
+    pub fn add_pass(&mut self, pass: Box<dyn Pass>) -> Result<&mut CompileOptions, PassError> {
+        self.passes.add_pass(pass)?;
+        Ok(self)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PlannedFile {
+    pub path: String,
+    /// fnv-1a hash of the file contents
+    pub hash: u64,
+    pub source: String,
+}
+
+/// everything a compile will do, worked out before any llvm state exists -
+/// the driver codegens exactly this ast
+#[derive(Debug, PartialEq, Clone)]
+pub struct Plan {
+    pub files: Vec<PlannedFile>,
+    pub ast: Vec<ASTNode>,
+    pub warnings: Vec<Warning>,
+    /// diagnostics from the passes that were run
+    pub notes: Diagnostics,
+    /// how many definitions the passes turned into calls to another
+    pub merged: usize,
+    /// host symbols externs link against without a known signature to
+    /// check them against
+    pub unchecked_externs: Vec<String>,
+}
+
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl PlannedFile {
+    fn new(path: &str, source: &str) -> PlannedFile {
+        PlannedFile {
+            path: path.to_string(),
+            hash: fnv1a(source.as_bytes()),
+            source: source.to_string(),
+        }
+    }
+}
+
+pub(crate) fn parser(options: &CompileOptions) -> Parser {
+    Parser {
+        allow_reserved: options.allow_reserved,
+        allow_inline_ir: options.allow_inline_ir,
+        ..Parser::default()
+    }
+}
+
+/// what an item is, to find the chunk of source it was parsed from again
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+enum ItemKey {
+    Function(String),
+    Extern(String),
+    Table(String),
+    Test(String),
+    InlineIr,
+    Expression,
+}
+
+fn node_key(node: &ASTNode) -> ItemKey {
+    match node {
+        ASTNode::Function(func) if func.prototype.name == "lambda" => ItemKey::Expression,
+        ASTNode::Function(func) => ItemKey::Function(func.prototype.name.clone()),
+        ASTNode::Extern(proto) => ItemKey::Extern(proto.name.clone()),
+        ASTNode::Table(table) => ItemKey::Table(table.name.clone()),
+        ASTNode::Test(test) => ItemKey::Test(test.name.clone()),
+        ASTNode::InlineIr(_) => ItemKey::InlineIr,
+        ASTNode::When(_, item) => node_key(item),
+    }
+}
+
+/// the key of the item in a chunk of tokens, looking past a when condition
+fn chunk_key(tokens: &[&Token]) -> ItemKey {
+    let starts_item = |rest: &[&Token]| {
+        matches!(
+            rest,
+            [Token::Def, ..]
+                | [Token::Extern, ..]
+                | [
+                    Token::Ident("table"),
+                    Token::Ident(_),
+                    Token::Ident("from"),
+                    ..
+                ]
+        )
+    };
+    let start = match tokens {
+        [Token::Ident("test"), Token::Str(name), ..] => {
+            return ItemKey::Test(lexer::unescape(name))
+        }
+        [Token::Ident("llvm"), Token::Str(_), ..] => return ItemKey::InlineIr,
+        [Token::Ident("when"), ..] => (0..tokens.len())
+            .find(|i| starts_item(&tokens[*i..]))
+            .unwrap_or(0),
+        _ => 0,
+    };
+    // the name comes after a modifier, unless the modifier is the name
+    let name = |rest: &[&Token]| match rest {
+        [Token::Ident("void" | "fast" | "strict"), Token::Ident(name), ..]
+        | [Token::Ident(name), ..] => lexer::normalize(name),
+        _ => String::new(),
+    };
+    match &tokens[start..] {
+        [Token::Def, rest @ ..] => ItemKey::Function(name(rest)),
+        [Token::Extern, rest @ ..] => ItemKey::Extern(name(rest)),
+        [Token::Ident("table"), Token::Ident(name), Token::Ident("from"), ..] => {
+            ItemKey::Table(lexer::normalize(name))
+        }
+        _ => ItemKey::Expression,
+    }
+}
+
+fn item_spans(files: &[PlannedFile], ast: &[ASTNode]) -> Vec<Option<SourceSpan>> {
+    let mut chunks: HashMap<ItemKey, VecDeque<SourceSpan>> = HashMap::new();
+    for (i, file) in files.iter().enumerate() {
+        let tokens = lexer::lex_spanned(&file.source).unwrap_or_default();
+        let index = LineIndex::new(&file.source);
+        for chunk in classify::chunks(&tokens) {
+            let chunk: Vec<_> = chunk
+                .iter()
+                .filter(|(tok, _)| *tok != Token::Delimiter)
+                .collect();
+            let (first, last) = match (chunk.first(), chunk.last()) {
+                (Some(first), Some(last)) => (first, last),
+                _ => continue,
+            };
+            let span = Span::new(FileId(i as u32), first.1.start..last.1.end);
+            let start = match index.location(span.start) {
+                Some(start) => start,
+                None => continue,
+            };
+            let tokens: Vec<&Token> = chunk.iter().map(|(tok, _)| tok).collect();
+            chunks
+                .entry(chunk_key(&tokens))
+                .or_default()
+                .push_back(SourceSpan {
+                    path: file.path.clone(),
+                    span,
+                    start,
+                });
+        }
+    }
+    ast.iter()
+        .map(|node| chunks.get_mut(&node_key(node))?.pop_front())
+        .collect()
+}
+
+/// put a resolve error at the name it's about in its item - the variable
+/// that should be called, with the call as the fix, or the parameter that
+/// was called where it's bound - or at the item when there's no one name
+fn locate_resolve(
+    files: &[PlannedFile],
+    item: Option<SourceSpan>,
+    error: ResolveError,
+) -> PlanError {
+    let item = match item {
+        Some(item) => item,
+        None => return PlanError::Resolve(error),
+    };
+    let file = &files[item.span.file.0 as usize];
+    let tokens = lexer::lex_spanned(&file.source).unwrap_or_default();
+    let tokens: Vec<_> = tokens
+        .iter()
+        .filter(|(_, range)| item.span.range().contains(&range.start))
+        .collect();
+    // the first use of the name that isn't a call, which for a parameter is
+    // where the prototype binds it
+    let uncalled = |name: &str| {
+        let found = (0..tokens.len()).find(|i| {
+            matches!(tokens[*i].0, Token::Ident(word) if lexer::normalize(word) == name)
+                && tokens.get(i + 1).map(|(tok, _)| tok) != Some(&Token::OpenParen)
+        })?;
+        let range = tokens[found].1.clone();
+        let start = LineIndex::new(&file.source).location(range.start as u32)?;
+        Some(SourceSpan {
+            path: file.path.clone(),
+            span: Span::new(item.span.file, range),
+            start,
+        })
+    };
+    let (span, suggestion) = match &error {
+        ResolveError::MissingCall(name) => match uncalled(name) {
+            Some(span) => {
+                let replacement = format!("{}🜄🜂", &file.source[span.span.range()]);
+                let suggestion = Suggestion {
+                    span: span.clone(),
+                    replacement,
+                };
+                (span, Some(Box::new(suggestion)))
+            }
+            None => (item, None),
+        },
+        ResolveError::CalledParameter(name, _) => (uncalled(name).unwrap_or(item), None),
+        _ => (item, None),
+    };
+    PlanError::ResolveIn {
+        span,
+        error: Box::new(error),
+        suggestion,
+    }
+}
+
+impl Plan {
+    /// parse, configure, resolve named arguments, derive, run the passes and
+    /// then lint, in that order
+    pub fn new(path: &str, source: &str, options: &mut CompileOptions) -> Result<Plan, PlanError> {
+        let _span = tracing::info_span!("plan", files = 1).entered();
+        let ast = parse(&parser(options), path, source, false)?;
+        Plan::finish(vec![PlannedFile::new(path, source)], ast, options)
+    }
+
+    /// like new, but for several named sources compiled in order as one
+    /// program, so later ones can use what earlier ones define
+    pub fn from_sources(
+        sources: &[(&str, &str)],
+        options: &mut CompileOptions,
+    ) -> Result<Plan, PlanError> {
+        let _span = tracing::info_span!("plan", files = sources.len()).entered();
+        let mut files = Vec::with_capacity(sources.len());
+        let mut ast = Vec::new();
+        for (name, source) in sources {
+            ast.extend(parse(&parser(options), name, source, true)?);
+            files.push(PlannedFile::new(name, source));
+        }
+        Plan::finish(files, ast, options)
+    }
+
+    fn finish(
+        files: Vec<PlannedFile>,
+        ast: Vec<ASTNode>,
+        options: &mut CompileOptions,
+    ) -> Result<Plan, PlanError> {
+        let mut ast = tracing::debug_span!("configure")
+            .in_scope(|| configure::configure(ast, &options.defines))?;
+        let resolved = tracing::debug_span!("resolve").in_scope(|| {
+            if options.implicit_calls {
+                resolve::implicit_calls(&mut ast);
+            }
+            resolve::resolve_items(&mut ast)
+        });
+        if let Err((index, error)) = resolved {
+            let item = item_spans(&files, &ast).swap_remove(index);
+            return Err(locate_resolve(&files, item, error));
+        }
+        let unchecked_externs = tracing::debug_span!("check_externs")
+            .in_scope(|| resolve::check_externs(&ast, &options.host_functions))?;
+        tracing::debug_span!("derive").in_scope(|| {
+            options
+                .derivatives
+                .iter()
+                .try_for_each(|name| derive::register_derivative(&mut ast, name))
+        })?;
+        let mut notes = Diagnostics::new();
+        options.passes.run(&mut ast, &mut notes)?;
+        let mut lints = options.lints.clone();
+        if options.allow_reserved && !lints.contains(&Lint::ReservedWord) {
+            lints.push(Lint::ReservedWord);
+        }
+        let span = tracing::debug_span!("lint", warnings = tracing::field::Empty);
+        let warnings = span.in_scope(|| lint::check(&ast, &lints));
+        span.record("warnings", &warnings.len());
+
+        Ok(Plan {
+            files,
+            ast,
+            warnings,
+            notes,
+            merged: options.passes.merged(),
+            unchecked_externs,
+        })
+    }
+
+    /// the notes of the passes and about unchecked externs followed by the
+    /// lint warnings
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let notes = self.notes.iter().map(|note| Diagnostic::note("pass", note));
+        let externs = self.unchecked_externs.iter().map(|symbol| {
+            Diagnostic::note(
+                "extern",
+                format!(
+                    "the signature of {} isn't known, so its extern has to match it",
+                    symbol
+                ),
+            )
+        });
+        notes
+            .chain(externs)
+            .chain(self.warnings.iter().map(Diagnostic::from))
+            .collect()
+    }
+
+    /// names of the functions that will be defined, in order
+    pub fn definitions(&self) -> Vec<&str> {
+        self.ast
+            .iter()
+            .filter_map(|node| match node {
+                ASTNode::Function(func) if func.prototype.name != "lambda" => {
+                    Some(func.prototype.name.as_str())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// the number of top level expressions that will be compiled as entries
+    pub fn entries(&self) -> usize {
+        self.ast
+            .iter()
+            .filter(|node| match node {
+                ASTNode::Function(func) => func.prototype.name == "lambda",
+                _ => false,
+            })
+            .count()
+    }
+
+    /// where each item of the ast was written, in the same order - an item is
+    /// matched to the next chunk of source holding an item of its kind and
+    /// name, and items no source holds, like derivatives, have None
+    pub fn item_spans(&self) -> Vec<Option<SourceSpan>> {
+        item_spans(&self.files, &self.ast)
+    }
+
+    /// the host symbols that have to be resolved when jitting
+    pub fn externs(&self) -> Vec<&str> {
+        self.ast
+            .iter()
+            .filter_map(|node| match node {
+                ASTNode::Extern(proto) => Some(proto.symbol()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        let strings = |names: Vec<&str>| -> String {
+            let quoted: Vec<String> = names.iter().map(|name| format!("{:?}", name)).collect();
+            quoted.join(",")
+        };
+        let files: Vec<String> = self
+            .files
+            .iter()
+            .map(|file| {
+                format!(
+                    "{{\"path\":{:?},\"hash\":\"{:016x}\"}}",
+                    file.path, file.hash
+                )
+            })
+            .collect();
+        let warnings: Vec<String> = self
+            .warnings
+            .iter()
+            .map(|w| format!("{:?}", w.to_string()))
+            .collect();
+        format!(
+            "{{\"files\":[{}],\"definitions\":[{}],\"entries\":{},\"externs\":[{}],\"warnings\":[{}]}}",
+            files.join(","),
+            strings(self.definitions()),
+            self.entries(),
+            strings(self.externs()),
+            warnings.join(",")
+        )
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "files:")?;
+        for file in &self.files {
+            writeln!(f, "  {} {:016x}", file.path, file.hash)?;
+        }
+        writeln!(f, "definitions: {}", self.definitions().join(", "))?;
+        writeln!(f, "entries: {}", self.entries())?;
+        write!(f, "externs: {}", self.externs().join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn item_spans_work() {
+        let first = "🜹sin🜄x🜂;\n🜙fast f🜄x🜂sin🜄x🜂;;\ntable + 1;\n  when debug 🜙g🜄🜂2;";
+        let second = "test \"f\" f🜄0🜂 expect 0;\n🜙f🜄x🜂x;\nf🜄1🜂;";
+        let mut options = CompileOptions::default();
+        options.define("debug");
+        let mut plan =
+            Plan::from_sources(&[("a.wiz", first), ("b.wiz", second)], &mut options).unwrap();
+        // nothing was written for an item added after parsing
+        plan.ast.push(ASTNode::InlineIr(String::new()));
+        let spans: Vec<Option<String>> = plan
+            .item_spans()
+            .iter()
+            .map(|span| span.as_ref().map(ToString::to_string))
+            .collect();
+        let expected = [
+            Some("a.wiz:1:1"),
+            Some("a.wiz:2:1"),
+            Some("a.wiz:3:1"),
+            Some("a.wiz:4:3"),
+            Some("b.wiz:1:1"),
+            Some("b.wiz:2:1"),
+            Some("b.wiz:3:1"),
+            None,
+        ];
+        assert_eq!(spans, expected.map(|span| span.map(str::to_string)));
+        let span = plan.item_spans()[1].clone().unwrap().span;
+        assert_eq!(&first[span.range()], "🜙fast f🜄x🜂sin🜄x🜂");
+    }
+
+    #[test]
+    fn plan_works() {
+        let source = r#"🜹sin🜄x🜂;🜹draw🜄x🜂 = "eng_draw";🜙f🜄x🜂sin🜄x🜂*x;f🜄2🜂;"#;
+        let mut options = CompileOptions {
+            derivatives: vec!["f".to_string()],
+            lints: vec![Lint::DomainCheck],
+            ..CompileOptions::default()
+        };
+        let plan = Plan::new("a.wiz", source, &mut options).unwrap();
+        assert_eq!(plan.definitions(), ["f", "f__dx"]);
+        assert_eq!(plan.entries(), 1);
+        assert_eq!(plan.externs(), ["sin", "eng_draw", "cos"]);
+        assert_eq!(plan.unchecked_externs, ["eng_draw"]);
+        assert_eq!(
+            plan.diagnostics()[0].to_string(),
+            "note[extern]: the signature of eng_draw isn't known, so its extern has to match it"
+        );
+        assert_eq!(
+            plan.ast.len(),
+            Parser::default().parse_str(source).unwrap().len() + 2
+        );
+        assert_eq!(plan.files[0].hash, fnv1a(source.as_bytes()));
+        assert_ne!(plan.files[0].hash, fnv1a(b"f;"));
+        assert!(plan
+            .to_json()
+            .contains("\"definitions\":[\"f\",\"f__dx\"],\"entries\":1"));
+        assert_eq!(
+            Plan::new("b.wiz", "🜙f🜄🜂1;", &mut options),
+            Err(PlanError::Derive(DeriveError::NotUnary("f".to_string(), 0)))
+        );
+    }
+
+    #[test]
+    fn from_sources_works() {
+        let mut options = CompileOptions::default();
+        let plan = Plan::from_sources(&[("<eval-1>", "1 + 2;")], &mut options).unwrap();
+        assert_eq!(plan.entries(), 1);
+
+        let sources = [
+            ("a.wiz", "🜙sq🜄x🜂x*x;"),
+            ("<eval-1>", "sq🜄7🜂;"),
+            ("<eval-2>", "🜙cube🜄x🜂x*sq🜄x🜂;cube🜄2🜂;"),
+        ];
+        let plan = Plan::from_sources(&sources, &mut options).unwrap();
+        assert_eq!(plan.definitions(), ["sq", "cube"]);
+        assert_eq!(plan.entries(), 2);
+        let paths: Vec<&str> = plan.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, ["a.wiz", "<eval-1>", "<eval-2>"]);
+
+        let sources = [
+            ("<eval-1>", "1;"),
+            ("<eval-2>", "🜙f🜄x🜂+;"),
+            ("<eval-3>", "2;"),
+        ];
+        let err = Plan::from_sources(&sources, &mut options).unwrap_err();
+        assert!(matches!(&err, PlanError::ParseIn(name, _) if name == "<eval-2>"));
+        assert!(err.to_string().starts_with("<eval-2>:1:6: "));
+
+        let err = Plan::new("a.wiz", "🜙f🜄x🜂x + * 2;🜙g x;", &mut options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a.wiz: 2 syntax errors\n  1:10: expected an expression, found '*'\n  1:17: expected '🜄', found 'x'"
+        );
+    }
+
+    #[test]
+    fn allow_reserved_works() {
+        let sources = [("old.wiz", "🜙var🜄x🜂x*2;var🜄3🜂;")];
+        let mut options = CompileOptions::default();
+        let err = Plan::from_sources(&sources, &mut options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "old.wiz:1:2: var is reserved for future syntax and can't name a function"
+        );
+
+        options.allow_reserved = true;
+        let plan = Plan::from_sources(&sources, &mut options).unwrap();
+        assert_eq!(plan.definitions(), ["var"]);
+        assert_eq!(plan.warnings.len(), 1);
+        assert_eq!(plan.warnings[0].lint, Lint::ReservedWord);
+    }
+
+    #[test]
+    fn resolve_errors_are_located() {
+        let mut options = CompileOptions::default();
+        let source = "🜙answer🜄🜂42;\n🜙half🜄x🜂x / 2;\nhalf🜄answer🜂 + pi;";
+        let err = Plan::new("a.wiz", source, &mut options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a.wiz:3:6: unknown variable answer, did you mean to call answer🜄🜂?"
+        );
+        let start = source.rfind("answer").unwrap();
+        assert_eq!(
+            err.diagnostic().to_json(),
+            format!(
+                "{{\"severity\":\"error\",\"code\":\"plan\",\"message\":{:?},\"suggestion\":\
+                 {{\"location\":\"a.wiz:3:6\",\"start\":{},\"end\":{},\"replacement\":\"answer🜄🜂\"}}}}",
+                err.to_string(),
+                start,
+                start + "answer".len()
+            )
+        );
+
+        // a called parameter is pointed out where it's bound
+        let err = Plan::new("b.wiz", "1;\n🜙f🜄a🜌x🜂a + x🜄🜂;", &mut options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "b.wiz:2:6: x is a parameter of f here, not a function"
+        );
+        assert_eq!(err.diagnostic().suggestion, None);
+
+        // an error in the middle one of several files is located in it
+        let sources = [
+            ("main.wiz", "🜙sq🜄x🜂x*x;\nquad🜄2🜂;"),
+            ("lib.wiz", "🜙cube🜄x🜂x*sq🜄x🜂;\n\n🜙quad🜄x🜂sq🜄sq🜄x🜂🜂 + pi;"),
+            ("<eval-1>", "cube🜄3🜂;"),
+        ];
+        let err = Plan::from_sources(&sources, &mut options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "lib.wiz:3:21: unknown variable pi, did you mean to call pi🜄🜂?"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt;
+
+use inkwell::{module::Module, values::FunctionValue};
+
+use crate::console;
+
+/// the size of a single compiled function
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionSize {
+    pub name: String,
+    pub instructions: usize,
+}
+
+/// per function llvm ir instruction counts, largest first
+#[derive(Debug, PartialEq, Clone)]
+pub struct SizeReport {
+    pub functions: Vec<FunctionSize>,
+    pub total_instructions: usize,
+    /// definitions merge-functions turned into calls to another
+    pub forwarders: usize,
+}
+
+fn count_instructions(func: FunctionValue) -> usize {
+    let mut count = 0;
+    for block in func.get_basic_blocks() {
+        let mut instr = block.get_first_instruction();
+        while let Some(cur) = instr {
+            count += 1;
+            instr = cur.get_next_instruction();
+        }
+    }
+    count
+}
+
+impl SizeReport {
+    /// measure every function with a body, externs are skipped - forwarders
+    /// is how many of them merge-functions turned into calls
+    pub fn new(module: &Module, forwarders: usize) -> SizeReport {
+        let mut functions = Vec::new();
+        let mut func = module.get_first_function();
+        while let Some(cur) = func {
+            if cur.get_first_basic_block().is_some() {
+                functions.push(FunctionSize {
+                    name: cur.get_name().to_string_lossy().to_string(),
+                    instructions: count_instructions(cur),
+                });
+            }
+            func = cur.get_next_function();
+        }
+        functions.sort_by(|a, b| {
+            b.instructions
+                .cmp(&a.instructions)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        let total_instructions = functions.iter().map(|f| f.instructions).sum();
+
+        SizeReport {
+            functions,
+            total_instructions,
+            forwarders,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let functions: Vec<String> = self
+            .functions
+            .iter()
+            .map(|f| {
+                format!(
+                    "{{\"name\":{:?},\"instructions\":{}}}",
+                    f.name, f.instructions
+                )
+            })
+            .collect();
+        format!(
+            "{{\"functions\":[{}],\"total_instructions\":{},\"forwarders\":{}}}",
+            functions.join(","),
+            self.total_instructions,
+            self.forwarders
+        )
+    }
+}
+
+impl SizeReport {
+    /// the report as a table at most width columns wide, names too long for
+    /// it being cut short - the count of forwarders is the last row when
+    /// there are any
+    pub fn render(&self, width: usize) -> String {
+        let forwarders = Some(("forwarders", self.forwarders)).filter(|(_, count)| *count > 0);
+        let rows: Vec<(&str, usize)> = self
+            .functions
+            .iter()
+            .map(|func| (func.name.as_str(), func.instructions))
+            .chain(std::iter::once(("total", self.total_instructions)))
+            .chain(forwarders)
+            .collect();
+        let count_width = self.total_instructions.to_string().len();
+        let name_width = rows
+            .iter()
+            .map(|(name, _)| console::display_width(name))
+            .max()
+            .unwrap_or(0)
+            .min(width.saturating_sub(count_width + 1).max(1));
+        let lines: Vec<String> = rows
+            .iter()
+            .map(|(name, count)| {
+                let name = console::fit(name, name_width);
+                let padding = " ".repeat(name_width - console::display_width(&name));
+                format!("{}{} {}", name, padding, count)
+            })
+            .collect();
+        lines.join("\n")
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render(usize::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use inkwell::context::Context;
+
+    use super::{FunctionSize, SizeReport};
+    use crate::{codegen::Codegen, parser::Parser, pass};
+
+    #[test]
+    fn size_report_works() {
+        let source = "🜹sin🜄x🜂;🜙small🜄x🜂x;\
+                      🜙big🜄x🜂sin🜄x🜂*x+x/2-sin🜄x*x🜂*3+x*x*x;small🜄1🜂;\
+                      🜙wave🜄x🜂sin🜄x🜂;🜙ripple🜄t🜂sin🜄t🜂;";
+        let mut ast = Parser::default().parse_str(source).unwrap();
+        let merged = pass::merge_functions(&mut ast);
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        let report = SizeReport::new(&codegen.module, merged);
+        let names: Vec<_> = report.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["big", "lambda", "ripple", "wave", "small"]);
+        // only ripple was merged, though wave and the entry are one call too
+        assert_eq!(report.forwarders, 1);
+        assert!(report.render(80).ends_with("\nforwarders 1"));
+        let report = SizeReport::new(&codegen.module, 0);
+        assert!(report
+            .render(80)
+            .ends_with(&format!("\ntotal {}", report.total_instructions)));
+        assert_eq!(
+            report.total_instructions,
+            report
+                .functions
+                .iter()
+                .map(|f| f.instructions)
+                .sum::<usize>()
+        );
+        assert!(report
+            .to_json()
+            .starts_with("{\"functions\":[{\"name\":\"big\",\"instructions\":"));
+    }
+
+    #[test]
+    fn render_fits_width() {
+        let name = "interpolate_the_coefficients_of_the_fitted_polynomial_at_every_sample_point";
+        let report = SizeReport {
+            functions: vec![
+                FunctionSize {
+                    name: name.to_string(),
+                    instructions: 1200,
+                },
+                FunctionSize {
+                    name: "lambda".to_string(),
+                    instructions: 34,
+                },
+            ],
+            total_instructions: 1234,
+            forwarders: 0,
+        };
+        let rendered: Vec<String> = [60, 80, 120].iter().map(|w| report.render(*w)).collect();
+        assert_eq!(
+            rendered[0],
+            "interpolate_the_coefficients_of_the_fitted_polynomial_… 1200\n\
+             lambda                                                  34\n\
+             total                                                   1234"
+        );
+        // the longest name only just fits in 80 columns
+        assert_eq!(
+            rendered[1],
+            format!("{} 1200\nlambda{:70}34\ntotal{:71}1234", name, "", "")
+        );
+        assert_eq!(rendered[2], rendered[1]);
+        assert_eq!(report.to_string(), rendered[2]);
+    }
+}
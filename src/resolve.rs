@@ -0,0 +1,699 @@
+use std::{collections::HashMap, mem};
+
+use crate::ast::{ASTNode, Expression, MathMode, Prototype};
+use crate::builtins;
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum ResolveError {
+    #[error("unknown function {0}")]
+    UnknownFunction(String),
+    #[error("{0} has no parameter named {1}")]
+    UnknownArgument(String, String),
+    #[error("{1} is given more than once in a call to {0}")]
+    DuplicateArgument(String, String),
+    #[error("positional argument after a named one in a call to {0}")]
+    PositionalAfterNamed(String),
+    #[error("missing argument {1} in a call to {0}")]
+    MissingArgument(String, String),
+    #[error("invalid number of args in call {0} expected {1} found {2}")]
+    InvalidCall(String, usize, usize),
+    #[error("the default of {1} in {0} has to be a constant")]
+    NonConstantDefault(String, String),
+    #[error("the default of {1} in {0} uses the parameter {2}")]
+    DefaultUsesParameter(String, String, String),
+    #[error("extern {0} can't have default arguments")]
+    ExternDefault(String),
+    #[error("{0} returns nothing, so its result can't be used")]
+    VoidValue(String),
+    #[error("invalid number of args in call to the builtin {0}, found {1}")]
+    BuiltinCall(String, usize),
+    #[error("extern {0} is declared with {1} args but the host's {2} takes {3}")]
+    ExternArity(String, usize, String, usize),
+    #[error("{0} still has a syntax error in it")]
+    SyntaxError(String),
+    #[error("unknown variable {0}, did you mean to call {0}🜄🜂?")]
+    MissingCall(String),
+    #[error("{0} is a parameter of {1} here, not a function")]
+    CalledParameter(String, String),
+}
+
+/// the prototype of everything that can be called, keeping the first of any
+/// duplicates like codegen does
+fn prototypes(ast: &[ASTNode]) -> HashMap<String, Prototype> {
+    let mut res = HashMap::new();
+    for node in ast {
+        add_prototypes(&mut res, node);
+    }
+    res
+}
+
+/// add the prototypes of what node defines, unless something already has
+/// their name
+fn add_prototypes(protos: &mut HashMap<String, Prototype>, node: &ASTNode) {
+    let proto = match node {
+        ASTNode::Function(func) => func.prototype.clone(),
+        ASTNode::Extern(proto) => proto.clone(),
+        ASTNode::Table(table) => {
+            protos
+                .entry(format!("{}_len", table.name))
+                .or_insert_with(|| Prototype {
+                    name: format!("{}_len", table.name),
+                    args: vec![],
+                    link_name: None,
+                    defaults: vec![],
+                    void: false,
+                    math: MathMode::default(),
+                });
+            Prototype {
+                name: table.name.clone(),
+                args: vec!["i".to_string()],
+                link_name: None,
+                defaults: vec![None],
+                void: false,
+                math: MathMode::default(),
+            }
+        }
+        ASTNode::When(_, item) => return add_prototypes(protos, item),
+        // calls to its functions are left as they are for codegen
+        ASTNode::InlineIr(_) | ASTNode::Test(_) => return,
+    };
+    protos.entry(proto.name.clone()).or_insert(proto);
+}
+
+/// defaults can only be built from literals, builtin constants and arithmetic
+fn check_default(proto: &Prototype, arg: &str, default: &Expression) -> Result<(), ResolveError> {
+    match default {
+        Expression::Literal(_) => Ok(()),
+        Expression::Variable(name) if proto.args.contains(name) => Err(
+            ResolveError::DefaultUsesParameter(proto.name.clone(), arg.to_string(), name.clone()),
+        ),
+        Expression::Binary(_, lhs, rhs) => {
+            check_default(proto, arg, lhs)?;
+            check_default(proto, arg, rhs)
+        }
+        Expression::Call(callee, args)
+            if args.is_empty() && builtins::constant(callee).is_some() =>
+        {
+            Ok(())
+        }
+        _ => Err(ResolveError::NonConstantDefault(
+            proto.name.clone(),
+            arg.to_string(),
+        )),
+    }
+}
+
+/// put the arguments of a call in the order of the callee's parameters,
+/// filling in defaults for any that are left out
+fn order(
+    callee: &str,
+    args: Vec<(Option<String>, Expression)>,
+    proto: &Prototype,
+) -> Result<Vec<Expression>, ResolveError> {
+    let params = &proto.args;
+    let count = args.len();
+    let mut slots: Vec<Option<Expression>> = vec![None; params.len()];
+    let mut named = false;
+    for (i, (name, arg)) in args.into_iter().enumerate() {
+        let index = match name {
+            Some(name) => {
+                named = true;
+                match params.iter().position(|param| *param == name) {
+                    Some(index) => index,
+                    None => return Err(ResolveError::UnknownArgument(callee.to_string(), name)),
+                }
+            }
+            None if named => return Err(ResolveError::PositionalAfterNamed(callee.to_string())),
+            None if i >= params.len() => {
+                return Err(ResolveError::InvalidCall(
+                    callee.to_string(),
+                    params.len(),
+                    count,
+                ))
+            }
+            None => i,
+        };
+        if slots[index].is_some() {
+            return Err(ResolveError::DuplicateArgument(
+                callee.to_string(),
+                params[index].clone(),
+            ));
+        }
+        slots[index] = Some(arg);
+    }
+
+    slots
+        .into_iter()
+        .enumerate()
+        .map(|(i, slot)| match (slot, proto.defaults.get(i)) {
+            (Some(arg), _) => Ok(arg),
+            (None, Some(Some(default))) => Ok(default.clone()),
+            (None, _) => Err(ResolveError::MissingArgument(
+                callee.to_string(),
+                params[i].clone(),
+            )),
+        })
+        .collect()
+}
+
+/// whether name can be called without arguments, so a variable with its
+/// name was probably meant to be a call
+fn is_nullary(name: &str, protos: &HashMap<String, Prototype>) -> bool {
+    match protos.get(name) {
+        Some(proto) => proto.args.is_empty() && !proto.void,
+        None => builtins::constant(name).is_some(),
+    }
+}
+
+/// a call to one of the parameters of the function it's in, which isn't a
+/// function itself
+fn check_callee(
+    callee: &str,
+    protos: &HashMap<String, Prototype>,
+    scope: Option<&Prototype>,
+) -> Result<(), ResolveError> {
+    match scope {
+        Some(proto)
+            if proto.args.iter().any(|arg| arg == callee)
+                && !protos.contains_key(callee)
+                && !builtins::is_builtin(callee) =>
+        {
+            Err(ResolveError::CalledParameter(
+                callee.to_string(),
+                proto.name.clone(),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// scope is the function the expression is the body of, if any
+fn resolve_expr(
+    expr: &mut Expression,
+    protos: &HashMap<String, Prototype>,
+    scope: Option<&Prototype>,
+) -> Result<(), ResolveError> {
+    match expr {
+        Expression::Variable(name)
+            if !scope.is_some_and(|proto| proto.args.contains(name))
+                && is_nullary(name, protos) =>
+        {
+            Err(ResolveError::MissingCall(name.clone()))
+        }
+        Expression::Literal(_) | Expression::Variable(_) | Expression::Error(_) => Ok(()),
+        Expression::Binary(_, lhs, rhs) => {
+            resolve_expr(lhs, protos, scope)?;
+            resolve_expr(rhs, protos, scope)
+        }
+        Expression::Call(callee, args) => {
+            for arg in args.iter_mut() {
+                resolve_expr(arg, protos, scope)?;
+            }
+            check_callee(callee, protos, scope)?;
+            // calls with too many args are left for codegen to report
+            match (
+                protos.get(callee.as_str()),
+                builtins::intrinsic_params(callee),
+            ) {
+                (Some(proto), _) if args.len() < proto.args.len() => {
+                    let given = mem::take(args).into_iter().map(|arg| (None, arg)).collect();
+                    *args = order(callee, given, proto)?;
+                }
+                (None, Some(params)) if args.len() != params.len() => {
+                    let signature = format!("{}🜄{}🜂", callee, params.join("🜌"));
+                    return Err(ResolveError::BuiltinCall(signature, args.len()));
+                }
+                _ => (),
+            }
+            Ok(())
+        }
+        Expression::NamedCall(callee, args) => {
+            for (_, arg) in args.iter_mut() {
+                resolve_expr(arg, protos, scope)?;
+            }
+            check_callee(callee, protos, scope)?;
+            let proto = match protos.get(callee.as_str()) {
+                Some(proto) => proto,
+                None => return Err(ResolveError::UnknownFunction(callee.clone())),
+            };
+            let args = order(callee, mem::take(args), proto)?;
+            *expr = Expression::Call(mem::take(callee), args);
+            Ok(())
+        }
+    }
+}
+
+/// calls to void externs can only be a whole top level expression, where
+/// nothing uses their result
+fn check_void(
+    expr: &Expression,
+    protos: &HashMap<String, Prototype>,
+    statement: bool,
+) -> Result<(), ResolveError> {
+    match expr {
+        Expression::Literal(_) | Expression::Variable(_) | Expression::Error(_) => Ok(()),
+        Expression::Binary(_, lhs, rhs) => {
+            check_void(lhs, protos, false)?;
+            check_void(rhs, protos, false)
+        }
+        Expression::Call(callee, args) => {
+            match protos.get(callee.as_str()) {
+                Some(proto) if proto.void && !statement => {
+                    return Err(ResolveError::VoidValue(callee.clone()))
+                }
+                _ => (),
+            }
+            args.iter()
+                .try_for_each(|arg| check_void(arg, protos, false))
+        }
+        Expression::NamedCall(_, args) => args
+            .iter()
+            .try_for_each(|(_, arg)| check_void(arg, protos, false)),
+    }
+}
+
+/// rewrite every named call into a positional one, using the parameter names
+/// of the function, extern or table it calls, and fill in the defaults of
+/// arguments calls leave out
+pub fn resolve(ast: &mut [ASTNode]) -> Result<(), ResolveError> {
+    resolve_items(ast).map_err(|(_, err)| err)
+}
+
+/// resolve, with the index of the top level item the error is in
+pub fn resolve_items(ast: &mut [ASTNode]) -> Result<(), (usize, ResolveError)> {
+    let protos = prototypes(ast);
+    for (i, node) in ast.iter_mut().enumerate() {
+        resolve_node(node, &protos).map_err(|err| (i, err))?;
+    }
+    Ok(())
+}
+
+fn resolve_node(
+    node: &mut ASTNode,
+    protos: &HashMap<String, Prototype>,
+) -> Result<(), ResolveError> {
+    match node {
+        ASTNode::Function(func) => {
+            let proto = &func.prototype;
+            // the parser has already reported the error itself
+            let defaults = proto.defaults.iter().flatten();
+            if func.body.has_error() || defaults.clone().any(Expression::has_error) {
+                return Err(ResolveError::SyntaxError(proto.name.clone()));
+            }
+            for (arg, default) in proto.args.iter().zip(&proto.defaults) {
+                if let Some(default) = default {
+                    check_default(proto, arg, default)?;
+                }
+            }
+            resolve_expr(&mut func.body, protos, Some(&func.prototype))?;
+            check_void(&func.body, protos, func.prototype.name == "lambda")
+        }
+        ASTNode::Extern(proto) if proto.defaults.iter().any(Option::is_some) => {
+            Err(ResolveError::ExternDefault(proto.name.clone()))
+        }
+        ASTNode::When(_, item) => resolve_node(item, protos),
+        ASTNode::Extern(_) | ASTNode::Table(_) | ASTNode::InlineIr(_) => Ok(()),
+        // the test runner resolves each test on its own, so a broken
+        // test only fails itself
+        ASTNode::Test(_) => Ok(()),
+    }
+}
+
+/// resolves a program one item at a time as it's parsed, keeping only the
+/// prototypes of the items so far - an item can call itself and what came
+/// before it, which is all codegen could compile a call to then anyway
+#[derive(Debug, Default)]
+pub struct Resolver {
+    protos: HashMap<String, Prototype>,
+    /// make implicit calls in each item before resolving it
+    pub implicit_calls: bool,
+}
+
+impl Resolver {
+    /// resolve the next item of the program
+    pub fn resolve(&mut self, node: &mut ASTNode) -> Result<(), ResolveError> {
+        add_prototypes(&mut self.protos, node);
+        if self.implicit_calls {
+            call_nullary_in(node, &self.protos);
+        }
+        resolve_node(node, &self.protos)
+    }
+}
+
+/// resolve an expression that isn't part of the ast, like a test's, against
+/// everything the ast defines
+pub fn resolve_expression(ast: &[ASTNode], expr: &mut Expression) -> Result<(), ResolveError> {
+    let protos = prototypes(ast);
+    resolve_expr(expr, &protos, None)?;
+    check_void(expr, &protos, false)
+}
+
+fn call_nullary(expr: &mut Expression, protos: &HashMap<String, Prototype>, params: &[String]) {
+    match expr {
+        Expression::Variable(name) if !params.contains(name) && is_nullary(name, protos) => {
+            tracing::debug!(name = name.as_str(), "calling a variable implicitly");
+            *expr = Expression::Call(mem::take(name), vec![]);
+        }
+        Expression::Literal(_) | Expression::Variable(_) | Expression::Error(_) => (),
+        Expression::Binary(_, lhs, rhs) => {
+            call_nullary(lhs, protos, params);
+            call_nullary(rhs, protos, params);
+        }
+        Expression::Call(_, args) => args
+            .iter_mut()
+            .for_each(|arg| call_nullary(arg, protos, params)),
+        Expression::NamedCall(_, args) => args
+            .iter_mut()
+            .for_each(|(_, arg)| call_nullary(arg, protos, params)),
+    }
+}
+
+/// turn every variable that isn't a parameter but names something that can
+/// be called without arguments into a call to it, so pi means pi🜄🜂 - run
+/// before resolve when implicit calls are allowed
+pub fn implicit_calls(ast: &mut [ASTNode]) {
+    let protos = prototypes(ast);
+    for node in ast {
+        call_nullary_in(node, &protos);
+    }
+}
+
+fn call_nullary_in(node: &mut ASTNode, protos: &HashMap<String, Prototype>) {
+    match node {
+        ASTNode::Function(func) => call_nullary(&mut func.body, protos, &func.prototype.args),
+        ASTNode::Test(test) => {
+            call_nullary(&mut test.expr, protos, &[]);
+            call_nullary(&mut test.expected, protos, &[]);
+        }
+        ASTNode::When(_, item) => call_nullary_in(item, protos),
+        ASTNode::Extern(_) | ASTNode::Table(_) | ASTNode::InlineIr(_) => (),
+    }
+}
+
+/// check each extern against the arity of the host symbol it links against,
+/// if that's known - functions the host registers itself come first, then
+/// the libm functions builtins knows - returning the symbols whose signature
+/// isn't known, once each
+pub fn check_externs(
+    ast: &[ASTNode],
+    host_functions: &[(String, usize)],
+) -> Result<Vec<String>, ResolveError> {
+    let mut unknown = Vec::new();
+    for node in ast {
+        let proto = match node {
+            ASTNode::Extern(proto) => proto,
+            _ => continue,
+        };
+        let symbol = proto.symbol();
+        let registered = host_functions
+            .iter()
+            .find(|(name, _)| name == symbol)
+            .map(|(_, arity)| *arity);
+        match registered.or_else(|| builtins::host_arity(symbol)) {
+            Some(arity) if arity != proto.args.len() => {
+                return Err(ResolveError::ExternArity(
+                    proto.name.clone(),
+                    proto.args.len(),
+                    symbol.to_string(),
+                    arity,
+                ))
+            }
+            Some(arity) => tracing::debug!(symbol, arity, "extern matches the host"),
+            None if unknown.iter().any(|name| name == symbol) => (),
+            None => {
+                tracing::debug!(symbol, "extern can't be checked against the host");
+                unknown.push(symbol.to_string())
+            }
+        }
+    }
+    Ok(unknown)
+}
+
+#[cfg(test)]
+mod tests {
+    use inkwell::context::Context;
+
+    use super::*;
+    use crate::{
+        codegen::Codegen,
+        engine::{Engine, EngineKind, OptLevel},
+        parser::Parser,
+    };
+
+    fn run(source: &str) -> Result<Vec<f64>, ResolveError> {
+        let mut ast = Parser::default().parse_str(source).unwrap();
+        resolve(&mut ast)?;
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        Ok(engine
+            .run_all(40)
+            .into_iter()
+            .map(|(_, value)| value.unwrap())
+            .collect())
+    }
+
+    #[test]
+    fn resolve_works() {
+        let source = "🜹pow🜄base🜌exponent🜂;\
+                      🜙sub🜄a🜌b🜂a - b;\
+                      🜙lerp🜄from🜌to🜌t🜂from + t * 🜄to - from🜂;\
+                      sub🜄b: 1🜌a: 10🜂;\
+                      sub🜄10🜌b: 1🜂;\
+                      pow🜄exponent: 3🜌base: 2🜂;\
+                      lerp🜄2🜌t: sub🜄b: 0.75🜌a: 1🜂🜌to: 6🜂;";
+        assert_eq!(run(source), Ok(vec![9.0, 9.0, 8.0, 3.0]));
+
+        let named = "simulate🜄dt: 0.1🜌steps: 300🜌9.81🜂";
+        let expr = Parser::default().parse_expression_str(named).unwrap();
+        match &expr {
+            Expression::NamedCall(callee, args) => {
+                assert_eq!(callee, "simulate");
+                let names: Vec<_> = args.iter().map(|(name, _)| name.as_deref()).collect();
+                assert_eq!(names, [Some("dt"), Some("steps"), None]);
+            }
+            expr => panic!("expected a named call, found {:?}", expr),
+        }
+        assert_eq!(expr.to_string(), named);
+        let plain = Parser::default().parse_expression_str("f🜄1🜌2🜂").unwrap();
+        assert!(matches!(plain, Expression::Call(..)));
+    }
+
+    #[test]
+    fn resolver_works() {
+        let source = "🜹pow🜄base🜌exponent🜂;\
+                      🜙fall🜄t🜌h = 100🜌g = 9.81 / 2🜂h - g * t * t;\
+                      fall🜄2🜌g: 1🜂;\
+                      🜙twice🜄x🜌n = 2🜂pow🜄exponent: n🜌base: x🜂;\
+                      twice🜄n: 3🜌x: 4🜂 + twice🜄5🜂;";
+        let mut ast = Parser::default().parse_str(source).unwrap();
+        let mut resolved = ast.clone();
+        resolve(&mut resolved).unwrap();
+        let mut resolver = Resolver::default();
+        for node in &mut ast {
+            resolver.resolve(node).unwrap();
+        }
+        assert_eq!(ast, resolved);
+
+        let source = "🜙answer🜄🜂42;🜙half🜄x🜂x / 2;half🜄answer🜂 + pi;";
+        let mut ast = Parser::default().parse_str(source).unwrap();
+        let mut resolved = ast.clone();
+        implicit_calls(&mut resolved);
+        resolve(&mut resolved).unwrap();
+        let mut resolver = Resolver {
+            implicit_calls: true,
+            ..Resolver::default()
+        };
+        for node in &mut ast {
+            resolver.resolve(node).unwrap();
+        }
+        assert_eq!(ast, resolved);
+
+        // an item only sees the ones before it
+        let mut ast = Parser::default()
+            .parse_str("sub🜄b: 1🜌a: 10🜂;🜙sub🜄a🜌b🜂a - b;")
+            .unwrap();
+        assert_eq!(
+            Resolver::default().resolve(&mut ast[0]),
+            Err(ResolveError::UnknownFunction("sub".to_string()))
+        );
+    }
+
+    #[test]
+    fn resolve_errors_work() {
+        let err = |call: &str| run(&format!("🜙sub🜄a🜌b🜂a - b;{};", call)).unwrap_err();
+        let sub = "sub".to_string();
+        assert_eq!(
+            err("sub🜄a: 1🜌c: 2🜂"),
+            ResolveError::UnknownArgument(sub.clone(), "c".to_string())
+        );
+        assert_eq!(
+            err("sub🜄1🜌a: 2🜂"),
+            ResolveError::DuplicateArgument(sub.clone(), "a".to_string())
+        );
+        assert_eq!(
+            err("sub🜄a: 1🜌b: 2🜌a: 3🜂"),
+            ResolveError::DuplicateArgument(sub.clone(), "a".to_string())
+        );
+        assert_eq!(
+            err("sub🜄a: 1🜌2🜂"),
+            ResolveError::PositionalAfterNamed(sub.clone())
+        );
+        assert_eq!(
+            err("sub🜄b: 1🜂"),
+            ResolveError::MissingArgument(sub.clone(), "a".to_string())
+        );
+        assert_eq!(err("sub🜄1🜌2🜌3🜌c: 4🜂"), ResolveError::InvalidCall(sub, 2, 4));
+        assert_eq!(
+            err("add🜄a: 1🜂"),
+            ResolveError::UnknownFunction("add".to_string())
+        );
+        assert_eq!(
+            err("clamp🜄1🜌2🜂").to_string(),
+            "invalid number of args in call to the builtin clamp🜄x🜌lo🜌hi🜂, found 2"
+        );
+        assert_eq!(run("🜙min🜄x🜂x;min🜄3🜂;"), Ok(vec![3.0]));
+    }
+
+    #[test]
+    fn call_hints_work() {
+        let source = "🜙answer🜄🜂42;🜙half🜄x🜂x / 2;";
+        let err = |expr: &str| {
+            run(&format!("{}{};", source, expr))
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            err("answer + 1"),
+            "unknown variable answer, did you mean to call answer🜄🜂?"
+        );
+        assert_eq!(
+            err("half🜄pi🜂"),
+            "unknown variable pi, did you mean to call pi🜄🜂?"
+        );
+        assert_eq!(
+            run("🜙f🜄x🜂x🜄🜂 + 1;f🜄1🜂;").unwrap_err(),
+            ResolveError::CalledParameter("x".to_string(), "f".to_string())
+        );
+        // a parameter named like a function is just the parameter, and a
+        // function a parameter's named after can still be called
+        assert_eq!(
+            run(&format!("{}🜙f🜄answer🜂answer🜄🜂 + answer;f🜄1🜂;", source)),
+            Ok(vec![43.0])
+        );
+    }
+
+    #[test]
+    fn implicit_calls_work() {
+        let source = "🜙answer🜄🜂42;🜙half🜄x🜂x / 2;🜙f🜄answer🜂answer * 2;\
+                      answer + 1;half🜄pi🜂;f🜄1🜂;half;";
+        let mut ast = Parser::default().parse_str(source).unwrap();
+        implicit_calls(&mut ast);
+        let printed: Vec<String> = ast[3..].iter().map(ASTNode::to_string).collect();
+        assert_eq!(printed, ["answer🜄🜂 + 1", "half🜄pi🜄🜂🜂", "f🜄1🜂", "half"]);
+        assert_eq!(ast[2].to_string(), "🜙f🜄answer🜂 answer * 2");
+    }
+
+    #[test]
+    fn defaults_work() {
+        let source = "🜙fall🜄t🜌h = 100🜌g = 9.81 / 2🜂h - g * t * t;\
+                      🜙span🜄from = 1🜌to🜂to - from;\
+                      fall🜄2🜂;\
+                      fall🜄2🜌50🜂;\
+                      fall🜄2🜌g: 1🜂;\
+                      span🜄to: 5🜂;\
+                      span🜄3🜌4🜂;";
+        assert_eq!(
+            run(source),
+            Ok(vec![100.0 - 19.62, 50.0 - 19.62, 96.0, 4.0, 1.0])
+        );
+        let proto = match &Parser::default().parse_str(source).unwrap()[0] {
+            ASTNode::Function(func) => func.prototype.to_string(),
+            node => panic!("expected a function, found {:?}", node),
+        };
+        assert_eq!(proto, "fall🜄t🜌h = 100🜌g = 9.81 / 2🜂");
+
+        let err = |source: &str| run(source).unwrap_err();
+        assert_eq!(
+            err("🜙span🜄from = 1🜌to🜂to - from;span🜄5🜂;"),
+            ResolveError::MissingArgument("span".to_string(), "to".to_string())
+        );
+        assert_eq!(
+            err("🜹sin🜄x🜂;🜙f🜄x = sin🜄1🜂🜂x;"),
+            ResolveError::NonConstantDefault("f".to_string(), "x".to_string())
+        );
+        assert_eq!(
+            err("🜙f🜄x🜌y = x * 2🜂y;"),
+            ResolveError::DefaultUsesParameter("f".to_string(), "y".to_string(), "x".to_string())
+        );
+        assert_eq!(
+            err("🜹pow🜄x🜌y = 2🜂;"),
+            ResolveError::ExternDefault("pow".to_string())
+        );
+        assert_eq!(run("🜙f🜄x = pi🜄🜂🜂x;f🜄🜂;"), Ok(vec![std::f64::consts::PI]));
+    }
+
+    #[test]
+    fn void_calls_are_statements() {
+        let check = |source: &str| {
+            let mut ast = Parser::default().parse_str(source).unwrap();
+            resolve(&mut ast)
+        };
+        let externs = "🜹void beep🜄🜂;🜹void log_value🜄x🜂;";
+        assert_eq!(
+            check(&format!("{}beep🜄🜂;log_value🜄x: 1🜂;", externs)),
+            Ok(())
+        );
+        let void_value = |name: &str| Err(ResolveError::VoidValue(name.to_string()));
+        for (use_, name) in [
+            ("1 + beep🜄🜂", "beep"),
+            ("log_value🜄beep🜄🜂🜂", "beep"),
+            ("🜙f🜄x🜂log_value🜄x🜂", "log_value"),
+        ]
+        .iter()
+        {
+            assert_eq!(check(&format!("{}{};", externs, use_)), void_value(name));
+        }
+    }
+
+    #[test]
+    fn check_externs_works() {
+        let check = |source: &str, host_functions: &[(String, usize)]| {
+            check_externs(
+                &Parser::default().parse_str(source).unwrap(),
+                host_functions,
+            )
+        };
+        assert_eq!(
+            check("🜹sin🜄x🜌y🜂;sin🜄1🜌2🜂;", &[]),
+            Err(ResolveError::ExternArity(
+                "sin".to_string(),
+                2,
+                "sin".to_string(),
+                1
+            ))
+        );
+        assert_eq!(check("🜹sin🜄x🜂;🜹pow🜄x🜌y🜂;", &[]), Ok(vec![]));
+        assert_eq!(
+            check(r#"🜹arc🜄y🜂 = "atan2";"#, &[]).unwrap_err().to_string(),
+            "extern arc is declared with 1 args but the host's atan2 takes 2"
+        );
+        assert_eq!(
+            check("🜹printd🜄x🜂;🜹printd🜄y🜂;🜹draw🜄🜂;", &[]),
+            Ok(vec!["printd".to_string(), "draw".to_string()])
+        );
+
+        let host_functions = [("halve".to_string(), 1)];
+        assert_eq!(check("🜹halve🜄x🜂;", &host_functions), Ok(vec![]));
+        assert_eq!(
+            check("🜹halve🜄x🜌y🜂;", &host_functions),
+            Err(ResolveError::ExternArity(
+                "halve".to_string(),
+                2,
+                "halve".to_string(),
+                1
+            ))
+        );
+    }
+}
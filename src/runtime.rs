@@ -0,0 +1,116 @@
+//! what generated code does when it can't go on - instead of aborting the
+//! process it records a trap in the module's trap slot and returns nan, and
+//! the engine checks the slot after every call it makes
+//!
+//! the slot keeps the first trap of a call, so whatever its callers compute
+//! from the nan afterwards is thrown away with their result
+
+/// the kind of trap each code in the slot stands for, 0 is none
+pub(crate) const OUT_OF_BOUNDS: f64 = 1.0;
+pub(crate) const OVERFLOW: f64 = 2.0;
+
+/// the helper generated code calls to trap, with the kind, site and value
+pub(crate) const TRAP: &str = "wizarding.trap";
+/// what the engine calls to read the kind of trap and clear it
+pub(crate) const TAKE: &str = "wizarding.trap.take";
+pub(crate) const SITE: &str = "wizarding.trap.site";
+pub(crate) const VALUE: &str = "wizarding.trap.value";
+
+/// the trap slot and the functions around it, linked into a module the
+/// first time something in it can trap - the site is an index into
+/// Codegen::trap_sites
+pub(crate) const IR: &str = r#"
+@wizarding.trap.slot.kind = internal global double 0.0
+@wizarding.trap.slot.site = internal global double 0.0
+@wizarding.trap.slot.value = internal global double 0.0
+define internal double @wizarding.trap(double %kind, double %site, double %value) cold noinline {
+  %old = load double, double* @wizarding.trap.slot.kind
+  %first = fcmp oeq double %old, 0.0
+  br i1 %first, label %record, label %done
+record:
+  store double %kind, double* @wizarding.trap.slot.kind
+  store double %site, double* @wizarding.trap.slot.site
+  store double %value, double* @wizarding.trap.slot.value
+  br label %done
+done:
+  ret double 0x7FF8000000000000
+}
+define double @wizarding.trap.take() {
+  %kind = load double, double* @wizarding.trap.slot.kind
+  store double 0.0, double* @wizarding.trap.slot.kind
+  ret double %kind
+}
+define double @wizarding.trap.site() {
+  %site = load double, double* @wizarding.trap.slot.site
+  ret double %site
+}
+define double @wizarding.trap.value() {
+  %value = load double, double* @wizarding.trap.slot.value
+  ret double %value
+}
+"#;
+
+/// why generated code stopped, and where
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum RuntimeError {
+    /// only with check_bounds, nan counts as out of bounds
+    #[error("index {index} is out of bounds of table {table}")]
+    OutOfBounds { table: String, index: f64 },
+    /// only with check_overflow, the first op of a call to make an infinity
+    /// from finite operands
+    #[error("overflow to {}Inf at {op}", sign(*.value))]
+    Overflow { op: String, value: f64 },
+}
+
+fn sign(value: f64) -> &'static str {
+    if value < 0.0 {
+        "-"
+    } else {
+        "+"
+    }
+}
+
+impl RuntimeError {
+    /// the trap a slot holds, with sites the trap_sites of the codegen that
+    /// compiled it
+    pub(crate) fn decode(kind: f64, site: f64, value: f64, sites: &[String]) -> RuntimeError {
+        let site = sites
+            .get(site as usize)
+            .cloned()
+            .unwrap_or_else(|| "?".to_string());
+        match kind {
+            k if k == OUT_OF_BOUNDS => RuntimeError::OutOfBounds {
+                table: site,
+                index: value,
+            },
+            k if k == OVERFLOW => RuntimeError::Overflow { op: site, value },
+            _ => unreachable!("generated code only traps with the kinds above"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traps_decode() {
+        let sites = ["coeffs".to_string(), "empty".to_string()];
+        assert_eq!(
+            RuntimeError::decode(OUT_OF_BOUNDS, 1.0, 3.0, &sites),
+            RuntimeError::OutOfBounds {
+                table: "empty".to_string(),
+                index: 3.0
+            }
+        );
+        assert_eq!(
+            RuntimeError::decode(OUT_OF_BOUNDS, 0.0, f64::NAN, &sites).to_string(),
+            "index NaN is out of bounds of table coeffs"
+        );
+        let sites = ["x * x in growth (growth.wiz:1:1)".to_string()];
+        assert_eq!(
+            RuntimeError::decode(OVERFLOW, 0.0, f64::NEG_INFINITY, &sites).to_string(),
+            "overflow to -Inf at x * x in growth (growth.wiz:1:1)"
+        );
+    }
+}
@@ -0,0 +1,1198 @@
+//! compiling source a piece at a time, each piece in its own module that
+//! links against what the pieces before it define - embedders and the c api
+//! keep a session instead of compiling everything again
+use std::os::raw::c_void;
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use inkwell::context::Context;
+
+use crate::ast::{ASTNode, Prototype};
+use crate::builtins;
+use crate::codegen::{Codegen, CodegenError};
+use crate::compile::{self, CompileError, CompiledProgram, SymbolKind};
+use crate::derive::calls_in;
+use crate::engine::{Engine, EngineError, EngineKind, OptLevel};
+use crate::parser::Parser;
+use crate::plan::{fnv1a, CompileOptions, PlanError};
+use crate::resolve::ResolveError;
+use crate::snapshot::{Piece, Snapshot, SnapshotError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SessionError {
+    #[error(transparent)]
+    Compile(#[from] CompileError),
+    /// a compile error about calls to names reload redefined, with a note
+    /// on the definitions of each
+    #[error("{error}\n{}", .notes.join("\n"))]
+    Redefined {
+        error: CompileError,
+        notes: Vec<String>,
+    },
+    #[error(transparent)]
+    Engine(EngineError),
+    #[error(transparent)]
+    Run(EngineError),
+    #[error("unknown function {0}")]
+    UnknownFunction(String),
+    #[error("{0} takes {1} arguments, not {2}")]
+    Arity(String, usize, usize),
+    /// a function inline ir defines that can't be called as one of doubles
+    #[error(transparent)]
+    Lookup(EngineError),
+    #[error("only a session made with Session::hot_reloading can reload or watch")]
+    NotHotReloading,
+    #[error("{0} is a host function, only functions the session defines can be watched")]
+    WatchHost(String),
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+}
+
+impl SessionError {
+    /// what failed, as the code of a diagnostic
+    pub fn code(&self) -> &'static str {
+        match self {
+            SessionError::Compile(_) | SessionError::Redefined { .. } => "compile",
+            SessionError::Engine(_) => "engine",
+            SessionError::Run(_) => "run",
+            SessionError::UnknownFunction(_)
+            | SessionError::Arity(..)
+            | SessionError::Lookup(_)
+            | SessionError::WatchHost(_) => "lookup",
+            SessionError::NotHotReloading => "reload",
+            SessionError::Snapshot(_) => "snapshot",
+        }
+    }
+}
+
+/// a compiled piece and the jit running it, kept together so the functions
+/// it compiled stay callable
+struct Program {
+    // fields are dropped in order, the engine before the program it runs and
+    // the program before the context everything is allocated in
+    engine: Engine<'static>,
+    _compiled: Box<CompiledProgram<'static>>,
+    _context: Box<Context>,
+    /// what a snapshot keeps of it
+    piece: Piece,
+}
+
+/// a definition that took effect in a session
+#[derive(Debug, PartialEq, Clone)]
+pub struct Definition {
+    /// the number of the source it was in, counting every source the
+    /// session kept from 1 - the N of `<eval-N>` in its errors
+    pub input: usize,
+    pub prototype: Prototype,
+    /// when it took effect in this process
+    pub at: SystemTime,
+}
+
+/// the name of the function each call error in err is about
+fn called_in(err: &CompileError) -> Vec<&str> {
+    fn resolve(err: &ResolveError) -> Option<&str> {
+        match err {
+            ResolveError::InvalidCall(name, _, _)
+            | ResolveError::MissingArgument(name, _)
+            | ResolveError::UnknownArgument(name, _)
+            | ResolveError::DuplicateArgument(name, _)
+            | ResolveError::PositionalAfterNamed(name) => Some(name),
+            _ => None,
+        }
+    }
+    match err {
+        CompileError::Plan(PlanError::Resolve(err)) => resolve(err).into_iter().collect(),
+        CompileError::Plan(PlanError::ResolveIn { error, .. }) => {
+            resolve(error).into_iter().collect()
+        }
+        CompileError::Items(items) => items
+            .iter()
+            .filter_map(|item| match &item.error {
+                CodegenError::InvalidCall(name, _, _) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// what Session::watch calls back with
+struct Watch {
+    predicate: Box<dyn FnMut(f64) -> bool + Send>,
+    callback: Box<dyn FnMut(f64, u64) + Send>,
+    /// how many times the watched function has returned
+    calls: u64,
+}
+
+/// every watch of every session, at the id compiled into its wrapper
+static WATCHES: Mutex<Vec<Option<Watch>>> = Mutex::new(Vec::new());
+
+fn watches() -> MutexGuard<'static, Vec<Option<Watch>>> {
+    // a watch that panicked aborted the process, so nothing is left half done
+    WATCHES.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// what each watch wrapper passes the value it returns through - the watch
+/// is taken out while it runs, so a callback calling back into a watched
+/// function doesn't deadlock
+extern "C" fn watch_hook(id: f64, value: f64) -> f64 {
+    let id = id as usize;
+    let watch = watches().get_mut(id).and_then(Option::take);
+    if let Some(mut watch) = watch {
+        watch.calls += 1;
+        if (watch.predicate)(value) {
+            (watch.callback)(value, watch.calls)
+        }
+        watches()[id] = Some(watch);
+    }
+    value
+}
+
+/// the name, watch id and wrapper address of each function a session
+/// watches, releasing the watches with the session
+#[derive(Default)]
+struct Watched(Vec<(String, usize, usize)>);
+
+impl Drop for Watched {
+    fn drop(&mut self) {
+        let mut watches = watches();
+        for (_, id, _) in &self.0 {
+            watches[*id] = None;
+        }
+    }
+}
+
+/// what compiling a piece defined, besides the program itself
+struct Compiled {
+    program: Program,
+    /// the name, arity and address of each function it gave a body
+    functions: Vec<(String, usize, usize)>,
+    /// the names it made a slot for, hot reloading only
+    slots: Vec<String>,
+}
+
+impl Program {
+    /// compile source as the piece called name, linking each of linked, a
+    /// name, arity and address, against the function the session declares
+    /// for it
+    ///
+    /// each of linked that has one of prototypes is declared with its
+    /// parameter names, so calls can name them
+    fn compile(
+        name: &str,
+        source: &str,
+        linked: &[(String, usize, usize)],
+        prototypes: &[&Prototype],
+        hot_reload: bool,
+        allow_inline_ir: bool,
+    ) -> Result<Compiled, SessionError> {
+        let context = Box::new(Context::create());
+        // the boxes don't move when the program does and outlive the
+        // references into them, see the field order
+        let context_ref: &'static Context = unsafe { &*(context.as_ref() as *const Context) };
+        let declarations: String = linked
+            .iter()
+            .map(|(name, arity, _)| {
+                let proto = prototypes
+                    .iter()
+                    .find(|proto| proto.name == *name && proto.args.len() == *arity);
+                let params: Vec<String> = match proto {
+                    Some(proto) => proto.args.clone(),
+                    None => (0..*arity).map(|i| format!("x{}", i)).collect(),
+                };
+                format!("🜹{}🜄{}🜂;", name, params.join("🜌"))
+            })
+            .collect();
+        let sources = [("<host>", declarations.as_str()), (name, source)];
+        let mut options = CompileOptions {
+            host_functions: linked
+                .iter()
+                .map(|(name, arity, _)| (name.clone(), *arity))
+                .collect(),
+            allow_inline_ir,
+            ..CompileOptions::default()
+        };
+        let mut codegen = Codegen::new(context_ref);
+        codegen.hot_reload = hot_reload;
+        let compilation = compile::compile(codegen, &sources, &mut options)?;
+        let defined: Vec<String> = compilation
+            .symbols()
+            .iter()
+            .filter(|symbol| symbol.kind != SymbolKind::Extern)
+            .map(|symbol| symbol.name.clone())
+            .collect();
+        let piece = Piece {
+            source: source.to_string(),
+            bitcode: compilation.bitcode(),
+            ..Piece::default()
+        };
+        let compiled = Box::new(compilation.into_program());
+        Program::link(context, compiled, linked, defined, piece)
+    }
+
+    /// the piece a snapshot kept, linked against linked like it was when it
+    /// was compiled
+    fn restore(
+        index: usize,
+        piece: Piece,
+        linked: &[(String, usize, usize)],
+    ) -> Result<Compiled, SessionError> {
+        let context = Box::new(Context::create());
+        let context_ref: &'static Context = unsafe { &*(context.as_ref() as *const Context) };
+        let compiled = CompiledProgram::from_bitcode(context_ref, &piece.bitcode)
+            .map_err(|err| SnapshotError::Bitcode(index, err))?;
+        let defined = piece.defined.clone();
+        Program::link(context, Box::new(compiled), linked, defined, piece)
+    }
+
+    /// start the jit for a compiled piece, registering each of linked with
+    /// it, and find the address of each of defined that has a body
+    fn link(
+        context: Box<Context>,
+        compiled: Box<CompiledProgram<'static>>,
+        linked: &[(String, usize, usize)],
+        defined: Vec<String>,
+        mut piece: Piece,
+    ) -> Result<Compiled, SessionError> {
+        let mut slots = Vec::new();
+        let mut next = compiled.module().get_first_function();
+        while let Some(func) = next {
+            next = func.get_next_function();
+            let name = func.get_name().to_string_lossy();
+            if let Some(name) = name.strip_suffix(".rebind") {
+                slots.push(name.to_string());
+            }
+        }
+
+        let compiled_ref: &'static CompiledProgram<'static> =
+            unsafe { &*(compiled.as_ref() as *const CompiledProgram) };
+        let engine = compiled_ref
+            .engine(EngineKind::Jit, OptLevel::None)
+            .map_err(SessionError::Engine)?;
+        for (name, arity, address) in linked {
+            unsafe { engine.register_function(name, *arity, *address) }
+                .map_err(SessionError::Engine)?;
+        }
+        let mut functions = Vec::new();
+        for name in defined {
+            // a definition that failed to compile has no body to point to
+            if let Ok(arity) = engine.arity(&name) {
+                let address = engine
+                    .function_address(&name, arity)
+                    .map_err(SessionError::Engine)?;
+                functions.push((name, arity, address));
+            }
+        }
+        piece.linked = linked
+            .iter()
+            .map(|(name, arity, _)| (name.clone(), *arity))
+            .collect();
+        piece.defined = functions.iter().map(|(name, _, _)| name.clone()).collect();
+        Ok(Compiled {
+            program: Program {
+                engine,
+                _compiled: compiled,
+                _context: context,
+                piece,
+            },
+            functions,
+            slots,
+        })
+    }
+
+    /// a wrapper that calls the function named name at address and passes
+    /// what it returns through the watch with id, and the address of it
+    fn watch(
+        name: &str,
+        arity: usize,
+        address: usize,
+        id: usize,
+    ) -> Result<(Program, usize), SessionError> {
+        let hook = format!("{}_watch_hook", name);
+        let wrapper = format!("{}_watched", name);
+        let params: Vec<String> = (0..arity).map(|i| format!("x{}", i)).collect();
+        let params = params.join("🜌");
+        let source = format!(
+            "🜙{}🜄{}🜂{}🜄{}🜌{}🜄{}🜂🜂;",
+            wrapper, params, hook, id, name, params
+        );
+        let linked = [
+            (hook, 2, watch_hook as *const () as usize),
+            (name.to_string(), arity, address),
+        ];
+        // without slots, so the wrapper calls the definition itself and not
+        // whatever the slots of name point to, which is the wrapper
+        let compiled = Program::compile("<watch>", &source, &linked, &[], false, false)?;
+        let address = compiled
+            .functions
+            .iter()
+            .find(|(other, _, _)| *other == wrapper)
+            .map(|(_, _, address)| *address)
+            .expect("the wrapper compiles");
+        Ok((compiled.program, address))
+    }
+
+    /// run every top level expression, returning the value of the last one
+    fn run(&self) -> Result<Option<f64>, SessionError> {
+        let mut value = None;
+        for (symbol, _) in self.engine.entries() {
+            value = Some(self.engine.run_entry(symbol).map_err(SessionError::Run)?);
+        }
+        Ok(value)
+    }
+}
+
+/// an embedded compiler - each source evaluated in it is compiled on its
+/// own, and calls what earlier ones define through their addresses
+///
+/// a name defined again keeps calling the first definition, like within
+/// one compile, unless the session hot reloads and the source is reloaded
+#[derive(Default)]
+pub struct Session {
+    /// accept `llvm "..."` items - function only hands out what they define
+    /// once its type checks
+    pub allow_inline_ir: bool,
+    hot_reload: bool,
+    /// the name, arity and address of each registered host function
+    host_functions: Vec<(String, usize, usize)>,
+    /// the name, arity and current address of each function defined so far,
+    /// in the order they were first defined
+    functions: Vec<(String, usize, usize)>,
+    /// the address of the name.hot wrapper of each function in the piece
+    /// that first defined it, hot reloading only
+    handles: Vec<(String, usize)>,
+    /// the address of every name.rebind in every piece, hot reloading only
+    rebinds: Vec<(String, usize)>,
+    /// every piece compiled so far, so the addresses into them stay valid
+    /// until the session is dropped
+    programs: Vec<Program>,
+    watched: Watched,
+    /// every watch wrapper compiled so far, kept like programs but left out
+    /// of snapshots
+    watchers: Vec<Program>,
+    /// every definition that took effect, oldest first
+    history: Vec<Definition>,
+    /// the name, a hash of the definition and the callees of each function
+    /// defined so far, what a cached result depends on
+    definitions: Vec<(String, u64, Vec<String>)>,
+    cache_results: bool,
+    /// the key, the functions it depends on and the value of each source
+    /// eval cached the result of
+    results: Vec<(u64, Vec<String>, Option<f64>)>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// a session whose functions call each other through slots, so reload
+    /// can swap what they call - each call costs a load more
+    pub fn hot_reloading() -> Session {
+        Session {
+            hot_reload: true,
+            ..Session::default()
+        }
+    }
+
+    /// make name callable from sources evaluated after this, as a function of
+    /// arity arguments that runs the host function at address - the session
+    /// declares the extern itself
+    ///
+    /// # Safety
+    ///
+    /// address has to be an `extern "C"` function taking arity doubles and
+    /// returning a double that doesn't unwind, and stay valid as long as the
+    /// session
+    pub unsafe fn register_function(&mut self, name: &str, arity: usize, address: usize) {
+        self.host_functions.retain(|(other, _, _)| other != name);
+        self.host_functions.push((name.to_string(), arity, address));
+    }
+
+    /// compile source against everything evaluated before it and run its top
+    /// level expressions, returning the value of the last one - nothing of a
+    /// source that fails is kept
+    pub fn eval(&mut self, source: &str) -> Result<Option<f64>, SessionError> {
+        let key = if self.cache_results {
+            self.cache_key(source)
+        } else {
+            None
+        };
+        if let Some((key, _)) = &key {
+            let cached = self.results.iter().find(|(other, _, _)| other == key);
+            if let Some((_, _, value)) = cached {
+                return Ok(*value);
+            }
+        }
+        let compiled = self.compile(source, &[])?;
+        let value = compiled.program.run()?;
+        self.commit(compiled);
+        if let Some((key, dependencies)) = key {
+            self.results.push((key, dependencies, value));
+        }
+        Ok(value)
+    }
+
+    /// whether eval keeps the value of a source of only top level
+    /// expressions, and returns it without compiling or running anything
+    /// when the same expressions are evaluated against the same definitions
+    /// of everything they call - turning it off forgets every value kept
+    ///
+    /// a source that calls a host function, an impure extern or a watched
+    /// function, even through the functions it calls, is always run
+    pub fn set_result_cache(&mut self, cache_results: bool) {
+        self.cache_results = cache_results;
+        if !cache_results {
+            self.results.clear();
+        }
+    }
+
+    /// a hash of source's expressions and the definition of every function
+    /// they call, directly or not, with the names of those functions - none
+    /// if it defines anything or calls something that isn't pure
+    fn cache_key(&self, source: &str) -> Option<(u64, Vec<String>)> {
+        let ast = Parser::default().parse_str(source).ok()?;
+        let mut calls = Vec::new();
+        for node in &ast {
+            match node {
+                ASTNode::Function(func) if func.prototype.name == "lambda" => {
+                    calls_in(&func.body, &mut calls)
+                }
+                _ => return None,
+            }
+        }
+        if ast.is_empty() {
+            return None;
+        }
+
+        let mut dependencies: Vec<String> = Vec::new();
+        while let Some(name) = calls.pop() {
+            if dependencies.contains(&name) {
+                continue;
+            }
+            let watched = self.watched.0.iter().any(|(other, _, _)| *other == name);
+            if watched
+                || self
+                    .host_functions
+                    .iter()
+                    .any(|(other, _, _)| *other == name)
+            {
+                return None;
+            }
+            match self.definitions.iter().find(|(other, _, _)| *other == name) {
+                Some((_, _, callees)) => calls.extend(callees.iter().cloned()),
+                None if builtins::is_pure(&name) => (),
+                None => return None,
+            }
+            dependencies.push(name);
+        }
+        dependencies.sort();
+
+        let mut key = format!("{:?}", ast);
+        for name in &dependencies {
+            let definition = self.definitions.iter().find(|(other, _, _)| other == name);
+            if let Some((_, hash, _)) = definition {
+                key += &format!(" {}={:x}", name, hash);
+            }
+        }
+        Some((fnv1a(key.as_bytes()), dependencies))
+    }
+
+    /// like eval, but what source defines replaces the functions of the same
+    /// name everywhere, so every caller compiled before it and every handle
+    /// from function calls the new definitions - its top level expressions
+    /// run once they are swapped in, and if one fails they are swapped back
+    pub fn reload(&mut self, source: &str) -> Result<Option<f64>, SessionError> {
+        if !self.hot_reload {
+            return Err(SessionError::NotHotReloading);
+        }
+        let replaced: Vec<String> = match Parser::default().parse_str(source) {
+            Ok(ast) => ast
+                .iter()
+                .filter_map(|node| match node {
+                    ASTNode::Function(func) if func.prototype.name != "lambda" => {
+                        Some(func.prototype.name.clone())
+                    }
+                    _ => None,
+                })
+                .collect(),
+            // compiling it reports the error
+            Err(_) => Vec::new(),
+        };
+        let compiled = self.compile(source, &replaced)?;
+        // a watch follows the new definition, wrapped before anything is
+        // swapped so a failure leaves everything as it was
+        let mut wrappers = Vec::new();
+        for (name, arity, address) in &compiled.functions {
+            let watched = self.watched.0.iter().find(|(other, _, _)| other == name);
+            if let Some((_, id, _)) = watched {
+                let (program, wrapper) = Program::watch(name, *arity, *address, *id)?;
+                wrappers.push((name.clone(), program, wrapper));
+            }
+        }
+
+        let mut swapped = Vec::new();
+        for (name, _, address) in &compiled.functions {
+            if let Some(old) = self.target(name) {
+                let wrapper = wrappers.iter().find(|(other, _, _)| other == name);
+                match wrapper {
+                    Some((_, _, wrapper)) => self.repoint(name, *wrapper),
+                    None => self.repoint(name, *address),
+                }
+                swapped.push((name.clone(), old));
+            }
+        }
+        match compiled.program.run() {
+            Ok(value) => {
+                self.commit(compiled);
+                for (name, program, wrapper) in wrappers {
+                    for watched in self
+                        .watched
+                        .0
+                        .iter_mut()
+                        .filter(|(other, _, _)| *other == name)
+                    {
+                        watched.2 = wrapper;
+                    }
+                    self.watchers.push(program);
+                }
+                Ok(value)
+            }
+            Err(err) => {
+                for (name, old) in swapped {
+                    self.repoint(&name, old);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// everything evaluated so far, as bytes restore turns back into the
+    /// session in another process without compiling any of it again
+    pub fn snapshot(&self) -> Vec<u8> {
+        Snapshot {
+            hot_reload: self.hot_reload,
+            allow_inline_ir: self.allow_inline_ir,
+            host_functions: self
+                .host_functions
+                .iter()
+                .map(|(name, arity, _)| (name.clone(), *arity))
+                .collect(),
+            pieces: self
+                .programs
+                .iter()
+                .map(|program| program.piece.clone())
+                .collect(),
+        }
+        .encode()
+    }
+
+    /// the session a snapshot was made of, with every definition callable
+    /// and the top level expressions not run again - addresses differ
+    /// between processes, so host gives the address of each host function
+    /// the snapshot names from its name and arity
+    ///
+    /// builtins are compiled into the pieces themselves, so only host
+    /// functions need linking again
+    ///
+    /// # Safety
+    ///
+    /// each address host gives has to be what register_function asks for
+    pub unsafe fn restore(
+        bytes: &[u8],
+        mut host: impl FnMut(&str, usize) -> Option<usize>,
+    ) -> Result<Session, SessionError> {
+        let snapshot = Snapshot::decode(bytes)?;
+        let mut session = Session {
+            allow_inline_ir: snapshot.allow_inline_ir,
+            hot_reload: snapshot.hot_reload,
+            ..Session::default()
+        };
+        for (name, arity) in snapshot.host_functions {
+            match host(&name, arity) {
+                Some(address) => session.register_function(&name, arity, address),
+                None => return Err(SnapshotError::Unresolved(name, arity).into()),
+            }
+        }
+        // each piece is linked against what the ones before it define, so
+        // they're committed in order like eval and reload did
+        for (index, piece) in snapshot.pieces.into_iter().enumerate() {
+            let linked = piece
+                .linked
+                .iter()
+                .map(|(name, arity)| {
+                    let host = session
+                        .host_functions
+                        .iter()
+                        .find(|(other, _, _)| other == name)
+                        .map(|(_, _, address)| *address);
+                    match session.address_of(name).or(host) {
+                        Some(address) => Ok((name.clone(), *arity, address)),
+                        None => Err(SnapshotError::Unresolved(name.clone(), *arity)),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let compiled = Program::restore(index, piece, &linked)?;
+            session.commit(compiled);
+        }
+        // every slot starts out at what its piece was linked against, and
+        // reloads had since pointed them all at the latest definition
+        for (name, _, address) in &session.functions {
+            session.repoint(name, *address);
+        }
+        Ok(session)
+    }
+
+    /// the address of the function named name that the sources evaluated so
+    /// far define with arity parameters, which takes and returns doubles with
+    /// the C calling convention - in a hot reloading session it's a wrapper
+    /// that follows every reload
+    pub fn function(&self, name: &str, arity: usize) -> Result<usize, SessionError> {
+        let found = match self.functions.iter().find(|(other, _, _)| other == name) {
+            Some((_, found, _)) => *found,
+            None => return self.ir_function(name, arity),
+        };
+        if found != arity {
+            return Err(SessionError::Arity(name.to_string(), found, arity));
+        }
+        let handle = self.handles.iter().find(|(other, _)| other == name);
+        match handle {
+            Some((_, address)) => Ok(*address),
+            None => Ok(self.address_of(name).unwrap()),
+        }
+    }
+
+    /// the address of a function inline ir defined, from the latest piece
+    /// that defines it, checked against the type function promises
+    fn ir_function(&self, name: &str, arity: usize) -> Result<usize, SessionError> {
+        let engine = self
+            .programs
+            .iter()
+            .rev()
+            .map(|program| &program.engine)
+            .find(|engine| engine.arity(name).is_ok());
+        match engine {
+            Some(engine) => engine
+                .function_address(name, arity)
+                .map_err(SessionError::Lookup),
+            None => Err(SessionError::UnknownFunction(name.to_string())),
+        }
+    }
+
+    fn address_of(&self, name: &str) -> Option<usize> {
+        self.functions
+            .iter()
+            .find(|(other, _, _)| other == name)
+            .map(|(_, _, address)| *address)
+    }
+
+    /// what the slots of name point to, its watch wrapper if it's watched
+    fn target(&self, name: &str) -> Option<usize> {
+        let watched = self.watched.0.iter().find(|(other, _, _)| other == name);
+        match watched {
+            Some((_, _, wrapper)) => Some(*wrapper),
+            None => self.address_of(name),
+        }
+    }
+
+    /// call callback with each value name returns that predicate holds for,
+    /// and how many times name has returned counting that time - every
+    /// caller and handle goes through the watch, and it follows reloads
+    ///
+    /// only a hot reloading session can watch, and only functions it
+    /// defines - watching a name again replaces its watch
+    ///
+    /// both run in whatever calls name, which aborts if either panics
+    pub fn watch(
+        &mut self,
+        name: &str,
+        predicate: impl FnMut(f64) -> bool + Send + 'static,
+        callback: impl FnMut(f64, u64) + Send + 'static,
+    ) -> Result<(), SessionError> {
+        if !self.hot_reload {
+            return Err(SessionError::NotHotReloading);
+        }
+        let (arity, address) = match self.functions.iter().find(|(other, _, _)| other == name) {
+            Some((_, arity, address)) => (*arity, *address),
+            None if self
+                .host_functions
+                .iter()
+                .any(|(other, _, _)| other == name) =>
+            {
+                return Err(SessionError::WatchHost(name.to_string()))
+            }
+            None => return Err(SessionError::UnknownFunction(name.to_string())),
+        };
+
+        let watch = Watch {
+            predicate: Box::new(predicate),
+            callback: Box::new(callback),
+            calls: 0,
+        };
+        let id = {
+            let mut watches = watches();
+            match watches.iter().position(Option::is_none) {
+                Some(id) => {
+                    watches[id] = Some(watch);
+                    id
+                }
+                None => {
+                    watches.push(Some(watch));
+                    watches.len() - 1
+                }
+            }
+        };
+        let (program, wrapper) = match Program::watch(name, arity, address, id) {
+            Ok(compiled) => compiled,
+            Err(err) => {
+                watches()[id] = None;
+                return Err(err);
+            }
+        };
+        self.repoint(name, wrapper);
+        self.watchers.push(program);
+        self.results
+            .retain(|(_, dependencies, _)| !dependencies.iter().any(|other| other == name));
+
+        let watched = self
+            .watched
+            .0
+            .iter_mut()
+            .find(|(other, _, _)| other == name);
+        match watched {
+            Some(watched) => {
+                watches()[watched.1] = None;
+                *watched = (name.to_string(), id, wrapper);
+            }
+            None => self.watched.0.push((name.to_string(), id, wrapper)),
+        }
+        Ok(())
+    }
+
+    /// compile source in a module of its own, linking it against the host
+    /// functions and every function defined so far except the replaced ones
+    fn compile(&self, source: &str, replaced: &[String]) -> Result<Compiled, SessionError> {
+        let linked: Vec<(String, usize, usize)> = self
+            .host_functions
+            .iter()
+            .chain(&self.functions)
+            .filter(|(name, _, _)| !replaced.contains(name))
+            .cloned()
+            .collect();
+        let prototypes: Vec<&Prototype> = self
+            .functions
+            .iter()
+            .filter_map(|(name, _, _)| self.definition(name))
+            .map(|definition| &definition.prototype)
+            .collect();
+        let name = format!("<eval-{}>", self.programs.len() + 1);
+        let compiled = Program::compile(
+            &name,
+            source,
+            &linked,
+            &prototypes,
+            self.hot_reload,
+            self.allow_inline_ir,
+        );
+        match compiled {
+            Err(SessionError::Compile(error)) => {
+                let mut notes = Vec::new();
+                for name in called_in(&error) {
+                    let history = self.history(name);
+                    if history.len() < 2 {
+                        continue;
+                    }
+                    let mut note = format!(
+                        "note: {} was defined at input #{} as {}",
+                        name, history[0].input, history[0].prototype
+                    );
+                    for definition in &history[1..] {
+                        note += &format!(
+                            ", redefined at input #{} as {}",
+                            definition.input, definition.prototype
+                        );
+                    }
+                    if !notes.contains(&note) {
+                        notes.push(note);
+                    }
+                }
+                if notes.is_empty() {
+                    Err(SessionError::Compile(error))
+                } else {
+                    Err(SessionError::Redefined { error, notes })
+                }
+            }
+            compiled => compiled,
+        }
+    }
+
+    /// each definition of name that took effect, oldest first - a name
+    /// defined again only takes effect through reload
+    pub fn history(&self, name: &str) -> Vec<&Definition> {
+        self.history
+            .iter()
+            .filter(|definition| definition.prototype.name == name)
+            .collect()
+    }
+
+    /// the definition of name in effect
+    fn definition(&self, name: &str) -> Option<&Definition> {
+        self.history
+            .iter()
+            .rev()
+            .find(|definition| definition.prototype.name == name)
+    }
+
+    /// keep a piece that compiled and ran, recording what it defines
+    fn commit(&mut self, compiled: Compiled) {
+        let Compiled {
+            program,
+            functions,
+            slots,
+        } = compiled;
+        // it compiled, so it parses
+        let ast = Parser::default()
+            .parse_str(&program.piece.source)
+            .unwrap_or_default();
+        let input = self.programs.len() + 1;
+        for (name, arity, address) in functions {
+            let func = ast.iter().find_map(|node| match node {
+                ASTNode::Function(func) if func.prototype.name == name => Some(func),
+                _ => None,
+            });
+            let definition = func.map(|func| {
+                let mut callees = Vec::new();
+                calls_in(&func.body, &mut callees);
+                for default in func.prototype.defaults.iter().flatten() {
+                    calls_in(default, &mut callees);
+                }
+                let hash = fnv1a(format!("{:?}", func).as_bytes());
+                (name.clone(), hash, callees)
+            });
+            let history = func.map(|func| Definition {
+                input,
+                prototype: func.prototype.clone(),
+                at: SystemTime::now(),
+            });
+            match self
+                .functions
+                .iter_mut()
+                .find(|(other, _, _)| *other == name)
+            {
+                // only reload gets here with a new definition of a name
+                Some(function) if self.hot_reload => {
+                    self.results
+                        .retain(|(_, dependencies, _)| !dependencies.contains(&name));
+                    self.definitions.retain(|(other, _, _)| *other != name);
+                    self.definitions.extend(definition);
+                    self.history.extend(history);
+                    *function = (name, arity, address)
+                }
+                Some(_) => (),
+                None => {
+                    self.definitions.extend(definition);
+                    self.history.extend(history);
+                    if self.hot_reload {
+                        let wrapper = format!("{}.hot", name);
+                        if let Ok(handle) = program.engine.address(&wrapper) {
+                            self.handles.push((name.clone(), handle));
+                        }
+                    }
+                    self.functions.push((name, arity, address));
+                }
+            }
+        }
+        for name in slots {
+            let rebind = format!("{}.rebind", name);
+            if let Ok(address) = program.engine.address(&rebind) {
+                self.rebinds.push((name, address));
+            }
+        }
+        self.programs.push(program);
+    }
+
+    /// point every slot for name, in every piece kept so far, at address
+    fn repoint(&self, name: &str, address: usize) {
+        for (_, rebind) in self.rebinds.iter().filter(|(other, _)| other == name) {
+            unsafe {
+                let rebind: unsafe extern "C" fn(*const c_void) = std::mem::transmute(*rebind);
+                rebind(address as *const c_void);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn halve(x: f64) -> f64 {
+        x / 2.0
+    }
+
+    #[test]
+    fn eval_works() {
+        let mut session = Session::new();
+        unsafe { session.register_function("halve", 1, halve as *const () as usize) };
+        assert_eq!(session.eval("🜙sq🜄x🜂x*x;").unwrap(), None);
+        assert_eq!(session.eval("sq🜄3🜂;halve🜄sq🜄5🜂🜂;").unwrap(), Some(12.5));
+        // a name defined again keeps calling the first definition
+        assert_eq!(session.eval("🜙sq🜄x🜂x;sq🜄3🜂;").unwrap(), Some(9.0));
+
+        let sq = session.function("sq", 1).unwrap();
+        let sq: extern "C" fn(f64) -> f64 = unsafe { std::mem::transmute(sq) };
+        assert_eq!(sq(4.0), 16.0);
+        assert!(matches!(
+            session.function("sq", 2),
+            Err(SessionError::Arity(name, 1, 2)) if name == "sq"
+        ));
+
+        let err = session.eval("🜙cube🜄x🜂x*x*x;nope🜄1🜂;").unwrap_err();
+        assert_eq!(err.code(), "compile");
+        assert_eq!(
+            err.to_string(),
+            "<eval-4>:1:15: top level expression 1: unknown function nope"
+        );
+        assert!(matches!(
+            session.function("cube", 1),
+            Err(SessionError::UnknownFunction(_))
+        ));
+        assert!(matches!(
+            session.reload("🜙sq🜄x🜂x;"),
+            Err(SessionError::NotHotReloading)
+        ));
+    }
+
+    #[test]
+    fn hot_reload_works() {
+        let mut session = Session::hot_reloading();
+        let source = "🜙speed🜄🜂2;🜙update🜄dt🜂dt*speed🜄🜂;";
+        assert_eq!(session.eval(source).unwrap(), None);
+        let update = session.function("update", 1).unwrap();
+        let update: extern "C" fn(f64) -> f64 = unsafe { std::mem::transmute(update) };
+        let speed = session.function("speed", 0).unwrap();
+        let speed: extern "C" fn() -> f64 = unsafe { std::mem::transmute(speed) };
+        assert_eq!((update(3.0), speed()), (6.0, 2.0));
+        assert_eq!(session.eval("🜙twice🜄dt🜂update🜄dt🜂*2;").unwrap(), None);
+
+        // callers compiled before and after the definition follow it
+        assert_eq!(session.reload("🜙speed🜄🜂5;twice🜄1🜂;").unwrap(), Some(10.0));
+        assert_eq!((update(3.0), speed()), (15.0, 5.0));
+
+        // a reload that doesn't compile leaves the old definitions in place
+        assert!(session.reload("🜙speed🜄🜂nope🜄🜂;").is_err());
+        assert_eq!(update(3.0), 15.0);
+        // eval doesn't swap anything in
+        assert_eq!(session.eval("🜙speed🜄🜂7;update🜄1🜂;").unwrap(), Some(5.0));
+        assert_eq!(speed(), 5.0);
+    }
+
+    #[test]
+    fn results_are_cached() {
+        let mut session = Session::new();
+        session.set_result_cache(true);
+        session.eval("🜙sq🜄x🜂x*x;🜙hyp🜄a🜌b🜂sq🜄a🜂+sq🜄b🜂;").unwrap();
+        assert_eq!(session.eval("hyp🜄3🜌4🜂;").unwrap(), Some(25.0));
+        assert_eq!(session.programs.len(), 2);
+        assert_eq!(session.eval("hyp🜄3🜌4🜂;").unwrap(), Some(25.0));
+        assert_eq!(session.eval("max🜄1🜌2🜂;").unwrap(), Some(2.0));
+        assert_eq!(session.eval("max🜄1🜌2🜂;").unwrap(), Some(2.0));
+        assert_eq!(session.programs.len(), 3);
+        // a source that defines something always compiles
+        session.eval("🜙one🜄🜂1;hyp🜄3🜌4🜂;").unwrap();
+        assert_eq!(session.programs.len(), 4);
+
+        session.set_result_cache(false);
+        assert_eq!(session.eval("hyp🜄3🜌4🜂;").unwrap(), Some(25.0));
+        assert_eq!(session.programs.len(), 5);
+    }
+
+    #[test]
+    fn redefinitions_invalidate_cached_results() {
+        let mut session = Session::hot_reloading();
+        session.set_result_cache(true);
+        session.eval("🜙speed🜄🜂2;🜙update🜄dt🜂dt*speed🜄🜂;").unwrap();
+        session.eval("🜙other🜄🜂1;").unwrap();
+        assert_eq!(session.eval("update🜄3🜂;").unwrap(), Some(6.0));
+        session.reload("🜙other🜄🜂2;").unwrap();
+        assert_eq!(session.eval("update🜄3🜂;").unwrap(), Some(6.0));
+        assert_eq!(session.programs.len(), 4);
+
+        session.reload("🜙speed🜄🜂5;").unwrap();
+        assert!(session.results.is_empty());
+        assert_eq!(session.eval("update🜄3🜂;").unwrap(), Some(15.0));
+        assert_eq!(session.programs.len(), 6);
+    }
+
+    #[test]
+    fn impure_results_are_not_cached() {
+        let mut session = Session::new();
+        unsafe { session.register_function("halve", 1, halve as *const () as usize) };
+        session.set_result_cache(true);
+        session.eval("🜙quarter🜄x🜂halve🜄halve🜄x🜂🜂;").unwrap();
+        for source in &["halve🜄4🜂;", "quarter🜄8🜂;", "1 + quarter🜄8🜂;"] {
+            let before = session.programs.len();
+            session.eval(source).unwrap();
+            session.eval(source).unwrap();
+            assert_eq!(session.programs.len(), before + 2, "{}", source);
+        }
+        assert!(session.results.is_empty());
+    }
+
+    #[test]
+    fn watches_see_every_return() {
+        use std::sync::{Arc, Mutex};
+
+        let mut session = Session::hot_reloading();
+        unsafe { session.register_function("halve", 1, halve as *const () as usize) };
+        session
+            .eval("🜙energy🜄t🜂10 - t*t;🜙step🜄t🜂energy🜄t🜂 + 1;")
+            .unwrap();
+        session.set_result_cache(true);
+        assert_eq!(session.eval("step🜄4🜂;").unwrap(), Some(-5.0));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_by_watch = Arc::clone(&seen);
+        session
+            .watch(
+                "energy",
+                |value| value < 0.0,
+                move |value, calls| seen_by_watch.lock().unwrap().push((value, calls)),
+            )
+            .unwrap();
+
+        let source = "step🜄1🜂;step🜄2🜂;step🜄3🜂;step🜄4🜂;";
+        assert_eq!(session.eval(source).unwrap(), Some(-5.0));
+        assert_eq!(*seen.lock().unwrap(), [(-6.0, 4)]);
+        // handles and a watched result that was cached before go through it
+        assert_eq!(session.eval("step🜄4🜂;").unwrap(), Some(-5.0));
+        let energy = session.function("energy", 1).unwrap();
+        let energy: extern "C" fn(f64) -> f64 = unsafe { std::mem::transmute(energy) };
+        assert_eq!(energy(5.0), -15.0);
+        assert_eq!(*seen.lock().unwrap(), [(-6.0, 4), (-6.0, 5), (-15.0, 6)]);
+
+        // and it follows a reload
+        session.reload("🜙energy🜄t🜂t - 1;").unwrap();
+        assert_eq!(energy(0.0), -1.0);
+        assert_eq!(session.eval("step🜄2🜂;").unwrap(), Some(2.0));
+        assert_eq!(seen.lock().unwrap().last(), Some(&(-1.0, 7)));
+
+        assert!(matches!(
+            session.watch("nope", |_| true, |_, _| ()),
+            Err(SessionError::UnknownFunction(name)) if name == "nope"
+        ));
+        let err = session.watch("halve", |_| true, |_, _| ()).unwrap_err();
+        assert_eq!(err.code(), "lookup");
+        assert_eq!(
+            err.to_string(),
+            "halve is a host function, only functions the session defines can be watched"
+        );
+        assert!(matches!(
+            Session::new().watch("energy", |_| true, |_, _| ()),
+            Err(SessionError::NotHotReloading)
+        ));
+    }
+
+    #[test]
+    fn redefinitions_are_remembered() {
+        let mut session = Session::hot_reloading();
+        session.eval("🜙f🜄x🜂x;🜙g🜄🜂1;").unwrap();
+        session.eval("🜙f🜄z🜂z*2;").unwrap();
+        session.reload("🜙f🜄x🜌y🜂x + y;").unwrap();
+        let history: Vec<(usize, String)> = session
+            .history("f")
+            .iter()
+            .map(|definition| (definition.input, definition.prototype.to_string()))
+            .collect();
+        // eval doesn't define a name again
+        assert_eq!(
+            history,
+            [(1, "f🜄x🜂".to_string()), (3, "f🜄x🜌y🜂".to_string())]
+        );
+        assert!(session.history("nope").is_empty());
+
+        let note = "note: f was defined at input #1 as f🜄x🜂, redefined at input #3 as f🜄x🜌y🜂";
+        for source in &["f🜄1🜂;", "f🜄1🜌2🜌3🜂;"] {
+            let err = session.eval(source).unwrap_err();
+            assert_eq!(err.code(), "compile");
+            let message = err.to_string();
+            assert!(message.starts_with("<eval-4>:"), "{}", message);
+            assert!(message.ends_with(note), "{}", message);
+        }
+        let err = session.eval("f🜄1🜂;").unwrap_err().to_string();
+        assert!(err.contains("missing argument y in a call to f"), "{}", err);
+        // later sources are checked against the parameters f has now
+        assert_eq!(session.eval("f🜄y: 1🜌 x: 2🜂;").unwrap(), Some(3.0));
+        let err = session.eval("g🜄1🜂;").unwrap_err().to_string();
+        assert!(!err.contains("note:"), "{}", err);
+    }
+
+    #[test]
+    fn snapshots_restore() {
+        let mut session = Session::new();
+        unsafe { session.register_function("halve", 1, halve as *const () as usize) };
+        session
+            .eval("🜙sq🜄x🜂x*x;🜙quarter🜄x🜂halve🜄halve🜄x🜂🜂;")
+            .unwrap();
+        session.eval("🜙hyp🜄a🜌b🜂sq🜄a🜂+sq🜄b🜂;").unwrap();
+        let bytes = session.snapshot();
+        drop(session);
+
+        let mut asked = Vec::new();
+        let mut restored = unsafe {
+            Session::restore(&bytes, |name, arity| {
+                asked.push((name.to_string(), arity));
+                Some(halve as *const () as usize)
+            })
+        }
+        .unwrap();
+        assert_eq!(asked, [("halve".to_string(), 1)]);
+        let hyp = restored.function("hyp", 2).unwrap();
+        let hyp: extern "C" fn(f64, f64) -> f64 = unsafe { std::mem::transmute(hyp) };
+        assert_eq!(hyp(3.0, 4.0), 25.0);
+        // later sources link against what was restored, and snapshot again
+        assert_eq!(restored.eval("quarter🜄hyp🜄3🜌4🜂🜂;").unwrap(), Some(6.25));
+        restored.eval("🜙cube🜄x🜂sq🜄x🜂*x;").unwrap();
+        let again = unsafe {
+            Session::restore(&restored.snapshot(), |_, _| {
+                Some(halve as *const () as usize)
+            })
+        }
+        .unwrap();
+        let cube = again.function("cube", 1).unwrap();
+        let cube: extern "C" fn(f64) -> f64 = unsafe { std::mem::transmute(cube) };
+        assert_eq!(cube(3.0), 27.0);
+
+        let err = unsafe { Session::restore(&bytes, |_, _| None) }
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), "snapshot");
+        assert_eq!(
+            err.to_string(),
+            "no host function halve of 1 arguments to link against"
+        );
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            unsafe { Session::restore(&corrupt, |_, _| None) },
+            Err(SessionError::Snapshot(SnapshotError::Checksum))
+        ));
+    }
+
+    #[test]
+    fn hot_reloading_snapshots_restore() {
+        let mut session = Session::hot_reloading();
+        session.eval("🜙speed🜄🜂2;🜙update🜄dt🜂dt*speed🜄🜂;").unwrap();
+        session.eval("🜙twice🜄dt🜂update🜄dt🜂*2;").unwrap();
+        session.reload("🜙speed🜄🜂5;").unwrap();
+        let bytes = session.snapshot();
+        drop(session);
+
+        let mut restored = unsafe { Session::restore(&bytes, |_, _| None) }.unwrap();
+        let twice = restored.function("twice", 1).unwrap();
+        let twice: extern "C" fn(f64) -> f64 = unsafe { std::mem::transmute(twice) };
+        assert_eq!(twice(1.0), 10.0);
+        // the restored session reloads like the one it was made of
+        assert_eq!(restored.reload("🜙speed🜄🜂7;").unwrap(), None);
+        assert_eq!(twice(1.0), 14.0);
+    }
+}
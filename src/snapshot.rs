@@ -0,0 +1,293 @@
+//! the bytes a session is saved as, so a script compiled once can be
+//! restored in other processes without running llvm on it again
+//!
+//! a snapshot is a header - magic, format version, a fingerprint of the
+//! build that made it and a checksum of the rest - followed by the host
+//! prototypes and each piece, numbers little endian and every string and
+//! buffer prefixed with its length
+use std::convert::TryInto;
+
+use crate::plan::fnv1a;
+
+const MAGIC: &[u8; 8] = b"wizsnap\0";
+/// the layout this build reads and writes, bumped whenever it changes
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("not a snapshot")]
+    NotASnapshot,
+    #[error("snapshot format {0}, this build reads format {current}", current = VERSION)]
+    Version(u32),
+    /// another version or target of the compiler links pieces differently
+    #[error("the snapshot was made by another build or kind of session")]
+    Fingerprint,
+    #[error("the snapshot is corrupt")]
+    Checksum,
+    #[error("the snapshot ends early")]
+    Truncated,
+    #[error("the snapshot is malformed")]
+    Malformed,
+    #[error("llvm can't read the bitcode of piece {0}: {1}")]
+    Bitcode(usize, String),
+    #[error("no host function {0} of {1} arguments to link against")]
+    Unresolved(String, usize),
+}
+
+/// what a session is made of, without the addresses that only mean
+/// something in the process that made it
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Snapshot {
+    pub hot_reload: bool,
+    pub allow_inline_ir: bool,
+    /// the name and arity of each host function the session had registered
+    pub host_functions: Vec<(String, usize)>,
+    /// every source evaluated, in order
+    pub pieces: Vec<Piece>,
+}
+
+/// one source a session compiled and kept
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Piece {
+    /// the source it was compiled from, which parses back to its ast
+    pub source: String,
+    /// its module as llvm bitcode, from before the jit saw it
+    pub bitcode: Vec<u8>,
+    /// the name and arity of each function it linked against, defined by
+    /// the host or a piece before it
+    pub linked: Vec<(String, usize)>,
+    /// the name of each function it gave a body
+    pub defined: Vec<String>,
+}
+
+/// what has to match between the build that makes a snapshot and the one
+/// that restores it
+fn fingerprint(hot_reload: bool, allow_inline_ir: bool) -> u64 {
+    let build = format!(
+        "{} {} {} {} {}",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::ARCH,
+        std::env::consts::OS,
+        hot_reload,
+        allow_inline_ir
+    );
+    fnv1a(build.as_bytes())
+}
+
+fn put_len(bytes: &mut Vec<u8>, len: usize) {
+    bytes.extend(&(len as u32).to_le_bytes());
+}
+
+fn put_bytes(bytes: &mut Vec<u8>, put: &[u8]) {
+    put_len(bytes, put.len());
+    bytes.extend(put);
+}
+
+fn put_prototypes(bytes: &mut Vec<u8>, prototypes: &[(String, usize)]) {
+    put_len(bytes, prototypes.len());
+    for (name, arity) in prototypes {
+        put_bytes(bytes, name.as_bytes());
+        put_len(bytes, *arity);
+    }
+}
+
+/// the unread part of a snapshot
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> Result<usize, SnapshotError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, SnapshotError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| SnapshotError::Malformed)
+    }
+
+    fn prototypes(&mut self) -> Result<Vec<(String, usize)>, SnapshotError> {
+        (0..self.len()?)
+            .map(|_| Ok((self.string()?, self.len()?)))
+            .collect()
+    }
+}
+
+impl Snapshot {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = vec![self.hot_reload as u8 | (self.allow_inline_ir as u8) << 1];
+        put_prototypes(&mut body, &self.host_functions);
+        put_len(&mut body, self.pieces.len());
+        for piece in &self.pieces {
+            put_bytes(&mut body, piece.source.as_bytes());
+            put_bytes(&mut body, &piece.bitcode);
+            put_prototypes(&mut body, &piece.linked);
+            put_len(&mut body, piece.defined.len());
+            for name in &piece.defined {
+                put_bytes(&mut body, name.as_bytes());
+            }
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(&VERSION.to_le_bytes());
+        bytes.extend(&fingerprint(self.hot_reload, self.allow_inline_ir).to_le_bytes());
+        bytes.extend(&fnv1a(&body).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    /// read a snapshot encode wrote, checking it was written in the format
+    /// and by a build this one can restore
+    pub fn decode(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(SnapshotError::Version(version));
+        }
+        let fingerprint_read = reader.u64()?;
+        if reader.u64()? != fnv1a(reader.bytes) {
+            return Err(SnapshotError::Checksum);
+        }
+
+        let flags = reader.take(1)?[0];
+        if flags > 0b11 {
+            return Err(SnapshotError::Malformed);
+        }
+        let mut snapshot = Snapshot {
+            hot_reload: flags & 1 != 0,
+            allow_inline_ir: flags & 0b10 != 0,
+            host_functions: reader.prototypes()?,
+            pieces: Vec::new(),
+        };
+        if fingerprint_read != fingerprint(snapshot.hot_reload, snapshot.allow_inline_ir) {
+            return Err(SnapshotError::Fingerprint);
+        }
+        for _ in 0..reader.len()? {
+            snapshot.pieces.push(Piece {
+                source: reader.string()?,
+                bitcode: reader.bytes()?.to_vec(),
+                linked: reader.prototypes()?,
+                defined: (0..reader.len()?)
+                    .map(|_| reader.string())
+                    .collect::<Result<_, _>>()?,
+            });
+        }
+        if !reader.bytes.is_empty() {
+            return Err(SnapshotError::Malformed);
+        }
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            hot_reload: true,
+            allow_inline_ir: false,
+            host_functions: vec![("halve".to_string(), 1)],
+            pieces: vec![Piece {
+                source: "🜙sq🜄x🜂x*x;".to_string(),
+                bitcode: b"BC\xc0\xde".to_vec(),
+                linked: vec![("halve".to_string(), 1)],
+                defined: vec!["sq".to_string()],
+            }],
+        }
+    }
+
+    /// bytes with the body replaced and the checksum fixed up to match
+    fn with_body(bytes: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut res = bytes[..20].to_vec();
+        res.extend(&fnv1a(body).to_le_bytes());
+        res.extend(body);
+        res
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        let bytes = snapshot().encode();
+        assert_eq!(Snapshot::decode(&bytes).unwrap(), snapshot());
+        let empty = Snapshot::default();
+        assert_eq!(Snapshot::decode(&empty.encode()).unwrap(), empty);
+    }
+
+    #[test]
+    fn bad_snapshots_are_typed() {
+        let bytes = snapshot().encode();
+        assert_eq!(
+            Snapshot::decode("🜙sq🜄x🜂x*x;".as_bytes()),
+            Err(SnapshotError::NotASnapshot)
+        );
+        assert_eq!(
+            Snapshot::decode(&bytes[..12]),
+            Err(SnapshotError::Truncated)
+        );
+
+        let mut version = bytes.clone();
+        version[8..12].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(Snapshot::decode(&version), Err(SnapshotError::Version(2)));
+
+        let mut other_build = bytes.clone();
+        other_build[12] ^= 1;
+        assert_eq!(
+            Snapshot::decode(&other_build),
+            Err(SnapshotError::Fingerprint)
+        );
+        // the fingerprint covers the kind of session, so flipping it is caught
+        // even with the checksum fixed up
+        let mut body = bytes[28..].to_vec();
+        body[0] = 0;
+        assert_eq!(
+            Snapshot::decode(&with_body(&bytes, &body)),
+            Err(SnapshotError::Fingerprint)
+        );
+
+        for flipped in &[28, 40, bytes.len() - 1] {
+            let mut corrupt = bytes.clone();
+            corrupt[*flipped] ^= 0x40;
+            assert_eq!(Snapshot::decode(&corrupt), Err(SnapshotError::Checksum));
+        }
+        assert_eq!(
+            Snapshot::decode(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Checksum)
+        );
+
+        let body = &bytes[28..];
+        assert_eq!(
+            Snapshot::decode(&with_body(&bytes, &body[..body.len() - 1])),
+            Err(SnapshotError::Truncated)
+        );
+        let mut trailing = body.to_vec();
+        trailing.push(0);
+        assert_eq!(
+            Snapshot::decode(&with_body(&bytes, &trailing)),
+            Err(SnapshotError::Malformed)
+        );
+    }
+}
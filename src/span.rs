@@ -0,0 +1,283 @@
+use std::{fmt, iter, ops::Range};
+
+/// which source a span is in, the index of the file in its plan
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Default)]
+pub struct FileId(pub u32);
+
+/// a range of bytes in one source
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Span {
+    pub file: FileId,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Span {
+    pub fn new(file: FileId, range: Range<usize>) -> Span {
+        Span {
+            file,
+            start: range.start as u32,
+            end: range.end as u32,
+        }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start as usize..self.end as usize
+    }
+}
+
+/// a span with the path of its source and the location it starts at, for
+/// pointing at it in a message
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct SourceSpan {
+    pub path: String,
+    pub span: Span,
+    pub start: Location,
+}
+
+impl fmt::Display for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.path, self.start)
+    }
+}
+
+/// a 1 based line and column - columns count unicode scalar values, so a
+/// character drawn as one glyph but built from several, like an e followed by
+/// a combining accent, takes up more than one column
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, PartialOrd, Ord)]
+pub struct Location {
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// where every line of a source starts, so offsets and locations convert in
+/// log time plus the length of the line - lines end at \n, \r\n or a lone \r
+pub struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<u32>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> LineIndex<'a> {
+        let bytes = text.as_bytes();
+        let mut starts = vec![0];
+        for (i, byte) in bytes.iter().enumerate() {
+            let ends_line = match byte {
+                b'\n' => true,
+                b'\r' => bytes.get(i + 1) != Some(&b'\n'),
+                _ => false,
+            };
+            if ends_line {
+                starts.push(i as u32 + 1);
+            }
+        }
+        LineIndex { text, starts }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// the text of a 0 based line without its line break
+    fn content(&self, line: usize) -> &'a str {
+        let start = self.starts[line] as usize;
+        let end = match self.starts.get(line + 1) {
+            Some(next) => *next as usize,
+            None => self.text.len(),
+        };
+        let content = &self.text[start..end];
+        content
+            .strip_suffix("\r\n")
+            .or_else(|| content.strip_suffix('\n'))
+            .or_else(|| content.strip_suffix('\r'))
+            .unwrap_or(content)
+    }
+
+    /// the text of a 1 based line without its line break, like the line of a
+    /// location
+    pub fn line(&self, line: u32) -> Option<&'a str> {
+        let line = line.checked_sub(1)? as usize;
+        match line < self.starts.len() {
+            true => Some(self.content(line)),
+            false => None,
+        }
+    }
+
+    /// the location of a byte offset, None past the end or inside a character
+    pub fn location(&self, offset: u32) -> Option<Location> {
+        let offset = offset as usize;
+        if !self.text.is_char_boundary(offset) {
+            return None;
+        }
+        let line = self
+            .starts
+            .partition_point(|start| *start as usize <= offset)
+            - 1;
+        let start = self.starts[line] as usize;
+        Some(Location {
+            line: line as u32 + 1,
+            column: self.text[start..offset].chars().count() as u32 + 1,
+        })
+    }
+
+    /// the byte offset of a location, which can be one column past the end of
+    /// its line but no further
+    pub fn offset(&self, location: Location) -> Option<u32> {
+        let line = location.line.checked_sub(1)? as usize;
+        let start = *self.starts.get(line)?;
+        let content = self.content(line);
+        let column = content
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(iter::once(content.len()))
+            .nth(location.column.checked_sub(1)? as usize)?;
+        Some(start + column as u32)
+    }
+}
+
+/// the sources of a compile one after another, each after a comment with
+/// its path, so they read as the one program they make up - with the range
+/// of the whole each takes up, so an offset into it maps back to its file
+pub struct SourceMap {
+    text: String,
+    ranges: Vec<Range<usize>>,
+}
+
+impl SourceMap {
+    pub fn new<'a>(sources: impl IntoIterator<Item = (&'a str, &'a str)>) -> SourceMap {
+        let mut text = String::new();
+        let mut ranges = Vec::new();
+        for (path, source) in sources {
+            if !text.is_empty() && !text.ends_with(&['\n', '\r'][..]) {
+                text.push('\n');
+            }
+            text += &format!("# --- {} ---\n", path);
+            let start = text.len();
+            text += source;
+            ranges.push(start..text.len());
+        }
+        SourceMap { text, ranges }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// the offset into the whole that span starts at
+    pub fn offset(&self, span: Span) -> Option<usize> {
+        let range = self.ranges.get(span.file.0 as usize)?;
+        Some(range.start + span.start as usize).filter(|offset| *offset <= range.end)
+    }
+
+    /// the file an offset into the whole is in and the offset in it, None in
+    /// the comment before a file
+    pub fn locate(&self, offset: usize) -> Option<(FileId, usize)> {
+        let file = self
+            .ranges
+            .iter()
+            .rposition(|range| range.start <= offset)?;
+        let range = &self.ranges[file];
+        match offset <= range.end {
+            true => Some((FileId(file as u32), offset - range.start)),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(line: u32, column: u32) -> Location {
+        Location { line, column }
+    }
+
+    #[test]
+    fn line_index_works() {
+        let text = "🜙f🜄x🜂\r\nx*2;\r🜹sin🜄é🜂;\n\nf🜄1🜂";
+        let index = LineIndex::new(text);
+        assert_eq!(index.line_count(), 5);
+        assert_eq!(index.location(0), Some(at(1, 1)));
+        assert_eq!(index.location(4), Some(at(1, 2)));
+        assert_eq!(index.location(1), None);
+        let second = text.find("x*").unwrap() as u32;
+        assert_eq!(index.location(second), Some(at(2, 1)));
+        assert_eq!(index.location(second - 1), Some(at(1, 7)));
+        let third = text.find('🜹').unwrap() as u32;
+        assert_eq!(index.location(third), Some(at(3, 1)));
+        assert_eq!(index.location(third - 1), Some(at(2, 5)));
+        let eof = text.len() as u32;
+        assert_eq!(index.location(eof), Some(at(5, 5)));
+        assert_eq!(index.location(eof + 1), None);
+
+        for (offset, _) in text.char_indices().chain(iter::once((text.len(), ' '))) {
+            let location = index.location(offset as u32).unwrap();
+            // the \n of a \r\n is a column past the \r, further along than
+            // offset goes
+            if offset as u32 != second - 1 {
+                assert_eq!(index.offset(location), Some(offset as u32), "{}", location);
+            }
+        }
+        assert_eq!(index.offset(at(1, 6)), Some(second - 2));
+        assert_eq!(index.offset(at(1, 7)), None);
+        assert_eq!(
+            index.offset(at(4, 1)),
+            index.offset(at(3, 9)).map(|o| o + 1)
+        );
+        assert_eq!(index.offset(at(6, 1)), None);
+        assert_eq!(index.offset(at(0, 1)), None);
+        assert_eq!(index.offset(at(1, 0)), None);
+        assert_eq!(at(3, 9).to_string(), "3:9");
+
+        assert_eq!(index.line(1), Some("🜙f🜄x🜂"));
+        assert_eq!(index.line(3), Some("🜹sin🜄é🜂;"));
+        assert_eq!(index.line(4), Some(""));
+        assert_eq!(index.line(5), Some("f🜄1🜂"));
+        assert_eq!(index.line(6), None);
+        assert_eq!(index.line(0), None);
+    }
+
+    #[test]
+    fn line_index_handles_trailing_newlines() {
+        let index = LineIndex::new("1;\n");
+        assert_eq!(index.line_count(), 2);
+        assert_eq!(index.location(3), Some(at(2, 1)));
+        assert_eq!(index.offset(at(2, 1)), Some(3));
+        let empty = LineIndex::new("");
+        assert_eq!(empty.location(0), Some(at(1, 1)));
+        assert_eq!(empty.offset(at(1, 1)), Some(0));
+
+        let span = Span::new(FileId(1), 1..3);
+        assert_eq!(span.range(), 1..3);
+        assert_eq!(LineIndex::new("1;\n").location(span.end), Some(at(2, 1)));
+    }
+
+    #[test]
+    fn source_map_works() {
+        let sources = [
+            ("a.wiz", "🜙sq🜄x🜂x*x;"),
+            ("<eval-1>", "sq🜄2🜂;\n"),
+            ("c.wiz", "1;"),
+        ];
+        let map = SourceMap::new(sources.iter().copied());
+        assert_eq!(
+            map.text(),
+            "# --- a.wiz ---\n🜙sq🜄x🜂x*x;\n# --- <eval-1> ---\nsq🜄2🜂;\n# --- c.wiz ---\n1;"
+        );
+        let call = Span::new(FileId(1), 0..2);
+        let offset = map.offset(call).unwrap();
+        assert_eq!(&map.text()[offset..offset + 2], "sq");
+        assert_eq!(map.locate(offset), Some((FileId(1), 0)));
+        assert_eq!(map.locate(map.text().len()), Some((FileId(2), 2)));
+        assert_eq!(map.locate(0), None);
+        assert_eq!(map.locate(offset - 1), None);
+        assert_eq!(map.offset(Span::new(FileId(3), 0..1)), None);
+        assert_eq!(map.offset(Span::new(FileId(2), 3..4)), None);
+    }
+}
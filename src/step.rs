@@ -0,0 +1,402 @@
+//! evaluating a top level expression one reduction at a time over the ast,
+//! for showing the order things happen in - each step reduces the leftmost
+//! innermost redex, a call to a definition expanding to its body with the
+//! arguments substituted and anything else, builtins and externs included,
+//! going straight to its value
+//!
+//! externs are evaluated when they're one of the libm functions std has,
+//! and angles are always in radians
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{ASTNode, Expression, Function, Prototype};
+use crate::builtins;
+
+/// the calls to definitions a trace expands before giving up, which a
+/// recursive function always reaches
+pub const DEFAULT_MAX_CALLS: usize = 64;
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum StepError {
+    #[error("unknown function {0}")]
+    UnknownFunction(String),
+    #[error("unknown variable {0}")]
+    UnknownVariable(String),
+    #[error("unknown operator {0}")]
+    UnknownOperator(String),
+    #[error("{0} takes {1} arguments but was given {2}")]
+    InvalidCall(String, usize, usize),
+    /// a host function std doesn't have, or one that returns nothing
+    #[error("can't step into the host function {0}")]
+    Host(String),
+    #[error("stopped after {0} calls")]
+    TooManyCalls(usize),
+    #[error("stopped")]
+    Aborted,
+    #[error("{0} can't be stepped")]
+    Unsteppable(String),
+}
+
+/// one state of a trace - the expression before a step, with the redex it
+/// reduces next, or the value it came out to
+#[derive(Debug, PartialEq, Clone)]
+pub struct Step {
+    pub expr: Expression,
+    path: Option<Vec<usize>>,
+}
+
+impl Step {
+    /// what reduces next, if anything does
+    pub fn redex(&self) -> Option<&Expression> {
+        self.path.as_ref().map(|path| at(&self.expr, path))
+    }
+}
+
+/// the expression with the redex in brackets
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => {
+                let redex = format!("⟦{}⟧", at(&self.expr, path));
+                replace(&self.expr, path, Expression::Variable(redex)).fmt(f)
+            }
+            None => self.expr.fmt(f),
+        }
+    }
+}
+
+/// the path to the child of expr that's reduced next, if it isn't a value
+fn redex(expr: &Expression) -> Option<Vec<usize>> {
+    let operands: Vec<&Expression> = match expr {
+        Expression::Literal(_) => return None,
+        Expression::Binary(_, lhs, rhs) => vec![lhs, rhs],
+        Expression::Call(_, args) => args.iter().collect(),
+        _ => return Some(Vec::new()),
+    };
+    for (i, operand) in operands.into_iter().enumerate() {
+        if let Some(mut path) = redex(operand) {
+            path.insert(0, i);
+            return Some(path);
+        }
+    }
+    Some(Vec::new())
+}
+
+fn at<'a>(expr: &'a Expression, path: &[usize]) -> &'a Expression {
+    match (expr, path) {
+        (_, []) => expr,
+        (Expression::Binary(_, lhs, _), [0, rest @ ..]) => at(lhs, rest),
+        (Expression::Binary(_, _, rhs), [_, rest @ ..]) => at(rhs, rest),
+        (Expression::Call(_, args), [i, rest @ ..]) => at(&args[*i], rest),
+        _ => unreachable!("paths only lead through operands"),
+    }
+}
+
+/// expr with the child at path replaced by with
+fn replace(expr: &Expression, path: &[usize], with: Expression) -> Expression {
+    match (expr, path) {
+        (_, []) => with,
+        (Expression::Binary(op, lhs, rhs), [0, rest @ ..]) => {
+            Expression::Binary(op.clone(), Box::new(replace(lhs, rest, with)), rhs.clone())
+        }
+        (Expression::Binary(op, lhs, rhs), [_, rest @ ..]) => {
+            Expression::Binary(op.clone(), lhs.clone(), Box::new(replace(rhs, rest, with)))
+        }
+        (Expression::Call(callee, args), [i, rest @ ..]) => {
+            let mut args = args.clone();
+            args[*i] = replace(&args[*i], rest, with);
+            Expression::Call(callee.clone(), args)
+        }
+        _ => unreachable!("paths only lead through operands"),
+    }
+}
+
+/// expr with each parameter replaced by its argument
+fn substitute(expr: &Expression, args: &HashMap<&str, f64>) -> Expression {
+    match expr {
+        Expression::Variable(name) => match args.get(name.as_str()) {
+            Some(value) => Expression::Literal(*value),
+            None => expr.clone(),
+        },
+        Expression::Binary(op, lhs, rhs) => Expression::Binary(
+            op.clone(),
+            Box::new(substitute(lhs, args)),
+            Box::new(substitute(rhs, args)),
+        ),
+        Expression::Call(callee, call_args) => Expression::Call(
+            callee.clone(),
+            call_args.iter().map(|arg| substitute(arg, args)).collect(),
+        ),
+        _ => expr.clone(),
+    }
+}
+
+/// the host functions std has, by their symbol
+fn host(symbol: &str, args: &[f64]) -> Option<f64> {
+    Some(match (symbol, args) {
+        ("sin", [x]) => x.sin(),
+        ("cos", [x]) => x.cos(),
+        ("tan", [x]) => x.tan(),
+        ("asin", [x]) => x.asin(),
+        ("acos", [x]) => x.acos(),
+        ("atan", [x]) => x.atan(),
+        ("atan2", [y, x]) => y.atan2(*x),
+        ("sinh", [x]) => x.sinh(),
+        ("cosh", [x]) => x.cosh(),
+        ("tanh", [x]) => x.tanh(),
+        ("exp", [x]) => x.exp(),
+        ("exp2", [x]) => x.exp2(),
+        ("log", [x]) => x.ln(),
+        ("log2", [x]) => x.log2(),
+        ("log10", [x]) => x.log10(),
+        ("sqrt", [x]) => x.sqrt(),
+        ("cbrt", [x]) => x.cbrt(),
+        ("pow", [x, y]) => x.powf(*y),
+        ("hypot", [x, y]) => x.hypot(*y),
+        ("fabs", [x]) => x.abs(),
+        ("floor", [x]) => x.floor(),
+        ("ceil", [x]) => x.ceil(),
+        ("round", [x]) => x.round(),
+        ("trunc", [x]) => x.trunc(),
+        ("fmod", [x, y]) => x % y,
+        ("fmin", [x, y]) => x.min(*y),
+        ("fmax", [x, y]) => x.max(*y),
+        ("copysign", [x, y]) => x.copysign(*y),
+        ("fma", [x, y, z]) => x.mul_add(*y, *z),
+        _ => return None,
+    })
+}
+
+/// what a program defines that a trace can call
+pub struct Stepper<'a> {
+    definitions: HashMap<&'a str, &'a Function>,
+    externs: HashMap<&'a str, &'a Prototype>,
+    tables: HashMap<&'a str, &'a [f64]>,
+    /// the tolerance of approx_eq
+    pub epsilon: f64,
+    pub max_calls: usize,
+}
+
+impl<'a> Stepper<'a> {
+    /// the definitions, externs and loaded tables of a planned ast - the
+    /// first of each name wins, like in codegen
+    pub fn new(ast: &'a [ASTNode]) -> Stepper<'a> {
+        let mut stepper = Stepper {
+            definitions: HashMap::new(),
+            externs: HashMap::new(),
+            tables: HashMap::new(),
+            epsilon: builtins::DEFAULT_EPSILON,
+            max_calls: DEFAULT_MAX_CALLS,
+        };
+        for node in ast {
+            match node {
+                ASTNode::Function(func) if func.prototype.name != "lambda" => {
+                    stepper
+                        .definitions
+                        .entry(&func.prototype.name)
+                        .or_insert(func);
+                }
+                ASTNode::Extern(proto) => {
+                    stepper.externs.entry(&proto.name).or_insert(proto);
+                }
+                ASTNode::Table(table) => {
+                    if let Some(values) = &table.values {
+                        stepper.tables.entry(&table.name).or_insert(values);
+                    }
+                }
+                _ => (),
+            }
+        }
+        stepper
+    }
+
+    /// every state expr goes through, ending with its value - calls has
+    /// each state as it's made, and stops the trace early by returning false
+    pub fn trace(
+        &self,
+        expr: &Expression,
+        mut visit: impl FnMut(&Step) -> bool,
+    ) -> Result<f64, StepError> {
+        let mut expr = expr.clone();
+        let mut calls = 0;
+        loop {
+            let path = redex(&expr);
+            let step = Step { expr, path };
+            if !visit(&step) {
+                return Err(StepError::Aborted);
+            }
+            let path = match step.path {
+                Some(path) => path,
+                None => match step.expr {
+                    Expression::Literal(value) => return Ok(value),
+                    _ => unreachable!("only literals have no redex"),
+                },
+            };
+            let reduced = self.reduce(at(&step.expr, &path), &mut calls)?;
+            expr = replace(&step.expr, &path, reduced);
+        }
+    }
+
+    /// a redex, whose operands are all literals, reduced one step
+    fn reduce(&self, redex: &Expression, calls: &mut usize) -> Result<Expression, StepError> {
+        let literal = |expr: &Expression| match expr {
+            Expression::Literal(value) => *value,
+            _ => unreachable!("a redex's operands are literals"),
+        };
+        let (callee, args) = match redex {
+            Expression::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (literal(lhs), literal(rhs));
+                return Ok(Expression::Literal(match op.as_str() {
+                    "+" => lhs + rhs,
+                    "-" => lhs - rhs,
+                    "*" => lhs * rhs,
+                    "/" => lhs / rhs,
+                    _ => return Err(StepError::UnknownOperator(op.clone())),
+                }));
+            }
+            Expression::Call(callee, args) => (
+                callee.as_str(),
+                args.iter().map(literal).collect::<Vec<_>>(),
+            ),
+            Expression::Variable(name) => return Err(StepError::UnknownVariable(name.clone())),
+            expr => return Err(StepError::Unsteppable(expr.to_string())),
+        };
+        let check_arity = |arity: usize| {
+            if arity == args.len() {
+                Ok(())
+            } else {
+                Err(StepError::InvalidCall(
+                    callee.to_string(),
+                    arity,
+                    args.len(),
+                ))
+            }
+        };
+
+        if let Some(func) = self.definitions.get(callee) {
+            check_arity(func.prototype.args.len())?;
+            *calls += 1;
+            if *calls > self.max_calls {
+                return Err(StepError::TooManyCalls(self.max_calls));
+            }
+            let params = func.prototype.args.iter().map(String::as_str);
+            return Ok(substitute(&func.body, &params.zip(args).collect()));
+        }
+        if let Some(proto) = self.externs.get(callee) {
+            check_arity(proto.args.len())?;
+            return match host(proto.symbol(), &args) {
+                Some(value) if !proto.void => Ok(Expression::Literal(value)),
+                _ => Err(StepError::Host(proto.symbol().to_string())),
+            };
+        }
+        if let Some(values) = self.tables.get(callee) {
+            check_arity(1)?;
+            // truncated, with nan out of bounds, like the compiled lookup
+            let i = args[0];
+            let value = if i >= 0.0 && i < values.len() as f64 {
+                values[i as usize]
+            } else {
+                f64::NAN
+            };
+            return Ok(Expression::Literal(value));
+        }
+        if let Some(values) = callee
+            .strip_suffix("_len")
+            .and_then(|table| self.tables.get(table))
+        {
+            check_arity(0)?;
+            return Ok(Expression::Literal(values.len() as f64));
+        }
+
+        let value = match (callee, args.as_slice()) {
+            (_, []) if builtins::constant(callee).is_some() => builtins::constant(callee).unwrap(),
+            ("approx_eq", [a, b]) => builtins::approx_eq(*a, *b, self.epsilon) as u8 as f64,
+            ("approx_eq_eps", [a, b, eps]) => builtins::approx_eq(*a, *b, *eps) as u8 as f64,
+            ("min", [a, b]) => a.min(*b),
+            ("max", [a, b]) => a.max(*b),
+            ("clamp", [x, lo, hi]) => x.max(*lo).min(*hi),
+            ("abs", [x]) => x.abs(),
+            _ => {
+                let arity = builtins::comparison_arity(callee)
+                    .or_else(|| builtins::intrinsic_params(callee).map(<[_]>::len))
+                    .or_else(|| builtins::constant(callee).map(|_| 0));
+                return match arity {
+                    Some(arity) => Err(StepError::InvalidCall(
+                        callee.to_string(),
+                        arity,
+                        args.len(),
+                    )),
+                    None => Err(StepError::UnknownFunction(callee.to_string())),
+                };
+            }
+        };
+        Ok(Expression::Literal(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn trace(source: &str) -> (Vec<String>, Result<f64, StepError>) {
+        let ast = Parser::default().parse_str(source).unwrap();
+        let entry = match ast.last() {
+            Some(ASTNode::Function(func)) => func.body.clone(),
+            _ => unreachable!(),
+        };
+        let mut stepper = Stepper::new(&ast);
+        stepper.max_calls = 3;
+        let mut states = Vec::new();
+        let res = stepper.trace(&entry, |step| {
+            states.push(step.to_string());
+            true
+        });
+        (states, res)
+    }
+
+    #[test]
+    fn arithmetic_steps_innermost_first() {
+        let (states, res) = trace("🜄1 + 2🜂 * 🜄10 - 🜄2 + 2🜂🜂 / 4;");
+        assert_eq!(
+            states,
+            [
+                "⟦1 + 2⟧ * 🜄10 - 🜄2 + 2🜂🜂 / 4",
+                "3 * 🜄10 - ⟦2 + 2⟧🜂 / 4",
+                "3 * ⟦10 - 4⟧ / 4",
+                "3 * ⟦6 / 4⟧",
+                "⟦3 * 1.5⟧",
+                "4.5",
+            ]
+        );
+        assert_eq!(res, Ok(4.5));
+    }
+
+    #[test]
+    fn calls_expand_to_their_bodies() {
+        let source = "🜹sqrt🜄x🜂;🜙sq🜄x🜂x * x;🜙hyp🜄a🜌b🜂sqrt🜄sq🜄a🜂 + sq🜄b🜂🜂;hyp🜄3🜌max🜄1🜌4🜂🜂;";
+        let (states, res) = trace(source);
+        assert_eq!(
+            states,
+            [
+                "hyp🜄3🜌⟦max🜄1🜌4🜂⟧🜂",
+                "⟦hyp🜄3🜌4🜂⟧",
+                "sqrt🜄⟦sq🜄3🜂⟧ + sq🜄4🜂🜂",
+                "sqrt🜄⟦3 * 3⟧ + sq🜄4🜂🜂",
+                "sqrt🜄9 + ⟦sq🜄4🜂⟧🜂",
+                "sqrt🜄9 + ⟦4 * 4⟧🜂",
+                "sqrt🜄⟦9 + 16⟧🜂",
+                "⟦sqrt🜄25🜂⟧",
+                "5",
+            ]
+        );
+        assert_eq!(res, Ok(5.0));
+
+        // nothing stops a recursive function but the limit
+        let (states, res) = trace("🜙f🜄n🜂f🜄n - 1🜂;f🜄3🜂;");
+        assert_eq!(states[..3], ["⟦f🜄3🜂⟧", "f🜄⟦3 - 1⟧🜂", "⟦f🜄2🜂⟧"]);
+        assert_eq!(res, Err(StepError::TooManyCalls(3)));
+        let (_, res) = trace("🜹printd🜄x🜂;printd🜄1🜂;");
+        assert_eq!(res, Err(StepError::Host("printd".to_string())));
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::ast::{ASTNode, Table};
+use crate::pass::{Diagnostics, Pass, PassError};
+
+/// the most values a single table may hold
+pub const MAX_TABLE_LEN: usize = 1 << 20;
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum TableError {
+    #[error("failed to read table {0}: {1}")]
+    Read(String, String),
+    #[error("table {0} is {1} bytes, which isn't a whole number of f64s")]
+    PartialValue(String, usize),
+    #[error("table {0} has {1} values but at most {2} are allowed")]
+    TooLarge(String, usize, usize),
+    #[error("table {0} line {1}: '{2}' is not a number")]
+    Malformed(String, usize, String),
+}
+
+/// how table files are encoded
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TableFormat {
+    /// packed little endian f64s
+    F64,
+    /// numbers separated by commas or newlines
+    Csv,
+}
+
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+#[error("unknown table format {0}, expected f64 or csv")]
+pub struct UnknownTableFormat(String);
+
+impl FromStr for TableFormat {
+    type Err = UnknownTableFormat;
+
+    fn from_str(format: &str) -> Result<TableFormat, UnknownTableFormat> {
+        match format {
+            "f64" => Ok(TableFormat::F64),
+            "csv" => Ok(TableFormat::Csv),
+            _ => Err(UnknownTableFormat(format.to_string())),
+        }
+    }
+}
+
+/// where table files come from - embedders can implement this to serve
+/// tables from somewhere other than the filesystem
+pub trait Loader {
+    fn load(&self, path: &str) -> io::Result<Vec<u8>>;
+
+    /// the size of the file in bytes if it's known without reading it, so
+    /// oversized tables are turned down before they're loaded
+    fn size(&self, _path: &str) -> Option<u64> {
+        None
+    }
+}
+
+/// loads tables relative to a directory, normally the one the source is in
+pub struct FsLoader {
+    pub dir: PathBuf,
+}
+
+impl FsLoader {
+    /// a loader for the tables of the source file at path
+    pub fn beside(path: &Path) -> FsLoader {
+        FsLoader {
+            dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        }
+    }
+}
+
+impl Loader for FsLoader {
+    fn load(&self, path: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.dir.join(path))
+    }
+
+    fn size(&self, path: &str) -> Option<u64> {
+        fs::metadata(self.dir.join(path))
+            .ok()
+            .map(|meta| meta.len())
+    }
+}
+
+/// a table of size bytes in format that would hold more than MAX_TABLE_LEN
+/// values - only f64 files say how many they hold up front
+fn check_size(path: &str, size: u64, format: TableFormat) -> Result<(), TableError> {
+    let count = (size / 8) as usize;
+    match format {
+        TableFormat::F64 if count > MAX_TABLE_LEN => {
+            Err(TableError::TooLarge(path.to_string(), count, MAX_TABLE_LEN))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn decode(path: &str, bytes: &[u8], format: TableFormat) -> Result<Vec<f64>, TableError> {
+    let values = match format {
+        TableFormat::F64 => {
+            check_size(path, bytes.len() as u64, format)?;
+            let chunks = bytes.chunks_exact(8);
+            if !chunks.remainder().is_empty() {
+                return Err(TableError::PartialValue(path.to_string(), bytes.len()));
+            }
+            let mut values = Vec::with_capacity(bytes.len() / 8);
+            for chunk in chunks {
+                let mut value = [0; 8];
+                value.copy_from_slice(chunk);
+                values.push(f64::from_le_bytes(value));
+            }
+            values
+        }
+        TableFormat::Csv => {
+            let text = String::from_utf8_lossy(bytes);
+            let mut values = Vec::new();
+            // past the limit fields are only counted, for the error
+            let mut count = 0;
+            for (i, line) in text.lines().enumerate() {
+                for field in line.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+                    count += 1;
+                    match field.parse() {
+                        Ok(_) if count > MAX_TABLE_LEN => (),
+                        Ok(value) => values.push(value),
+                        Err(_) => {
+                            return Err(TableError::Malformed(
+                                path.to_string(),
+                                i + 1,
+                                field.to_string(),
+                            ))
+                        }
+                    }
+                }
+            }
+            if count > MAX_TABLE_LEN {
+                return Err(TableError::TooLarge(path.to_string(), count, MAX_TABLE_LEN));
+            }
+            values
+        }
+    };
+    Ok(values)
+}
+
+/// fill in the values of the table if it hasn't been loaded yet
+pub fn load_table(
+    table: &mut Table,
+    loader: &dyn Loader,
+    format: TableFormat,
+) -> Result<(), TableError> {
+    if table.values.is_some() {
+        return Ok(());
+    }
+    if let Some(size) = loader.size(&table.path) {
+        check_size(&table.path, size, format)?;
+    }
+    let bytes = loader
+        .load(&table.path)
+        .map_err(|e| TableError::Read(table.path.clone(), e.to_string()))?;
+    table.values = Some(decode(&table.path, &bytes, format)?);
+    Ok(())
+}
+
+/// fill in the values of every table that hasn't been loaded yet
+pub fn load_tables(
+    ast: &mut [ASTNode],
+    loader: &dyn Loader,
+    format: TableFormat,
+) -> Result<(), TableError> {
+    for node in ast.iter_mut() {
+        if let ASTNode::Table(table) = node {
+            load_table(table, loader, format)?;
+        }
+    }
+    Ok(())
+}
+
+/// loads tables as a pass, so it runs before anything that needs their values
+pub struct LoadTables {
+    pub loader: Box<dyn Loader>,
+    pub format: TableFormat,
+}
+
+impl Pass for LoadTables {
+    fn name(&self) -> &str {
+        "load-tables"
+    }
+
+    fn run(&mut self, ast: &mut Vec<ASTNode>, _: &mut Diagnostics) -> Result<(), PassError> {
+        Ok(load_tables(ast, self.loader.as_ref(), self.format)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use inkwell::context::Context;
+
+    use super::*;
+    use crate::{
+        ast::Table,
+        codegen::Codegen,
+        engine::{Engine, EngineError, EngineKind, OptLevel},
+        parser::Parser,
+        runtime::RuntimeError,
+    };
+
+    struct MemoryLoader(HashMap<&'static str, Vec<u8>>);
+
+    /// a loader of files too big to read
+    struct HugeLoader;
+
+    impl Loader for HugeLoader {
+        fn load(&self, _: &str) -> io::Result<Vec<u8>> {
+            panic!("an oversized table was read")
+        }
+
+        fn size(&self, _: &str) -> Option<u64> {
+            Some(1 << 40)
+        }
+    }
+
+    impl Loader for MemoryLoader {
+        fn load(&self, path: &str) -> io::Result<Vec<u8>> {
+            match self.0.get(path) {
+                Some(bytes) => Ok(bytes.clone()),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, "not found")),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_works() {
+        let bytes: Vec<u8> = [1.5f64, -2.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        assert_eq!(decode("a", &bytes, TableFormat::F64), Ok(vec![1.5, -2.0]));
+        assert_eq!(
+            decode("a", &bytes[..9], TableFormat::F64),
+            Err(TableError::PartialValue("a".to_string(), 9))
+        );
+        assert_eq!(
+            decode("b", b"1, 2.5,\n3\n\n", TableFormat::Csv),
+            Ok(vec![1.0, 2.5, 3.0])
+        );
+        assert_eq!(
+            decode("b", b"1\n2, two", TableFormat::Csv),
+            Err(TableError::Malformed("b".to_string(), 2, "two".to_string()))
+        );
+        let big = vec![0; (MAX_TABLE_LEN + 1) * 8];
+        assert_eq!(
+            decode("c", &big, TableFormat::F64),
+            Err(TableError::TooLarge(
+                "c".to_string(),
+                MAX_TABLE_LEN + 1,
+                MAX_TABLE_LEN
+            ))
+        );
+        let big = "0\n".repeat(MAX_TABLE_LEN + 2);
+        assert_eq!(
+            decode("d", big.as_bytes(), TableFormat::Csv),
+            Err(TableError::TooLarge(
+                "d".to_string(),
+                MAX_TABLE_LEN + 2,
+                MAX_TABLE_LEN
+            ))
+        );
+
+        // the size is checked before anything is read
+        let mut ast = Parser::default()
+            .parse_str(r#"table huge from "huge.f64";"#)
+            .unwrap();
+        assert_eq!(
+            load_tables(&mut ast, &HugeLoader, TableFormat::F64),
+            Err(TableError::TooLarge(
+                "huge.f64".to_string(),
+                1 << 37,
+                MAX_TABLE_LEN
+            ))
+        );
+    }
+
+    #[test]
+    fn traps_stop_the_whole_call() {
+        let source = r#"table coeffs from "coeffs.csv";
+                        🜙inner🜄i🜂coeffs🜄i🜂 * 2;
+                        🜙outer🜄i🜂inner🜄i🜂 + inner🜄i - 1🜂 + coeffs🜄0 - 1🜂;
+                        outer🜄1🜂;outer🜄3🜂;inner🜄0🜂;"#;
+        let mut ast = Parser::default().parse_str(source).unwrap();
+        let mut files = HashMap::new();
+        files.insert("coeffs.csv", b"0.5, 1.5, 2.5".to_vec());
+        load_tables(&mut ast, &MemoryLoader(files), TableFormat::Csv).unwrap();
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.check_bounds = true;
+        codegen.codegen(&ast).unwrap();
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        // the first trap of a run is the one reported, however deep it was
+        let results: Vec<_> = engine.run_all(40).into_iter().map(|(_, res)| res).collect();
+        assert_eq!(
+            results,
+            [
+                Err(EngineError::Trap(RuntimeError::OutOfBounds {
+                    table: "coeffs".to_string(),
+                    index: -1.0
+                })),
+                Err(EngineError::Trap(RuntimeError::OutOfBounds {
+                    table: "coeffs".to_string(),
+                    index: 3.0
+                })),
+                Ok(1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn table_works() {
+        let source = r#"table coeffs from "coeffs.csv";table empty from "empty.csv";
+                        🜙table🜄from🜂from;"#;
+        let mut ast = Parser::default().parse_str(source).unwrap();
+        assert_eq!(
+            ast[0],
+            ASTNode::Table(Table {
+                name: "coeffs".to_string(),
+                path: "coeffs.csv".to_string(),
+                values: None,
+            })
+        );
+        let mut files = HashMap::new();
+        files.insert("coeffs.csv", b"0.5, 1.5, 2.5".to_vec());
+        files.insert("empty.csv", Vec::new());
+        let loader = MemoryLoader(files);
+        load_tables(&mut ast, &loader, TableFormat::Csv).unwrap();
+
+        let context = Context::create();
+        let mut codegen = Codegen::new(&context);
+        codegen.codegen(&ast).unwrap();
+        let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+        assert_eq!(engine.call("coeffs_len", &[]), Ok(3.0));
+        assert_eq!(engine.call("coeffs", &[0.0]), Ok(0.5));
+        assert_eq!(engine.call("coeffs", &[2.9]), Ok(2.5));
+        for i in [-1.0, 3.0, f64::NAN].iter() {
+            assert!(engine.call("coeffs", &[*i]).unwrap().is_nan());
+        }
+        assert_eq!(engine.call("empty_len", &[]), Ok(0.0));
+        assert!(engine.call("empty", &[0.0]).unwrap().is_nan());
+        assert_eq!(engine.call("table", &[4.0]), Ok(4.0));
+
+        let mut codegen = Codegen::new(&context);
+        codegen.check_bounds = true;
+        codegen.codegen(&ast).unwrap();
+        let ir = codegen.module.print_to_string().to_string();
+        assert_eq!(ir.matches("call double @wizarding.trap(").count(), 2);
+        for kind in [EngineKind::Jit, EngineKind::Interpreter].iter() {
+            let engine = Engine::new(&codegen, *kind, OptLevel::None).unwrap();
+            assert_eq!(engine.call("coeffs", &[1.0]), Ok(1.5));
+            let out_of_bounds = |table: &str, index| {
+                Err(EngineError::Trap(RuntimeError::OutOfBounds {
+                    table: table.to_string(),
+                    index,
+                }))
+            };
+            assert_eq!(engine.call("coeffs", &[3.0]), out_of_bounds("coeffs", 3.0));
+            assert_eq!(engine.call("empty", &[0.0]), out_of_bounds("empty", 0.0));
+            // the trap doesn't outlive the call that set it off
+            assert_eq!(engine.call("coeffs", &[2.0]), Ok(2.5));
+            assert!(matches!(
+                engine.call("coeffs", &[f64::NAN]),
+                Err(EngineError::Trap(RuntimeError::OutOfBounds { index, .. })) if index.is_nan()
+            ));
+        }
+
+        let mut ast = Parser::default()
+            .parse_str(r#"table missing from "missing.f64";"#)
+            .unwrap();
+        assert_eq!(
+            load_tables(&mut ast, &loader, TableFormat::F64),
+            Err(TableError::Read(
+                "missing.f64".to_string(),
+                "not found".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,345 @@
+use std::fmt;
+
+use inkwell::context::Context;
+
+use crate::ast::{ASTNode, Expression, Function, MathMode, Prototype, Test};
+use crate::builtins;
+use crate::codegen::{Codegen, CodegenError};
+use crate::diagnostic::{Diagnostic, Sink};
+use crate::engine::{Engine, EngineError, EngineKind, OptLevel};
+use crate::lint;
+use crate::plan::Plan;
+use crate::resolve;
+use crate::span::SourceSpan;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TestOptions {
+    /// only run the tests whose name contains this
+    pub filter: Option<String>,
+    /// the tolerance results are compared with, like approx_eq's
+    pub epsilon: f64,
+}
+
+impl Default for TestOptions {
+    fn default() -> Self {
+        TestOptions {
+            filter: None,
+            epsilon: builtins::DEFAULT_EPSILON,
+        }
+    }
+}
+
+/// how a test came out
+#[derive(Debug, PartialEq, Clone)]
+pub enum Outcome {
+    Passed,
+    Failed {
+        value: f64,
+        expected: f64,
+    },
+    /// it couldn't be compiled or run
+    Errored(Diagnostic),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "pass"),
+            Outcome::Failed { .. } => write!(f, "fail"),
+            Outcome::Errored(_) => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TestReport {
+    pub name: String,
+    /// where the test is written, None when it was added after parsing
+    pub span: Option<SourceSpan>,
+    pub outcome: Outcome,
+}
+
+/// the symbols of the two functions a test is compiled into
+fn symbols(index: usize) -> (String, String) {
+    (format!("test.{}", index), format!("test.{}.expect", index))
+}
+
+fn entry(name: String, body: Expression) -> ASTNode {
+    ASTNode::Function(Function {
+        prototype: Prototype {
+            name,
+            args: vec![],
+            link_name: None,
+            defaults: vec![],
+            void: false,
+            math: MathMode::default(),
+        },
+        body,
+    })
+}
+
+/// resolve the test against the rest of the program and compile its
+/// expression and expected value into their own functions
+fn compile_test(
+    codegen: &mut Codegen,
+    ast: &[ASTNode],
+    defined: &[&str],
+    test: &Test,
+    index: usize,
+) -> Result<(), Diagnostic> {
+    let mut test = test.clone();
+    for expr in [&mut test.expr, &mut test.expected].iter_mut() {
+        resolve::resolve_expression(ast, expr).map_err(|e| Diagnostic::error("resolve", e))?;
+    }
+    if !lint::is_constant(&test.expected, defined) {
+        return Err(Diagnostic::error(
+            "test",
+            "the expected value has to be a constant",
+        ));
+    }
+    let (symbol, expect_symbol) = symbols(index);
+    for (symbol, body) in [(symbol, test.expr), (expect_symbol, test.expected)].iter() {
+        codegen
+            .codegen_node(&entry(symbol.clone(), body.clone()))
+            .map_err(|e| Diagnostic::error("codegen", e))?;
+    }
+    Ok(())
+}
+
+fn run_test(engine: &Engine, index: usize, epsilon: f64) -> Result<Outcome, EngineError> {
+    let (symbol, expect_symbol) = symbols(index);
+    let value = engine.run_entry(&symbol)?;
+    let expected = engine.run_entry(&expect_symbol)?;
+    Ok(match builtins::approx_eq(value, expected, epsilon) {
+        true => Outcome::Passed,
+        false => Outcome::Failed { value, expected },
+    })
+}
+
+/// compile the program and run each of its tests that the filter lets
+/// through with the jit, in source order - the rest of the program only
+/// fails the run when it doesn't compile, while a test that doesn't only
+/// fails itself
+pub fn run(plan: &Plan, options: &TestOptions) -> Result<Vec<TestReport>, CodegenError> {
+    let context = Context::create();
+    let mut codegen = Codegen::new(&context);
+    codegen.epsilon = options.epsilon;
+    codegen.codegen(&plan.ast)?;
+
+    let spans = plan.item_spans();
+    let tests = plan
+        .ast
+        .iter()
+        .zip(spans)
+        .filter_map(|(node, span)| match node {
+            ASTNode::Test(test) => Some((test, span)),
+            _ => None,
+        });
+    let selected = tests.filter(|(test, _)| match &options.filter {
+        Some(filter) => test.name.contains(filter.as_str()),
+        None => true,
+    });
+    let defined = plan.definitions();
+    let compiled: Vec<(&Test, Option<SourceSpan>, Result<(), Diagnostic>)> = selected
+        .enumerate()
+        .map(|(i, (test, span))| {
+            let compiled = compile_test(&mut codegen, &plan.ast, &defined, test, i);
+            (test, span, compiled)
+        })
+        .collect();
+
+    let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None);
+    Ok(compiled
+        .into_iter()
+        .enumerate()
+        .map(|(i, (test, span, compiled))| {
+            let outcome = match (compiled, &engine) {
+                (Err(diagnostic), _) => Outcome::Errored(diagnostic),
+                (Ok(()), Ok(engine)) => run_test(engine, i, options.epsilon)
+                    .unwrap_or_else(|e| Outcome::Errored(Diagnostic::error("run", e))),
+                (Ok(()), Err(e)) => Outcome::Errored(Diagnostic::error("engine", e)),
+            };
+            TestReport {
+                name: test.name.clone(),
+                span,
+                outcome,
+            }
+        })
+        .collect())
+}
+
+/// the number of reports with each outcome, passed, failed and errored
+pub fn counts(reports: &[TestReport]) -> (usize, usize, usize) {
+    let mut res = (0, 0, 0);
+    for report in reports {
+        match report.outcome {
+            Outcome::Passed => res.0 += 1,
+            Outcome::Failed { .. } => res.1 += 1,
+            Outcome::Errored(_) => res.2 += 1,
+        }
+    }
+    res
+}
+
+/// a line for each test, the totals and then the diagnostics of the tests
+/// that errored, each at the test's span or at path and its name without one
+pub fn summarize(path: &str, reports: &[TestReport]) -> String {
+    let mut res = String::new();
+    let mut sink = Sink::default();
+    for report in reports {
+        res += &format!("{} {}", report.outcome, report.name);
+        match &report.outcome {
+            Outcome::Failed { value, expected } => {
+                res += &format!(": {} isn't close to {}", value, expected)
+            }
+            Outcome::Errored(diagnostic) => {
+                let location = match &report.span {
+                    Some(span) => span.to_string(),
+                    None => format!("{}: test \"{}\"", path, report.name),
+                };
+                sink.report(&location, diagnostic.clone())
+            }
+            Outcome::Passed => (),
+        }
+        res += "\n";
+    }
+    let (passed, failed, errored) = counts(reports);
+    res += &format!(
+        "{} tests: {} passed, {} failed, {} errored",
+        reports.len(),
+        passed,
+        failed,
+        errored
+    );
+    if !sink.is_empty() {
+        res += "\n";
+        res += &sink.render();
+    }
+    res
+}
+
+/// json has no nan or infinities, so those are null
+fn json_number(value: f64) -> String {
+    match value.is_finite() {
+        true => format!("{:?}", value),
+        false => "null".to_string(),
+    }
+}
+
+pub fn to_json(reports: &[TestReport]) -> String {
+    let tests: Vec<String> = reports
+        .iter()
+        .map(|report| {
+            let details = match &report.outcome {
+                Outcome::Passed => String::new(),
+                Outcome::Failed { value, expected } => format!(
+                    ",\"value\":{},\"expected\":{}",
+                    json_number(*value),
+                    json_number(*expected)
+                ),
+                Outcome::Errored(diagnostic) => {
+                    format!(",\"diagnostic\":{}", diagnostic.to_json())
+                }
+            };
+            let location = match &report.span {
+                Some(span) => format!(",\"location\":{:?}", span.to_string()),
+                None => String::new(),
+            };
+            format!(
+                "{{\"name\":{:?}{},\"status\":\"{}\"{}}}",
+                report.name, location, report.outcome, details
+            )
+        })
+        .collect();
+    let (passed, failed, errored) = counts(reports);
+    format!(
+        "{{\"tests\":[{}],\"passed\":{},\"failed\":{},\"errored\":{}}}",
+        tests.join(","),
+        passed,
+        failed,
+        errored
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::CompileOptions;
+
+    const SOURCE: &str = "🜙sq🜄x🜂x*x;
+test \"squares\" sq🜄3🜂 expect 9;
+test \"close enough\" 0.1 + 0.2 expect 0.3;
+test \"wrong\" sq🜄2🜂 expect 5;
+test \"unknown\" cube🜄2🜂 expect 8;
+test \"not constant\" 1 expect sq🜄1🜂;
+sq🜄4🜂;";
+
+    fn run_source(options: &TestOptions) -> Vec<TestReport> {
+        let plan = Plan::new("sq.wiz", SOURCE, &mut CompileOptions::default()).unwrap();
+        assert_eq!(plan.entries(), 1);
+        run(&plan, options).unwrap()
+    }
+
+    #[test]
+    fn run_works() {
+        let reports = run_source(&TestOptions::default());
+        let outcomes: Vec<(&str, &Outcome)> = reports
+            .iter()
+            .map(|report| (report.name.as_str(), &report.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("squares", &Outcome::Passed),
+                ("close enough", &Outcome::Passed),
+                (
+                    "wrong",
+                    &Outcome::Failed {
+                        value: 4.0,
+                        expected: 5.0
+                    }
+                ),
+                (
+                    "unknown",
+                    &Outcome::Errored(Diagnostic::error("codegen", "unknown function cube"))
+                ),
+                (
+                    "not constant",
+                    &Outcome::Errored(Diagnostic::error(
+                        "test",
+                        "the expected value has to be a constant"
+                    ))
+                ),
+            ]
+        );
+        assert_eq!(counts(&reports), (2, 1, 2));
+        let summary = summarize("sq.wiz", &reports);
+        assert!(summary.contains("\nfail wrong: 4 isn't close to 5\n"));
+        assert!(summary.contains("5 tests: 2 passed, 1 failed, 2 errored\n"));
+        assert!(summary.contains("sq.wiz:5:1: error[codegen]: unknown function cube\n"));
+        assert!(to_json(&reports).contains("{\"name\":\"unknown\",\"location\":\"sq.wiz:5:1\","));
+        assert!(to_json(&reports).ends_with("],\"passed\":2,\"failed\":1,\"errored\":2}"));
+    }
+
+    #[test]
+    fn run_filters_and_uses_epsilon() {
+        let options = TestOptions {
+            filter: Some("o".to_string()),
+            epsilon: 0.0,
+        };
+        let reports = run_source(&options);
+        let names: Vec<(&str, String)> = reports
+            .iter()
+            .map(|report| (report.name.as_str(), report.outcome.to_string()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("close enough", "fail".to_string()),
+                ("wrong", "fail".to_string()),
+                ("unknown", "error".to_string()),
+                ("not constant", "error".to_string()),
+            ]
+        );
+    }
+}
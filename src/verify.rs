@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::{ASTNode, Expression};
+use crate::builtins;
+use crate::engine::{self, Engine, EngineError};
+
+/// the ulps apart an entry's results may be before they count as diverged
+/// unless another tolerance is asked for
+pub const DEFAULT_ULP_TOLERANCE: u64 = 0;
+
+/// how an entry came out at both levels
+#[derive(Debug, PartialEq, Clone)]
+pub enum Verdict {
+    Agreed,
+    Diverged {
+        baseline: f64,
+        optimized: f64,
+        ulps: u64,
+    },
+    /// not run, since it calls the named function that can have effects
+    Skipped(String),
+    Failed(EngineError),
+}
+
+/// the check of one top level expression
+#[derive(Debug, PartialEq, Clone)]
+pub struct EntryCheck {
+    pub snippet: String,
+    pub verdict: Verdict,
+}
+
+impl fmt::Display for EntryCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}) ", self.snippet)?;
+        match &self.verdict {
+            Verdict::Agreed => write!(f, "agreed"),
+            Verdict::Diverged {
+                baseline,
+                optimized,
+                ulps,
+            } => write!(
+                f,
+                "diverged: {} unoptimized but {} optimized, {} ulps apart",
+                baseline, optimized, ulps
+            ),
+            Verdict::Skipped(callee) => write!(f, "skipped, it calls {} which isn't pure", callee),
+            Verdict::Failed(err) => write!(f, "failed: {}", err),
+        }
+    }
+}
+
+/// the first call in expr to something that isn't known to be pure, looking
+/// through the bodies of the functions the program defines - its tables have
+/// no body and are always pure
+fn impure_call<'a>(
+    expr: &'a Expression,
+    functions: &'a HashMap<String, Option<&'a Expression>>,
+    visited: &mut HashSet<&'a str>,
+) -> Option<String> {
+    match expr {
+        Expression::Literal(_) | Expression::Variable(_) | Expression::Error(_) => None,
+        Expression::Binary(_, lhs, rhs) => {
+            impure_call(lhs, functions, visited).or_else(|| impure_call(rhs, functions, visited))
+        }
+        // resolve has turned every named call into a call before codegen
+        Expression::NamedCall(callee, _) => Some(callee.clone()),
+        Expression::Call(callee, args) => {
+            let callee_call = match functions.get_key_value(callee.as_str()) {
+                // a function that's being looked through already is only
+                // impure if something else in it is
+                Some((name, Some(body))) if visited.insert(name) => {
+                    impure_call(body, functions, visited)
+                }
+                Some(_) => None,
+                None if builtins::is_pure(callee) => None,
+                None => Some(callee.clone()),
+            };
+            callee_call.or_else(|| {
+                args.iter()
+                    .find_map(|arg| impure_call(arg, functions, visited))
+            })
+        }
+    }
+}
+
+/// run every entry of baseline, which should be compiled without fast math
+/// and run without optimizations, and of optimized, which has to come from
+/// the same ast, comparing their results - entries that call anything that
+/// can have effects are skipped so they only run once
+pub fn verify(
+    ast: &[ASTNode],
+    baseline: &Engine,
+    optimized: &Engine,
+    tolerance: u64,
+    width: usize,
+) -> Vec<EntryCheck> {
+    let mut functions = HashMap::new();
+    for node in ast {
+        match node {
+            ASTNode::Function(func) if func.prototype.name != "lambda" => {
+                functions.insert(func.prototype.name.clone(), Some(&func.body));
+            }
+            ASTNode::Table(table) => {
+                functions.insert(table.name.clone(), None);
+                functions.insert(format!("{}_len", table.name), None);
+            }
+            _ => (),
+        }
+    }
+
+    baseline
+        .entries()
+        .iter()
+        .zip(optimized.entries())
+        .map(|((baseline_symbol, expr), (optimized_symbol, _))| {
+            let verdict = match impure_call(expr, &functions, &mut HashSet::new()) {
+                Some(callee) => Verdict::Skipped(callee),
+                None => match (
+                    baseline.run_entry(baseline_symbol),
+                    optimized.run_entry(optimized_symbol),
+                ) {
+                    (Ok(baseline), Ok(optimized)) => {
+                        let ulps = builtins::ulp_distance(baseline, optimized);
+                        if ulps <= tolerance {
+                            Verdict::Agreed
+                        } else {
+                            Verdict::Diverged {
+                                baseline,
+                                optimized,
+                                ulps,
+                            }
+                        }
+                    }
+                    (Err(err), _) | (_, Err(err)) => Verdict::Failed(err),
+                },
+            };
+            EntryCheck {
+                snippet: engine::snippet(expr, width),
+                verdict,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use inkwell::context::Context;
+
+    use super::*;
+    use crate::codegen::Codegen;
+    use crate::compile::compile;
+    use crate::engine::{EngineKind, OptLevel};
+    use crate::plan::CompileOptions;
+
+    fn checks(source: &str, fast_math: bool, tolerance: u64) -> Vec<EntryCheck> {
+        let context = Context::create();
+        let compilation = |fast_math: bool| {
+            let mut codegen = Codegen::new(&context);
+            codegen.fast_math = fast_math;
+            compile(
+                codegen,
+                &[("verify.wiz", source)],
+                &mut CompileOptions::default(),
+            )
+            .unwrap()
+        };
+        let baseline = compilation(false);
+        let ast = baseline.ast().to_vec();
+        let (baseline, optimized) = (
+            baseline.into_program(),
+            compilation(fast_math).into_program(),
+        );
+        let baseline = baseline.engine(EngineKind::Jit, OptLevel::None).unwrap();
+        let optimized = optimized
+            .engine(EngineKind::Jit, OptLevel::Aggressive)
+            .unwrap();
+        verify(&ast, &baseline, &optimized, tolerance, 40)
+    }
+
+    #[test]
+    fn verify_catches_fast_math() {
+        let source = "🜙total🜄a🜌b🜂a + b + a * 0;total🜄1🜌2🜂;total🜄inf🜄🜂🜌1🜂;";
+        let verdicts: Vec<Verdict> = checks(source, true, 0)
+            .into_iter()
+            .map(|check| check.verdict)
+            .collect();
+        assert_eq!(verdicts[0], Verdict::Agreed);
+        match verdicts[1] {
+            Verdict::Diverged {
+                baseline,
+                optimized,
+                ulps,
+            } => {
+                assert!(baseline.is_nan());
+                assert_eq!((optimized, ulps), (f64::INFINITY, u64::MAX));
+            }
+            ref verdict => panic!("{:?}", verdict),
+        }
+        assert!(checks(source, false, 0)
+            .iter()
+            .all(|check| check.verdict == Verdict::Agreed));
+    }
+
+    #[test]
+    fn verify_passes_benign_programs() {
+        let source = "🜹printd🜄x🜂;🜙sq🜄x🜂x*x;🜙noisy🜄x🜂printd🜄x🜂;sq🜄3🜂;sq🜄0.1🜂 + 0.2;noisy🜄sq🜄2🜂🜂;";
+        let checks: Vec<String> = checks(source, true, 0)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            checks,
+            [
+                "(sq🜄3🜂) agreed",
+                "(sq🜄0.1🜂 + 0.2) agreed",
+                "(noisy🜄sq🜄2🜂🜂) skipped, it calls printd which isn't pure",
+            ]
+        );
+    }
+}
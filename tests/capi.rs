@@ -0,0 +1,65 @@
+//! builds libwizarding with the capi feature and examples/embed.c against
+//! it, and runs the example when there's a c compiler to build it with
+#![cfg(feature = "capi")]
+use std::{env, path::Path, process::Command};
+
+#[test]
+fn embed_example_runs() {
+    // the c library isn't one of the crate's default targets, so build it
+    // apart from the test's own target dir, which cargo may still hold
+    let target_dir = Path::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+        .parent()
+        .and_then(Path::parent)
+        .unwrap()
+        .join("capi");
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(&cargo)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(&[
+            "rustc",
+            "--lib",
+            "--features",
+            "capi",
+            "--crate-type",
+            "cdylib",
+        ])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "building libwizarding failed");
+    let target = target_dir.join("debug");
+
+    let exe = env::temp_dir().join(format!("wizarding-embed-{}", std::process::id()));
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let built = Command::new(&cc)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(&["examples/embed.c", "-Iinclude", "-lwizarding", "-o"])
+        .arg(&exe)
+        .arg("-L")
+        .arg(&target)
+        .status();
+    match built {
+        Ok(status) => assert!(status.success(), "{} failed to build the example", cc),
+        Err(_) => {
+            eprintln!("skipping, there's no c compiler at {}", cc);
+            return;
+        }
+    }
+
+    let output = Command::new(&exe)
+        .env("LD_LIBRARY_PATH", &target)
+        .env("DYLD_LIBRARY_PATH", &target)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&exe);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "eval 50\n\
+         hyp 5\n\
+         status 1\n\
+         error <eval-2>:1:1: top level expression 1: unknown function missing\n\
+         json {\"severity\":\"error\",\"code\":\"compile\",\"message\":\"<eval-2>:1:1: top level expression 1: unknown function missing\"}\n"
+    );
+}
@@ -0,0 +1,540 @@
+//! runs the wizarding binary and checks what it prints against the library
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use inkwell::context::Context;
+use wizarding::{codegen::Codegen, compile, plan::CompileOptions};
+
+#[test]
+fn dump_ir_matches_compilation() {
+    let source = "🜹sin🜄x🜂;🜙wave🜄t🜂sin🜄t*2🜂 + 1;wave🜄0🜂;";
+    let output = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+        .args(&["--dump-ir", "-q", "-e", source])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    let context = Context::create();
+    let compilation = compile::compile(
+        Codegen::new(&context),
+        &[("<eval-1>", source)],
+        &mut CompileOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(stdout, "1\n");
+    assert!(stderr.contains(&format!(
+        "--- ir ---\n{}\n--- end ir ---\n",
+        compilation.ir().trim_end()
+    )));
+}
+
+#[test]
+fn dumps_go_to_their_files() {
+    let dir = std::env::temp_dir().join(format!("wizarding-dump-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (symbols, ir, bitcode) = (dir.join("symbols.txt"), dir.join("a.ll"), dir.join("a.bc"));
+    let source = "🜹sin🜄x🜂;🜙wave🜄t🜂sin🜄t*2🜂 + 1;wave🜄0🜂;2 + 3;";
+    let dump = |kind: &str, path: &Path| format!("--dump={}={}", kind, path.display());
+    let output = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+        .args(&[
+            dump("symbols", &symbols),
+            dump("ir", &ir),
+            dump("bytecode", &bitcode),
+        ])
+        .args(&["--dump", "tokens", "-e", source])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "Result:\n#1 (wave🜄0🜂) = 1\n#2 (2 + 3) = 5\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("--- tokens ---\n<eval-1>:1:1 🜹\n"));
+    assert!(stderr.ends_with("--- end tokens ---\n"));
+
+    assert_eq!(
+        std::fs::read_to_string(&symbols).unwrap(),
+        "extern sin🜄x🜂\nfunction wave🜄t🜂\n"
+    );
+    let context = Context::create();
+    let compilation = compile::compile(
+        Codegen::new(&context),
+        &[("<eval-1>", source)],
+        &mut CompileOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(std::fs::read_to_string(&ir).unwrap(), compilation.ir());
+    assert_eq!(std::fs::read(&bitcode).unwrap(), compilation.bitcode());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_subcommand_reports_failures() {
+    let dir = std::env::temp_dir().join(format!("wizarding-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("checks.wiz");
+    std::fs::write(
+        &path,
+        "🜙sq🜄x🜂x*x;test \"squares\" sq🜄3🜂 expect 9;test \"wrong\" sq🜄2🜂 expect 5;test \"unknown\" cube🜄2🜂 expect 8;",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .arg("test")
+            .args(args)
+            .arg(&path)
+            .output()
+            .unwrap();
+        (
+            output.status.success(),
+            String::from_utf8(output.stdout).unwrap(),
+        )
+    };
+
+    let (success, stdout) = run(&[]);
+    assert!(!success);
+    assert!(stdout.starts_with("pass squares\nfail wrong: 4 isn't close to 5\nerror unknown\n"));
+    assert!(stdout.contains("3 tests: 1 passed, 1 failed, 1 errored"));
+    let unknown = format!(
+        "{}:1:69: error[codegen]: unknown function cube",
+        path.display()
+    );
+    assert!(stdout.contains(&unknown), "{}", stdout);
+    let (success, stdout) = run(&["--filter", "squ", "--output", "json"]);
+    assert!(success);
+    assert_eq!(
+        stdout,
+        format!(
+            "{{\"tests\":[{{\"name\":\"squares\",\"location\":{:?},\"status\":\"pass\"}}],\"passed\":1,\"failed\":0,\"errored\":0}}\n",
+            format!("{}:1:11", path.display())
+        )
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn annotate_matches_golden_file() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/annotate");
+    let dir = std::env::temp_dir().join(format!("wizarding-annotate-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (sheet, annotated) = (dir.join("worksheet.wiz"), dir.join("annotated.wiz"));
+    std::fs::copy(fixtures.join("worksheet.wiz"), &sheet).unwrap();
+    let annotate = |args: &[&Path]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .arg("annotate")
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+
+    let expected = std::fs::read_to_string(fixtures.join("worksheet.expected")).unwrap();
+    annotate(&[&sheet, Path::new("-o"), &annotated]);
+    assert_eq!(std::fs::read_to_string(&annotated).unwrap(), expected);
+    // annotating again in place changes nothing
+    annotate(&[&annotated]);
+    assert_eq!(std::fs::read_to_string(&annotated).unwrap(), expected);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn step_prints_each_reduction() {
+    let dir = std::env::temp_dir().join(format!("wizarding-step-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("sq.wiz");
+    std::fs::write(&path, "🜙sq🜄x🜂x * x;sq🜄1 + 2🜂;").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+        .args(&["step", "--all"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "#1\nsq🜄⟦1 + 2⟧🜂\n⟦sq🜄3🜂⟧\n⟦3 * 3⟧\n9\n"
+    );
+    // enter takes one step and q stops
+    let mut child = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+        .arg("step")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"\nq\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "#1\nsq🜄⟦1 + 2⟧🜂\n⟦sq🜄3🜂⟧\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn strict_fp_conflicts_with_fast_math() {
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .args(args)
+            .args(&["-q", "-e", "0.1 + 0.2;"])
+            .output()
+            .unwrap()
+    };
+    let output = run(&["--strict-fp"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "0.30000000000000004\n"
+    );
+    for other in ["--fast-math", "--denormals-are-zero"].iter() {
+        let output = run(&["--strict-fp", other]);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("cannot be used with"), "{}", stderr);
+    }
+}
+
+#[test]
+fn log_level_logs_each_phase() {
+    let run = |command: &mut Command| {
+        let output = command.args(&["-q", "-e", "1 + 2;"]).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n");
+        String::from_utf8(output.stderr).unwrap()
+    };
+    let bin = env!("CARGO_BIN_EXE_wizarding-jit");
+    assert_eq!(run(Command::new(bin).env_remove("WIZARDING_LOG")), "");
+    let flag = run(Command::new(bin).args(&["--log-level", "debug"]));
+    let env = run(Command::new(bin).env("WIZARDING_LOG", "debug"));
+    for logged in [flag, env].iter() {
+        assert!(logged.contains("plan{files=1}:parse{"), "{}", logged);
+        assert!(logged.contains("}:item{"), "{}", logged);
+        assert!(logged.contains("close time.busy"), "{}", logged);
+    }
+}
+
+#[test]
+fn streaming_rejects_allow_reserved() {
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .arg("--allow-reserved")
+            .args(args)
+            .args(&["-q", "-e", "🜙f🜄var🜂var + 1;f🜄1🜂;"])
+            .output()
+            .unwrap()
+    };
+    let output = run(&[]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n");
+    // streaming has no warnings to report the names with
+    let output = run(&["--streaming"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("cannot be used with"), "{}", stderr);
+}
+
+#[test]
+fn streaming_has_no_ast_to_dump() {
+    let output = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+        .args(&["--streaming", "--dump", "ast", "-e", "1;"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("--streaming doesn't keep the ast to dump"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn streaming_resolves_named_arguments() {
+    let source = "🜙sub🜄a🜌b🜂a - b;sub🜄b: 1🜌a: 10🜂;🜙twice🜄x🜂sub🜄b: 0🜌a: x*2🜂;twice🜄4🜂;";
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .args(args)
+            .args(&["-e", source])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(output.status.success(), "{:?}: {}", args, stderr);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let stdout = run(&[]);
+    assert!(stdout.contains(") = 9\n#2 ("), "{}", stdout);
+    assert!(stdout.ends_with(") = 8\n"), "{}", stdout);
+    assert_eq!(run(&["--streaming"]), stdout);
+}
+
+#[test]
+fn streaming_fills_in_defaults() {
+    // 60 + 80 + 40
+    let source = "🜙fall🜄t🜌h = 100🜌g = 10🜂h - g * t * t;fall🜄2🜂 + fall🜄2🜌g: 5🜂 + fall🜄1🜌50🜂;";
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .args(args)
+            .args(&["-q", "-e", source])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(output.status.success(), "{:?}: {}", args, stderr);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(run(&[]), "180\n");
+    assert_eq!(run(&["--streaming"]), "180\n");
+}
+
+#[test]
+fn streaming_allows_inline_ir() {
+    let source = "llvm \"define double @twice(double %x) {\n  %r = fmul double %x, 2.0\n  ret double %r\n}\";twice🜄4🜂;";
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .arg("--streaming")
+            .args(args)
+            .args(&["-q", "-e", source])
+            .output()
+            .unwrap()
+    };
+    let output = run(&[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("inline llvm ir isn't allowed"),
+        "{}",
+        stderr
+    );
+    let output = run(&["--allow-inline-ir"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}", stderr);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "8\n");
+}
+
+#[test]
+fn streaming_makes_implicit_calls() {
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .arg("--implicit-call")
+            .args(args)
+            .args(&["-q", "-e", "🜙answer🜄🜂42;🜙half🜄x🜂x / 2;half🜄answer🜂 + 1;"])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(output.status.success(), "{:?}: {}", args, stderr);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(run(&[]), "22\n");
+    assert_eq!(run(&["--streaming"]), "22\n");
+}
+
+#[test]
+fn streaming_runs_what_compiled() {
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .args(args)
+            .args(&["-e", "🜙f🜄x🜂nope🜄x🜂;🜙g🜄x🜂f🜄x🜂 + 1;🜙k🜄x🜂x*2;k🜄2🜂;"])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("f: unknown function nope"), "{}", stderr);
+        assert!(
+            stderr.contains("g: not compiled because f failed to compile"),
+            "{}",
+            stderr
+        );
+        assert!(stderr.contains("2 items failed to compile"), "{}", stderr);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let stdout = run(&[]);
+    assert!(stdout.ends_with(") = 4\n"), "{}", stdout);
+    assert_eq!(run(&["--streaming"]), stdout);
+}
+
+#[test]
+fn streaming_loads_tables_and_checks_bounds() {
+    let dir = std::env::temp_dir().join(format!("wizarding-bounds-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let sheet = dir.join("coeffs.wiz");
+    std::fs::write(dir.join("coeffs.csv"), "0.5, 1.5").unwrap();
+    std::fs::write(
+        &sheet,
+        "table coeffs from \"coeffs.csv\";coeffs🜄1🜂;coeffs🜄5🜂;",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .args(&["--streaming", "--table-format", "csv"])
+            .args(args)
+            .arg(&sheet)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("#1 (coeffs🜄1🜂) = 1.5"), "{}", stdout);
+    assert!(stdout.contains("#2 (coeffs🜄5🜂) = NaN"), "{}", stdout);
+    // out of bounds traps instead, failing only its own entry
+    let output = run(&["--check-bounds"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("#1 (coeffs🜄1🜂) = 1.5"), "{}", stdout);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("#2 (coeffs🜄5🜂) failed: index 5 is out of bounds of table coeffs"),
+        "{}",
+        stderr
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn libraries_link_through_their_interface() {
+    let dir = std::env::temp_dir().join(format!("wizarding-link-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let lib = dir.join("lib.wiz");
+    std::fs::write(&lib, "🜙sq🜄x🜂x*x;🜙hyp🜄a🜌b🜂sq🜄a🜂+sq🜄b🜂;").unwrap();
+    let (interface, bitcode) = (dir.join("lib.wizi"), dir.join("lib.bc"));
+    let output = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+        .arg("--dump")
+        .arg(format!("interface={}", interface.display()))
+        .arg("--dump")
+        .arg(format!("bytecode={}", bitcode.display()))
+        .arg(&lib)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let run = |source: &str| {
+        Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .arg("--with-interface")
+            .arg(&interface)
+            .arg("--link")
+            .arg(&bitcode)
+            .args(&["-e", source])
+            .output()
+            .unwrap()
+    };
+
+    let output = run("hyp🜄3🜌4🜂;");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("#1 (hyp🜄3🜌4🜂) = 25"), "{}", stdout);
+    // calls are checked against the interface without the library's source
+    let output = run("hyp🜄3🜂;");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("missing argument b"), "{}", stderr);
+
+    // by name, from the library path
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .arg("--lib-path")
+            .arg(&dir)
+            .args(&["--with-interface", "lib.wizi", "--link", "lib.bc"])
+            .args(args)
+            .args(&["-e", "hyp🜄3🜌4🜂;"])
+            .output()
+            .unwrap()
+    };
+    let output = run(&[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("= 25"), "{}", stdout);
+    let output = run(&["--frozen"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("lib.wizi is only on the library path"),
+        "{}",
+        stderr
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failed_items_fail_the_run() {
+    let output = Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+        .args(&["-e", "🜙f🜄x🜂nope🜄x🜂;2 + 3;"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    // what compiled still runs
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "Result:\n#1 (2 + 3) = 5\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("<eval-1>:1:1: error[codegen]: f: unknown function nope"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("1 items failed to compile"), "{}", stderr);
+}
+
+#[test]
+fn eval_snippets_run_in_order() {
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let output = run(&["-q", "-e", "🜙f🜄x🜂x*x;", "-e", "f🜄7🜂;"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "49\n");
+
+    // the file comes first, so the snippets can call what it defines
+    let dir = std::env::temp_dir().join(format!("wizarding-eval-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("sq.wiz");
+    std::fs::write(&file, "🜙sq🜄x🜂x*x;sq🜄2🜂;").unwrap();
+    let output = run(&[file.to_str().unwrap(), "-e", "sq🜄3🜂;"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Result:\n#1 (sq🜄2🜂) = 4\n#2 (sq🜄3🜂) = 9\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let output = run(&["-e", "1 + 1;", "-e", "nope🜄1🜂;", "-e", "2 + 2;"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "<eval-2>:1:1: error[codegen]: top level expression 2: unknown function nope"
+        ),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn snippet_width_has_to_fit() {
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_wizarding-jit"))
+            .args(&["--width", "60"])
+            .args(args)
+            .args(&["-e", "1 + 2 + 3;"])
+            .output()
+            .unwrap()
+    };
+    let output = run(&["--snippet-width", "5"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Result:\n#1 (1 + …) = 6\n"
+    );
+    for width in ["0", "40"].iter() {
+        let output = run(&["--snippet-width", width]);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("it has to be from 1 to 28"), "{}", stderr);
+    }
+}
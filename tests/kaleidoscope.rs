@@ -0,0 +1,70 @@
+//! runs the kaleidoscope tutorial programs in tests/kaleidoscope and compares
+//! their results with the .expected file beside each one - set
+//! WIZARDING_BLESS to rewrite the expectations instead
+use std::{env, fs, path::Path};
+
+use inkwell::context::Context;
+use wizarding::{
+    batch,
+    codegen::Codegen,
+    engine::{Engine, EngineKind, OptLevel},
+    numfmt::NumberFormat,
+    plan::{CompileOptions, Plan},
+};
+
+/// the ir and one line per entry with its result
+fn run(path: &Path) -> (String, String) {
+    let source = fs::read_to_string(path).unwrap();
+    let plan = Plan::new(
+        &path.to_string_lossy(),
+        &source,
+        &mut CompileOptions::default(),
+    )
+    .unwrap();
+    let context = Context::create();
+    let mut codegen = Codegen::new(&context);
+    codegen.codegen(&plan.ast).unwrap();
+    let ir = codegen.module.print_to_string().to_string();
+
+    let engine = Engine::new(&codegen, EngineKind::Jit, OptLevel::None).unwrap();
+    let mut results = String::new();
+    for (snippet, value) in engine.run_all(60) {
+        let value = match value {
+            Ok(value) => NumberFormat::Shortest.format(value),
+            Err(err) => format!("failed: {}", err),
+        };
+        results += &format!("{} = {}\n", snippet, value);
+    }
+    (ir, results)
+}
+
+#[test]
+fn kaleidoscope_programs_work() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/kaleidoscope");
+    let bless = env::var_os("WIZARDING_BLESS").is_some();
+    let programs = batch::discover(&dir).unwrap();
+    assert!(!programs.is_empty());
+
+    for path in programs {
+        let (ir, results) = run(&path);
+        let expected = path.with_extension("expected");
+        if bless {
+            fs::write(&expected, &results).unwrap();
+        } else {
+            let expected = fs::read_to_string(&expected).unwrap_or_default();
+            assert_eq!(results, expected, "{}", path.display());
+        }
+
+        if let Ok(properties) = fs::read_to_string(path.with_extension("ir")) {
+            for property in properties.lines().filter(|line| !line.trim().is_empty()) {
+                assert!(
+                    ir.contains(property.trim()),
+                    "{} is missing '{}' in\n{}",
+                    path.display(),
+                    property,
+                    ir
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! streams a generated program of a million functions through
+//! Compilation::streaming, checking the heap never holds much more than
+//! what the compilation keeps - ignored by default since it takes a while,
+//! run with cargo test --release --test streaming -- --ignored
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    mem,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use inkwell::context::Context;
+use wizarding::{
+    codegen::Codegen,
+    compile::Compilation,
+    lexer::{Lexer, Token},
+    plan::CompileOptions,
+    table::{FsLoader, LoadTables, TableFormat},
+};
+
+/// the system allocator, keeping track of the bytes live and the most that
+/// have been live at once
+struct Peak;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn grow(bytes: usize) {
+    let live = LIVE.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    PEAK.fetch_max(live, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for Peak {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        grow(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        match new_size.checked_sub(layout.size()) {
+            Some(more) => grow(more),
+            None => {
+                LIVE.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+            }
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Peak = Peak;
+
+const FUNCTIONS: usize = 1_000_000;
+
+#[test]
+#[ignore]
+fn streaming_a_million_functions_stays_flat() {
+    let mut source = String::new();
+    for n in 0..FUNCTIONS {
+        source += &format!("🜙f{}🜄x🜌 y🜂x*y + x/{}.5 - y*y*3;\n", n, n);
+    }
+    source += "f7🜄2🜌 3🜂;";
+    // what holding every token at once would take, counted without holding them
+    let tokens = Lexer::new(&source).count() * mem::size_of::<Token>();
+    let tables = LoadTables {
+        loader: Box::new(FsLoader {
+            dir: PathBuf::new(),
+        }),
+        format: TableFormat::Csv,
+    };
+    let options = CompileOptions::default();
+    let context = Context::create();
+    let codegen = Codegen::new(&context);
+
+    let before = LIVE.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let compilation =
+        Compilation::streaming(codegen, &[("huge.wiz", &source)], &options, &tables).unwrap();
+    let kept = LIVE.load(Ordering::Relaxed) - before;
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+
+    assert!(compilation.ast().is_empty());
+    assert_eq!(compilation.symbols().len(), FUNCTIONS);
+    // what's kept is the copy of the source and each function's symbol and
+    // entry in codegen, and beyond that only the item being compiled and
+    // those tables growing are ever live
+    assert!(
+        peak - kept < tokens / 4,
+        "peaked at {} bytes, keeping {}, where the tokens take {}",
+        peak,
+        kept,
+        tokens
+    );
+}